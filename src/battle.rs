@@ -21,9 +21,12 @@ use serde::{Serialize, Deserialize};
 
 use super::{GameObject, GameState, Message, Status};
 use crate::effects;
+use crate::map;
 use crate::npc;
 use crate::player;
+use crate::religion;
 use crate::game_obj::{Ability, GameObjectDB, Person};
+use crate::items::{Item, ItemType};
 use crate::util;
 use crate::util::StringUtils;
 
@@ -37,60 +40,200 @@ pub enum DamageType {
     Electricity,
     Acid,
     Poison,
+    Force,
+}
+
+// The damage an attack does: how many dice, of what size, and of what type
+#[derive(Debug, Clone, Copy)]
+pub struct Strike {
+    pub num_dice: u8,
+    pub die: u8,
+    pub dmg_type: DamageType,
+}
+
+impl Strike {
+    // Fists and feet. Not much, but an empty-handed player isn't entirely helpless.
+    pub fn unarmed() -> Strike {
+        Strike { num_dice: 1, die: 2, dmg_type: DamageType::Bludgeoning }
+    }
+
+    pub fn with_weapon(weapon: &Item) -> Strike {
+        Strike { num_dice: weapon.dmg_dice, die: weapon.dmg_die, dmg_type: weapon.dmg_type }
+    }
+
+    // Things that weren't made for fighting still hurt when they connect. Bottles break into 
+    // jagged pieces and anything with some heft to it makes a decent bludgeon.
+    pub fn improvised(item: &Item) -> Strike {
+        match item.item_type {
+            ItemType::Weapon | ItemType::Ammunition => Strike::with_weapon(item),
+            ItemType::Potion | ItemType::Bottle => Strike { num_dice: 1, die: 3, dmg_type: DamageType::Slashing },
+            _ if item.weight >= 3 => Strike { num_dice: 1, die: 4, dmg_type: DamageType::Bludgeoning },
+            _ => Strike::unarmed(),
+        }
+    }
+
+    pub fn roll<R: Rng>(&self, rng: &mut R) -> u8 {
+        (0..self.num_dice).map(|_| rng.gen_range(1, self.die + 1)).sum()
+    }
+}
+
+// NB: the flanking and sneak bonuses are balance changes, not just bookkeeping. Before the to-hit
+// rules were gathered up into resolve_attack() a monster with pack tactics had no edge when a 
+// packmate was also engaged with the player, and an invisible player attacking got no bonus either.
+// Now they're +2 (10% more likely to hit) and +4 (20%). The unseen penalty is unchanged.
+const FLANKING_BONUS: i8 = 2;
+const SNEAK_BONUS: i8 = 4;
+const UNSEEN_PENALTY: i8 = 5;
+
+// Everything that goes into an attack roll besides the d20 itself. Both the player's and the monsters'
+// attacks are resolved through resolve_attack() so there's one place to look to see what helps or
+// hinders landing a blow.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AttackMods {
+    pub base: i8, // the attacker's own skill: the player's attack bonus or a monster's attack_mod
+    pub enchantment: i8, // bonus from the weapon itself
+    pub flanking: bool, // an ally of the attacker is also engaged with the defender
+    pub sneak: bool, // the defender is unaware of the attacker
+    pub unseen: bool, // the attacker is blind or the defender invisible
+    pub status: i8, // adjustments from statuses like Bane (negative for penalties)
+}
+
+impl AttackMods {
+    pub fn total(&self) -> i8 {
+        let mut total = self.base + self.enchantment + self.status;
+        if self.flanking {
+            total += FLANKING_BONUS;
+        }
+        if self.sneak {
+            total += SNEAK_BONUS;
+        }
+        if self.unseen {
+            total -= UNSEEN_PENALTY;
+        }
+
+        total
+    }
+}
+
+// The to-hit rule: roll a d20 and add the attacker's modifiers. If the result meets or beats the 
+// defender's AC, the attack lands. So an attacker with a total of +4 against AC 15 needs an 11 or better
+// on the die, a 50% chance, and each point of bonus or AC shifts that by 5%.
+pub fn resolve_attack<R: Rng>(rng: &mut R, mods: &AttackMods, defender_ac: u8) -> bool {
+    let roll = rng.gen_range(1, 21) as i8 + mods.total();
+
+    roll >= defender_ac as i8
+}
+
+// Damage for catching a foe unawares. Worked out in i32 since a high level rogue's multiplier
+// can easily push the total past what fits in an i8.
+fn sneak_attack_dmg(dmg_roll: u8, weapon_bonus: i8, str_mod: i8, multiplier: u8) -> u8 {
+    let total = (dmg_roll as i32 + weapon_bonus as i32 + str_mod as i32) * multiplier as i32;
+
+    total.clamp(0, u8::MAX as i32) as u8
+}
+
+// Is another hostile monster besides the attacker engaged with the target?
+fn flanked(game_obj_db: &mut GameObjectDB, target_loc: (i32, i32, i8), attacker_id: usize) -> bool {
+    for adj in util::ADJ.iter() {
+        let loc = (target_loc.0 + adj.0, target_loc.1 + adj.1, target_loc.2);
+        if let Some(id) = game_obj_db.npc_at(&loc) {
+            if id == attacker_id {
+                continue;
+            }
+            let npc = game_obj_db.npc(id).unwrap();
+            if npc.is_monster && npc.alive && npc.attitude == npc::Attitude::Hostile {
+                return true;
+            }
+        }
+    }
+
+    false
 }
 
 pub fn player_attacks(state: &mut GameState, opponent_id: usize, game_obj_db: &mut GameObjectDB) {
-    let mut rng = rand::thread_rng();
+    // Striking from invisibility gives the player the drop on their foe (SNEAK_BONUS), but reveals them
+    let sneaking = game_obj_db.get(0).unwrap().hidden();
+    effects::break_invisibility(state, game_obj_db);
     let npc = game_obj_db.get(opponent_id).unwrap();
     let npc_loc = npc.get_loc();
     let invisible_opponent = npc.hidden();
 
+    // Spilling blood in Woden's sanctuary angers him
+    let player_loc = game_obj_db.get(0).unwrap().get_loc();
+    if state.aura_sqs.contains(&player_loc) || state.aura_sqs.contains(&npc_loc) {
+        religion::sacrilege(state);
+    }
+
     // Fetch the attack bonuses for the player's weapon. Do it here so that Player needs to know
     // less about GameObject and such. 
-    let weapon_attack_bonus;
-    let weapon_dmg_dice;
-    let num_dmg_die;
-    let dmg_type;
     let player = game_obj_db.player().unwrap();
     let blind = player.has_status(Status::Blind);
     let baned = player.has_status(Status::Bane);
-    if let Some(weapon_info) = player.readied_weapon() {
-        weapon_attack_bonus = weapon_info.0.attack_bonus;
-        dmg_type = weapon_info.0.dmg_type;
-        num_dmg_die = weapon_info.0.dmg_die;
-        weapon_dmg_dice = weapon_info.0.dmg_dice;
-    } else {
-        weapon_attack_bonus = 0;
-        num_dmg_die = 1;
-        weapon_dmg_dice = 2;
-        dmg_type = DamageType::Bludgeoning; 
-    }
+    let (strike, weapon_attack_bonus) = match player.readied_weapon() {
+        Some(weapon_info) => (Strike::with_weapon(weapon_info.0), weapon_info.0.attack_bonus),
+        None => (Strike::unarmed(), 0),
+    };
+    let dmg_type = strike.dmg_type;
     
-    let attack_bonus = player.attack_bonus();
-    let mut attack_roll = rng.gen_range(1, 21) + attack_bonus + weapon_attack_bonus;
-    if blind || invisible_opponent {
-        attack_roll -= 5;
-    }
-    if baned {
-        attack_roll -= rand::thread_rng().gen_range(1, 5);
-    }
+    let player_level = player.level;
+    let rogue = player.role == player::Role::Rogue;
+    let mods = AttackMods {
+        base: player.attack_bonus(&mut state.rng),
+        enchantment: weapon_attack_bonus,
+        sneak: sneaking,
+        unseen: blind || invisible_opponent,
+        status: if baned { -state.rng.gen_range(1, 5) } else { 0 },
+        ..Default::default()
+    };
     let str_mod = player::stat_to_mod(player.str);
 
     let mut xp_earned = 0;
+    let mut rend = false;
     let foe = game_obj_db.npc(opponent_id).unwrap();
-    if attack_roll >= foe.ac as i8 {
+
+    // A monster that has never noticed the player is a sitting duck. Rogues can dispatch
+    // weaker foes outright and everyone else at least gets a solid blow in.
+    let unaware = foe.is_monster && !foe.active && !foe.recently_saw_player;
+    if unaware && !blind {
+        if rogue && foe.level < player_level {
+            let s = format!("You assassinate {}!", foe.npc_name(false));
+            state.msg_queue.push_back(Message::new(opponent_id, npc_loc, &s, "You strike something!"));
+            let hp = foe.curr_hp;
+            state.stats.dmg_dealt += hp as u32;
+            foe.damaged(state, hp, dmg_type, 0, "player");
+        } else {
+            let s = format!("You catch {} unawares!", foe.npc_name(false));
+            state.msg_queue.push_back(Message::new(opponent_id, npc_loc, &s, "You strike something!"));
+            let multiplier = if rogue { 2 + player_level / 4 } else { 2 };
+            let dmg_roll = strike.roll(&mut state.rng);
+            let dmg_total = sneak_attack_dmg(dmg_roll, weapon_attack_bonus, str_mod, multiplier);
+            if dmg_total > 0 {
+                state.stats.dmg_dealt += dmg_total as u32;
+                foe.damaged(state, dmg_total, dmg_type, 0, "player");
+            }
+        }
+
+        if !foe.alive {
+            xp_earned = foe.xp_value;
+            state.stats.record_kill(&foe.get_fullname());
+        }
+    } else if resolve_attack(&mut state.rng, &mods, foe.ac) {
         let s = format!("You hit {}!", foe.npc_name(false));
         state.msg_queue.push_back(Message::new(opponent_id, npc_loc, &s, "You hit something!"));
         
-        let dmg_roll: u8 = (0..num_dmg_die).map(|_| rng.gen_range(1, weapon_dmg_dice + 1)).sum();
+        let dmg_roll = strike.roll(&mut state.rng);
         let dmg_total = dmg_roll as i8 + weapon_attack_bonus + str_mod;    
+        // A slashing blow that rolls maximum damage opens a bleeding wound
+        rend = strike.dmg_type == DamageType::Slashing && dmg_roll == strike.num_dice * strike.die;
         if dmg_total > 0 {
+            state.stats.dmg_dealt += dmg_total as u32;
             foe.damaged(state, dmg_total as u8, dmg_type, 0, "player");
             
             // I don't know if this is the best spot for this? But for now, if the monsters is no longer
             // alive after the player must have killed it so award xp
             if !foe.alive {
                 xp_earned = foe.xp_value;
+                state.stats.record_kill(&foe.get_fullname());
             }
         }
     } else {
@@ -103,14 +246,68 @@ pub fn player_attacks(state: &mut GameState, opponent_id: usize, game_obj_db: &m
         state.msg_queue.push_back(Message::new(opponent_id, npc_loc, &s, "You miss entirely!"));
     }
     
+    if rend {
+        apply_bleeding(state, opponent_id, game_obj_db);
+    }
+
+    if xp_earned > 0 {
+        let player = game_obj_db.player().unwrap();
+        player.add_xp(xp_earned, state, (0, 0, 0));
+    }
+}
+
+// Returns true if the thrown item struck its target
+pub fn player_throws(state: &mut GameState, target_id: usize, item: &Item, game_obj_db: &mut GameObjectDB) -> bool {
+    let player = game_obj_db.player().unwrap();
+    let blind = player.has_status(Status::Blind);
+    let strike = Strike::improvised(item);
+    let mods = AttackMods {
+        base: player.attack_bonus(&mut state.rng),
+        enchantment: if item.item_type == ItemType::Weapon { item.attack_bonus } else { 0 },
+        unseen: blind,
+        ..Default::default()
+    };
+    let str_mod = player::stat_to_mod(player.str);
+    let item_name = item.base_info.name.with_def_article();
+
+    let foe = game_obj_db.npc(target_id).unwrap();
+    let foe_loc = foe.get_loc();
+    let mut xp_earned = 0;
+    let hit = resolve_attack(&mut state.rng, &mods, foe.ac);
+    if hit {
+        let s = format!("{} hits {}!", item_name.capitalize(), foe.npc_name(false));
+        state.msg_queue.push_back(Message::new(target_id, foe_loc, &s, "You hear a thud."));
+
+        let dmg_total = strike.roll(&mut state.rng) as i8 + str_mod;
+        if dmg_total > 0 {
+            state.stats.dmg_dealt += dmg_total as u32;
+            foe.damaged(state, dmg_total as u8, strike.dmg_type, 0, "player");
+            if !foe.alive {
+                xp_earned = foe.xp_value;
+                state.stats.record_kill(&foe.get_fullname());
+            }
+        }
+    } else {
+        let s = format!("{} misses {}.", item_name.capitalize(), foe.npc_name(false));
+        state.msg_queue.push_back(Message::new(target_id, foe_loc, &s, ""));
+    }
+
     if xp_earned > 0 {
         let player = game_obj_db.player().unwrap();
         player.add_xp(xp_earned, state, (0, 0, 0));
     }
+
+    hit
 }
 
-pub fn monster_attacks_player(state: &mut GameState, monster_id: usize, game_obj_db: &mut GameObjectDB) {
-    let mut rng = rand::thread_rng();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttackOutcome {
+    Hit,
+    Blocked,
+    Missed,
+}
+
+pub fn monster_attacks_player(state: &mut GameState, monster_id: usize, game_obj_db: &mut GameObjectDB) -> AttackOutcome {
     let npc = game_obj_db.npc(monster_id).unwrap();
     let monster_loc = npc.get_loc();
     let monster_name_indef = npc.npc_name(true);
@@ -122,17 +319,31 @@ pub fn monster_attacks_player(state: &mut GameState, monster_id: usize, game_obj
     let monster_dc = npc.edc;
     let monster_attributes = npc.attributes;
 
+    let player_loc = game_obj_db.get(0).unwrap().get_loc();
+    // Pack hunters get FLANKING_BONUS when one of their fellows is also engaged with the player
+    let pack_tactics = monster_attributes & npc::MA_PACK_TACTICS > 0 && flanked(game_obj_db, player_loc, monster_id);
+
     let player = game_obj_db.player().unwrap();
-    let mut attack_roll = rng.gen_range(1, 21) + attack_mod;
-    if player.base_info.hidden {
-        attack_roll -= 5;
-    }    
+    let mods = AttackMods {
+        base: attack_mod as i8,
+        flanking: pack_tactics,
+        unseen: player.base_info.hidden,
+        ..Default::default()
+    };
+    
+    let hits = resolve_attack(&mut state.rng, &mods, player.ac);
     
-    if attack_roll >= player.ac {
+    // Even a blow that would land can be turned aside by a shield
+    if hits && state.rng.gen_range(0.0, 1.0) < player.block_chance() {
+        let s = format!("You block {}'s attack with your shield!", monster_name);
+        state.msg_queue.push_back(Message::new(monster_id, monster_loc, &s, "You block something's attack with your shield!"));
+
+        AttackOutcome::Blocked
+    } else if hits {
         let s = format!("{} hits you!", monster_name.capitalize());
         state.msg_queue.push_back(Message::new(monster_id, monster_loc, &s, "You are hit!"));
-        let dmg_roll: u8 = (0..dmg_dice).map(|_| rng.gen_range(1, dmg_die + 1)).sum();
-        let dmg_total = (dmg_roll + dmg_bonus) as i8;
+        let dmg_roll: u8 = (0..dmg_dice).map(|_| state.rng.gen_range(1, dmg_die + 1)).sum();
+        let dmg_total = state.difficulty.monster_dmg(dmg_roll + dmg_bonus) as i8;
         if dmg_total > 0 {
             // I'm not yet assigning damage types to monsters so just sending Piercing as a good default
             player.damaged(state, dmg_total as u8, DamageType::Piercing, monster_id, &monster_name_indef);
@@ -144,24 +355,70 @@ pub fn monster_attacks_player(state: &mut GameState, monster_id: usize, game_obj
             if monster_attributes & npc::MA_PARALYZE > 0 {
                 paralyze(state, 0, game_obj_db, monster_dc);
             }
+            if monster_attributes & npc::MA_REND > 0 && state.rng.gen_range(0, 3) == 0 {
+                apply_bleeding(state, 0, game_obj_db);
+            }
         }
+
+        AttackOutcome::Hit
     } else {
         let s = format!("{} misses you!", monster_name.capitalize());
         state.msg_queue.push_back(Message::new(monster_id, monster_loc, &s, "Something misses you!"));
+
+        AttackOutcome::Missed
+    }
+}
+
+// A skilled fighter can turn an opponent's missed swing into an opening of their own
+pub fn player_reacts_to_miss(state: &mut GameState, monster_id: usize, game_obj_db: &mut GameObjectDB) {
+    let player = game_obj_db.player().unwrap();
+    if state.rng.gen_range(0.0, 1.0) >= player.riposte_chance() {
+        return;
+    }
+
+    let npc = game_obj_db.npc(monster_id).unwrap();
+    if !npc.alive {
+        return;
+    }
+    let s = format!("You riposte against {}!", npc.npc_name(false));
+    state.msg_queue.push_back(Message::new(monster_id, npc.get_loc(), &s, "You riposte!"));
+    player_attacks(state, monster_id, game_obj_db);
+}
+
+// Deep cuts and savage bites leave wounds that keep bleeding for a while. The undead, plants, and 
+// illusions have no blood to lose.
+pub fn apply_bleeding(state: &mut GameState, victim_id: usize, game_obj_db: &mut GameObjectDB) {
+    let until = state.turn + state.rng.gen_range(5, 11);
+    if victim_id == 0 {
+        let p = game_obj_db.player().unwrap();
+        if !p.has_status(Status::Bleeding) {
+            state.msg_queue.push_back(Message::info("You are bleeding!"));
+        }
+        effects::add_status(p, Status::Bleeding, until);
+    } else {
+        let npc = game_obj_db.npc(victim_id).unwrap();
+        if !npc.alive || npc.attributes & (npc::MA_UNDEAD | npc::MA_ILLUSION) > 0 || npc.mode == npc::NPCPersonality::Plant {
+            return;
+        }
+        if !npc.has_status(Status::Bleeding) {
+            let s = format!("{} is bleeding!", npc.npc_name(false).capitalize());
+            state.msg_queue.push_back(Message::new(victim_id, npc.get_loc(), &s, ""));
+        }
+        effects::add_status(npc, Status::Bleeding, until);
     }
 }
 
 pub fn paralyze(state: &mut GameState, victim_id: usize, game_obj_db: &mut GameObjectDB, dc: u8) {
     if victim_id == 0 {
         let p = game_obj_db.player().unwrap();
-        let con_save = p.ability_check(Ability::Con);
+        let con_save = p.ability_check(Ability::Con, &mut state.rng);
         if con_save <= dc {
             effects::add_status(p, Status::Paralyzed, dc as u32);
             state.msg_queue.push_back(Message::info("You cannot move!"));
         }        
     } else {
         let npc = game_obj_db.npc(victim_id).unwrap();
-        let con_save = npc.ability_check(Ability::Con);
+        let con_save = npc.ability_check(Ability::Con, &mut state.rng);
         if con_save <= dc {
             effects::add_status(npc, Status::Paralyzed, dc as u32);
         }
@@ -171,14 +428,14 @@ pub fn paralyze(state: &mut GameState, victim_id: usize, game_obj_db: &mut GameO
 pub fn apply_weak_poison(state: &mut GameState, victim_id: usize, game_obj_db: &mut GameObjectDB, dc: u8) {
     if victim_id == 0 {
         let p = game_obj_db.player().unwrap();
-        let con_save = p.ability_check(Ability::Con);
+        let con_save = p.ability_check(Ability::Con, &mut state.rng);
         if con_save <= dc {
             effects::add_status(p, Status::WeakVenom, dc as u32);
             state.msg_queue.push_back(Message::info("You feel ill."));
         }        
     } else {
         let npc = game_obj_db.npc(victim_id).unwrap();
-        let con_save = npc.ability_check(Ability::Con);
+        let con_save = npc.ability_check(Ability::Con, &mut state.rng);
         if con_save <= dc {
             effects::add_status(npc, Status::WeakVenom, dc as u32);
         }
@@ -188,17 +445,17 @@ pub fn apply_weak_poison(state: &mut GameState, victim_id: usize, game_obj_db: &
 pub fn apply_confusion(state: &mut GameState, victim_id: usize, game_obj_db: &mut GameObjectDB, dc: u8) {
     if victim_id == 0 {
         let p = game_obj_db.player().unwrap();
-        let con_save = p.ability_check(Ability::Apt);
+        let con_save = p.ability_check(Ability::Apt, &mut state.rng);
         if con_save <= dc {
-            let until = state.turn + rand::thread_rng().gen_range(10, 16);
+            let until = state.turn + state.rng.gen_range(10, 16);
             effects::add_status(p, Status::Confused, until);
             state.msg_queue.push_back(Message::info("Your head swims!"));
         }        
     } else {
         let npc = game_obj_db.npc(victim_id).unwrap();
-        let con_save = npc.ability_check(Ability::Apt);
+        let con_save = npc.ability_check(Ability::Apt, &mut state.rng);
         if con_save <= dc {
-            let until = state.turn + rand::thread_rng().gen_range(10, 16);
+            let until = state.turn + state.rng.gen_range(10, 16);
             effects::add_status(npc, Status::Confused, until);
         }
     }
@@ -208,13 +465,13 @@ pub fn knock_back(state: &mut GameState, game_obj_db: &mut GameObjectDB, target_
     let p = game_obj_db.player().unwrap();
     let player_size = p.size();
     let player_loc = p.base_info.location;
-    let str_check = p.ability_check(Ability::Str);
+    let str_check = p.ability_check(Ability::Str, &mut state.rng);
 
     let npc_id = game_obj_db.npc_at(&target_loc).unwrap();
     let target = game_obj_db.npc(npc_id).unwrap();
     let target_loc = target.get_loc();
     let target_size = target.size();
-    let target_str_check = target.ability_check(Ability::Str);
+    let target_str_check = target.ability_check(Ability::Str, &mut state.rng);
     let target_name = target.npc_name(false);
 
     if target_size > player_size {
@@ -227,14 +484,17 @@ pub fn knock_back(state: &mut GameState, game_obj_db: &mut GameObjectDB, target_
         let s = format!("You bash {}!", target_name);
         state.msg_queue.push_back(Message::new(npc_id, target_loc, &s, "You bash into something!"));
 
-        if !state.map[&new_loc].passable() {
+        if !map::tile_at(&state.map, &new_loc).passable() {
             let s = format!("{} does not move.", target_name.capitalize());
             state.msg_queue.push_back(Message::new(npc_id, target_loc, &s, "Whatever you hit doesn't move."));        
-        } else if let Some(bystander_id) = game_obj_db.npc_at(&target_loc) {
+        } else if let Some(bystander_id) = game_obj_db.npc_at(&new_loc) {
             let bystander = game_obj_db.npc(bystander_id).unwrap();
             let name = bystander.npc_name(false);
             let s = format!("{} blunders into {}!", target_name.capitalize(), name);
             state.msg_queue.push_back(Message::new(npc_id, target_loc, &s, ""));            
+        } else if game_obj_db.blocking_obj_at(&new_loc) {
+            let s = format!("{} stumbles but has nowhere to go.", target_name.capitalize());
+            state.msg_queue.push_back(Message::new(npc_id, target_loc, &s, ""));
         } else {
             let s = format!("{} staggers back!", target_name.capitalize());
             state.msg_queue.push_back(Message::new(npc_id, target_loc, &s, "Something staggers!"));
@@ -245,4 +505,118 @@ pub fn knock_back(state: &mut GameState, game_obj_db: &mut GameObjectDB, target_
         let s = util::format_msg(npc_id, "hold", "[pronoun] ground!", game_obj_db);
         state.msg_queue.push_back(Message::new(npc_id, target_loc, &s, "You bash something but they do not move!"));
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_obj::GameObjects;
+    use crate::npc::MonsterFactory;
+    use crate::util::GameRng;
+
+    fn hit_rate(mods: AttackMods, ac: u8) -> f64 {
+        let mut rng = GameRng::seeded(2218);
+        let trials = 20_000;
+        let hits = (0..trials).filter(|_| resolve_attack(&mut rng, &mods, ac)).count();
+
+        hits as f64 / trials as f64
+    }
+
+    #[test]
+    fn hit_chance_matches_the_to_hit_rule() {
+        let plus_four = AttackMods { base: 4, ..Default::default() };
+        // +4 against AC 15 needs an 11 or better
+        assert!((hit_rate(plus_four, 15) - 0.5).abs() < 0.02);
+        // each point of AC is 5%
+        assert!((hit_rate(plus_four, 19) - 0.3).abs() < 0.02);
+        // a natural 1 still hits when the bonus is big enough, and a 20 can't reach an AC that's too high
+        assert_eq!(hit_rate(plus_four, 5), 1.0);
+        assert_eq!(hit_rate(plus_four, 25), 0.0);
+    }
+
+    #[test]
+    fn attack_bonuses_shift_the_odds() {
+        let base = AttackMods { base: 4, ..Default::default() };
+        let flanking = AttackMods { flanking: true, ..base };
+        let sneak = AttackMods { sneak: true, ..base };
+        let unseen = AttackMods { unseen: true, ..base };
+        let enchanted = AttackMods { enchantment: 2, status: -1, ..base };
+
+        assert!((hit_rate(flanking, 15) - 0.6).abs() < 0.02);
+        assert!((hit_rate(sneak, 15) - 0.7).abs() < 0.02);
+        assert!((hit_rate(unseen, 15) - 0.25).abs() < 0.02);
+        assert!((hit_rate(enchanted, 15) - 0.55).abs() < 0.02);
+    }
+
+    #[test]
+    fn sneak_attack_damage_doesnt_overflow() {
+        assert_eq!(sneak_attack_dmg(6, 1, 2, 2), 18);
+        assert_eq!(sneak_attack_dmg(20, 5, 4, 7), 203);
+        assert_eq!(sneak_attack_dmg(24, 10, 5, 12), 255);
+        assert_eq!(sneak_attack_dmg(1, -2, -3, 2), 0);
+    }
+
+    #[test]
+    fn improvised_weapons_hit_by_what_they_are() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut rng = GameRng::seeded(1);
+        let mut strike = |name: &str| match Item::get_item(&mut game_obj_db, name, &mut rng) {
+            Some(GameObjects::Item(i)) => Strike::improvised(&i),
+            _ => panic!("no such item: {}", name),
+        };
+
+        let dagger = strike("dagger");
+        assert_eq!((dagger.num_dice, dagger.die), (1, 4));
+        let potion = strike("potion of healing");
+        assert_eq!((potion.num_dice, potion.die, potion.dmg_type), (1, 3, DamageType::Slashing));
+        let armour = strike("ringmail");
+        assert_eq!((armour.num_dice, armour.die, armour.dmg_type), (1, 4, DamageType::Bludgeoning));
+        let torch = strike("torch");
+        assert_eq!((torch.num_dice, torch.die, torch.dmg_type), (1, 2, DamageType::Bludgeoning));
+    }
+
+    #[test]
+    fn strikes_roll_within_their_dice() {
+        let mut rng = GameRng::seeded(2219);
+        let strike = Strike { num_dice: 2, die: 4, dmg_type: DamageType::Slashing };
+        let rolls: Vec<u8> = (0..1000).map(|_| strike.roll(&mut rng)).collect();
+        assert_eq!(*rolls.iter().min().unwrap(), 2);
+        assert_eq!(*rolls.iter().max().unwrap(), 8);
+    }
+
+    // A few rounds of the player and a kobold trading blows, everything drawn from one seed
+    fn skirmish(seed: u64) -> (Vec<String>, u8, u8) {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = crate::tests::test_level(&mut game_obj_db);
+        let player = game_obj_db.player().unwrap();
+        player.curr_hp = 30;
+        player.max_hp = 30;
+
+        state.rng = GameRng::seeded(seed);
+        let mf = MonsterFactory::init();
+        mf.monster("kobold", (5, 6, 1), &mut game_obj_db, &mut state.rng);
+        let kobold_id = game_obj_db.npc_at(&(5, 6, 1)).unwrap();
+        let kobold = game_obj_db.npc(kobold_id).unwrap();
+        kobold.curr_hp = 50;
+        kobold.active = true;
+        kobold.attitude = npc::Attitude::Hostile;
+
+        for _ in 0..5 {
+            player_attacks(&mut state, kobold_id, &mut game_obj_db);
+            monster_attacks_player(&mut state, kobold_id, &mut game_obj_db);
+        }
+
+        let msgs = state.msg_queue.iter().map(|m| m.text.to_string()).collect();
+        let kobold_hp = game_obj_db.npc(kobold_id).unwrap().curr_hp;
+        let player_hp = game_obj_db.player().unwrap().curr_hp;
+
+        (msgs, kobold_hp, player_hp)
+    }
+
+    #[test]
+    fn the_same_seed_fights_the_same_fight() {
+        let first = skirmish(2218);
+        assert!(!first.0.is_empty());
+        assert_eq!(skirmish(2218), first);
+    }
+}
@@ -0,0 +1,352 @@
+// This file is part of RogueVillage, a roguelike game.
+//
+// RogueVillage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RogueVillage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RogueVillage.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+
+use rand::Rng;
+
+use super::{GameState, Message, PLAYER_INV};
+use crate::display::UserInterface;
+use crate::game_obj::{Ability, GameObject, GameObjectDB, GameObjects, Person};
+use crate::items::{Item, ItemType};
+use crate::map::Tile;
+use crate::player::Player;
+use crate::util;
+use crate::util::{GameRng, StringUtils};
+
+const ORE: &str = "lump of iron ore";
+const MAX_FORGE_BONUS: i8 = 3;
+pub const SMITH_FEE: u32 = 15;
+
+pub fn next_to_forge(state: &GameState, loc: (i32, i32, i8)) -> bool {
+    util::ADJ.iter()
+        .chain([(0, 0)].iter())
+        .any(|d| state.map.get(&(loc.0 + d.0, loc.1 + d.1, loc.2)) == Some(&Tile::Forge))
+}
+
+// The slot holding the stack of items with the given name, if the player has any
+pub fn inv_slot_of(player: &Player, name: &str) -> Option<char> {
+    player.inventory.iter()
+        .find_map(|obj| match obj {
+            GameObjects::Item(item) if item.base_info.name == name => Some(item.slot),
+            _ => None,
+        })
+}
+
+fn ore_needed(item_type: ItemType) -> usize {
+    if item_type == ItemType::Weapon { 2 } else { 3 }
+}
+
+// Hammering ore into a weapon makes it keener; into armour or a shield makes it sturdier.
+// Either way, there's only so much improvement a piece of gear can take. The smith always
+// gets it right (for a fee) but a player working on their own needs a strong arm.
+pub fn forge_work(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface, smith: bool) -> f32 {
+    let sbi = state.curr_sidebar_info(game_obj_db);
+    let player = game_obj_db.player().unwrap();
+
+    let ore_slot = match inv_slot_of(player, ORE) {
+        Some(slot) => slot,
+        None => {
+            let s = if smith {
+                "\"Bring me some iron ore and I can improve your gear.\""
+            } else {
+                "You have no ore to work."
+            };
+            state.msg_queue.push_back(Message::info(s));
+            return 0.0;
+        },
+    };
+    let ore = player.inv_count_in_slot(ore_slot);
+
+    let mut menu = Vec::new();
+    for obj in player.inventory.iter() {
+        if let GameObjects::Item(item) = obj {
+            let bonus = if item.item_type == ItemType::Weapon { item.attack_bonus } else { item.ac_bonus };
+            if matches!(item.item_type, ItemType::Weapon | ItemType::Armour | ItemType::Shield) && bonus < MAX_FORGE_BONUS {
+                menu.push((item.slot, item.get_fullname(), ore_needed(item.item_type)));
+            }
+        }
+    }
+
+    if menu.is_empty() {
+        state.msg_queue.push_back(Message::info("You have nothing that can be improved at the forge."));
+        return 0.0;
+    }
+
+    let mut text = format!("You have {} lump{} of ore. Improve which?\n", ore, if ore == 1 { "" } else { "s" });
+    for m in menu.iter() {
+        text.push_str(&format!("\n{}) {} ({} ore)", m.0, m.1, m.2));
+    }
+    if smith {
+        text.push_str(&format!("\n\nThe smith charges {}$ for the work.", SMITH_FEE));
+    }
+    let options: HashSet<char> = menu.iter().map(|m| m.0).collect();
+    let choice = match gui.popup_menu("The forge", &text, &options, Some(&sbi)) {
+        Some(ch) => menu.iter().find(|m| m.0 == ch).unwrap().clone(),
+        None => {
+            state.msg_queue.push_back(Message::info("Never mind."));
+            return 0.0;
+        },
+    };
+
+    if ore < choice.2 {
+        state.msg_queue.push_back(Message::info("You don't have enough ore for that."));
+        return 0.0;
+    }
+
+    let player = game_obj_db.player().unwrap();
+    if smith {
+        if player.purse < SMITH_FEE {
+            state.msg_queue.push_back(Message::info("\"You can't afford my work, I'm afraid.\""));
+            return 0.0;
+        }
+        player.purse -= SMITH_FEE;
+    }
+
+    let _ = player.inv_remove_from_slot(ore_slot, choice.2 as u32);
+    if !smith && player.ability_check(Ability::Str, &mut state.rng) < 14 {
+        state.msg_queue.push_back(Message::info("You botch the work and the ore is wasted."));
+        return 5.0;
+    }
+
+    if let Some(GameObjects::Item(item)) = player.inv_item_in_slot(choice.0) {
+        let s = if item.item_type == ItemType::Weapon {
+            item.attack_bonus += 1;
+            format!("Your {} is keener than before.", item.base_info.name)
+        } else {
+            item.ac_bonus += 1;
+            format!("Your {} is sturdier than before.", item.base_info.name)
+        };
+        item.value += 10;
+        state.msg_queue.push_back(Message::info(&s));
+    }
+    player.calc_ac();
+
+    if smith { 1.0 } else { 5.0 }
+}
+
+pub struct Recipe {
+    pub output: &'static str,
+    pub count: u8,
+    pub inputs: &'static [(&'static str, usize)],
+    pub needs_forge: bool,
+}
+
+pub const RECIPES: [Recipe; 5] = [
+    Recipe { output: "arrow", count: 6, inputs: &[(ORE, 1)], needs_forge: true },
+    Recipe { output: "shield", count: 1, inputs: &[(ORE, 4)], needs_forge: true },
+    Recipe { output: "longsword", count: 1, inputs: &[(ORE, 5)], needs_forge: true },
+    Recipe { output: "dagger", count: 1, inputs: &[(ORE, 2)], needs_forge: true },
+    Recipe { output: "ration", count: 1, inputs: &[("apple", 2), ("piece of mushroom", 2)], needs_forge: false },
+];
+
+fn has_materials(player: &Player, recipe: &Recipe) -> bool {
+    recipe.inputs.iter().all(|(name, amt)| {
+        match inv_slot_of(player, name) {
+            Some(slot) => player.inv_count_in_slot(slot) >= *amt,
+            None => false,
+        }
+    })
+}
+
+// Use up the recipe's materials and hand the player what they've made. Assumes the caller
+// has already checked the player has everything needed.
+pub fn make_recipe(game_obj_db: &mut GameObjectDB, recipe: &Recipe, rng: &mut GameRng) {
+    let player = game_obj_db.player().unwrap();
+    for (name, amt) in recipe.inputs.iter() {
+        if let Some(slot) = inv_slot_of(player, name) {
+            let _ = player.inv_remove_from_slot(slot, *amt as u32);
+        }
+    }
+
+    for _ in 0..recipe.count {
+        let mut obj = Item::get_item(game_obj_db, recipe.output, rng).unwrap();
+        obj.set_loc(PLAYER_INV);
+        game_obj_db.player().unwrap().add_to_inv(obj);
+    }
+}
+
+fn recipe_desc(recipe: &Recipe) -> String {
+    let inputs: Vec<String> = recipe.inputs.iter()
+        .map(|(name, amt)| if *amt == 1 { name.to_string().with_indef_article() } else { format!("{} x{}", name, amt) })
+        .collect();
+    let output = if recipe.count == 1 { recipe.output.to_string().with_indef_article() } else { format!("{} x{}", recipe.output, recipe.count) };
+
+    format!("{} from {}", output, inputs.join(", "))
+}
+
+const REAGENTS: [&str; 3] = ["apple", "lump of iron ore", "piece of mushroom"];
+
+// Ingredient lists are kept sorted so a brew can be matched regardless of the order things went in
+const BREWS: [(&[&str], &str); 5] = [
+    (&["piece of mushroom", "piece of mushroom"], "potion of healing"),
+    (&["apple", "piece of mushroom"], "potion of speed"),
+    (&["piece of mushroom", "piece of mushroom", "piece of mushroom"], "potion of levitation"),
+    (&["apple", "apple", "piece of mushroom"], "potion of healing"),
+    (&["lump of iron ore", "piece of mushroom", "piece of mushroom"], "potion of invisibility"),
+];
+
+fn next_to_water(state: &GameState, loc: (i32, i32, i8)) -> bool {
+    util::ADJ.iter()
+        .chain([(0, 0)].iter())
+        .any(|d| matches!(state.map.get(&(loc.0 + d.0, loc.1 + d.1, loc.2)), Some(Tile::Well) | Some(Tile::Spring) | Some(Tile::Water)))
+}
+
+// What a set of ingredients turns into, if they make anything useful at all
+pub fn brew_result(ingredients: &[&str]) -> Option<&'static str> {
+    let mut sorted = ingredients.to_vec();
+    sorted.sort_unstable();
+    BREWS.iter()
+         .find(|(recipe, _)| *recipe == sorted.as_slice())
+         .map(|(_, potion)| *potion)
+}
+
+// Toss up to three reagents into some water and see what comes of it. Even a good combination
+// can go wrong for a player with a poor head for alchemy.
+fn brew(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) -> f32 {
+    let sbi = state.curr_sidebar_info(game_obj_db);
+    let mut chosen: Vec<&str> = Vec::new();
+    while chosen.len() < 3 {
+        let player = game_obj_db.player().unwrap();
+        let mut text = "Add which ingredient? (escape when done)\n".to_string();
+        let mut options = HashSet::new();
+        for (j, reagent) in REAGENTS.iter().enumerate() {
+            let have = inv_slot_of(player, reagent).map_or(0, |slot| player.inv_count_in_slot(slot));
+            let left = have - chosen.iter().filter(|c| *c == reagent).count();
+            if left > 0 {
+                let ch = (b'a' + j as u8) as char;
+                text.push_str(&format!("\n{}) {} ({} left)", ch, reagent, left));
+                options.insert(ch);
+            }
+        }
+        if !chosen.is_empty() {
+            text.push_str(&format!("\n\nIn the pot: {}", chosen.join(", ")));
+        }
+
+        if options.is_empty() {
+            break;
+        }
+        match gui.popup_menu("Alchemy", &text, &options, Some(&sbi)) {
+            Some(ch) => chosen.push(REAGENTS[(ch as u8 - b'a') as usize]),
+            None => break,
+        }
+    }
+
+    if chosen.is_empty() {
+        state.msg_queue.push_back(Message::info("Never mind."));
+        return 0.0;
+    }
+
+    let player = game_obj_db.player().unwrap();
+    for reagent in chosen.iter() {
+        if let Some(slot) = inv_slot_of(player, reagent) {
+            let _ = player.inv_remove_from_slot(slot, 1);
+        }
+    }
+
+    let botched = player.ability_check(Ability::Apt, &mut state.rng) < 10;
+    let result = match brew_result(&chosen) {
+        Some(potion) if !botched => Some(potion),
+        _ if state.rng.gen_range(0.0, 1.0) < 0.5 => Some("murky potion"),
+        _ => None,
+    };
+
+    match result {
+        Some(potion) => {
+            let mut obj = Item::get_item(game_obj_db, potion, &mut state.rng).unwrap();
+            obj.set_loc(PLAYER_INV);
+            game_obj_db.player().unwrap().add_to_inv(obj);
+            let s = format!("You brew {}.", potion.to_string().with_indef_article());
+            state.msg_queue.push_back(Message::info(&s));
+        },
+        None => state.msg_queue.push_back(Message::info("The brew boils away into a foul-smelling sludge.")),
+    }
+
+    3.0
+}
+
+// Pick something to make from the materials the player has gathered. Standing next to a forge
+// opens up the metalworking recipes and improving gear, and next to water the player can try
+// their hand at brewing potions.
+pub fn craft(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) -> f32 {
+    let sbi = state.curr_sidebar_info(game_obj_db);
+    let player_loc = game_obj_db.get(0).unwrap().get_loc();
+    let at_forge = next_to_forge(state, player_loc);
+    let at_water = next_to_water(state, player_loc);
+    let player = game_obj_db.player().unwrap();
+
+    let mut text = "What would you like to make?\n".to_string();
+    let mut options = HashSet::new();
+    if at_forge {
+        text.push_str("\na) work ore into your gear");
+        options.insert('a');
+    }
+    let mut slot = 'b';
+    for recipe in RECIPES.iter() {
+        let mut line = format!("\n{}) {}", slot, recipe_desc(recipe));
+        if recipe.needs_forge && !at_forge {
+            line.push_str(" (needs a forge)");
+        } else if !has_materials(player, recipe) {
+            line.push_str(" (missing materials)");
+        } else {
+            options.insert(slot);
+        }
+        text.push_str(&line);
+        slot = (slot as u8 + 1) as char;
+    }
+    if at_water {
+        text.push_str("\n\nz) brew a potion");
+        options.insert('z');
+    }
+
+    match gui.popup_menu("Crafting", &text, &options, Some(&sbi)) {
+        Some('a') => forge_work(state, game_obj_db, gui, false),
+        Some('z') => brew(state, game_obj_db, gui),
+        Some(ch) => {
+            let recipe = &RECIPES[(ch as u8 - b'b') as usize];
+            make_recipe(game_obj_db, recipe, &mut state.rng);
+            let s = format!("You make {}.", if recipe.count == 1 { recipe.output.to_string().with_indef_article() } else { recipe.output.to_string().pluralize() });
+            state.msg_queue.push_back(Message::info(&s));
+
+            if recipe.needs_forge { 5.0 } else { 2.0 }
+        },
+        None => {
+            state.msg_queue.push_back(Message::info("Never mind."));
+            0.0
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn brews_match_whatever_order_things_go_in() {
+        assert_eq!(brew_result(&["apple", "piece of mushroom"]), Some("potion of speed"));
+        assert_eq!(brew_result(&["piece of mushroom", "apple"]), Some("potion of speed"));
+        assert_eq!(brew_result(&["piece of mushroom", "lump of iron ore", "piece of mushroom"]), Some("potion of invisibility"));
+        assert_eq!(brew_result(&["piece of mushroom", "apple", "apple"]), Some("potion of healing"));
+        assert_eq!(brew_result(&["piece of mushroom"; 3]), Some("potion of levitation"));
+    }
+
+    #[test]
+    fn poor_combinations_brew_nothing() {
+        assert_eq!(brew_result(&[]), None);
+        assert_eq!(brew_result(&["apple"]), None);
+        assert_eq!(brew_result(&["lump of iron ore", "lump of iron ore"]), None);
+        assert_eq!(brew_result(&["apple", "apple", "apple"]), None);
+    }
+}
@@ -16,49 +16,69 @@
 use std::{collections::HashMap};
 use std::fs;
 
-use rand::{Rng, thread_rng};
+use rand::Rng;
 
 use super::GameState;
 use crate::npc::Attitude;
-use crate::util::StringUtils;
+use crate::util::{GameRng, StringUtils};
 
 pub type DialogueLibrary = HashMap<String, Vec<(Attitude, String, String)>>;
 
-// Not doing any error checking yet; assuming a well-formed dialogue file...
-pub fn read_dialogue_lib() -> DialogueLibrary {
+// Parse the dialogue file, collecting a description of any malformed lines rather than
+// stopping at the first one.
+fn parse_dialogue_lib() -> (DialogueLibrary, Vec<String>) {
     let mut dl: DialogueLibrary = HashMap::new();
+    let mut errors = Vec::new();
 
     let contents = fs::read_to_string("dialogue.txt")
         .expect("Unable to find dialogue file!");
     
     let mut curr_voice = "";
-    for line in contents.lines() {        
+    for (j, line) in contents.lines().enumerate() {        
         if line.starts_with("voice:") {
             let pieces: Vec<&str> = line.split(':').collect();
             curr_voice = pieces[1];
             dl.insert(String::from(curr_voice), Vec::new());
             continue;
-        } else if line.starts_with('#') {
+        } else if line.starts_with('#') || line.trim().is_empty() {
             continue;
         }
 
         let pieces: Vec<&str> = line.split('|').collect();
+        if pieces.len() < 3 {
+            errors.push(format!("dialogue.txt line {}: expected attitude|context|line", j + 1));
+            continue;
+        }
         let attitude = match pieces[0] {
             "Indifferent" => Attitude::Indifferent,
             "Friendly" => Attitude::Friendly,
             "Hostile" => Attitude::Hostile,
             "Stranger" => Attitude::Stranger,
-            _ => panic!("Unknown atttitude in dialogue.txt"),
+            _ => {
+                errors.push(format!("dialogue.txt line {}: unknown attitude {}", j + 1, pieces[0]));
+                continue;
+            }
         };
         let context = pieces[1].to_string();
         let line = pieces[2].trim().to_string();
-        dl.get_mut(curr_voice).unwrap().push((attitude, context, line));
+        match dl.get_mut(curr_voice) {
+            Some(lines) => lines.push((attitude, context, line)),
+            None => errors.push(format!("dialogue.txt line {}: line given before any voice:", j + 1)),
+        }
     }
 
-    dl
+    (dl, errors)
+}
+
+pub fn read_dialogue_lib() -> DialogueLibrary {
+    parse_dialogue_lib().0
+}
+
+pub fn validate_dialogue_lib() -> Vec<String> {
+    parse_dialogue_lib().1
 }
 
-pub fn pick_voice_line(lib: &DialogueLibrary, voice: &str, attitude: Attitude, context: &str) -> String {
+pub fn pick_voice_line(lib: &DialogueLibrary, voice: &str, attitude: Attitude, context: &str, rng: &mut GameRng) -> String {
     let mut lines = Vec::new();
     for line in lib.get(voice).unwrap().iter() {
         if line.0 == attitude && line.1 == context {
@@ -66,7 +86,7 @@ pub fn pick_voice_line(lib: &DialogueLibrary, voice: &str, attitude: Attitude, c
         }
     }
 
-    let j = thread_rng().gen_range(0, lines.len());
+    let j = rng.gen_range(0, lines.len());
     
     String::from(lines[j].to_string())
 }
@@ -137,7 +157,7 @@ pub fn parse_voice_line(line: &str, state: &GameState, speaker: &str, speaker_lo
     s.capitalize()
 }
 
-pub fn rnd_innkeeper_voice() -> String {
+pub fn rnd_innkeeper_voice(rng: &mut GameRng) -> String {
     let contents = fs::read_to_string("dialogue.txt")
         .expect("Unable to find dialogue file!");
     
@@ -149,7 +169,6 @@ pub fn rnd_innkeeper_voice() -> String {
         }
     }
 
-    let mut rng = rand::thread_rng();
     let pick = rng.gen_range(0, voices.len());
 
     voices[pick].to_string()
@@ -25,6 +25,7 @@ use crate::game_obj::{GameObject, GameObjectDB};
 use crate::map;
 use crate::map::{Tile, DoorState};
 use crate::util;
+use crate::util::GameRng;
 
 use super::{Cmd, GameState, FOV_WIDTH, FOV_HEIGHT};
 
@@ -33,8 +34,8 @@ use sdl2::EventPump;
 use sdl2::keyboard::Mod;
 use sdl2::keyboard::Keycode;
 use sdl2::rect::Rect;
-use sdl2::render::WindowCanvas;
-use sdl2::surface::Surface;
+use sdl2::render::{Texture, TextureCreator, WindowCanvas};
+use sdl2::video::WindowContext;
 use sdl2::ttf::Font;
 use sdl2::pixels::Color;
 
@@ -46,6 +47,7 @@ pub const GREY: Colour = (136, 136, 136);
 pub const LIGHT_GREY: Colour = (220, 220, 220);
 pub const DARK_GREY: Colour = (72, 73, 75);
 pub const GRANITE: Colour = (169, 169, 169);
+pub const DARK_GRANITE: Colour = (95, 95, 110);
 pub const GREEN: Colour = (144, 238, 144);
 pub const DARK_GREEN: Colour = (0, 71, 49);
 pub const LIGHT_BROWN: Colour = (150, 75, 0);
@@ -72,27 +74,70 @@ const DEFAULT_FONT: &str = "DejaVuSansMono.ttf";
 const SM_FONT_PT: u16 = 18;
 const LG_FONT_PT: u16 = 25;
 const ANIMATION_DELAY: u64 = 75;
+const POPUP_PAGE_LINES: usize = 18;
 
 #[derive(Debug)]
 pub struct SidebarInfo {
-	name: String,
-	ac: u8,
-	curr_hp: u8,
-	max_hp: u8,
-	turn: u32,
-	zorkmids: u32,
-	weapon: String,
-	curr_level: u8,
-	poisoned: bool,
-	confused: bool,
-	paralyzed: bool,
-	flying: bool,
+	pub name: String,
+	pub ac: u8,
+	pub curr_hp: u8,
+	pub max_hp: u8,
+	pub curr_mana: u8,
+	pub max_mana: u8,
+	pub turn: u32,
+	pub zorkmids: u32,
+	pub weapon: String,
+	pub curr_level: u8,
+	pub poisoned: bool,
+	pub confused: bool,
+	pub paralyzed: bool,
+	pub flying: bool,
+	pub hasted: bool,
+	pub slowed: bool,
+	pub blind: bool,
+	pub bleeding: bool,
+	pub hungry: bool,
+	pub weak: bool,
+	pub thirsty: bool,
+	pub parched: bool,
 }
 
-impl SidebarInfo {
-	pub fn new(name: String, curr_hp: u8, max_hp: u8, turn: u32, ac: u8, zorkmids: u32, weapon: String, curr_level: u8, poisoned: bool, confused: bool,
-			paralyzed: bool, flying: bool) -> SidebarInfo {
-		SidebarInfo { name, curr_hp, max_hp, turn, ac, zorkmids, weapon, curr_level, poisoned, confused, paralyzed, flying, }
+// Word-wrap text to the given width and then split it into pages. Easiest thing to do is to split 
+// the text into words and then append them to a line so long as there is room left on the current line.
+pub fn paginate_text(text: &str, width: usize, page_len: usize) -> Vec<Vec<String>> {
+	let mut lines = Vec::new();
+	let mut line = "".to_string();
+	for word in util::split_msg(text) {
+		if word == "\n" {
+			lines.push(line);
+			line = "".to_string();
+			continue;
+		}
+
+		if !line.is_empty() && line.len() + word.len() >= width {
+			lines.push(line);
+			line = "".to_string();
+		}
+		line.push_str(&word);
+		line.push(' ');
+	}
+	lines.push(line);
+
+	lines.chunks(page_len)
+		 .map(|page| page.to_vec())
+		 .collect()
+}
+
+fn dir_to_str(dir: (i32, i32)) -> &'static str {
+	match dir {
+		(-1, 0) => "N",
+		(1, 0) => "S",
+		(0, 1) => "E",
+		(0, -1) => "W",
+		(-1, -1) => "NW",
+		(-1, 1) => "NE",
+		(1, -1) => "SW",
+		_ => "SE",
 	}
 }
 
@@ -100,7 +145,32 @@ fn tuple_to_sdl2_color(ct: &(u8, u8, u8)) -> Color {
 	Color::RGBA(ct.0, ct.1, ct.2, 255)
 }
  
-pub struct GameUI<'a, 'b> {
+// The game logic only talks to the screen and keyboard through this trait, so nothing outside of
+// display.rs needs to know about SDL. (And in principle something other than GameUI could drive the
+// game, say a scripted input source for testing.)
+pub trait UserInterface {
+	fn set_v_matrix(&mut self, v_matrix: [(Tile, bool); FOV_HEIGHT * FOV_WIDTH]);
+	fn query_yes_no(&mut self, question: &str, sbi: Option<&SidebarInfo>) -> char;
+	fn query_natural_num(&mut self, query: &str, sbi: Option<&SidebarInfo>) -> Option<u32>;
+	fn query_user(&mut self, question: &str, max: u8, sbi: Option<&SidebarInfo>) -> Option<String>;
+	fn select_dir(&mut self, prompt: &str, state: &GameState, game_obj_db: &mut GameObjectDB) -> Option<(i32, i32, i8)>;
+	fn draw_effects(&mut self, state: &GameState, game_obj_db: &mut GameObjectDB, sqs_affected: &[(i32, i32, i8)], effect: u128);
+	fn select_target(&mut self, state: &GameState, game_obj_db: &mut GameObjectDB, prompt: &str) -> Option<(i32, i32, i8)>;
+	fn get_command(&mut self, state: &GameState, game_obj_db: &mut GameObjectDB) -> Cmd;
+	fn pause_for_more(&mut self);
+	fn show_in_side_pane(&mut self, blurb: &str, lines: &[(String, bool)]) -> Option<char>;
+	fn write_long_msg(&mut self, lines: &[&str], small_text: bool);
+	fn popup_menu(&mut self, title: &str, text: &str, options: &HashSet<char>, sbi: Option<&SidebarInfo>) -> Option<char>;
+	fn popup_msg(&mut self, title: &str, text: &str, sbi: Option<&SidebarInfo>) -> Option<char>;
+	fn show_message_history(&mut self);
+	fn recent_messages(&self, count: usize) -> Vec<String>;
+	fn clear_message_history(&mut self);
+	fn update(&mut self, msg_queue: &mut VecDeque<String>, sbi: Option<&SidebarInfo>);
+	fn side_pane_menu(&mut self, preamble: String, menu: &[(String, char)], single_choice: bool) -> Option<HashSet<char>>;
+	fn menu_wordy_picker(&mut self, menu: &[&str], answers: &HashSet<&char>) -> Option<char>;
+}
+
+pub struct GameUI<'a, 'b, 'c> {
 	screen_width_px: u32,
 	screen_height_px: u32,
 	font_width: u32,
@@ -111,45 +181,54 @@ pub struct GameUI<'a, 'b> {
 	sm_font: &'a Font<'a, 'b>,
 	canvas: WindowCanvas,
 	event_pump: EventPump,
-	pub v_matrix: [(Tile, bool); FOV_HEIGHT * FOV_WIDTH],
-	surface_cache: HashMap<(char, Colour, Colour), Surface<'a>>,
-	msg_line: String,
+	v_matrix: [(Tile, bool); FOV_HEIGHT * FOV_WIDTH],
+	texture_creator: &'c TextureCreator<WindowContext>,
+	glyph_cache: HashMap<(char, Colour, Colour), Texture<'c>>,
 	messages: VecDeque<(String, bool)>,
 	message_history: VecDeque<(String, u8)>,
+	// Only for cosmetic flourishes, so drawing never disturbs the game's own rng
+	rng: GameRng,
 }
 
-impl<'a, 'b> GameUI<'a, 'b> {
-	pub fn init(font: &'b Font, sm_font: &'b Font) -> Result<GameUI<'a, 'b>, String> {
+// The window is opened outside of GameUI so that main() can own the canvas's texture creator. The
+// glyph textures GameUI caches borrow from it, so it has to outlive the GameUI.
+pub fn open_window(font: &Font) -> Result<(EventPump, WindowCanvas), String> {
+	let (font_width, font_height) = font.size_of_char(' ').unwrap();
+	let sdl_context = sdl2::init()?;
+	let video_subsystem = sdl_context.video()?;
+	let window = video_subsystem.window("rv 0.0.1", SCREEN_WIDTH * font_width + 50, SCREEN_HEIGHT * font_height)
+		.position_centered()
+		.opengl()
+		.build()
+		.map_err(|e| e.to_string())?;
+	let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+
+	Ok((sdl_context.event_pump()?, canvas))
+}
+
+impl<'a, 'b, 'c> GameUI<'a, 'b, 'c> {
+	pub fn init(font: &'b Font, sm_font: &'b Font, event_pump: EventPump, canvas: WindowCanvas, 
+			texture_creator: &'c TextureCreator<WindowContext>) -> GameUI<'a, 'b, 'c> {
 		let (font_width, font_height) = font.size_of_char(' ').unwrap();
 		let screen_width_px = SCREEN_WIDTH * font_width + 50;
 		let screen_height_px = SCREEN_HEIGHT * font_height;
 
 		let (sm_font_width, sm_font_height) = sm_font.size_of_char(' ').unwrap();
 
-		let sdl_context = sdl2::init()?;
-		let video_subsystem = sdl_context.video()?;
-		let window = video_subsystem.window("rv 0.0.1", screen_width_px, screen_height_px)
-			.position_centered()
-			.opengl()
-			.build()
-			.map_err(|e| e.to_string())?;
-
 		let v_matrix = [(map::Tile::Blank, false); FOV_WIDTH * FOV_HEIGHT];
-		let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
-		let gui = GameUI { 
+		GameUI { 
 			screen_width_px, screen_height_px, 
 			font, font_width, font_height, 
 			canvas,
-			event_pump: sdl_context.event_pump().unwrap(),
+			event_pump,
 			sm_font, sm_font_width, sm_font_height,
 			v_matrix,
-			surface_cache: HashMap::new(),
-			msg_line: "".to_string(),
+			texture_creator,
+			glyph_cache: HashMap::new(),
 			messages: VecDeque::new(),
 			message_history: VecDeque::new(),
-		};
-
-		Ok(gui)
+			rng: GameRng::default(),
+		}
 	}
 
 	// I need to handle quitting the app actions here too
@@ -285,7 +364,7 @@ impl<'a, 'b> GameUI<'a, 'b> {
 		}
 	}
 
-	pub fn draw_effects(&mut self, state: &GameState, game_obj_db: &mut GameObjectDB, sqs_affected: &Vec<(i32, i32, i8)>, effect: u128) {
+	pub fn draw_effects(&mut self, state: &GameState, game_obj_db: &mut GameObjectDB, sqs_affected: &[(i32, i32, i8)], effect: u128) {
 		let sbi = state.curr_sidebar_info(game_obj_db);
 		let player_loc = game_obj_db.player().unwrap().get_loc();
 		let center = (FOV_HEIGHT / 2, FOV_WIDTH / 2);
@@ -295,7 +374,7 @@ impl<'a, 'b> GameUI<'a, 'b> {
 			let loc = (sq.0 - player_loc.0 + center.0 as i32, sq.1 - player_loc.1 + center.1 as i32);
 
 			let colour = if effect & effects::EF_FROST > 0 {
-				let roll = rand::thread_rng().gen_range(0.0, 1.0);
+				let roll = self.rng.gen_range(0.0, 1.0);
 				if roll <= 0.6 {
 					WHITE
 				} else if roll <= 0.8 {
@@ -425,10 +504,16 @@ impl<'a, 'b> GameUI<'a, 'b> {
 					Event::TextInput { text:val, .. } => {
 						if val == "Q" {
 							return Cmd::Quit;	
+						} else if val == "J" {
+							return Cmd::ShowJournal;
+						} else if val == "W" {
+							return Cmd::WriteNote;
 						} else if val == "i" {
 							return Cmd::ShowInventory
 						} else if val == "@" {
 							return Cmd::ShowCharacterSheet;	
+						} else if val == "E" {
+							return Cmd::ShowEquipment;
 						} else if val == "e" {
 							return Cmd::ToggleEquipment;
 						} else if val == "." {
@@ -447,6 +532,14 @@ impl<'a, 'b> GameUI<'a, 'b> {
 							}
 						} else if val == "a" {
                             return Cmd::Use;
+						} else if val == "m" {
+							return Cmd::Craft;
+						} else if val == "Z" {
+							return Cmd::Cast;
+						} else if val == "p" {
+							return Cmd::Pray;
+						} else if val == "t" {
+							return Cmd::Throw;
                         } else if val == "?" {
 							return Cmd::Help;
 						} else if val == "o" {
@@ -475,16 +568,28 @@ impl<'a, 'b> GameUI<'a, 'b> {
 							return Cmd::Move(String::from("SW"));
 						} else if val == "n" {
 							return Cmd::Move(String::from("SE"));
+						} else if val == "G" {
+							let sbi = state.curr_sidebar_info(game_obj_db);
+							match self.pick_direction("Run which way?", Some(&sbi)) {
+								Some(dir) => return Cmd::Run(dir_to_str(dir).to_string()),
+								None => self.draw_frame("Nevermind.", Some(&sbi), true),
+							}
+						} else if val == "/" {
+							return Cmd::Repeat;
 						} else if val == "," {
 							return Cmd::PickUp;
 						} else if val == "d" {
 							return Cmd::DropItem;
+						} else if val == "q" {
+							return Cmd::Drink;
 						} else if val == "s" {
 							return Cmd::Search;
 						} else if val == ">" {
 							return Cmd::Down;
 						} else if val == "<" {
 							return Cmd::Up;
+						} else if val == "T" {
+							return Cmd::Travel;
 						} else if val == ":" {
 							return Cmd::WizardCommand;
 						} else if val == "@" {
@@ -553,7 +658,7 @@ impl<'a, 'b> GameUI<'a, 'b> {
 			.expect("Error copying message line texture to canvas!");
 	}
 
-	pub fn show_in_side_pane(&mut self, blurb: &str, lines: &Vec<(String, bool)>) -> Option<char> {
+	pub fn show_in_side_pane(&mut self, blurb: &str, lines: &[(String, bool)]) -> Option<char> {
 		self.canvas.clear();
 		self.draw_frame(&"", None, false);
 		self.write_line(0, blurb, false, WHITE);
@@ -590,7 +695,7 @@ impl<'a, 'b> GameUI<'a, 'b> {
 	// screen without being cut off. For the moment, I just gotta make sure any
 	// lines don't have too many characterse. Something for a post 7DRL world
 	// I guess.
-	pub fn write_long_msg(&mut self, lines: &Vec<&str>, small_text: bool) {
+	pub fn write_long_msg(&mut self, lines: &[&str], small_text: bool) {
 		self.canvas.clear();
 		
 		// lines may contain strings at that are too wide for our screen, so we'll run through and check that 
@@ -682,14 +787,33 @@ impl<'a, 'b> GameUI<'a, 'b> {
 		}
 	}
 
-	// I'll probably need to eventually add pagination but rendering the text into
-	// lines was plenty for my brain for now...
 	pub fn popup_msg(&mut self, title: &str, text: &str, sbi: Option<&SidebarInfo>) -> Option<char> {
+		let line_width = 45; // eventually this probably shouldn't be hardcoded here
+		let pages = paginate_text(text, line_width as usize - 5, POPUP_PAGE_LINES);
+
+		// Long texts (books, etc) are shown a page at a time. Escape bails out early.
+		for (p, page) in pages.iter().enumerate() {
+			let last_page = p == pages.len() - 1;
+			let title = if pages.len() > 1 {
+				format!("{} ({}/{})", title, p + 1, pages.len())
+			} else {
+				title.to_string()
+			};
+			let ch = self.draw_popup_page(&title, page, line_width, last_page, sbi);
+
+			if last_page || ch.is_none() {
+				return ch;
+			}
+		}
+
+		None
+	}
+
+	fn draw_popup_page(&mut self, title: &str, page: &[String], line_width: u16, last_page: bool, sbi: Option<&SidebarInfo>) -> Option<char> {
 		self.canvas.clear();
 		self.draw_frame(&"", sbi, false);
 		self.write_line(0, "", false, WHITE);
 
-		let line_width = 45; // eventually this probably shouldn't be hardcoded here
 		let r_offset = self.font_height as i32 * 3;
 		let c_offset = self.font_width as i32 * 3;
 
@@ -697,34 +821,15 @@ impl<'a, 'b> GameUI<'a, 'b> {
 		lines.push("+-------------------------------------------+".to_string());
 		lines.push(self.center_line_for_popup(title, line_width));
 		lines.push("|                                           |".to_string());
-
-		// Easiest thing to do is to split the text into words and then append them to 
-		// a line so long as there is room left on the current line.
-		let words = util::split_msg(text);
-		let mut wc = 0;
-		let mut line = "".to_string();
-		loop {
-			if &words[wc] == "\n" {
-				lines.push(self.pad_line_for_popup(&line, line_width));
-				line = "".to_string();
-				wc += 1;
-			} else if line.len() + words[wc].len() < line_width as usize - 5 {
-				line.push_str(&words[wc]);
-				line.push(' ');
-				wc += 1;
-			} else {
-				lines.push(self.pad_line_for_popup(&line, line_width));
-				line = "".to_string();
-			}
-
-			if wc == words.len() {
-				lines.push(self.pad_line_for_popup(&line, line_width));
-				break;
-			}
+		for line in page.iter() {
+			lines.push(self.pad_line_for_popup(line, line_width));
 		}
-
-		lines.push("|                                           |".to_string());
 		lines.push("|                                           |".to_string());
+		if last_page {
+			lines.push("|                                           |".to_string());
+		} else {
+			lines.push(self.center_line_for_popup("-- Press space to continue --", line_width));
+		}
 		lines.push("+-------------------------------------------+".to_string());
 
 		for j in 0..lines.len() {
@@ -763,7 +868,12 @@ impl<'a, 'b> GameUI<'a, 'b> {
 
 		let fov_w = (FOV_WIDTH + 1) as i32 * self.font_width as i32; 
 		self.write_sidebar_line(&sbi.name, fov_w, 1, white, 0);
-		let s = format!("AC: {}", sbi.ac);
+		// Only spellcasters have a mana pool worth showing
+		let s = if sbi.max_mana > 0 {
+			format!("AC: {}  MP: {} ({})", sbi.ac, sbi.curr_mana, sbi.max_mana)
+		} else {
+			format!("AC: {}", sbi.ac)
+		};
 		self.write_sidebar_line(&s, fov_w, 2, white, 0);
 		let s = format!("HP: {} ({})", sbi.curr_hp, sbi.max_hp);
 		self.write_sidebar_line(&s, fov_w, 3, white, 0);
@@ -819,6 +929,36 @@ impl<'a, 'b> GameUI<'a, 'b> {
 			self.write_sidebar_line("CONFUSED", fov_w, effects_line, tuple_to_sdl2_color(&PINK), 0);
 			effects_line -= 1;
 		}
+		if sbi.hasted {
+			self.write_sidebar_line("HASTED", fov_w, effects_line, tuple_to_sdl2_color(&YELLOW), 0);
+			effects_line -= 1;
+		}
+		if sbi.slowed {
+			self.write_sidebar_line("SLOWED", fov_w, effects_line, tuple_to_sdl2_color(&GREY), 0);
+			effects_line -= 1;
+		}
+		if sbi.blind {
+			self.write_sidebar_line("BLIND", fov_w, effects_line, tuple_to_sdl2_color(&LIGHT_GREY), 0);
+			effects_line -= 1;
+		}
+		if sbi.bleeding {
+			self.write_sidebar_line("BLEEDING", fov_w, effects_line, tuple_to_sdl2_color(&BRIGHT_RED), 0);
+			effects_line -= 1;
+		}
+		if sbi.weak {
+			self.write_sidebar_line("WEAK", fov_w, effects_line, tuple_to_sdl2_color(&BRIGHT_RED), 0);
+			effects_line -= 1;
+		} else if sbi.hungry {
+			self.write_sidebar_line("HUNGRY", fov_w, effects_line, tuple_to_sdl2_color(&YELLOW_ORANGE), 0);
+			effects_line -= 1;
+		}
+		if sbi.parched {
+			self.write_sidebar_line("PARCHED", fov_w, effects_line, tuple_to_sdl2_color(&BRIGHT_RED), 0);
+			effects_line -= 1;
+		} else if sbi.thirsty {
+			self.write_sidebar_line("THIRSTY", fov_w, effects_line, tuple_to_sdl2_color(&LIGHT_BLUE), 0);
+			effects_line -= 1;
+		}
 		if sbi.paralyzed {
 			self.write_sidebar_line("PARALYZED", fov_w, effects_line, tuple_to_sdl2_color(&BLUE), 0);
 		}
@@ -834,6 +974,25 @@ impl<'a, 'b> GameUI<'a, 'b> {
 		self.write_sidebar_line(&s, fov_w, 21, white, 0);		
 	}
 
+	// Most of the squares in view are the same handful of glyphs so we render each (char, fg, bg)
+	// combination once and reuse the texture across frames. The font can't change out from under
+	// the GameUI, so nothing in the cache ever goes stale. Blended glyphs are drawn without a
+	// background (that's how the separator has always looked).
+	fn cache_glyph(&mut self, ti: (char, Colour, Colour), blended: bool) {
+		if !self.glyph_cache.contains_key(&ti) {
+			let (ch, fg_colour, bg_colour) = ti;
+			let surface = if blended {
+				self.font.render_char(ch).blended(fg_colour)
+			} else {
+				self.font.render_char(ch).shaded(fg_colour, bg_colour)
+			};
+			let surface = surface.expect("Error creating character");
+			let texture = self.texture_creator.create_texture_from_surface(&surface)
+											  .expect("Error creating texture!");
+			self.glyph_cache.insert(ti, texture);
+		}
+	}
+
 	fn draw_frame(&mut self, msg: &str, sbi: Option<&SidebarInfo>, render: bool) {
 		if render {
 			self.canvas.set_draw_color(BLACK);
@@ -841,47 +1000,22 @@ impl<'a, 'b> GameUI<'a, 'b> {
 		}
 		self.write_line(0, msg, false, WHITE);
 
-		// I wonder if, since I've got rid of write_sq() and am generating a bunch of textures here,
-		// if I can keep a texture_creator instance in the GUI struct and thereby placate Rust and 
-		// keep a hashmap of textures. I should only have to generate a few since most times in view will
-		// be repeated but still...
-		let texture_creator = self.canvas.texture_creator();
-		let mut textures = HashMap::new();
 		let separator = sq_info_for_tile(&Tile::Separator, true);
-		let separator_surface = self.font.render_char(separator.0)
-										 .blended(separator.1)
-										 .expect("Error creating character!");  
-		let separator_texture = texture_creator.create_texture_from_surface(&separator_surface)
-											   .expect("Error creating texture!");
+		self.cache_glyph(separator, true);
 
 		for row in 0..FOV_HEIGHT {
 			for col in 0..FOV_WIDTH {
 				let ti = sq_info_for_tile(&self.v_matrix[row * FOV_WIDTH + col].0, self.v_matrix[row * FOV_WIDTH + col].1);
-				let (ch, fg_colour, bg_colour) = ti;
-
-				if !self.surface_cache.contains_key(&ti) {					
-					let s = self.font.render_char(ch)
-										.shaded(fg_colour, bg_colour)
-										.expect("Error creating character");
-					self.surface_cache.insert(ti, s);
-				}
-				
-				let surface = self.surface_cache.get(&ti).unwrap();
-				
-				if !textures.contains_key(&ti) {
-					let texture = texture_creator.create_texture_from_surface(&surface)
-												 .expect("Error creating texture!");
-					textures.insert(ti, texture);
-				}
+				self.cache_glyph(ti, false);
 
 				let rect = Rect::new(col as i32 * self.font_width as i32, 
 					(row as i32 + 1) * self.font_height as i32, self.font_width, self.font_height);
-				self.canvas.copy(&textures[&ti], None, Some(rect))
+				self.canvas.copy(&self.glyph_cache[&ti], None, Some(rect))
 					.expect("Error copying to canvas!");
 			}
 			let rect = Rect::new(FOV_WIDTH as i32 * self.font_width as i32, 
 				(row as i32 + 1) * self.font_height as i32, self.font_width, self.font_height);
-			self.canvas.copy(&separator_texture, None, Some(rect))
+			self.canvas.copy(&self.glyph_cache[&separator], None, Some(rect))
 					.expect("Error copying to canvas!");			
 		}
 
@@ -924,7 +1058,8 @@ impl<'a, 'b> GameUI<'a, 'b> {
 		}
 	}
 
-	pub fn show_message_history(&mut self) {
+	// Newest messages first
+	fn history_lines(&self) -> Vec<String> {
 		let mut history = Vec::new();
 		for j in 0..self.message_history.len() {
 			if self.message_history[j].1 == 1 {
@@ -936,7 +1071,12 @@ impl<'a, 'b> GameUI<'a, 'b> {
 			}
 			
 		}
-		
+
+		history
+	}
+
+	pub fn show_message_history(&mut self) {
+		let history = self.history_lines();
 		let lines: Vec<&str> = history.iter().map(AsRef::as_ref).collect();
 		self.write_long_msg(&lines, true);
 	}
@@ -979,12 +1119,8 @@ impl<'a, 'b> GameUI<'a, 'b> {
 		self.draw_frame("", sbi, true);
 	}
 
-	pub fn clear_msg_buff(&mut self) {
-		self.msg_line = "".to_string();
-	}
-
 	// Currently not handling a menu with more options than there are are lines on the screen...
-	pub fn side_pane_menu(&mut self, preamble: String, menu: &Vec<(String, char)>, single_choice: bool) -> Option<HashSet<char>> {
+	pub fn side_pane_menu(&mut self, preamble: String, menu: &[(String, char)], single_choice: bool) -> Option<HashSet<char>> {
 		let mut answers: HashSet<char> = HashSet::new();
 		let possible_answers: HashSet<char> = menu.iter().map(|m| m.1).collect();
 
@@ -1050,7 +1186,7 @@ impl<'a, 'b> GameUI<'a, 'b> {
 	// the player to select options, but more free form (as opposed to just presenting a list of options)
 	// This isn't yet handling someone hitting Esc, quitting the program, or just otherwise wanting to
 	// bail out of the menu
-	pub fn menu_wordy_picker(&mut self, menu: &Vec<&str>, answers: &HashSet<&char>) -> Option<char> {
+	pub fn menu_wordy_picker(&mut self, menu: &[&str], answers: &HashSet<&char>) -> Option<char> {
 		loop {
 			self.canvas.clear();
 			for line in 0..menu.len() {
@@ -1079,6 +1215,85 @@ fn fov_coord_to_map_loc(i: i32, player_loc: (i32, i32, i8)) -> (i32, i32, i8) {
 	(player_loc.0 + fov_loc.0, player_loc.1 + fov_loc.1 , player_loc.2)
 }
 
+impl<'a, 'b, 'c> UserInterface for GameUI<'a, 'b, 'c> {
+	fn set_v_matrix(&mut self, v_matrix: [(Tile, bool); FOV_HEIGHT * FOV_WIDTH]) {
+		self.v_matrix = v_matrix;
+	}
+
+	fn query_yes_no(&mut self, question: &str, sbi: Option<&SidebarInfo>) -> char {
+		GameUI::query_yes_no(self, question, sbi)
+	}
+
+	fn query_natural_num(&mut self, query: &str, sbi: Option<&SidebarInfo>) -> Option<u32> {
+		GameUI::query_natural_num(self, query, sbi)
+	}
+
+	fn query_user(&mut self, question: &str, max: u8, sbi: Option<&SidebarInfo>) -> Option<String> {
+		GameUI::query_user(self, question, max, sbi)
+	}
+
+	fn select_dir(&mut self, prompt: &str, state: &GameState, game_obj_db: &mut GameObjectDB) -> Option<(i32, i32, i8)> {
+		GameUI::select_dir(self, prompt, state, game_obj_db)
+	}
+
+	fn draw_effects(&mut self, state: &GameState, game_obj_db: &mut GameObjectDB, sqs_affected: &[(i32, i32, i8)], effect: u128) {
+		GameUI::draw_effects(self, state, game_obj_db, sqs_affected, effect)
+	}
+
+	fn select_target(&mut self, state: &GameState, game_obj_db: &mut GameObjectDB, prompt: &str) -> Option<(i32, i32, i8)> {
+		GameUI::select_target(self, state, game_obj_db, prompt)
+	}
+
+	fn get_command(&mut self, state: &GameState, game_obj_db: &mut GameObjectDB) -> Cmd {
+		GameUI::get_command(self, state, game_obj_db)
+	}
+
+	fn pause_for_more(&mut self) {
+		GameUI::pause_for_more(self)
+	}
+
+	fn show_in_side_pane(&mut self, blurb: &str, lines: &[(String, bool)]) -> Option<char> {
+		GameUI::show_in_side_pane(self, blurb, lines)
+	}
+
+	fn write_long_msg(&mut self, lines: &[&str], small_text: bool) {
+		GameUI::write_long_msg(self, lines, small_text)
+	}
+
+	fn popup_menu(&mut self, title: &str, text: &str, options: &HashSet<char>, sbi: Option<&SidebarInfo>) -> Option<char> {
+		GameUI::popup_menu(self, title, text, options, sbi)
+	}
+
+	fn popup_msg(&mut self, title: &str, text: &str, sbi: Option<&SidebarInfo>) -> Option<char> {
+		GameUI::popup_msg(self, title, text, sbi)
+	}
+
+	fn show_message_history(&mut self) {
+		GameUI::show_message_history(self)
+	}
+
+	// The last few messages, oldest first
+	fn recent_messages(&self, count: usize) -> Vec<String> {
+		self.history_lines().into_iter().take(count).rev().collect()
+	}
+
+	fn clear_message_history(&mut self) {
+		self.message_history.clear();
+	}
+
+	fn update(&mut self, msg_queue: &mut VecDeque<String>, sbi: Option<&SidebarInfo>) {
+		GameUI::update(self, msg_queue, sbi)
+	}
+
+	fn side_pane_menu(&mut self, preamble: String, menu: &[(String, char)], single_choice: bool) -> Option<HashSet<char>> {
+		GameUI::side_pane_menu(self, preamble, menu, single_choice)
+	}
+
+	fn menu_wordy_picker(&mut self, menu: &[&str], answers: &HashSet<&char>) -> Option<char> {
+		GameUI::menu_wordy_picker(self, menu, answers)
+	}
+}
+
 fn sq_info_for_tile(tile: &map::Tile, lit: bool) -> (char, Colour, Colour) {
 	match tile {
 		map::Tile::Blank => (' ', BLACK, BLACK),
@@ -1093,7 +1308,7 @@ fn sq_info_for_tile(tile: &map::Tile, lit: bool) -> (char, Colour, Colour) {
 			if lit {
 				('#', BLACK, GRANITE)
 			} else {
-				('#', BLACK, DARK_GREY)
+				('#', BLACK, DARK_GRANITE)
 			}
 		},
 		map::Tile::LitWall(colour) => {
@@ -1265,6 +1480,13 @@ fn sq_info_for_tile(tile: &map::Tile, lit: bool) -> (char, Colour, Colour) {
 				(*ch, BROWN, BLACK)
 			}
 		},
+		map::Tile::BrokenWindow(ch) => {
+			if lit {
+				(*ch, GREY, BLACK)
+			} else {
+				(*ch, DARK_GREY, BLACK)
+			}
+		},
 		map::Tile::Spring => {
 			if lit {
 				('~', LIGHT_BLUE, BLACK)
@@ -1325,6 +1547,51 @@ fn sq_info_for_tile(tile: &map::Tile, lit: bool) -> (char, Colour, Colour) {
 			} else {
 				('.', LIGHT_GREY, BLACK)
 			}
+		},
+		map::Tile::Bed => {
+			if lit {
+				('=', BEIGE, BLACK)
+			} else {
+				('=', BROWN, BLACK)
+			}
+		},
+		map::Tile::Table => {
+			if lit {
+				('π', LIGHT_BROWN, BLACK)
+			} else {
+				('π', BROWN, BLACK)
+			}
+		},
+		map::Tile::Counter => {
+			if lit {
+				('≡', LIGHT_BROWN, BLACK)
+			} else {
+				('≡', BROWN, BLACK)
+			}
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn paginate_text_wraps_at_width() {
+		let pages = paginate_text("the quick brown fox jumps over the lazy dog", 12, 10);
+		assert_eq!(pages.len(), 1);
+		for line in pages[0].iter() {
+			assert!(line.trim_end().len() < 12, "line too long: '{}'", line);
 		}
+		let rejoined: Vec<&str> = pages[0].iter().flat_map(|l| l.split_whitespace()).collect();
+		assert_eq!(rejoined.join(" "), "the quick brown fox jumps over the lazy dog");
+	}
+
+	#[test]
+	fn paginate_text_breaks_pages() {
+		let text = (0..25).map(|j| format!("line{}", j)).collect::<Vec<String>>().join("\n");
+		let pages = paginate_text(&text, 40, 10);
+		assert_eq!(pages.iter().map(|p| p.len()).collect::<Vec<usize>>(), vec![10, 10, 5]);
+		assert_eq!(pages[1][0].trim(), "line10");
 	}
 }
@@ -13,7 +13,7 @@
 // You should have received a copy of the GNU General Public License
 // along with RogueVillage.  If not, see <https://www.gnu.org/licenses/>.
 
-use rand::thread_rng;
+use crate::util::GameRng;
 use rand::Rng;
 use rand::seq::SliceRandom;
 
@@ -34,8 +34,7 @@ impl Vault {
     }
 }
 
-fn pick_room() -> (Vec<Vec<Tile>>, usize, usize) {
-    let mut rng = rand::thread_rng();
+fn pick_room(rng: &mut GameRng) -> (Vec<Vec<Tile>>, usize, usize) {
     let rn = rng.gen_range(0.0, 1.0);
     let mut height;
     let mut width;
@@ -155,8 +154,7 @@ fn room_fits(level: &mut Vec<Tile>, bounds: (i32, i32, i32, i32), width: usize)
     true
 }
 
-fn add_doorway_horizonal(level: &mut Vec<Tile>, row: usize, lo: usize, hi: usize, width: usize) {
-    let mut rng = thread_rng();
+fn add_doorway_horizonal(level: &mut Vec<Tile>, row: usize, lo: usize, hi: usize, width: usize, rng: &mut GameRng) {
     let mut options = Vec::new();
     for col in lo..hi {
         if level[(row - 1) * width + col] == Tile::StoneFloor && level[(row + 1) * width + col] == Tile::StoneFloor {
@@ -189,8 +187,7 @@ fn add_doorway_horizonal(level: &mut Vec<Tile>, row: usize, lo: usize, hi: usize
     }
 }
 
-fn add_doorway_vertical(level: &mut Vec<Tile>, col: usize, lo: usize, hi: usize, width: usize) {    
-    let mut rng = thread_rng();
+fn add_doorway_vertical(level: &mut Vec<Tile>, col: usize, lo: usize, hi: usize, width: usize, rng: &mut GameRng) {    
     let mut options = Vec::new();
     for row in lo..hi {
         if level[row * width + col - 1] == Tile::StoneFloor && level[row * width + col + 1] == Tile::StoneFloor {
@@ -225,11 +222,10 @@ fn add_doorway_vertical(level: &mut Vec<Tile>, col: usize, lo: usize, hi: usize,
 }
 
 fn place_room(level: &mut Vec<Tile>, rooms: &mut Vec<(Vec<Vec<Tile>>, usize, usize, usize, usize, &str)>,
-    parent_index: usize, room: &(Vec<Vec<Tile>>, usize, usize), width: usize) -> bool {
+    parent_index: usize, room: &(Vec<Vec<Tile>>, usize, usize), width: usize, rng: &mut GameRng) -> bool {
 
-    let mut rng = thread_rng();
     let mut sides = vec!['n', 's', 'e', 'w'];
-    sides.shuffle(&mut rng);
+    sides.shuffle(rng);
 
     // We'll try a few times per side to place the new room
     let num_of_tries = 5;
@@ -257,7 +253,7 @@ fn place_room(level: &mut Vec<Tile>, rooms: &mut Vec<(Vec<Vec<Tile>>, usize, usi
                     } else {
                         end_col as usize
                     };
-                    add_doorway_horizonal(level, end_row as usize - 1, lo as usize, hi as usize, width);
+                    add_doorway_horizonal(level, end_row as usize - 1, lo, hi, width, rng);
                     return true;
                 }
             }
@@ -283,7 +279,7 @@ fn place_room(level: &mut Vec<Tile>, rooms: &mut Vec<(Vec<Vec<Tile>>, usize, usi
                     } else {
                         end_col as usize
                     };
-                    add_doorway_horizonal(level, start_row as usize, lo as usize, hi as usize, width);
+                    add_doorway_horizonal(level, start_row as usize, lo, hi, width, rng);
                     return true;
                 }
             }
@@ -308,7 +304,7 @@ fn place_room(level: &mut Vec<Tile>, rooms: &mut Vec<(Vec<Vec<Tile>>, usize, usi
                     } else {
                         end_row as usize
                     };
-                    add_doorway_vertical(level, end_col as usize - 1, lo as usize, hi as usize, width);
+                    add_doorway_vertical(level, end_col as usize - 1, lo, hi, width, rng);
                     return true;
                 }
             }
@@ -333,7 +329,7 @@ fn place_room(level: &mut Vec<Tile>, rooms: &mut Vec<(Vec<Vec<Tile>>, usize, usi
                     } else {
                         end_row as usize
                     };
-                    add_doorway_vertical(level, start_col as usize, lo as usize, hi as usize, width);
+                    add_doorway_vertical(level, start_col as usize, lo, hi, width, rng);
                     return true;
                 }
             }
@@ -344,15 +340,14 @@ fn place_room(level: &mut Vec<Tile>, rooms: &mut Vec<(Vec<Vec<Tile>>, usize, usi
 }
 
 fn find_spot_for_room(level: &mut Vec<Tile>, rooms: &mut Vec<(Vec<Vec<Tile>>, usize, usize, usize, usize, &str)>,
-                            room: &(Vec<Vec<Tile>>, usize, usize), width: usize) -> bool {
+                            room: &(Vec<Vec<Tile>>, usize, usize), width: usize, rng: &mut GameRng) -> bool {
     // We want to try every room in the dungeon so far to see if we can attach the new room to it
-    let mut rng = thread_rng();
     let mut tries: Vec<usize> = (0..rooms.len()).collect();
-    tries.shuffle(&mut rng);
+    tries.shuffle(rng);
     
     while !tries.is_empty() {
         let i = tries.pop().unwrap();
-        if place_room(level, rooms, i, room, width) {
+        if place_room(level, rooms, i, room, width, rng) {
             return true;
         }
     }
@@ -360,8 +355,7 @@ fn find_spot_for_room(level: &mut Vec<Tile>, rooms: &mut Vec<(Vec<Vec<Tile>>, us
     false
 }
 
-fn add_extra_door_to_horizontal_wall(level: &mut Vec<Tile>, width: usize, row: usize, col_lo: usize, col_hi: usize) -> bool {
-    let mut rng = rand::thread_rng();
+fn add_extra_door_to_horizontal_wall(level: &mut Vec<Tile>, width: usize, row: usize, col_lo: usize, col_hi: usize, rng: &mut GameRng) -> bool {
     let mut already_connected = false;
     let mut options = Vec::new();
     for col in col_lo..col_hi {
@@ -387,8 +381,7 @@ fn add_extra_door_to_horizontal_wall(level: &mut Vec<Tile>, width: usize, row: u
     false
 }
 
-fn add_extra_door_to_vertical_wall(level: &mut Vec<Tile>, width: usize, col: usize, row_lo: usize, row_hi: usize) -> bool {
-    let mut rng = rand::thread_rng();
+fn add_extra_door_to_vertical_wall(level: &mut Vec<Tile>, width: usize, col: usize, row_lo: usize, row_hi: usize, rng: &mut GameRng) -> bool {
     let mut already_connected = false;
     let mut options = Vec::new();
     for row in row_lo..row_hi {
@@ -415,23 +408,23 @@ fn add_extra_door_to_vertical_wall(level: &mut Vec<Tile>, width: usize, col: usi
 // interesting to explore a dungeon with some loops. So this function finds places
 // we can add doors between rooms that aren't currently connected.
 // (These are probably also good candidates for secret doors once I implement those!)
-fn add_extra_doors(level: &mut Vec<Tile>, rooms: &Vec<(Vec<Vec<Tile>>, usize, usize, usize, usize, &str)>, width: usize) {    
+fn add_extra_doors(level: &mut Vec<Tile>, rooms: &Vec<(Vec<Vec<Tile>>, usize, usize, usize, usize, &str)>, width: usize, rng: &mut GameRng) {    
     let height = level.len() / width;
 
     for room in rooms {
         // check north wall
-        if add_extra_door_to_horizontal_wall(level, width, room.1, room.2 + 1,room.4 - 1)  {
+        if add_extra_door_to_horizontal_wall(level, width, room.1, room.2 + 1,room.4 - 1, rng)  {
             continue;
         }        
-        if (room.3 as usize) < height - 2 && add_extra_door_to_horizontal_wall(level, width, room.3 - 1, room.2 + 1,room.4 - 1)  {
+        if (room.3 as usize) < height - 2 && add_extra_door_to_horizontal_wall(level, width, room.3 - 1, room.2 + 1,room.4 - 1, rng)  {
             continue;
         }
         // check west wall
-        if add_extra_door_to_vertical_wall(level, width, room.2, room.1 + 1, room.3 - 1) {
+        if add_extra_door_to_vertical_wall(level, width, room.2, room.1 + 1, room.3 - 1, rng) {
             continue;
         }
         // check east wall
-        if add_extra_door_to_vertical_wall(level, width, room.4 - 1, room.1 + 1, room.3 - 1) {
+        if add_extra_door_to_vertical_wall(level, width, room.4 - 1, room.1 + 1, room.3 - 1, rng) {
             continue;
         }
     }
@@ -539,8 +532,7 @@ fn draw_corridor_east(level: &mut Vec<Tile>, row: usize, col: usize, width: usiz
 }
 
 // Once again, we'll look for walls that don't already have an egress
-fn try_to_add_corridor(level: &mut Vec<Tile>, rooms: &Vec<(Vec<Vec<Tile>>, usize, usize, usize, usize, &str)>, width: usize) {
-    let mut rng = rand::thread_rng();
+fn try_to_add_corridor(level: &mut Vec<Tile>, rooms: &Vec<(Vec<Vec<Tile>>, usize, usize, usize, usize, &str)>, width: usize, rng: &mut GameRng) {
     for room in rooms {
         // check east wall
         let col = room.4 - 1;
@@ -663,9 +655,8 @@ fn find_vaults(level: &[Tile],  width: usize, rooms: Vec<(usize, usize, usize, u
     vaults
 }
 
-fn carve(level: &mut Vec<Tile>, width: usize, height: usize) -> Vec<Vault> {
+fn carve(level: &mut Vec<Tile>, width: usize, height: usize, rng: &mut GameRng) -> Vec<Vault> {
     let mut rooms = Vec::new();
-    let mut rng = rand::thread_rng();
     let center_row = (height / 2) as i16;
     let center_col = (width / 2) as i16;
     let row = (center_row + rng.gen_range(-6, 6)) as usize;
@@ -673,24 +664,24 @@ fn carve(level: &mut Vec<Tile>, width: usize, height: usize) -> Vec<Vault> {
 
     // Draw the starting room to the dungeon map. (This is just the first room we make on the
     // level, not necessaily the entrance room)
-    let room = pick_room();
-    draw_room(level, row as usize, col as usize, &room.0, width as usize);
+    let room = pick_room(rng);
+    draw_room(level, row, col, &room.0, width as usize);
     rooms.push((room.0, row, col, row + room.1, col + room.2, "Start"));
 
     loop {
-        let room = pick_room();
+        let room = pick_room(rng);
         // keep trying to add new rooms until we fail to place one and that's probably
         // enough rooms for a decent dungeon level
-        if !find_spot_for_room(level, &mut rooms, &room, width as usize) {
+        if !find_spot_for_room(level, &mut rooms, &room, width as usize, rng) {
             break;
         }
     }
 
-    add_extra_doors(level, &rooms, width as usize);
+    add_extra_doors(level, &rooms, width as usize, rng);
 
     // try to add up to three extra corridors between rooms
     for _ in 0..3 {
-        try_to_add_corridor(level, &rooms, width as usize);
+        try_to_add_corridor(level, &rooms, width as usize, rng);
     }
 
     let room_borders: Vec<(usize, usize, usize, usize)> = rooms.iter().map(|r| (r.1, r.2, r.3, r.4)).collect();
@@ -700,7 +691,7 @@ fn carve(level: &mut Vec<Tile>, width: usize, height: usize) -> Vec<Vault> {
 // I originally had a floodfill check to make sure the level was fully connected 
 // but after generating 100,000 levels and not hitting a single disjoint map, I 
 //dropped the check.
-pub fn draw_level(width: usize, height: usize) -> (Vec<Tile>, Vec<Vault>) {
+pub fn draw_level(width: usize, height: usize, rng: &mut GameRng) -> (Vec<Tile>, Vec<Vault>) {
     let mut level;
     
     // Loop unitl we generate a level with sufficient open space. 35% seems
@@ -714,7 +705,7 @@ pub fn draw_level(width: usize, height: usize) -> (Vec<Tile>, Vec<Vault>) {
             level.push(Tile::Wall);
         }
 
-        vaults = carve(&mut level, width, height);
+        vaults = carve(&mut level, width, height, rng);
 
         let mut non_walls = 0;
         for &sq in &level {
@@ -728,10 +719,12 @@ pub fn draw_level(width: usize, height: usize) -> (Vec<Tile>, Vec<Vault>) {
         }
     }
 
-    // For a bit of variety, switch up roughly 1/3 of wall tiles to granite walls
-    for j in 0..level.len() {
-        if level[j] == Tile::Wall && rand::thread_rng().gen_range(0.0, 1.0) < 0.33 {
-            level[j] = Tile::GraniteWall;
+    // The outer edge of each level is a shell of granite that can't be broken through
+    for r in 0..height {
+        for c in 0..width {
+            if r == 0 || c == 0 || r == height - 1 || c == width - 1 {
+                level[r * width + c] = Tile::GraniteWall;
+            }
         }
     }
     
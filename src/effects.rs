@@ -23,9 +23,15 @@ use serde::{Serialize, Deserialize};
 
 use super::{GameState, Message};
 use crate::battle::DamageType;
-use crate::game_obj::{Ability, GameObject, GameObjectDB, Person};
-use crate::map::Tile;
+use crate::fov;
+use crate::game_obj::{Ability, GameObject, GameObjectDB, GameObjects, Person};
+use crate::items;
+use crate::items::ItemType;
+use crate::map::{tile_at, Tile};
+use crate::npc;
+use crate::npc::NPCPersonality;
 use crate::util;
+use crate::util::StringUtils;
 
 pub const EF_MINOR_HEAL: u128     = 0x00000001;
 pub const EF_BLINK: u128          = 0x00000002;
@@ -34,6 +40,18 @@ pub const EF_WEAK_BLINDNESS: u128 = 0x00000008;
 pub const EF_FROST: u128          = 0x00000010;
 pub const EF_LEVITATION: u128     = 0x00000020;
 pub const EF_PROTECTION: u128     = 0x00000040;
+pub const EF_HASTE: u128          = 0x00000080;
+pub const EF_SLOW: u128           = 0x00000100;
+pub const EF_INVISIBILITY: u128   = 0x00000200;
+pub const EF_DETECT_MONSTERS: u128 = 0x00000400;
+pub const EF_DETECT_TREASURE: u128 = 0x00000800;
+pub const EF_MAGIC_MAPPING: u128  = 0x00001000;
+pub const EF_RECALL: u128         = 0x00002000;
+pub const EF_DESCENT: u128        = 0x00004000;
+pub const EF_GUST_OF_WIND: u128   = 0x00008000;
+pub const EF_DARKNESS: u128       = 0x00010000;
+pub const EF_TELEPATHY: u128      = 0x00020000;
+pub const EF_STANCH: u128         = 0x00040000;
 
 fn apply_xp(state: &mut GameState, game_obj_db: &mut GameObjectDB, xp: u32) {
     let player = game_obj_db.player().unwrap();
@@ -41,7 +59,7 @@ fn apply_xp(state: &mut GameState, game_obj_db: &mut GameObjectDB, xp: u32) {
 }
 
 pub fn frost(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8), _src_obj_id: usize) {
-    if state.map[&loc] == Tile::Water || state.map[&loc] == Tile::DeepWater || state.map[&loc] == Tile::UndergroundRiver {
+    if matches!(tile_at(&state.map, &loc), Tile::Water | Tile::DeepWater | Tile::UndergroundRiver) {
         state.map.insert(loc, Tile::Ice);
         state.msg_queue.push_back(Message::new(0, loc, "The water freezes over!", "You hear a cracking sound."));
         // need to add in an event for the ice to later melt
@@ -49,7 +67,7 @@ pub fn frost(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc: (i32, i
 
     let mut killed_by_effect = None; 
     if let Some(victim_id) = game_obj_db.person_at(loc) {
-        let dmg = rand::thread_rng().gen_range(1, 9) + rand::thread_rng().gen_range(1, 9) + rand::thread_rng().gen_range(1, 9);
+        let dmg = state.rng.gen_range(1, 9) + state.rng.gen_range(1, 9) + state.rng.gen_range(1, 9);
         let victim = game_obj_db.as_person(victim_id).unwrap();
         victim.damaged(state, dmg, DamageType::Cold, 0, "frost");
 
@@ -59,16 +77,126 @@ pub fn frost(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc: (i32, i
     }
 
     if let Some(id) = killed_by_effect {
-        let xp = if let Some(npc) = game_obj_db.npc(id) {
-            npc.xp_value
+        credit_kill(state, game_obj_db, id);
+    }
+}
+
+pub fn magic_missile(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8)) {
+    if let Some(victim_id) = game_obj_db.person_at(loc) {
+        let dmg = state.rng.gen_range(1, 5) + state.rng.gen_range(1, 5) + 1;
+        let victim = game_obj_db.as_person(victim_id).unwrap();
+        victim.damaged(state, dmg, DamageType::Force, 0, "a magic missile");
+
+        if !victim.alive() {
+            credit_kill(state, game_obj_db, victim_id);
+        }
+    }
+}
+
+pub fn smite(state: &mut GameState, game_obj_db: &mut GameObjectDB, victim_id: usize) {
+    let dmg = state.rng.gen_range(1, 7) + state.rng.gen_range(1, 7) + state.rng.gen_range(1, 7) + state.rng.gen_range(1, 7);
+    let victim = game_obj_db.as_person(victim_id).unwrap();
+    victim.damaged(state, dmg, DamageType::Electricity, 0, "divine wrath");
+
+    if !victim.alive() {
+        credit_kill(state, game_obj_db, victim_id);
+    }
+}
+
+// The player gets the xp for monsters killed by their spells and wands
+fn credit_kill(state: &mut GameState, game_obj_db: &mut GameObjectDB, id: usize) {
+    let xp = if let Some(npc) = game_obj_db.npc(id) {
+        npc.xp_value
+    } else {
+        0
+    };
+
+    if xp > 0 {
+        let name = game_obj_db.get(id).unwrap().get_fullname();
+        state.stats.record_kill(&name);
+        apply_xp(state, game_obj_db, xp);
+    }
+}
+
+pub fn slow(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8)) {
+    if let Some(victim_id) = game_obj_db.person_at(loc) {
+        let until = state.turn + state.rng.gen_range(10, 21);
+        if victim_id == 0 {
+            let player = game_obj_db.player().unwrap();
+            add_status(player, Status::Slowed, until);
+            state.msg_queue.push_back(Message::info("You feel sluggish."));
         } else {
-            0
+            let npc = game_obj_db.npc(victim_id).unwrap();
+            let s = format!("{} slows down.", npc.npc_name(false).capitalize());
+            add_status(npc, Status::Slowed, until);
+            state.msg_queue.push_back(Message::new(victim_id, loc, &s, ""));
+        }
+    }
+}
+
+// Magical darkness snuffs out any light falling in the area (torches included) until it fades.
+// The squares are stripped from lit_sqs each turn when the lights are recalculated.
+pub fn cast_darkness(state: &mut GameState, loc: (i32, i32, i8), radius: u8, until: u32) {
+    let area = fov::calc_fov(state, loc, radius, true);
+    for sq in area {
+        let prev = state.darkness.entry(sq).or_insert(until);
+        if *prev < until {
+            *prev = until;
+        }
+    }
+}
+
+// A monster has blundered into one of the player's traps. The player knows where their own traps
+// are so they never set them off, and they're laid so that villagers aren't caught either. A bear 
+// trap holds its victim in place for a while and can be picked back up; caltrops slow the victim 
+// down and are lost.
+pub fn spring_player_trap(state: &mut GameState, game_obj_db: &mut GameObjectDB, trap_id: usize, victim_id: usize) {
+    match game_obj_db.npc(victim_id) {
+        Some(npc) if npc.is_monster => { },
+        _ => { return; },
+    }
+
+    let trap = game_obj_db.remove(trap_id);
+    let trap_name = trap.get_fullname();
+    let loc = trap.get_loc();
+
+    let bear_trap = trap_name == "bear trap";
+    let (dmg, status, until) = if bear_trap {
+        (state.rng.gen_range(1, 5) + state.rng.gen_range(1, 5), Status::Held, state.turn + state.rng.gen_range(3, 7))
+    } else {
+        (state.rng.gen_range(1, 5), Status::Slowed, state.turn + state.rng.gen_range(10, 21))
+    };
+
+    if let Some(npc) = game_obj_db.npc(victim_id) {
+        let s = if bear_trap {
+            format!("{} is caught in a bear trap!", npc.npc_name(false).capitalize())
+        } else {
+            format!("{} steps on caltrops!", npc.npc_name(false).capitalize())
         };
+        state.msg_queue.push_back(Message::new(victim_id, loc, &s, "You hear a snap!"));
+        add_status(npc, status, until);
+        npc.damaged(state, dmg, DamageType::Piercing, 0, "trap");
+    }
+
+    if bear_trap {
+        let mut t = items::Item::get_item(game_obj_db, "bear trap", &mut state.rng).unwrap();
+        t.set_loc(loc);
+        game_obj_db.add(t);
+    }
+}
 
-        if xp > 0 {
-            apply_xp(state, game_obj_db, xp);
+// How much energy someone regains each turn is scaled by haste/slow
+pub fn speed_factor(statuses: &[(Status, u32)]) -> f32 {
+    let mut factor = 1.0;
+    for s in statuses.iter() {
+        if s.0 == Status::Hasted {
+            factor *= 2.0;
+        } else if s.0 == Status::Slowed {
+            factor *= 0.5;
         }
     }
+
+    factor
 }
 
 // Short range, untargeted teleport
@@ -87,13 +215,13 @@ fn blink(state: &mut GameState, obj_id: usize, game_obj_db: &mut GameObjectDB) {
         }
     }
 
-    let mut rng = rand::thread_rng();
-    if sqs.is_empty() {
+    // I should probably call lands_on_sq() too?
+    let blinked = match sqs.choose(&mut state.rng) {
+        Some(landing_spot) => game_obj_db.set_to_loc(obj_id, *landing_spot),
+        None => false,
+    };
+    if !blinked {
         state.msg_queue.push_back(Message::new(obj_id, loc, "The magic fizzles", ""));
-    } else {
-        let landing_spot = sqs.choose(&mut rng).unwrap();
-        // I should probably call lands_on_sq() too?
-        game_obj_db.set_to_loc(obj_id, *landing_spot);        
     }
 }
 
@@ -102,14 +230,28 @@ fn blink(state: &mut GameState, obj_id: usize, game_obj_db: &mut GameObjectDB) {
 fn minor_healing(state: &mut GameState, user: &mut dyn Person) {
     let (curr_hp, max_hp) = user.get_hp();
 
-    let amt = rand::thread_rng().gen_range(5, 11);
+    let amt = state.rng.gen_range(5, 11);
     if curr_hp < max_hp {
         user.add_hp(state, amt);
     } 
 }
 
+fn stanch(state: &mut GameState, obj_id: usize, game_obj_db: &mut GameObjectDB, bandaged: bool) {
+    if obj_id == 0 {
+        let player = game_obj_db.player().unwrap();
+        if player.has_status(Status::Bleeding) {
+            remove_status(player, Status::Bleeding);
+            state.msg_queue.push_back(Message::info("Your bleeding stops."));
+        } else if bandaged {
+            state.msg_queue.push_back(Message::info("You aren't bleeding."));
+        }
+    } else if let Some(npc) = game_obj_db.npc(obj_id) {
+        remove_status(npc, Status::Bleeding);
+    }
+}
+
 fn weak_venom(state: &mut GameState, victim: &mut dyn Person) {
-    let dmg = rand::thread_rng().gen_range(1, 5);
+    let dmg = state.rng.gen_range(1, 5);
     victim.damaged(state, dmg, DamageType::Poison, 0, "poison");
 }
 
@@ -120,6 +262,11 @@ pub fn apply_effects(state: &mut GameState, obj_id: usize, game_obj_db: &mut Gam
         }        
     }
 
+    // Healing magic closes up open wounds too
+    if effects & (EF_MINOR_HEAL | EF_STANCH) > 0 {
+        stanch(state, obj_id, game_obj_db, effects & EF_STANCH > 0);
+    }
+
     if effects & EF_BLINK > 0 {
         blink(state, obj_id, game_obj_db);
     }
@@ -141,6 +288,86 @@ pub fn apply_effects(state: &mut GameState, obj_id: usize, game_obj_db: &mut Gam
         }
     }
 
+    if effects & EF_HASTE > 0 {
+        let until = state.turn + state.rng.gen_range(20, 31);
+        if obj_id == 0 {
+            let player = game_obj_db.player().unwrap();
+            add_status(player, Status::Hasted, until);
+            state.msg_queue.push_back(Message::info("You feel yourself speed up!"));
+        } else {
+            let npc = game_obj_db.npc(obj_id).unwrap();
+            add_status(npc, Status::Hasted, until);
+        }
+    }
+
+    if effects & EF_INVISIBILITY > 0 {
+        let until = state.turn + state.rng.gen_range(25, 41);
+        if obj_id == 0 {
+            let player = game_obj_db.player().unwrap();
+            add_status(player, Status::Invisible, until);
+            state.msg_queue.push_back(Message::info("You fade from view!"));
+        } else {
+            let npc = game_obj_db.npc(obj_id).unwrap();
+            add_status(npc, Status::Invisible, until);
+        }
+    }
+
+    if obj_id == 0 && effects & (EF_DETECT_MONSTERS | EF_DETECT_TREASURE) > 0 {
+        state.detecting |= effects & (EF_DETECT_MONSTERS | EF_DETECT_TREASURE);
+        state.detected_until = state.turn + 20;
+        let player = game_obj_db.player().unwrap();
+        let level = player.get_loc().2;
+        let telepathic = player.has_status(Status::Telepathic);
+        refresh_detected(state, game_obj_db, level, telepathic);
+
+        if state.detected.is_empty() {
+            state.msg_queue.push_back(Message::info("You sense nothing unusual."));
+        } else if effects & EF_DETECT_MONSTERS > 0 {
+            state.msg_queue.push_back(Message::info("You sense the presence of creatures!"));
+        } else {
+            state.msg_queue.push_back(Message::info("You sense the presence of treasure!"));
+        }
+    }
+
+    if obj_id == 0 && effects & EF_TELEPATHY > 0 {
+        let player = game_obj_db.player().unwrap();
+        add_status(player, Status::Telepathic, state.turn + state.rng.gen_range(30, 51));
+        let level = player.get_loc().2;
+        state.msg_queue.push_back(Message::info("Your mind expands!"));
+        refresh_detected(state, game_obj_db, level, true);
+    }
+
+    if obj_id == 0 && effects & EF_MAGIC_MAPPING > 0 {
+        let player_loc = game_obj_db.player().unwrap().get_loc();
+        magic_mapping(state, player_loc);
+    }
+
+    if obj_id == 0 && effects & EF_RECALL > 0 {
+        let player = game_obj_db.player().unwrap();
+        add_status(player, Status::Recalling, state.turn + state.rng.gen_range(15, 36));
+        state.msg_queue.push_back(Message::info("The air about you becomes charged..."));
+    }
+
+    if obj_id == 0 && effects & EF_DESCENT > 0 {
+        descend(state, game_obj_db);
+    }
+
+    if effects & EF_GUST_OF_WIND > 0 {
+        let loc = game_obj_db.get(obj_id).unwrap().get_loc();
+        if disperse_fog(state, loc, 8) {
+            state.msg_queue.push_back(Message::new(obj_id, loc, "A gust of wind tears the fog to tatters!", ""));
+        } else {
+            state.msg_queue.push_back(Message::new(obj_id, loc, "A brisk wind swirls about.", ""));
+        }
+    }
+
+    if effects & EF_DARKNESS > 0 {
+        let loc = game_obj_db.get(obj_id).unwrap().get_loc();
+        let until = state.turn + state.rng.gen_range(15, 26);
+        cast_darkness(state, loc, 4, until);
+        state.msg_queue.push_back(Message::new(obj_id, loc, "Darkness swallows the area!", "Darkness swallows the area!"));
+    }
+
     if effects & EF_PROTECTION > 0 {
         if obj_id == 0 {
             let player = game_obj_db.player().unwrap();
@@ -155,8 +382,183 @@ pub fn apply_effects(state: &mut GameState, obj_id: usize, game_obj_db: &mut Gam
     }
 }
 
+// Fill in the player's memory of the level's layout. The wilderness is far too big to map all
+// at once so there just reveal the surrounding region.
+pub fn magic_mapping(state: &mut GameState, player_loc: (i32, i32, i8)) {
+    let sqs = state.map.keys()
+                       .filter(|sq| sq.2 == player_loc.2)
+                       .filter(|sq| player_loc.2 > 0 || util::distance(player_loc.0, player_loc.1, sq.0, sq.1) <= 30.0)
+                       .copied()
+                       .collect::<Vec<(i32, i32, i8)>>();
+
+    for sq in sqs {
+        // Traps aren't part of the layout
+        let tile = match state.map[&sq] {
+            Tile::Blank => continue,
+            Tile::Trigger | Tile::TeleportTrap => Tile::StoneFloor,
+            t => t,
+        };
+        state.tile_memory.insert(sq, tile);
+    }
+
+    if player_loc.2 > 0 {
+        state.msg_queue.push_back(Message::info("An image of your surroundings coalesces in your mind!"));
+    } else {
+        state.msg_queue.push_back(Message::info("An image of the surrounding countryside coalesces in your mind!"));
+    }
+}
+
+// Once the delay from a scroll of recall has passed, whisk the player back to town
+pub fn check_recall(state: &mut GameState, game_obj_db: &mut GameObjectDB) {
+    let player = game_obj_db.player().unwrap();
+    if !player.statuses.iter().any(|s| s.0 == Status::Recalling && s.1 <= state.turn) {
+        return;
+    }
+    remove_status(player, Status::Recalling);
+
+    if player.get_loc().2 == 0 {
+        state.msg_queue.push_back(Message::info("You feel a brief tug, but you are already above ground."));
+        return;
+    }
+
+    let sqs = state.world_info.town_square.iter()
+                                          .filter(|sq| !game_obj_db.location_occupied(sq))
+                                          .copied()
+                                          .collect::<Vec<(i32, i32, i8)>>();
+    let recalled = match sqs.choose(&mut state.rng) {
+        Some(sq) => game_obj_db.set_to_loc(0, *sq),
+        None => false,
+    };
+    if recalled {
+        let s = format!("You find yourself back in {}!", state.world_info.town_name);
+        state.msg_queue.push_back(Message::info(&s));
+    } else {
+        state.msg_queue.push_back(Message::info("You feel a brief tug, but nothing happens."));
+    }
+}
+
+// Blow away any fog within radius of loc. Returns true if there was any to clear.
+pub fn disperse_fog(state: &mut GameState, loc: (i32, i32, i8), radius: i32) -> bool {
+    let mut dispersed = false;
+    for r in loc.0 - radius..=loc.0 + radius {
+        for c in loc.1 - radius..=loc.1 + radius {
+            let sq = (r, c, loc.2);
+            if state.map.get(&sq) == Some(&Tile::Fog) && util::distance(loc.0, loc.1, r, c) <= radius as f64 {
+                state.map.insert(sq, Tile::StoneFloor);
+                dispersed = true;
+            }
+        }
+    }
+
+    dispersed
+}
+
+// Drop the player down to a random spot on the next level of the dungeon
+fn descend(state: &mut GameState, game_obj_db: &mut GameObjectDB) {
+    let player_loc = game_obj_db.player().unwrap().get_loc();
+    let next_level = player_loc.2 + 1;
+    if next_level as u8 > state.world_info.dungeon_depth {
+        state.msg_queue.push_back(Message::info("You feel a tug downward, but nothing happens."));
+        return;
+    }
+
+    let sqs = state.map.iter()
+                       .filter(|(sq, tile)| sq.2 == next_level && **tile == Tile::StoneFloor)
+                       .map(|(sq, _)| *sq)
+                       .filter(|sq| !game_obj_db.location_occupied(sq))
+                       .collect::<Vec<(i32, i32, i8)>>();
+
+    let descended = match sqs.choose(&mut state.rng) {
+        Some(sq) => game_obj_db.set_to_loc(0, *sq),
+        None => false,
+    };
+    if descended {
+        state.msg_queue.push_back(Message::info("The floor dissolves beneath your feet!"));
+        if state.stats.levels_reached.insert(next_level) {
+            let s = format!("Descended to the {} level of the dungeon.", util::num_to_nth(next_level as u8));
+            state.add_journal_entry(&s);
+            for feeling in state.world_info.level_feelings(next_level) {
+                state.msg_queue.push_back(Message::info(feeling));
+            }
+        }
+        let player = game_obj_db.player().unwrap();
+        if next_level as u8 > player.max_depth {
+            player.max_depth = next_level as u8;
+        }
+    } else {
+        state.msg_queue.push_back(Message::info("You feel a tug downward, but nothing happens."));
+    }
+}
+
+// Lingering beside lava or up among the snowy peaks will hurt the player unless they are
+// wearing something to protect them from the heat or cold
+pub fn check_temperature(state: &mut GameState, game_obj_db: &mut GameObjectDB) {
+    let player = game_obj_db.player().unwrap();
+    let loc = player.get_loc();
+
+    let mut hot = false;
+    let mut cold = false;
+    for adj in util::ADJ.iter().chain([(0, 0)].iter()) {
+        match state.map.get(&(loc.0 + adj.0, loc.1 + adj.1, loc.2)) {
+            Some(Tile::Lava) => hot = true,
+            Some(Tile::SnowPeak) => cold = true,
+            _ => { },
+        }
+    }
+
+    let gear = player.gear_attributes();
+    if hot && gear & items::IA_FIRE_RESISTANT == 0 && state.turn.is_multiple_of(3) {
+        state.msg_queue.push_back(Message::info("The heat from the lava sears you!"));
+        let dmg = state.rng.gen_range(1, 5);
+        player.damaged(state, dmg, DamageType::Fire, 0, "the heat");
+    }
+
+    if cold && gear & items::IA_WARM == 0 && state.turn.is_multiple_of(5) {
+        state.msg_queue.push_back(Message::info("The bitter cold chills you to the bone!"));
+        let dmg = state.rng.gen_range(1, 4);
+        player.damaged(state, dmg, DamageType::Cold, 0, "exposure");
+    }
+}
+
+// Recalculate which squares on the player's level hold things they've magically detected.
+// Monsters move around, so this is redone each turn until the detection wears off. A telepathic
+// player senses any thinking creature, but the undead and mindless things like plants are blanks.
+pub fn refresh_detected(state: &mut GameState, game_obj_db: &GameObjectDB, level: i8, telepathic: bool) {
+    state.detected.clear();
+    if state.turn >= state.detected_until {
+        state.detecting = 0;
+    }
+    if state.detecting == 0 && !telepathic {
+        return;
+    }
+
+    for (loc, ids) in game_obj_db.obj_locs.iter() {
+        if loc.2 != level {
+            continue;
+        }
+
+        for id in ids.iter() {
+            let detected = match game_obj_db.get(*id) {
+                Some(GameObjects::NPC(npc)) => npc.alive && (state.detecting & EF_DETECT_MONSTERS > 0
+                                            || (telepathic && npc.attributes & npc::MA_UNDEAD == 0 && npc.mode != NPCPersonality::Plant)),
+                Some(GameObjects::GoldPile(_)) => state.detecting & EF_DETECT_TREASURE > 0,
+                Some(GameObjects::Item(item)) => state.detecting & EF_DETECT_TREASURE > 0 && item.item_type != ItemType::Obstacle,
+                _ => false,
+            };
+
+            if detected {
+                state.detected.insert(*loc);
+                break;
+            }
+        }
+    }
+}
+
 // Constants used to track abilities that have cool down times
 pub const AB_CREATE_PHANTASM: u128 = 0;
+pub const AB_RAISE_ALARM: u128 = 1;
+pub const AB_REMARK_ON_WOUNDS: u128 = 2;
+pub const AB_PRAYER: u128 = 3;
 
 #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Status {
@@ -172,6 +574,13 @@ pub enum Status {
     Paralyzed,
     Flying,
     Protection(i8),
+    Hasted,
+    Slowed,
+    Held,
+    Telepathic,
+    Bleeding,
+    Routed,
+    Recalling,
 }
 
 pub trait HasStatuses {
@@ -208,6 +617,20 @@ pub fn add_status<T: HasStatuses + GameObject>(person: &mut T, status: Status, t
             statuses[j].1 = time;
             return;
         }
+        if (status == Status::Hasted || status == Status::Slowed) && statuses[j].0 == status && time > statuses[j].1 {
+            statuses[j].1 = time;
+            return;
+        }
+    }
+
+    // Haste and slow cancel each other out
+    if status == Status::Hasted && statuses.iter().any(|s| s.0 == Status::Slowed) {
+        statuses.retain(|s| s.0 != Status::Slowed);
+        return;
+    }
+    if status == Status::Slowed && statuses.iter().any(|s| s.0 == Status::Hasted) {
+        statuses.retain(|s| s.0 != Status::Hasted);
+        return;
     }
     
     if let Status::CoolingDown(ability) = status {
@@ -242,6 +665,15 @@ pub fn add_status<T: HasStatuses + GameObject>(person: &mut T, status: Status, t
     statuses.push((status, time));
 }
 
+// Attacking (or zapping a wand) gives away the player's position
+pub fn break_invisibility(state: &mut GameState, game_obj_db: &mut GameObjectDB) {
+    let player = game_obj_db.player().unwrap();
+    if player.has_status(Status::Invisible) {
+        remove_status(player, Status::Invisible);
+        state.msg_queue.push_back(Message::info("You flicker back into view!"));
+    }
+}
+
 pub fn remove_status<T: HasStatuses + GameObject>(person: &mut T, status: Status) {
     let statuses = person.get_statuses().unwrap();
     statuses.retain(|s| s.0 != status);
@@ -253,12 +685,13 @@ pub fn remove_status<T: HasStatuses + GameObject>(person: &mut T, status: Status
 
 pub fn check_statuses<T: HasStatuses + GameObject + Person>(person: &mut T, state: &mut GameState) {
     let obj_id = person.obj_id();
-    let con_check = person.ability_check(Ability::Con) as u32; // gotta do this here for borrow checker reasons...
+    let con_check = person.ability_check(Ability::Con, &mut state.rng) as u32; // gotta do this here for borrow checker reasons...
     let statuses = person.get_statuses().unwrap();
     
     let mut reveal = false;
     let mut killed = false;
     let mut calc_ac = false;
+    let mut bleeding = false;
     for j in 0..statuses.len() {
         if statuses[j].0 == Status::Passing && statuses[j].1 <= state.turn {
             statuses.remove(j);
@@ -302,6 +735,9 @@ pub fn check_statuses<T: HasStatuses + GameObject + Person>(person: &mut T, stat
         if statuses[j].0 == Status::Invisible && statuses[j].1 <= state.turn {
             statuses.remove(j);
             reveal = true;
+            if obj_id == 0 {
+                state.msg_queue.push_back(Message::info("You are visible once more."));
+            }
             continue;
         }
         if statuses[j].0 == Status::FadeAfter && statuses[j].1 <= state.turn {
@@ -322,6 +758,48 @@ pub fn check_statuses<T: HasStatuses + GameObject + Person>(person: &mut T, stat
             }
             continue;
         }
+        if statuses[j].0 == Status::Hasted && statuses[j].1 <= state.turn {
+            statuses.remove(j);
+            if obj_id == 0 {
+                state.msg_queue.push_back(Message::info("You feel yourself slow down."));
+            }
+            continue;
+        }
+        if statuses[j].0 == Status::Slowed && statuses[j].1 <= state.turn {
+            statuses.remove(j);
+            if obj_id == 0 {
+                state.msg_queue.push_back(Message::info("You no longer feel sluggish."));
+            }
+            continue;
+        }
+        if statuses[j].0 == Status::Routed && statuses[j].1 <= state.turn {
+            statuses.remove(j);
+            continue;
+        }
+        if statuses[j].0 == Status::Telepathic && statuses[j].1 <= state.turn {
+            statuses.remove(j);
+            if obj_id == 0 {
+                state.msg_queue.push_back(Message::info("Your mind contracts."));
+            }
+            continue;
+        }
+        if statuses[j].0 == Status::Bleeding {
+            if statuses[j].1 <= state.turn {
+                statuses.remove(j);
+                if obj_id == 0 {
+                    state.msg_queue.push_back(Message::info("Your bleeding stops."));
+                }
+                continue;
+            }
+            bleeding = true;
+        }
+        if statuses[j].0 == Status::Held && statuses[j].1 <= state.turn {
+            statuses.remove(j);
+            if obj_id == 0 {
+                state.msg_queue.push_back(Message::info("You pull yourself free."));
+            }
+            continue;
+        }
         if statuses[j].0 == Status::Flying {
             if statuses[j].1 <= state.turn {
                 statuses.remove(j);
@@ -360,6 +838,10 @@ pub fn check_statuses<T: HasStatuses + GameObject + Person>(person: &mut T, stat
         person.calc_ac();
     }
 
+    if bleeding {
+        person.damaged(state, 1, DamageType::Piercing, 0, "blood loss");
+    }
+
     if reveal {
         person.reveal();
         if obj_id == 0 {
@@ -377,3 +859,85 @@ pub fn check_statuses<T: HasStatuses + GameObject + Person>(person: &mut T, stat
         state.msg_queue.push_back(Message::new(obj_id, person.get_loc(), &s, ""));         
     }    
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::npc::{MonsterFactory, NPC};
+
+    fn lay_bear_trap(game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8)) -> usize {
+        let mut trap = items::Item::get_item(game_obj_db, "bear trap", &mut util::GameRng::seeded(1)).unwrap();
+        trap.set_loc(loc);
+        let trap_id = trap.obj_id();
+        game_obj_db.add(trap);
+
+        trap_id
+    }
+
+    #[test]
+    fn player_traps_catch_monsters_but_not_villagers() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = crate::tests::test_level(&mut game_obj_db);
+        let loc = (5, 6, 1);
+
+        let trap_id = lay_bear_trap(&mut game_obj_db, loc);
+        let villager = NPC::villager("Alice".to_string(), loc, None, "villager1", &mut game_obj_db, &mut state.rng);
+        let villager_id = villager.obj_id();
+        game_obj_db.add(villager);
+        spring_player_trap(&mut state, &mut game_obj_db, trap_id, villager_id);
+        assert!(game_obj_db.get(trap_id).is_some());
+        assert!(!game_obj_db.npc(villager_id).unwrap().has_status(Status::Held));
+        game_obj_db.remove(villager_id);
+
+        let mf = MonsterFactory::init();
+        mf.monster("kobold", loc, &mut game_obj_db, &mut state.rng);
+        let monster_id = game_obj_db.npc_at(&loc).unwrap();
+        spring_player_trap(&mut state, &mut game_obj_db, trap_id, monster_id);
+        assert!(game_obj_db.get(trap_id).is_none());
+        assert!(game_obj_db.npc(monster_id).unwrap().has_status(Status::Held));
+    }
+
+    #[test]
+    fn bleeding_wounds_drain_hp_until_stanched_or_closed() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = crate::tests::test_level(&mut game_obj_db);
+        crate::battle::apply_bleeding(&mut state, 0, &mut game_obj_db);
+        assert!(state.msg_queue.iter().any(|m| m.text == "You are bleeding!"));
+
+        let player = game_obj_db.player().unwrap();
+        let hp = player.curr_hp;
+        check_statuses(player, &mut state);
+        assert_eq!(player.curr_hp, hp - 1);
+
+        apply_effects(&mut state, 0, &mut game_obj_db, EF_STANCH);
+        let player = game_obj_db.player().unwrap();
+        assert!(!player.has_status(Status::Bleeding));
+        check_statuses(player, &mut state);
+        assert_eq!(player.curr_hp, hp - 1);
+
+        // Left alone, a wound closes by itself
+        add_status(player, Status::Bleeding, 2);
+        state.turn = 2;
+        state.msg_queue.clear();
+        check_statuses(player, &mut state);
+        assert!(!player.has_status(Status::Bleeding));
+        assert_eq!(player.curr_hp, hp - 1);
+        assert_eq!(state.msg_queue.back().unwrap().text, "Your bleeding stops.");
+    }
+
+    #[test]
+    fn the_undead_have_no_blood_to_lose() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = crate::tests::test_level(&mut game_obj_db);
+        let mf = MonsterFactory::init();
+        mf.monster("kobold", (3, 3, 1), &mut game_obj_db, &mut state.rng);
+        mf.monster("skeleton", (7, 7, 1), &mut game_obj_db, &mut state.rng);
+        let kobold_id = game_obj_db.npc_at(&(3, 3, 1)).unwrap();
+        let skeleton_id = game_obj_db.npc_at(&(7, 7, 1)).unwrap();
+
+        crate::battle::apply_bleeding(&mut state, kobold_id, &mut game_obj_db);
+        crate::battle::apply_bleeding(&mut state, skeleton_id, &mut game_obj_db);
+        assert!(game_obj_db.npc(kobold_id).unwrap().has_status(Status::Bleeding));
+        assert!(!game_obj_db.npc(skeleton_id).unwrap().has_status(Status::Bleeding));
+    }
+}
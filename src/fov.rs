@@ -18,6 +18,53 @@ use std::collections::HashSet;
 
 use crate::map;
 
+// (centre, radius, fov_only, fingerprint of the surrounding squares)
+type FovKey = ((i32, i32, i8), u8, bool, Vec<u8>);
+
+// Calculating FOV is the most expensive thing done each turn, and often nothing relevant has
+// changed since the last time (the player searched, a monster is standing still, etc). So we
+// remember the last result along with a fingerprint of everything in the viewing window that
+// could affect it (whether each square exists, blocks sight, is a tree or fog, or is lit) and only
+// recalculate when the centre, radius or fingerprint differ.
+#[derive(Debug, Default)]
+pub struct FovCache {
+	key: Option<FovKey>,
+	visible: HashSet<(i32, i32, i8)>,
+	pub recalcs: u32,
+}
+
+fn fingerprint(state: &GameState, centre: (i32, i32, i8)) -> Vec<u8> {
+	let width_radius = (FOV_WIDTH / 2) as i32;
+	let height_radius = (FOV_HEIGHT / 2) as i32;
+	let mut fp = Vec::with_capacity(FOV_HEIGHT * FOV_WIDTH);
+	for r in centre.0 - height_radius..=centre.0 + height_radius {
+		for c in centre.1 - width_radius..=centre.1 + width_radius {
+			let loc = (r, c, centre.2);
+			let b = match state.map.get(&loc) {
+				Some(tile) => {
+					1 | (tile.clear() as u8) << 1 | ((*tile == map::Tile::Tree) as u8) << 2 
+						| (state.lit_sqs.contains_key(&loc) as u8) << 3 | ((*tile == map::Tile::Fog) as u8) << 4
+				},
+				None => 0,
+			};
+			fp.push(b);
+		}
+	}
+
+	fp
+}
+
+pub fn calc_fov_cached<'a>(state: &GameState, cache: &'a mut FovCache, centre: (i32, i32, i8), radius: u8, fov_only: bool) -> &'a HashSet<(i32, i32, i8)> {
+	let key = (centre, radius, fov_only, fingerprint(state, centre));
+	if cache.key.as_ref() != Some(&key) {
+		cache.visible = calc_fov(state, centre, radius, fov_only);
+		cache.key = Some(key);
+		cache.recalcs += 1;
+	}
+
+	&cache.visible
+}
+
 // Kind of ugly by why recalculate these everytime?
 #[inline]
 fn radius_3() -> Vec<(i32, i32)> {
@@ -138,6 +185,11 @@ fn mark_visible(r1: i32, c1: i32, r2: i32, c2: i32, sq_radius: i32,
 				return;
 			}
 
+			// Fog doesn't block sight outright but nothing past the first square of it can be seen
+			if map::Tile::Fog == state.map[&(r, c, depth)] && !(r == r1 && c == c1) {
+				return;
+			}
+
 			// I want trees to not totally block light, but instead reduce visibility, but fog 
             // completely blocks light.           
 			if map::Tile::Tree == state.map[&(r, c, depth)] && !(r == r1 && c == c1) {
@@ -176,6 +228,11 @@ fn mark_visible(r1: i32, c1: i32, r2: i32, c2: i32, sq_radius: i32,
 				return;
 			}
 
+			// Fog doesn't block sight outright but nothing past the first square of it can be seen
+			if map::Tile::Fog == state.map[&(r, c, depth)] && !(r == r1 && c == c1) {
+				return;
+			}
+
 			// Same as above, trees partially block vision instead of cutting it off
             //if curr_weather.clouds.contains(&(r as usize, c as usize)) && !no_fog.contains(&(r as usize, c as usize)) {
             if map::Tile::Tree == state.map[&(r, c, depth)] && !(r == r1 && c == c1) {
@@ -238,8 +295,8 @@ pub fn calc_fov(state: &GameState, centre: (i32, i32, i8), radius: u8, fov_only:
 }
 
 // Translates the set of visible squares into the grid used to select which tiles to show to the player
-pub fn visible_sqs(state: &GameState, centre: (i32, i32, i8), radius: u8, fov_only: bool) -> [((i32, i32, i8), bool); FOV_HEIGHT * FOV_WIDTH] {
-	let visible = calc_fov(state, centre, radius, fov_only);
+pub fn visible_sqs(state: &GameState, cache: &mut FovCache, centre: (i32, i32, i8), radius: u8, fov_only: bool) -> [((i32, i32, i8), bool); FOV_HEIGHT * FOV_WIDTH] {
+	let visible = calc_fov_cached(state, cache, centre, radius, fov_only);
 
     // Now we know which locations are actually visible from the player's loc, 
     // copy the tiles into the v_matrix
@@ -256,3 +313,54 @@ pub fn visible_sqs(state: &GameState, centre: (i32, i32, i8), radius: u8, fov_on
 
 	v_matrix
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::map::Tile;
+
+	#[test]
+	fn cached_fov_only_recalculates_when_something_changes() {
+		let mut state = crate::tests::test_state();
+		for r in 0..30 {
+			for c in 0..50 {
+				state.map.insert((r, c, 1), Tile::StoneFloor);
+			}
+		}
+		let mut cache = FovCache::default();
+		let centre = (15, 25, 1);
+
+		let first = calc_fov_cached(&state, &mut cache, centre, 8, true).clone();
+		let second = calc_fov_cached(&state, &mut cache, centre, 8, true).clone();
+		assert_eq!(cache.recalcs, 1);
+		assert_eq!(first, second);
+
+		// A wall going up in view invalidates the cache, as does moving
+		state.map.insert((15, 27, 1), Tile::Wall);
+		calc_fov_cached(&state, &mut cache, centre, 8, true);
+		assert_eq!(cache.recalcs, 2);
+		calc_fov_cached(&state, &mut cache, (15, 24, 1), 8, true);
+		assert_eq!(cache.recalcs, 3);
+	}
+
+	#[test]
+	fn windows_block_movement_but_not_sight() {
+		let mut state = crate::tests::test_state();
+		for r in 0..30 {
+			for c in 0..50 {
+				state.map.insert((r, c, 1), Tile::StoneFloor);
+			}
+		}
+		for r in 0..30 {
+			state.map.insert((r, 27, 1), Tile::WoodWall);
+		}
+		state.map.insert((15, 27, 1), Tile::Window('|'));
+		let centre = (15, 25, 1);
+
+		assert!(!state.map[&(15, 27, 1)].passable());
+		let visible = calc_fov(&state, centre, 8, true);
+		assert!(visible.contains(&(15, 29, 1)));
+		// Whereas the wall on either side of it hides what's behind
+		assert!(!visible.contains(&(11, 29, 1)));
+	}
+}
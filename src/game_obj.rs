@@ -16,17 +16,20 @@
 extern crate serde;
 
 use serde::{Deserialize, Serialize};
+#[cfg(test)]
+use std::cell::Cell;
 use std::collections::{HashMap, HashSet, VecDeque};
 
 use super::{EventResponse, EventType, GameState, PLAYER_INV};
 use crate::battle::DamageType;
 use crate::effects;
+use crate::effects::Status;
 use crate::items::{Item, GoldPile};
 use crate::map::{SpecialSquare, Tile};
 use crate::npc;
 use crate::npc::NPC;
 use crate::player::Player;
-use crate::util::StringUtils;
+use crate::util::{GameRng, StringUtils};
 use crate::items;
 use crate::items::ItemType;
 
@@ -187,14 +190,26 @@ pub struct GameObjectDB {
     next_obj_id: usize,
     pub obj_locs: HashMap<(i32, i32, i8), VecDeque<usize>>,
     pub objects: HashMap<usize, GameObjects>,
+    player_slot: Option<GameObjects>,
     pub listeners: HashSet<(usize, EventType)>,
+    #[cfg(test)]
+    #[serde(skip)]
+    hashed_lookups: Cell<u64>,
+    #[cfg(test)]
+    #[serde(skip)]
+    player_lookups: Cell<u64>,
 }
 
 impl GameObjectDB {
     pub fn new() -> GameObjectDB {
         // start at 1 because we assume the player is object 0
-        GameObjectDB { next_obj_id: 1, obj_locs: HashMap::new(), objects: HashMap::new(),
-            listeners: HashSet::new(), }
+        GameObjectDB { next_obj_id: 1, obj_locs: HashMap::new(), objects: HashMap::new(), player_slot: None,
+            listeners: HashSet::new(),
+            #[cfg(test)]
+            hashed_lookups: Cell::new(0),
+            #[cfg(test)]
+            player_lookups: Cell::new(0),
+        }
     }
 
     pub fn next_id(&mut self) -> usize {
@@ -204,22 +219,40 @@ impl GameObjectDB {
         c
     }
 
+    // These get called constantly. The player is fetched far more often than anything else (every
+    // monster wants to know where they are, the main loop grabs them several times a turn) so they
+    // live in their own slot rather than in the objects table and finding them skips the hashing.
     pub fn get(&self, obj_id: usize) -> Option<&GameObjects> {
-        if !self.objects.contains_key(&obj_id) {
-            None
+        if obj_id == 0 {
+            #[cfg(test)]
+            self.player_lookups.set(self.player_lookups.get() + 1);
+            self.player_slot.as_ref()
         } else {
+            #[cfg(test)]
+            self.hashed_lookups.set(self.hashed_lookups.get() + 1);
             self.objects.get(&obj_id)
         }
     }
 
     pub fn get_mut(&mut self, obj_id: usize) -> Option<&mut GameObjects> {
-        if !self.objects.contains_key(&obj_id) {
-            None
+        if obj_id == 0 {
+            #[cfg(test)]
+            self.player_lookups.set(self.player_lookups.get() + 1);
+            self.player_slot.as_mut()
         } else {
+            #[cfg(test)]
+            self.hashed_lookups.set(self.hashed_lookups.get() + 1);
             self.objects.get_mut(&obj_id)
         }
     }
 
+    // Running counts of (objects table, player slot) lookups, for keeping an eye on how much work
+    // a turn does. Only tracked in test builds so the real game doesn't pay for the bookkeeping
+    #[cfg(test)]
+    pub fn lookup_counts(&self) -> (u64, u64) {
+        (self.hashed_lookups.get(), self.player_lookups.get())
+    }
+
     pub fn add(&mut self, obj: GameObjects) {
         let loc = obj.get_loc();
         let obj_id = obj.obj_id();
@@ -241,29 +274,80 @@ impl GameObjectDB {
                 }            
             }
         }
+
+        // Perishable food only rots via a listener while it's lying on the map. The player's pack
+        // is handled in Player::spoil_food and shopkeepers keep their stock fresh.
+        if let GameObjects::Item(item) = &obj {
+            if item.attributes & items::IA_PERISHABLE > 0 {
+                self.listeners.insert((obj_id, EventType::EndOfTurn));
+            }
+        }
                 
-        self.set_to_loc(obj_id, loc);
-        self.objects.insert(obj_id, obj);        
+        self.obj_locs.entry(loc)
+                     .or_default()
+                     .push_front(obj_id);
+        if obj_id == 0 {
+            self.player_slot = Some(obj);
+        } else {
+            self.objects.insert(obj_id, obj);
+        }
     }
 
     pub fn remove(&mut self, obj_id: usize) -> GameObjects {  
-        let obj = self.objects.get(&obj_id).unwrap();
+        let obj = self.get(obj_id).unwrap();
         let loc = obj.get_loc();
 
         self.listeners.retain(|l| l.0 != obj_id);
         self.remove_from_loc(obj_id, loc);
-        self.objects.remove(&obj_id).unwrap()        
+        if obj_id == 0 {
+            self.player_slot.take().unwrap()
+        } else {
+            self.objects.remove(&obj_id).unwrap()
+        }
     }
 
-    pub fn set_to_loc(&mut self, obj_id: usize, loc: (i32, i32, i8)) {
-        self.obj_locs.entry(loc).or_insert(VecDeque::new());
+    // All movement of objects on the map should go through here. Creatures (and anything else that
+    // blocks) won't be placed on a square some other blocking object already occupies; the return
+    // value tells the caller whether the move actually happened.
+    #[must_use]
+    pub fn set_to_loc(&mut self, obj_id: usize, loc: (i32, i32, i8)) -> bool {
+        if let Some(obj) = self.get(obj_id) {
+            if obj.blocks() && self.other_blocker_at(obj_id, &loc) {
+                return false;
+            }
 
-        self.obj_locs.get_mut(&loc).unwrap().push_front(obj_id);
-        if self.objects.contains_key(&obj_id) {
-            let prev_loc = self.objects[&obj_id].get_loc();
-            self.remove_from_loc(obj_id, prev_loc);
-            self.objects.get_mut(&obj_id).unwrap().set_loc(loc);
+            let prev_loc = obj.get_loc();
+            if let Some(q) = self.obj_locs.get_mut(&prev_loc) {
+                q.retain(|v| *v != obj_id);
+            }
+            self.get_mut(obj_id).unwrap().set_loc(loc);
         }
+
+        self.obj_locs.entry(loc)
+                     .or_default()
+                     .push_front(obj_id);
+
+        true
+    }
+
+    fn other_blocker_at(&self, obj_id: usize, loc: &(i32, i32, i8)) -> bool {
+        match self.obj_locs.get(loc) {
+            Some(q) => q.iter().any(|id| *id != obj_id && self.get(*id).unwrap().blocks()),
+            None => false,
+        }
+    }
+
+    // For the occasional case where two creatures trade places, which set_to_loc() would
+    // otherwise refuse
+    pub fn swap_locs(&mut self, id_a: usize, id_b: usize) {
+        let loc_a = self.get(id_a).unwrap().get_loc();
+        let loc_b = self.get(id_b).unwrap().get_loc();
+        self.remove_from_loc(id_a, loc_a);
+        self.remove_from_loc(id_b, loc_b);
+        self.get_mut(id_a).unwrap().set_loc(loc_b);
+        self.get_mut(id_b).unwrap().set_loc(loc_a);
+        self.obj_locs.entry(loc_b).or_default().push_front(id_a);
+        self.obj_locs.entry(loc_a).or_default().push_front(id_b);
     }
 
     pub fn remove_from_loc(&mut self, obj_id: usize, loc: (i32, i32, i8)) {
@@ -280,22 +364,22 @@ impl GameObjectDB {
             // Ensure the player or a monster occupying a square is displayed in 
             // preference to items on the square. Check for them first
             for obj_id in self.obj_locs[&loc].iter() {
-                if let GameObjects::Player(_) = self.objects[&obj_id] {
-                    return Some((self.objects[&obj_id].get_tile(), false));
+                if let GameObjects::Player(_) = self.get(*obj_id).unwrap() {
+                    return Some((self.get(*obj_id).unwrap().get_tile(), false));
                 }
             }
 
             for obj_id in self.obj_locs[&loc].iter() {
-                if let GameObjects::NPC(npc) = &self.objects[&obj_id] {
+                if let GameObjects::NPC(npc) = self.get(*obj_id).unwrap() {
                     if !npc.hidden() {
-                        return Some((self.objects[&obj_id].get_tile(), false));
+                        return Some((self.get(*obj_id).unwrap().get_tile(), false));
                     }
                 }
             }
 
             for obj_id in self.obj_locs[&loc].iter() {
-                if !self.objects[obj_id].hidden() {
-                    return Some((self.objects[&obj_id].get_tile(), true));
+                if !self.get(*obj_id).unwrap().hidden() {
+                    return Some((self.get(*obj_id).unwrap().get_tile(), true));
                 }
             }
         }
@@ -304,13 +388,12 @@ impl GameObjectDB {
     }
 
     pub fn blocking_obj_at(&self, loc: &(i32, i32, i8)) -> bool {
-        if self.obj_locs.contains_key(&loc) && !self.obj_locs[&loc].is_empty() {
-            for obj_id in self.obj_locs[&loc].iter() {
-                if !self.objects.contains_key(obj_id) {
-                    panic!("{}", format!("Should find obj_id {}!", obj_id));
-                }
-                if self.objects[&obj_id].blocks() {
-                    return true;
+        if let Some(objs) = self.obj_locs.get(loc) {
+            for obj_id in objs.iter() {
+                match self.get(*obj_id) {
+                    Some(obj) if obj.blocks() => return true,
+                    Some(_) => { },
+                    None => panic!("{}", format!("Should find obj_id {}!", obj_id)),
                 }
             }
         }
@@ -318,8 +401,8 @@ impl GameObjectDB {
         false
     }
 
-    pub fn stepped_on_event(&mut self, state: &mut GameState, loc: (i32, i32, i8)) {
-        let ploc = self.objects[&0].get_loc();
+    pub fn stepped_on_event(&mut self, state: &mut GameState, loc: (i32, i32, i8), stepper_id: usize) {
+        let ploc = self.get(0).unwrap().get_loc();
 
         let listeners: Vec<usize> = self.listeners.iter()
             .filter(|l| l.1 == EventType::SteppedOn)
@@ -331,13 +414,14 @@ impl GameObjectDB {
                 if let Some(result) = obj.receive_event(EventType::SteppedOn, state, ploc) {
                     match result.event_type {
                         EventType::TrapRevealed => {
-                            let target = self.objects.get_mut(&obj_id).unwrap();
+                            let target = self.get_mut(obj_id).unwrap();
                             target.reveal();
                         },
                         EventType::Triggered => {                            
-                            let target = self.objects.get_mut(&obj_id).unwrap();
+                            let target = self.get_mut(obj_id).unwrap();
                             target.receive_event(EventType::Triggered, state, ploc);
                         },
+                        EventType::TrapSprung => effects::spring_player_trap(state, self, obj_id, stepper_id),
                         _ => { /* Should maybe panic! here? */ },
                     }
                 }
@@ -353,6 +437,12 @@ impl GameObjectDB {
         }
     }
 
+    pub fn npc_carrying(&self, obj_id: usize) -> Option<usize> {
+        self.objects.values()
+            .find(|o| matches!(o, GameObjects::NPC(npc) if npc.inventory.iter().any(|i| i.obj_id() == obj_id)))
+            .map(|o| o.obj_id())
+    }
+
     pub fn npc(&mut self, obj_id: usize) -> Option<&mut NPC> {
         if let Some(GameObjects::NPC(npc)) = self.get_mut(obj_id) {
             Some(npc)
@@ -394,10 +484,10 @@ impl GameObjectDB {
         if self.obj_locs.contains_key(loc) {
             for j in 0..self.obj_locs[&loc].len() {                
                 let obj_id = self.obj_locs[&loc][j];
-                if obj_id == 0 || self.objects[&obj_id].hidden() {
+                if obj_id == 0 || self.get(obj_id).unwrap().hidden() {
                     continue;
                 }
-                let name = self.objects[&obj_id].get_fullname();
+                let name = self.get(obj_id).unwrap().get_fullname();
                 let i = items.entry(name).or_insert(0);
                 *i += 1;
             }
@@ -417,12 +507,21 @@ impl GameObjectDB {
         v
     }
 
+    // Cheaper than obstacles_at_loc() when we only care whether something is in the way. (The
+    // pathfinding code asks this about every square it considers)
+    pub fn obstacle_at(&self, loc: &(i32, i32, i8)) -> bool {
+        match self.obj_locs.get(loc) {
+            Some(objs) => objs.iter().any(|id| matches!(self.get(*id), Some(GameObjects::Item(item)) if item.item_type == ItemType::Obstacle)),
+            None => false,
+        }
+    }
+
     pub fn obstacles_at_loc(&self, loc: (i32, i32, i8)) -> Vec<&Item> {
         let mut obstacles = Vec::new();
 
         if self.obj_locs.contains_key(&loc) {
             for id in self.obj_locs[&loc].iter() {
-                if let GameObjects::Item(item) = &self.objects[&id] {
+                if let GameObjects::Item(item) = self.get(*id).unwrap() {
                     if ItemType::Obstacle == item.item_type {
                         obstacles.push(item);
                     }
@@ -438,7 +537,7 @@ impl GameObjectDB {
             let ids = self.obj_locs[&loc]
                 .iter().copied();
             
-            ids.filter(|id| self.objects[&id].hidden()).collect()
+            ids.filter(|id| self.get(*id).unwrap().hidden()).collect()
         } else {
             Vec::new()
         }
@@ -448,8 +547,8 @@ impl GameObjectDB {
         if self.obj_locs.contains_key(&loc) {
             let mut ids = Vec::new();
             for id in self.obj_locs[&loc].iter() {
-                if *id == 0 || self.objects[&id].hidden() { continue; }
-                match &self.objects[&id] {
+                if *id == 0 || self.get(*id).unwrap().hidden() { continue; }
+                match self.get(*id).unwrap() {
                     GameObjects::Item(item) => {
                         if item.attributes & items::IA_IMMOBILE == 0 {
                             ids.push(*id);
@@ -470,8 +569,8 @@ impl GameObjectDB {
         if self.obj_locs.contains_key(&loc) {
             let mut ids = Vec::new();
             for id in self.obj_locs[&loc].iter() {
-                if *id == 0 || self.objects[&id].hidden() { continue; }
-                if let GameObjects::SpecialSquare(_) = self.objects[&id] {
+                if *id == 0 || self.get(*id).unwrap().hidden() { continue; }
+                if let GameObjects::SpecialSquare(_) = self.get(*id).unwrap() {
                     continue;
                 }
 
@@ -490,22 +589,27 @@ impl GameObjectDB {
         if self.obj_locs.contains_key(&loc) {
             let obj_ids = self.obj_locs[&loc].iter().copied();
             for id in obj_ids {
-                if id == 0 || self.objects[&id].hidden() { continue; }
-                if let GameObjects::SpecialSquare(_) = self.objects[&id] {
+                if id == 0 || self.get(id).unwrap().hidden() { continue; }
+                if let GameObjects::SpecialSquare(_) = self.get(id).unwrap() {
                     continue;
                 }
-                if let GameObjects::Item(i) = &self.objects[&id] {
+                if let GameObjects::Item(i) = self.get(id).unwrap() {
                     if i.attributes & items::IA_IMMOBILE > 0 {
                         continue;
                     }
                 }
 
-                if let GameObjects::GoldPile(zorkmids) = &self.objects[&id] {
+                if let GameObjects::GoldPile(zorkmids) = self.get(id).unwrap() {
                     let amt = zorkmids.amount;
                     let s = format!("{} gold pieces", amt);
                     menu.push((s, id));
                 } else {
-                    menu.push((self.objects[&id].get_fullname().with_indef_article(), id));
+                    let mut s = self.get(id).unwrap().get_fullname().with_indef_article();
+                    if let GameObjects::Item(i) = self.get(id).unwrap() {
+                        s.push(' ');
+                        s.push_str(&Item::value_and_weight(i.value as u32, i.weight as u32));
+                    }
+                    menu.push((s, id));
                 }
             }            
         }
@@ -514,23 +618,13 @@ impl GameObjectDB {
     }
 
     pub fn location_occupied(&self, loc: &(i32, i32, i8)) -> bool {
-        if !self.obj_locs.contains_key(loc) {
-            return false;
-        }
-
-        for id in self.obj_locs[loc].iter() {
-            if self.objects[&id].blocks() {
-                return true;
-            }
-        }
-
-        false
+        self.blocking_obj_at(loc)
     }
 
     pub fn npc_at(&mut self, loc: &(i32, i32, i8)) -> Option<usize> {        
         if let Some(objs) = self.obj_locs.get(loc) {
             for id in objs {
-                if let GameObjects::NPC(_) = &self.objects[&id] {                
+                if let GameObjects::NPC(_) = self.get(*id).unwrap() {                
                     return Some(*id);
                 }
             }
@@ -561,22 +655,41 @@ impl GameObjectDB {
                         to_remove.push((obj_id, false));                        
                     }
                 }                
-            } else {
+            } else if self.player().unwrap().inv_obj_of_id(obj_id).is_some() {
                 let p = self.player().unwrap();
-                if let Some(obj) = p.inv_obj_of_id(obj_id) {
-                    obj.set_loc(PLAYER_INV);
-                    match obj.receive_event(event_type, state, ploc) {
-                        Some(response) => {
-                            if response.event_type == EventType::LightExpired {
-                                to_remove.push((obj_id, true));                        
-                            }
-                        },
-                        _ => { },
+                let obj = p.inv_obj_of_id(obj_id).unwrap();
+                obj.set_loc(PLAYER_INV);
+                match obj.receive_event(event_type, state, ploc) {
+                    Some(response) => {
+                        if response.event_type == EventType::LightExpired {
+                            to_remove.push((obj_id, true));                        
+                        }
+                    },
+                    _ => { },
+                }
+            } else if let Some(npc_id) = self.npc_carrying(obj_id) {
+                // Items an NPC is carrying (like a torch) go wherever they go
+                let npc = self.npc(npc_id).unwrap();
+                let npc_loc = npc.get_loc();
+                let obj = npc.inventory.iter_mut().find(|i| i.obj_id() == obj_id).unwrap();
+                obj.set_loc(npc_loc);
+                if let Some(response) = obj.receive_event(event_type, state, ploc) {
+                    if response.event_type == EventType::LightExpired {
+                        npc.inventory.retain(|i| i.obj_id() != obj_id);
+                        self.listeners.retain(|l| l.0 != obj_id);
                     }
                 }
             }
         }
 
+        if event_type == EventType::Update {
+            // Magical darkness swallows any light falling into it
+            let turn = state.turn;
+            state.darkness.retain(|_, until| *until > turn);
+            let darkness = &state.darkness;
+            state.lit_sqs.retain(|sq, _| !darkness.contains_key(sq));
+        }
+
         for item in to_remove {
             if item.1 {
                 let p = self.player().unwrap();
@@ -597,11 +710,11 @@ impl GameObjectDB {
         }
     }
 
-    fn clear_dead_npc(&mut self, npc_id: usize) {
+    fn clear_dead_npc(&mut self, npc_id: usize, rng: &mut GameRng) {
         self.listeners.retain(|l| l.0 != npc_id);
         let mut npc = self.remove(npc_id);
         if let GameObjects::NPC(npc) = &mut npc {                    
-            self.drop_npc_inventory(npc);
+            self.drop_npc_inventory(npc, rng);
         }
     }
 
@@ -624,21 +737,24 @@ impl GameObjectDB {
             // Got to remove the NPC from the objects table so I don't hit a mutual borrow situation when interacting 
             // with other game objects
             let npc = self.npc(npc_id).unwrap();
-            let npc_loc = npc.get_loc();
             
             // Has the npc died since their last turn?
-            let is_alive = npc.alive;
-            if !is_alive {
-                self.clear_dead_npc(npc_id);
+            if !npc.alive {
+                self.clear_dead_npc(npc_id, &mut state.rng);
                 continue;   
             }
             
             // I don't want to have every single monster in the game taking a turn every round, so
             // only update monsters on the surface or on the same level as the player. (Who knows, in
             // the end maybe it'll be fast enough to always update 100s of monsters..)
-            let curr_dungeon_level =  player_loc.2;      
-            if npc_loc.2 == 0 || npc_loc.2 == curr_dungeon_level {    
-                npc::take_turn(npc_id, state, self);                
+            let npc_level = npc.get_loc().2;
+            if npc_level == 0 || npc_level == player_loc.2 {    
+                // NPCs get energy the same way the player does, so fast monsters may act
+                // more than once a turn and slow ones will sometimes not act at all
+                npc.energy += npc.energy_restore * effects::speed_factor(&npc.statuses);
+                while npc::ready_to_act(npc_id, self) {
+                    npc::take_turn(npc_id, state, self);
+                }
             }
 
             // // Was the npc killed during their turn?
@@ -646,14 +762,15 @@ impl GameObjectDB {
             effects::check_statuses(npc, state);
             let is_alive = npc.alive;
             if !is_alive {
-                self.clear_dead_npc(npc_id);
+                self.clear_dead_npc(npc_id, &mut state.rng);
             }
         }
     }
 
-    pub fn drop_npc_inventory(&mut self, npc: &mut NPC) {
-        if npc.attributes & npc::MA_LEAVE_CORPSE > 0 {
-            let mut pieces = npc.get_corpse(self);
+    pub fn drop_npc_inventory(&mut self, npc: &mut NPC, rng: &mut GameRng) {
+        // Illusions don't leave anything behind
+        if npc.attributes & npc::MA_LEAVE_CORPSE > 0 && !npc.has_status(Status::FadeAfter) {
+            let mut pieces = npc.get_corpse(self, rng);
             while !pieces.is_empty() {
                 let piece = pieces.remove(0);
                 self.add(piece);
@@ -678,8 +795,8 @@ impl GameObjectDB {
                 .iter().copied();
             
             for id in ids.into_iter() {
-                if let GameObjects::SpecialSquare(_) = &self.objects[&id]{
-                    specials.push(self.objects.get(&id).unwrap());
+                if let GameObjects::SpecialSquare(_) = self.get(id).unwrap(){
+                    specials.push(self.get(id).unwrap());
                 }
             }            
         } 
@@ -687,12 +804,12 @@ impl GameObjectDB {
         specials
     }
 
-    pub fn check_for_dead_npcs(&mut self) {
+    pub fn check_for_dead_npcs(&mut self, rng: &mut GameRng) {
         let ids: Vec<usize> = self.objects.keys().map(|k| *k).collect();
 
         let mut corpses = Vec::new();
         for id in ids {
-            if let GameObjects::NPC(npc) = &self.objects[&id] {
+            if let GameObjects::NPC(npc) = self.get(id).unwrap() {
                 if !npc.alive {
                     corpses.push(id);                    
                 }
@@ -702,7 +819,7 @@ impl GameObjectDB {
         for id in corpses.iter() {
             let npc = self.remove(*id);
             if let GameObjects::NPC(mut npc) = npc {                
-                self.drop_npc_inventory(&mut npc);
+                self.drop_npc_inventory(&mut npc, rng);
             }            
         }
     }
@@ -713,10 +830,141 @@ pub trait Person {
     fn damaged(&mut self, state: &mut GameState, amount: u8, dmg_type: DamageType, assailant_id: usize, assailant_name: &str);
     fn get_hp(&self) -> (u8, u8);
     fn add_hp(&mut self, state: &mut GameState, amt: u8);
-    fn ability_check(&self, ability: Ability) -> u8;
+    fn ability_check(&self, ability: Ability, rng: &mut GameRng) -> u8;
     fn attributes(&self) -> u128;
     fn size(&self) -> u8;
     fn mark_dead(&mut self);
     fn alive(&self) -> bool;
     fn calc_ac(&mut self);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::npc::MonsterFactory;
+
+    fn items_at(game_obj_db: &GameObjectDB, loc: (i32, i32, i8)) -> usize {
+        game_obj_db.things_at_loc(loc).iter()
+            .filter(|id| matches!(game_obj_db.get(**id), Some(GameObjects::Item(_))))
+            .count()
+    }
+
+    #[test]
+    fn obstacle_at_only_sees_obstacles() {
+        let mut game_obj_db = GameObjectDB::new();
+        let loc = (3, 3, 1);
+        let rubble = Item::rubble(&mut game_obj_db, loc);
+        game_obj_db.add(rubble);
+        let torch = Item::get_item(&mut game_obj_db, "torch", &mut GameRng::seeded(1)).unwrap();
+        let torch_id = torch.obj_id();
+        game_obj_db.add(torch);
+        assert!(game_obj_db.set_to_loc(torch_id, (3, 4, 1)));
+
+        assert!(game_obj_db.obstacle_at(&loc));
+        assert!(!game_obj_db.obstacle_at(&(3, 4, 1)));
+        assert!(!game_obj_db.obstacle_at(&(9, 9, 1)));
+    }
+
+    #[test]
+    fn only_monsters_flagged_to_leave_corpses_do() {
+        let mf = MonsterFactory::init();
+        let mut rng = GameRng::seeded(1);
+        let mut game_obj_db = GameObjectDB::new();
+        let loc = (10, 10, 1);
+        for _ in 0..20 {
+            mf.monster("zombie", loc, &mut game_obj_db, &mut rng);
+            let id = *game_obj_db.obj_locs[&loc].iter().last().unwrap();
+            game_obj_db.npc(id).unwrap().alive = false;
+            game_obj_db.check_for_dead_npcs(&mut rng);
+        }
+
+        assert_eq!(items_at(&game_obj_db, loc), 0);
+
+        // Rats do leave corpses, at least some of the time...
+        let rat_loc = (12, 12, 1);
+        for _ in 0..20 {
+            mf.monster("dire rat", rat_loc, &mut game_obj_db, &mut rng);
+            let id = game_obj_db.npc_at(&rat_loc).unwrap();
+            game_obj_db.npc(id).unwrap().alive = false;
+            game_obj_db.check_for_dead_npcs(&mut rng);
+        }
+        assert!(items_at(&game_obj_db, rat_loc) > 0);
+
+        // ...but an illusion of one never does
+        let illusion_loc = (14, 14, 1);
+        for _ in 0..20 {
+            mf.monster("dire rat", illusion_loc, &mut game_obj_db, &mut rng);
+            let id = game_obj_db.npc_at(&illusion_loc).unwrap();
+            let illusion = game_obj_db.npc(id).unwrap();
+            effects::add_status(illusion, Status::FadeAfter, 100);
+            illusion.alive = false;
+            game_obj_db.check_for_dead_npcs(&mut rng);
+        }
+        assert_eq!(items_at(&game_obj_db, illusion_loc), 0);
+    }
+
+    #[test]
+    fn creatures_cant_be_moved_onto_each_other() {
+        let mf = MonsterFactory::init();
+        let mut rng = GameRng::seeded(1);
+        let mut game_obj_db = GameObjectDB::new();
+        mf.monster("zombie", (5, 5, 1), &mut game_obj_db, &mut rng);
+        mf.monster("zombie", (5, 6, 1), &mut game_obj_db, &mut rng);
+        let second = game_obj_db.npc_at(&(5, 6, 1)).unwrap();
+
+        assert!(!game_obj_db.set_to_loc(second, (5, 5, 1)));
+        assert_eq!(game_obj_db.get(second).unwrap().get_loc(), (5, 6, 1));
+        assert!(game_obj_db.set_to_loc(second, (5, 7, 1)));
+        assert_eq!(game_obj_db.npc_at(&(5, 7, 1)), Some(second));
+    }
+
+    // Not a timing benchmark (those are too noisy to assert on) but a count of the work a turn of
+    // monster moves does. Run with --nocapture to see the numbers.
+    #[test]
+    fn bench_lookups_per_npc_turn() {
+        let mut state = crate::tests::test_state();
+        for r in 0..20 {
+            for c in 0..40 {
+                state.map.insert((r, c, 1), Tile::StoneFloor);
+            }
+        }
+        let mf = MonsterFactory::init();
+        let mut rng = GameRng::seeded(1);
+        let mut game_obj_db = GameObjectDB::new();
+        Player::new_warrior(&mut game_obj_db, "Tester", crate::player::Race::Human, &mut rng);
+        assert!(game_obj_db.set_to_loc(0, (10, 20, 1)));
+        for j in 0..10 {
+            mf.monster("kobold", (2 + j, 3, 1), &mut game_obj_db, &mut rng);
+        }
+
+        let turns = 20;
+        let (hashed_before, player_before) = game_obj_db.lookup_counts();
+        for _ in 0..turns {
+            game_obj_db.do_npc_turns(&mut state);
+            state.turn += 1;
+        }
+        let (hashed_after, player_after) = game_obj_db.lookup_counts();
+        let hashed = (hashed_after - hashed_before) / turns;
+        let player = (player_after - player_before) / turns;
+
+        // Every monster checks up on the player, and none of that should touch the objects table.
+        // What's left is each monster fetching itself and a few neighbours, so keep it to a handful
+        let per_monster = hashed / 10;
+        assert!(player >= 10);
+        assert!(per_monster <= 10);
+    }
+
+    #[test]
+    fn food_only_rots_on_the_map() {
+        let mut game_obj_db = GameObjectDB::new();
+        let apple = Item::get_item(&mut game_obj_db, "apple", &mut GameRng::seeded(1)).unwrap();
+        let apple_id = apple.obj_id();
+        assert!(!game_obj_db.listeners.contains(&(apple_id, EventType::EndOfTurn)));
+
+        game_obj_db.add(apple);
+        assert!(game_obj_db.listeners.contains(&(apple_id, EventType::EndOfTurn)));
+
+        game_obj_db.remove(apple_id);
+        assert!(!game_obj_db.listeners.contains(&(apple_id, EventType::EndOfTurn)));
+    }
+}
@@ -0,0 +1,266 @@
+// This file is part of RogueVillage, a roguelike game.
+//
+// RogueVillage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RogueVillage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RogueVillage.  If not, see <https://www.gnu.org/licenses/>.
+
+// A UserInterface that never opens a window. Keystrokes and commands are queued up ahead of time
+// and everything the game would have put on screen is recorded instead, so that game logic can be
+// driven (and checked) without SDL.
+
+use std::collections::{HashSet, VecDeque};
+
+use crate::display::{SidebarInfo, UserInterface};
+use crate::game_obj::{GameObject, GameObjectDB};
+use crate::map::Tile;
+
+use super::{Cmd, GameState, FOV_HEIGHT, FOV_WIDTH};
+
+// The squares an effect covered and which effect it was
+type DrawnEffect = (Vec<(i32, i32, i8)>, u128);
+
+pub struct HeadlessUI {
+    keys: VecDeque<char>,
+    commands: VecDeque<Cmd>,
+    targets: VecDeque<(i32, i32, i8)>,
+    pub messages: Vec<String>,
+    pub screens: Vec<String>,
+    pub v_matrix: [(Tile, bool); FOV_HEIGHT * FOV_WIDTH],
+    pub effects_drawn: Vec<DrawnEffect>,
+}
+
+impl HeadlessUI {
+    pub fn new() -> HeadlessUI {
+        HeadlessUI {
+            keys: VecDeque::new(),
+            commands: VecDeque::new(),
+            targets: VecDeque::new(),
+            messages: Vec::new(),
+            screens: Vec::new(),
+            v_matrix: [(Tile::Blank, false); FOV_HEIGHT * FOV_WIDTH],
+            effects_drawn: Vec::new(),
+        }
+    }
+
+    pub fn queue_keys(&mut self, keys: &str) {
+        self.keys.extend(keys.chars());
+    }
+
+    pub fn queue_cmd(&mut self, cmd: Cmd) {
+        self.commands.push_back(cmd);
+    }
+
+    pub fn queue_target(&mut self, loc: (i32, i32, i8)) {
+        self.targets.push_back(loc);
+    }
+
+    // Running out of queued input behaves like the player hitting escape
+    fn next_key(&mut self) -> Option<char> {
+        self.keys.pop_front()
+    }
+
+    // Read queued keys up to a newline (or the end of the queue)
+    fn read_line(&mut self) -> String {
+        let mut s = String::from("");
+        while let Some(ch) = self.next_key() {
+            if ch == '\n' {
+                break;
+            }
+            s.push(ch);
+        }
+
+        s
+    }
+}
+
+impl UserInterface for HeadlessUI {
+    fn set_v_matrix(&mut self, v_matrix: [(Tile, bool); FOV_HEIGHT * FOV_WIDTH]) {
+        self.v_matrix = v_matrix;
+    }
+
+    fn query_yes_no(&mut self, question: &str, _sbi: Option<&SidebarInfo>) -> char {
+        self.screens.push(question.to_string());
+        loop {
+            match self.next_key() {
+                Some('y') => { return 'y'; },
+                Some('n') | None => { return 'n'; },
+                Some(_) => { continue; },
+            }
+        }
+    }
+
+    fn query_natural_num(&mut self, query: &str, _sbi: Option<&SidebarInfo>) -> Option<u32> {
+        self.screens.push(query.to_string());
+        self.read_line().trim().parse::<u32>().ok()
+    }
+
+    fn query_user(&mut self, question: &str, max: u8, _sbi: Option<&SidebarInfo>) -> Option<String> {
+        self.screens.push(question.to_string());
+        let answer: String = self.read_line().chars().take(max as usize).collect();
+        if answer.is_empty() {
+            None
+        } else {
+            Some(answer)
+        }
+    }
+
+    fn select_dir(&mut self, prompt: &str, _state: &GameState, game_obj_db: &mut GameObjectDB) -> Option<(i32, i32, i8)> {
+        self.screens.push(prompt.to_string());
+        let dir = loop {
+            match self.next_key() {
+                Some('h') => break (0, -1),
+                Some('j') => break (1, 0),
+                Some('k') => break (-1, 0),
+                Some('l') => break (0, 1),
+                Some('y') => break (-1, -1),
+                Some('u') => break (-1, 1),
+                Some('b') => break (1, -1),
+                Some('n') => break (1, 1),
+                Some(_) => continue,
+                None => return None,
+            }
+        };
+        let loc = game_obj_db.get(0).unwrap().get_loc();
+
+        Some((loc.0 + dir.0, loc.1 + dir.1, loc.2))
+    }
+
+    fn draw_effects(&mut self, _state: &GameState, _game_obj_db: &mut GameObjectDB, sqs_affected: &[(i32, i32, i8)], effect: u128) {
+        self.effects_drawn.push((sqs_affected.to_vec(), effect));
+    }
+
+    fn select_target(&mut self, _state: &GameState, _game_obj_db: &mut GameObjectDB, prompt: &str) -> Option<(i32, i32, i8)> {
+        self.screens.push(prompt.to_string());
+        self.targets.pop_front()
+    }
+
+    // Once the queued commands run out, the player quits
+    fn get_command(&mut self, _state: &GameState, _game_obj_db: &mut GameObjectDB) -> Cmd {
+        self.commands.pop_front().unwrap_or(Cmd::Quit)
+    }
+
+    fn pause_for_more(&mut self) { }
+
+    fn show_in_side_pane(&mut self, blurb: &str, lines: &[(String, bool)]) -> Option<char> {
+        let mut screen = blurb.to_string();
+        for line in lines {
+            screen.push('\n');
+            screen.push_str(&line.0);
+        }
+        self.screens.push(screen);
+        self.next_key()
+    }
+
+    fn write_long_msg(&mut self, lines: &[&str], _small_text: bool) {
+        self.screens.push(lines.join("\n"));
+    }
+
+    fn popup_menu(&mut self, title: &str, text: &str, options: &HashSet<char>, _sbi: Option<&SidebarInfo>) -> Option<char> {
+        self.screens.push(format!("{}\n{}", title, text));
+        while let Some(ch) = self.next_key() {
+            if options.contains(&ch) {
+                return Some(ch);
+            }
+        }
+
+        None
+    }
+
+    fn popup_msg(&mut self, title: &str, text: &str, _sbi: Option<&SidebarInfo>) -> Option<char> {
+        self.screens.push(format!("{}\n{}", title, text));
+        self.next_key()
+    }
+
+    fn show_message_history(&mut self) {
+        self.screens.push(self.messages.join("\n"));
+    }
+
+    // The last few messages, oldest first
+    fn recent_messages(&self, count: usize) -> Vec<String> {
+        let start = self.messages.len().saturating_sub(count);
+        self.messages[start..].to_vec()
+    }
+
+    fn clear_message_history(&mut self) {
+        self.messages.clear();
+    }
+
+    fn update(&mut self, msg_queue: &mut VecDeque<String>, _sbi: Option<&SidebarInfo>) {
+        self.messages.extend(msg_queue.drain(..));
+    }
+
+    fn side_pane_menu(&mut self, preamble: String, menu: &[(String, char)], single_choice: bool) -> Option<HashSet<char>> {
+        let mut screen = preamble;
+        for item in menu {
+            screen.push_str(&format!("\n{}) {}", item.1, item.0));
+        }
+        self.screens.push(screen);
+
+        let mut picked = HashSet::new();
+        while let Some(ch) = self.next_key() {
+            if ch == '\n' {
+                break;
+            }
+            if menu.iter().any(|item| item.1 == ch) {
+                picked.insert(ch);
+                if single_choice {
+                    break;
+                }
+            }
+        }
+
+        if picked.is_empty() {
+            None
+        } else {
+            Some(picked)
+        }
+    }
+
+    fn menu_wordy_picker(&mut self, menu: &[&str], answers: &HashSet<&char>) -> Option<char> {
+        self.screens.push(menu.join("\n"));
+        while let Some(ch) = self.next_key() {
+            if answers.contains(&ch) {
+                return Some(ch);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queued_keys_answer_prompts_in_order() {
+        let mut gui = HeadlessUI::new();
+        gui.queue_keys("xy42\nAlice\n");
+        assert_eq!(gui.query_yes_no("Really?", None), 'y');
+        assert_eq!(gui.query_natural_num("How many?", None), Some(42));
+        assert_eq!(gui.query_user("Who are you?", 3, None), Some("Ali".to_string()));
+        assert_eq!(gui.query_yes_no("Really?", None), 'n');
+        assert_eq!(gui.screens.len(), 4);
+    }
+
+    #[test]
+    fn messages_are_recorded() {
+        let mut gui = HeadlessUI::new();
+        let mut msgs = VecDeque::new();
+        msgs.push_back("Hello.".to_string());
+        msgs.push_back("Goodbye.".to_string());
+        gui.update(&mut msgs, None);
+        assert!(msgs.is_empty());
+        assert_eq!(gui.recent_messages(1), vec!["Goodbye.".to_string()]);
+        assert_eq!(gui.recent_messages(5).len(), 2);
+    }
+}
@@ -26,6 +26,7 @@ use crate::effects;
 use crate::fov;
 use crate::game_obj::{GameObject, GameObjectBase, GameObjectDB, GameObjects};
 use crate::map::Tile;
+use crate::util::GameRng;
 use std::u128;
 use rand::Rng;
 
@@ -37,6 +38,10 @@ pub const IA_HEAVY_ARMOUR: u128 = 0x00000004;
 pub const IA_CONSUMABLE: u128   = 0x00000010;
 pub const IA_TWO_HANDED: u128   = 0x00000020;
 pub const IA_IMMOBILE: u128     = 0x00000040;
+pub const IA_PERISHABLE: u128   = 0x00000080;
+pub const IA_WARM: u128         = 0x00000100;
+pub const IA_FIRE_RESISTANT: u128 = 0x00000200;
+pub const IA_UNIDENTIFIED: u128 = 0x00000400;
 
 #[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ItemType {
@@ -53,6 +58,26 @@ pub enum ItemType {
     Obstacle,
     Ammunition,
     Wand,
+    Cloak,
+    Material,
+    Trap,
+    Spellbook,
+    Bandage,
+}
+
+impl ItemType {
+    // The heading an item is grouped under when the inventory is sorted, along with where
+    // that group falls in the list
+    pub fn category(&self) -> (u8, &'static str) {
+        match self {
+            ItemType::Weapon | ItemType::Ammunition => (0, "Weapons"),
+            ItemType::Armour | ItemType::Shield | ItemType::Cloak => (1, "Armour"),
+            ItemType::Potion => (2, "Potions"),
+            ItemType::Scroll | ItemType::Spellbook => (3, "Scrolls"),
+            ItemType::Food => (4, "Food"),
+            _ => (5, "Miscellaneous"),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -77,16 +102,50 @@ pub struct Item {
     pub value: u16,
     pub effects: u128,
     pub item_dc: u8,
+    pub nutrition: u16,
+    pub freshness: u16, // turns left before perishable food goes off
 }
 
 impl Item {    
     fn new(object_id: usize, symbol: char, lit_colour: (u8, u8, u8), unlit_colour: (u8, u8, u8), name: &str, item_type: ItemType, weight: u8, stackable: bool, value: u16) -> Item {
 		Item { base_info: GameObjectBase::new(object_id, (-1, -1, -1), false, symbol, lit_colour, unlit_colour, false, name),
              item_type, weight, stackable, slot: '\0', dmg_die: 1, dmg_dice: 1, attack_bonus: 0, ac_bonus: 0, range: 0, equiped: false, 
-                attributes: 0, active: false, charges: 0, aura: 0, text: None, dmg_type: DamageType::Bludgeoning, value, effects: 0, item_dc: 10 }								
+                attributes: 0, active: false, charges: 0, aura: 0, text: None, dmg_type: DamageType::Bludgeoning, value, effects: 0, item_dc: 10,
+                nutrition: 0, freshness: 0 }								
 	}
     
-    pub fn get_item(game_obj_db: &mut GameObjectDB, name: &str) -> Option<GameObjects> {
+    pub fn corpse(game_obj_db: &mut GameObjectDB, monster_name: &str, size: u8) -> GameObjects {
+        let name = format!("{} corpse", monster_name);
+        let mut c = Item::new(game_obj_db.next_id(), '%', display::LIGHT_GREY, display::GREY, &name, ItemType::Food, size * 5, false, 0);
+        c.attributes |= IA_CONSUMABLE | IA_PERISHABLE;
+        c.nutrition = size as u16 * 150;
+        c.freshness = 250;
+
+        GameObjects::Item(c)
+    }
+
+    // Perishable food counts down until it rots, at which point it's no longer fit to eat
+    pub fn spoil(&mut self, state: &mut GameState, loc: (i32, i32, i8)) {
+        if self.attributes & IA_PERISHABLE == 0 {
+            return;
+        }
+
+        self.freshness = self.freshness.saturating_sub(1);
+        if self.freshness == 0 {
+            let s = if loc == PLAYER_INV {
+                format!("Your {} has rotted away.", self.base_info.name)
+            } else {
+                format!("The {} has rotted away.", self.base_info.name)
+            };
+            state.msg_queue.push_back(Message::new(self.obj_id(), loc, &s, ""));
+
+            self.attributes &= !IA_PERISHABLE;
+            self.nutrition = 0;
+            self.base_info.name = format!("rotten {}", self.base_info.name);
+        }
+    }
+
+    pub fn get_item(game_obj_db: &mut GameObjectDB, name: &str, rng: &mut GameRng) -> Option<GameObjects> {
         match name {
             "longsword" => {
                 let mut i = Item::new(game_obj_db.next_id(), ')',display::WHITE, display::GREY, name, ItemType::Weapon, 3, false, 15);
@@ -152,12 +211,43 @@ impl Item {
                 
                 Some(GameObjects::Item(i))
             },
+            "plate armour" => {
+                let mut i = Item::new(game_obj_db.next_id(), '[',display::WHITE, display::GREY, name, ItemType::Armour, 25, false, 150);
+                i.ac_bonus = 7;
+                i.attributes |= IA_HEAVY_ARMOUR;
+                
+                Some(GameObjects::Item(i))
+            },
             "shield" => {
                 let mut i = Item::new(game_obj_db.next_id(), '[',display::GREY, display::DARK_GREY, name, ItemType::Shield, 5, false, 10);
                 i.ac_bonus = 1;
                 
                 Some(GameObjects::Item(i))
             },         
+            "buckler" => {
+                let mut i = Item::new(game_obj_db.next_id(), '[',display::BROWN, display::DARK_BROWN, name, ItemType::Shield, 2, false, 8);
+                i.ac_bonus = 1;
+                
+                Some(GameObjects::Item(i))
+            },
+            "tower shield" => {
+                let mut i = Item::new(game_obj_db.next_id(), '[',display::GREY, display::DARK_GREY, name, ItemType::Shield, 12, false, 40);
+                i.ac_bonus = 2;
+                
+                Some(GameObjects::Item(i))
+            },
+            "warm cloak" => {
+                let mut i = Item::new(game_obj_db.next_id(), '[',display::BROWN, display::DARK_BROWN, name, ItemType::Cloak, 2, false, 10);
+                i.attributes |= IA_WARM;
+                
+                Some(GameObjects::Item(i))
+            },
+            "cloak of fire resistance" => {
+                let mut i = Item::new(game_obj_db.next_id(), '[',display::BRIGHT_RED, display::DULL_RED, name, ItemType::Cloak, 2, false, 75);
+                i.attributes |= IA_FIRE_RESISTANT;
+                
+                Some(GameObjects::Item(i))
+            },
             "torch" => {
                 let mut i = Item::new(game_obj_db.next_id(), '(',display::LIGHT_BROWN, display::BROWN, name, ItemType::Light, 1, true, 1);
                 i.charges = 1000;
@@ -171,11 +261,24 @@ impl Item {
 
                 Some(GameObjects::Item(w))
             },
+            "waterskin" => {
+                let mut w = Item::new(game_obj_db.next_id(), '(',display::LIGHT_BROWN, display::BROWN, name, ItemType::Bottle, 1, false, 2);
+                w.charges = 2;
+
+                Some(GameObjects::Item(w))
+            },
             "note" => {
                 let i = Item::new(game_obj_db.next_id(), '?',display::WHITE, display::LIGHT_GREY, name, ItemType::Note, 0, false, 0);
                 
                 Some(GameObjects::Item(i))
             },
+            "bandage" => {
+                let mut i = Item::new(game_obj_db.next_id(), '~',display::WHITE, display::LIGHT_GREY, name, ItemType::Bandage, 0, true, 3);
+                i.attributes |= IA_CONSUMABLE;
+                i.effects |= effects::EF_STANCH;
+                
+                Some(GameObjects::Item(i))
+            },
             "potion of healing" => {
                 let mut i = Item::new(game_obj_db.next_id(), '!',display::WHITE, display::LIGHT_GREY, name, ItemType::Potion, 2, true, 10);
                 i.attributes |= IA_CONSUMABLE;
@@ -183,6 +286,20 @@ impl Item {
                 
                 Some(GameObjects::Item(i))
             },
+            "potion of telepathy" => {
+                let mut i = Item::new(game_obj_db.next_id(), '!',display::PURPLE, display::PINK, name, ItemType::Potion, 2, true, 25);
+                i.attributes |= IA_CONSUMABLE;
+                i.effects |= effects::EF_TELEPATHY;
+                
+                Some(GameObjects::Item(i))
+            },
+            "murky potion" => {
+                let mut i = Item::new(game_obj_db.next_id(), '!',display::BROWN, display::DARK_BROWN, name, ItemType::Potion, 2, true, 0);
+                i.attributes |= IA_CONSUMABLE;
+                i.effects |= effects::EF_WEAK_VENOM;
+                
+                Some(GameObjects::Item(i))
+            },
             "potion of levitation" => {
                 let mut i = Item::new(game_obj_db.next_id(), '!',display::WHITE, display::LIGHT_GREY, name, ItemType::Potion, 2, true, 10);
                 i.attributes |= IA_CONSUMABLE;
@@ -190,6 +307,20 @@ impl Item {
                 
                 Some(GameObjects::Item(i))
             },
+            "potion of invisibility" => {
+                let mut i = Item::new(game_obj_db.next_id(), '!',display::WHITE, display::LIGHT_GREY, name, ItemType::Potion, 2, true, 25);
+                i.attributes |= IA_CONSUMABLE;
+                i.effects |= effects::EF_INVISIBILITY;
+                
+                Some(GameObjects::Item(i))
+            },
+            "potion of speed" => {
+                let mut i = Item::new(game_obj_db.next_id(), '!',display::WHITE, display::LIGHT_GREY, name, ItemType::Potion, 2, true, 25);
+                i.attributes |= IA_CONSUMABLE;
+                i.effects |= effects::EF_HASTE;
+                
+                Some(GameObjects::Item(i))
+            },
             "scroll of protection" => {
                 let mut i = Item::new(game_obj_db.next_id(), '?',display::WHITE, display::LIGHT_GREY, name, ItemType::Scroll, 1, true, 20);
                 i.attributes |= IA_CONSUMABLE;
@@ -197,6 +328,60 @@ impl Item {
                 
                 Some(GameObjects::Item(i))
             },
+            "scroll of detect monsters" => {
+                let mut i = Item::new(game_obj_db.next_id(), '?',display::WHITE, display::LIGHT_GREY, name, ItemType::Scroll, 1, true, 15);
+                i.attributes |= IA_CONSUMABLE;
+                i.effects |= effects::EF_DETECT_MONSTERS;
+                
+                Some(GameObjects::Item(i))
+            },
+            "scroll of treasure detection" => {
+                let mut i = Item::new(game_obj_db.next_id(), '?',display::WHITE, display::LIGHT_GREY, name, ItemType::Scroll, 1, true, 15);
+                i.attributes |= IA_CONSUMABLE;
+                i.effects |= effects::EF_DETECT_TREASURE;
+                
+                Some(GameObjects::Item(i))
+            },
+            "scroll of magic mapping" => {
+                let mut i = Item::new(game_obj_db.next_id(), '?',display::WHITE, display::LIGHT_GREY, name, ItemType::Scroll, 1, true, 25);
+                i.attributes |= IA_CONSUMABLE;
+                i.effects |= effects::EF_MAGIC_MAPPING;
+                
+                Some(GameObjects::Item(i))
+            },
+            "scroll of recall" => {
+                let mut i = Item::new(game_obj_db.next_id(), '?',display::WHITE, display::LIGHT_GREY, name, ItemType::Scroll, 1, true, 30);
+                i.attributes |= IA_CONSUMABLE;
+                i.effects |= effects::EF_RECALL;
+                
+                Some(GameObjects::Item(i))
+            },
+            "scroll of descent" => {
+                let mut i = Item::new(game_obj_db.next_id(), '?',display::WHITE, display::LIGHT_GREY, name, ItemType::Scroll, 1, true, 20);
+                i.attributes |= IA_CONSUMABLE;
+                i.effects |= effects::EF_DESCENT;
+                
+                Some(GameObjects::Item(i))
+            },
+            "scroll of gust of wind" => {
+                let mut i = Item::new(game_obj_db.next_id(), '?',display::WHITE, display::LIGHT_GREY, name, ItemType::Scroll, 1, true, 15);
+                i.attributes |= IA_CONSUMABLE;
+                i.effects |= effects::EF_GUST_OF_WIND;
+                
+                Some(GameObjects::Item(i))
+            },
+            "spellbook of magic missile" | "spellbook of blink" | "spellbook of shield" | "spellbook of haste" => {
+                let i = Item::new(game_obj_db.next_id(), '+', display::LIGHT_PURPLE, display::PURPLE, name, ItemType::Spellbook, 3, false, 50);
+                
+                Some(GameObjects::Item(i))
+            },
+            "scroll of darkness" => {
+                let mut i = Item::new(game_obj_db.next_id(), '?',display::WHITE, display::LIGHT_GREY, name, ItemType::Scroll, 1, true, 15);
+                i.attributes |= IA_CONSUMABLE;
+                i.effects |= effects::EF_DARKNESS;
+                
+                Some(GameObjects::Item(i))
+            },
             "scroll of blink" => {
                 let mut i = Item::new(game_obj_db.next_id(), '?',display::WHITE, display::LIGHT_GREY, name, ItemType::Scroll, 1, true, 20);
                 i.attributes |= IA_CONSUMABLE;
@@ -212,20 +397,61 @@ impl Item {
                 
                 Some(GameObjects::Item(a))
             },
+            "lump of iron ore" => {
+                let o = Item::new(game_obj_db.next_id(), '*', display::GREY, display::DARK_GREY, name, ItemType::Material, 3, true, 5);
+
+                Some(GameObjects::Item(o))
+            },
+            "bear trap" => {
+                let t = Item::new(game_obj_db.next_id(), '^', display::GREY, display::DARK_GREY, name, ItemType::Trap, 8, false, 20);
+
+                Some(GameObjects::Item(t))
+            },
+            "caltrops" => {
+                let t = Item::new(game_obj_db.next_id(), '^', display::GREY, display::DARK_GREY, name, ItemType::Trap, 1, true, 5);
+
+                Some(GameObjects::Item(t))
+            },
             "piece of mushroom" => {
                 let mut m = Item::new(game_obj_db.next_id(), '%', display::LIGHT_BLUE, display::BLUE, name, ItemType::Food, 0, true, 0);
                 m.attributes |= IA_CONSUMABLE;
+                m.nutrition = 25;
 
                 Some(GameObjects::Item(m))
             },
+            "ration" => {
+                let mut r = Item::new(game_obj_db.next_id(), '%', display::BROWN, display::DARK_BROWN, name, ItemType::Food, 1, true, 3);
+                r.attributes |= IA_CONSUMABLE;
+                r.nutrition = 800;
+
+                Some(GameObjects::Item(r))
+            },
+            "apple" => {
+                let mut a = Item::new(game_obj_db.next_id(), '%', display::BRIGHT_RED, display::DULL_RED, name, ItemType::Food, 0, true, 1);
+                a.attributes |= IA_CONSUMABLE | IA_PERISHABLE;
+                a.nutrition = 150;
+                a.freshness = 2000;
+
+                Some(GameObjects::Item(a))
+            },
             "wand of frost" => {
                 let mut w = Item::new(game_obj_db.next_id(), '-', display::WHITE, display::LIGHT_GREY, name, ItemType::Wand, 1, false, 150);
                 w.effects |= effects::EF_FROST;
-                w.charges = rand::thread_rng().gen_range(4, 9);
+                w.charges = rng.gen_range(4, 9);
                 w.range = 8;
+                w.attributes |= IA_UNIDENTIFIED;
 
                 Some(GameObjects::Item(w))
             }
+            "wand of slow monster" => {
+                let mut w = Item::new(game_obj_db.next_id(), '-', display::WHITE, display::LIGHT_GREY, name, ItemType::Wand, 1, false, 100);
+                w.effects |= effects::EF_SLOW;
+                w.charges = rng.gen_range(4, 9);
+                w.range = 8;
+                w.attributes |= IA_UNIDENTIFIED;
+
+                Some(GameObjects::Item(w))
+            },
             _ => None,
         }
     }
@@ -247,8 +473,8 @@ impl Item {
         GameObjects::Item(rubble)
     }
 
-    pub fn mushroom(game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8)) {
-        let roll = rand::thread_rng().gen_range(0.0, 1.0);
+    pub fn mushroom(game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8), rng: &mut GameRng) {
+        let roll = rng.gen_range(0.0, 1.0);
         let (lit_colour, colour) = if roll < 0.33 {
             (display::GREEN, display::DARK_GREEN)
         } else if roll < 0.66 {
@@ -274,6 +500,7 @@ impl Item {
                 ItemType::Weapon =>  String::from("(in hand)"),
                 ItemType::Armour => String::from("(being worn)"),
                 ItemType::Shield => String::from("(on your arm)"),
+                ItemType::Cloak => String::from("(being worn)"),
                 _ => "".to_string(),
             }        
         } else if self.active {
@@ -286,11 +513,18 @@ impl Item {
             } else {
                 String::from("(empty)")
             };      
+        } else if self.item_type == ItemType::Wand && self.attributes & IA_UNIDENTIFIED == 0 {
+            return format!("({} charges)", self.charges);
         }
 
 		"".to_string()
     }
 
+    // Shown alongside the item in inventory and pickup menus
+    pub fn value_and_weight(value: u32, weight: u32) -> String {
+        format!("[{}gp, wt {}]", value, weight)
+    }
+
     pub fn equip(&mut self) {
         self.equiped = true;
     }
@@ -300,13 +534,14 @@ impl Item {
     }
 
     pub fn equipable(&self) -> bool {
-        matches!(self.item_type, ItemType::Armour | ItemType::Weapon | ItemType::Shield)
+        matches!(self.item_type, ItemType::Armour | ItemType::Weapon | ItemType::Shield | ItemType::Cloak)
     }
 
     pub fn useable(&self) -> bool {
         self.item_type == ItemType::Light || self.item_type == ItemType::Potion ||
             self.item_type == ItemType::Scroll || self.item_type == ItemType::Food ||
-            self.item_type == ItemType::Wand
+            self.item_type == ItemType::Wand || self.item_type == ItemType::Trap ||
+            self.item_type == ItemType::Spellbook || self.item_type == ItemType::Bandage
     }
 
     pub fn stackable(&self) -> bool {
@@ -324,7 +559,13 @@ impl Item {
 			loc
 		};
 
-		let lit = fov::calc_fov(state, location, self.aura, true);
+		// Some of the deeper dungeon levels are so dark they swallow some of the light
+		let radius = if location.2 > 0 && state.world_info.level_has("darkness", location.2) {
+			self.aura.saturating_sub(2).max(1)
+		} else {
+			self.aura
+		};
+		let lit = fov::calc_fov(state, location, radius, true);
 		for sq in lit {
             state.lit_sqs.entry(sq).or_insert(colour);
 		}		
@@ -376,9 +617,8 @@ impl GameObject for Item {
             EventType::Update => {
                 // right now light sources are the only things in the game which times like this
 				// This'll mark squares that are lit independent of the player's vision. Don't bother
-				// with the calculation if the light source is on another level of the dungeon
-                // Note this currently isn't working for a monster carrying a lit light source (or, eventually
-                // other items that may have charges)
+				// with the calculation if the light source is on another level of the dungeon. (A light
+                // carried by a monster has its location kept up to date with the monster's)
                 if self.charges > 0 && (loc == PLAYER_INV || self.get_loc().2 == player_loc.2) {
                     let colour = if self.base_info.name == "torch" {
                         display::WHITE
@@ -387,6 +627,9 @@ impl GameObject for Item {
                     };
                     self.mark_lit_sqs(state, loc, player_loc, colour);
 				}                
+            },
+			EventType::EndOfTurn if self.item_type == ItemType::Food => {
+                self.spoil(state, loc);
             },
 			EventType::EndOfTurn => {
 				self.charges -= 1;
@@ -488,3 +731,29 @@ impl GameObject for GoldPile {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value_and_weight_labels() {
+        assert_eq!(Item::value_and_weight(0, 0), "[0gp, wt 0]");
+        assert_eq!(Item::value_and_weight(60, 3), "[60gp, wt 3]");
+    }
+
+    #[test]
+    fn items_fall_into_menu_categories() {
+        let mut game_obj_db = GameObjectDB::new();
+        let category = |db: &mut GameObjectDB, name: &str| match Item::get_item(db, name, &mut GameRng::seeded(1)) {
+            Some(GameObjects::Item(i)) => i.item_type.category().1,
+            _ => panic!("no such item: {}", name),
+        };
+        assert_eq!(category(&mut game_obj_db, "dagger"), "Weapons");
+        assert_eq!(category(&mut game_obj_db, "ringmail"), "Armour");
+        assert_eq!(category(&mut game_obj_db, "potion of healing"), "Potions");
+        assert_eq!(category(&mut game_obj_db, "scroll of blink"), "Scrolls");
+        assert_eq!(category(&mut game_obj_db, "ration"), "Food");
+        assert_eq!(category(&mut game_obj_db, "torch"), "Miscellaneous");
+    }
+}
@@ -0,0 +1,232 @@
+// This file is part of RogueVillage, a roguelike game.
+//
+// RogueVillage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RogueVillage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RogueVillage.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{GameState, Message};
+use crate::display::UserInterface;
+use crate::effects;
+use crate::game_obj::{Ability, GameObject, GameObjectDB, Person};
+use crate::player::Role;
+use crate::util;
+
+const MAGIC_MISSILE_RANGE: usize = 8;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Spell {
+    MagicMissile,
+    Blink,
+    Shield,
+    Haste,
+}
+
+pub const SPELLBOOKS: [&str; 4] = ["spellbook of magic missile", "spellbook of blink", "spellbook of shield", "spellbook of haste"];
+
+impl Spell {
+    pub fn name(&self) -> &str {
+        match self {
+            Spell::MagicMissile => "magic missile",
+            Spell::Blink => "blink",
+            Spell::Shield => "shield",
+            Spell::Haste => "haste",
+        }
+    }
+
+    pub fn mana_cost(&self) -> u8 {
+        match self {
+            Spell::MagicMissile => 2,
+            Spell::Blink => 3,
+            Spell::Shield => 4,
+            Spell::Haste => 6,
+        }
+    }
+
+    pub fn level(&self) -> u8 {
+        match self {
+            Spell::MagicMissile | Spell::Blink => 1,
+            Spell::Shield => 2,
+            Spell::Haste => 3,
+        }
+    }
+
+    pub fn from_book(name: &str) -> Option<Spell> {
+        match name {
+            "spellbook of magic missile" => Some(Spell::MagicMissile),
+            "spellbook of blink" => Some(Spell::Blink),
+            "spellbook of shield" => Some(Spell::Shield),
+            "spellbook of haste" => Some(Spell::Haste),
+            _ => None,
+        }
+    }
+}
+
+// Studying a spellbook takes a while. First level spells are simple enough that any mage can pick them
+// up but the more advanced ones need an Aptitude check, and a botched attempt ruins the book.
+pub fn read_spellbook(state: &mut GameState, game_obj_db: &mut GameObjectDB, book_id: usize, book_name: &str) -> f32 {
+    let spell = match Spell::from_book(book_name) {
+        Some(spell) => spell,
+        None => return 0.0,
+    };
+
+    let player = game_obj_db.player().unwrap();
+    if player.role != Role::Mage {
+        state.msg_queue.push_back(Message::info("You can make no sense of the arcane scribbles."));
+        return 1.0;
+    }
+
+    if player.known_spells.contains(&spell) {
+        let s = format!("You already know the {} spell.", spell.name());
+        state.msg_queue.push_back(Message::info(&s));
+        return 0.0;
+    }
+
+    if spell.level() > 1 && player.ability_check(Ability::Apt, &mut state.rng) < 8 + 3 * spell.level() {
+        state.msg_queue.push_back(Message::info("The words slip from your grasp and the pages crumble to dust!"));
+    } else {
+        let s = format!("You learn the {} spell!", spell.name());
+        state.msg_queue.push_back(Message::info(&s));
+        player.known_spells.push(spell);
+    }
+    player.inv_remove(book_id);
+
+    3.0
+}
+
+// Returns false if the player backed out of picking a target
+fn magic_missile(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) -> bool {
+    let player_loc = game_obj_db.get(0).unwrap().get_loc();
+    let target = match gui.select_target(state, game_obj_db, "Select target:") {
+        Some(loc) => loc,
+        None => return false,
+    };
+
+    // The missile flies until it hits someone or something solid
+    let mut path = Vec::new();
+    for pt in util::bresenham(player_loc.0, player_loc.1, target.0, target.1).iter().skip(1).take(MAGIC_MISSILE_RANGE) {
+        let loc = (pt.0, pt.1, player_loc.2);
+        if !state.map.contains_key(&loc) || state.map[&loc].solid() {
+            break;
+        }
+        path.push(loc);
+        if game_obj_db.person_at(loc).is_some() {
+            break;
+        }
+    }
+
+    if path.is_empty() {
+        return false;
+    }
+
+    state.msg_queue.push_back(Message::info("A glowing dart streaks from your fingertips!"));
+    effects::break_invisibility(state, game_obj_db);
+    gui.draw_effects(state, game_obj_db, &path, 0);
+    effects::magic_missile(state, game_obj_db, *path.last().unwrap());
+
+    true
+}
+
+pub fn cast(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) -> f32 {
+    let sbi = state.curr_sidebar_info(game_obj_db);
+    let player = game_obj_db.player().unwrap();
+
+    if player.known_spells.is_empty() {
+        state.msg_queue.push_back(Message::info("You don't know any spells."));
+        return 0.0;
+    }
+
+    let mut text = format!("Cast which spell? (Mana: {}/{})\n", player.curr_mana, player.max_mana);
+    let mut options = HashSet::new();
+    let mut slot = 'a';
+    for spell in player.known_spells.iter() {
+        let s = format!("\n{}) {} ({} mana)", slot, spell.name(), spell.mana_cost());
+        text.push_str(&s);
+        options.insert(slot);
+        slot = (slot as u8 + 1) as char;
+    }
+
+    let spell = match gui.popup_menu("Spells", &text, &options, Some(&sbi)) {
+        Some(ch) => game_obj_db.player().unwrap().known_spells[(ch as u8 - b'a') as usize],
+        None => {
+            state.msg_queue.push_back(Message::info("Never mind."));
+            return 0.0;
+        },
+    };
+
+    if game_obj_db.player().unwrap().curr_mana < spell.mana_cost() {
+        state.msg_queue.push_back(Message::info("You don't have enough mana to cast that."));
+        return 0.0;
+    }
+
+    let player = game_obj_db.player().unwrap();
+    if state.rng.gen_range(0.0, 1.0) < player.spell_failure_chance() {
+        player.curr_mana -= spell.mana_cost();
+        state.msg_queue.push_back(Message::info("Your armour hampers your gestures and the spell fizzles!"));
+        return 1.0;
+    }
+
+    match spell {
+        Spell::MagicMissile => {
+            if !magic_missile(state, game_obj_db, gui) {
+                state.msg_queue.push_back(Message::info("Never mind."));
+                return 0.0;
+            }
+        },
+        Spell::Blink => effects::apply_effects(state, 0, game_obj_db, effects::EF_BLINK),
+        Spell::Shield => effects::apply_effects(state, 0, game_obj_db, effects::EF_PROTECTION),
+        Spell::Haste => effects::apply_effects(state, 0, game_obj_db, effects::EF_HASTE),
+    }
+
+    let player = game_obj_db.player().unwrap();
+    player.curr_mana -= spell.mana_cost();
+
+    1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::HeadlessUI;
+    use crate::npc::MonsterFactory;
+    use crate::player::{Player, Race};
+
+    #[test]
+    fn magic_missile_costs_mana_and_hurts_the_target() {
+        let mut scratch_db = GameObjectDB::new();
+        let mut state = crate::tests::test_level(&mut scratch_db);
+        let mut game_obj_db = GameObjectDB::new();
+        Player::new_mage(&mut game_obj_db, "Tester", Race::Human, &mut state.rng);
+        assert!(game_obj_db.set_to_loc(0, (5, 5, 1)));
+        let player = game_obj_db.player().unwrap();
+        player.max_mana = 5;
+        player.curr_mana = 5;
+
+        let mf = MonsterFactory::init();
+        mf.monster("kobold", (5, 8, 1), &mut game_obj_db, &mut state.rng);
+        let kobold_id = game_obj_db.npc_at(&(5, 8, 1)).unwrap();
+        game_obj_db.npc(kobold_id).unwrap().curr_hp = 50;
+
+        let mut gui = HeadlessUI::new();
+        gui.queue_keys("a");
+        gui.queue_target((5, 8, 1));
+        assert_eq!(cast(&mut state, &mut game_obj_db, &mut gui), 1.0);
+
+        assert_eq!(game_obj_db.player().unwrap().curr_mana, 5 - Spell::MagicMissile.mana_cost());
+        assert!(game_obj_db.npc(kobold_id).unwrap().curr_hp < 50);
+        assert_eq!(gui.effects_drawn[0].0.last(), Some(&(5, 8, 1)));
+    }
+}
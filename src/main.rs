@@ -15,22 +15,28 @@
 
 #![allow(dead_code)]
 
+extern crate flate2;
 extern crate rand;
 extern crate sdl2;
 extern crate serde;
 
 mod battle;
+mod crafting;
 mod dialogue;
 mod display;
 mod dungeon;
 mod effects;
 mod game_obj;
+mod headless;
 mod fov;
 mod items;
+mod magic;
 mod map;
 mod npc;
 mod pathfinding;
 mod player;
+mod religion;
+mod scores;
 mod shops;
 mod town;
 mod util;
@@ -38,33 +44,40 @@ mod wilderness;
 mod world;
 
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
 use std::io::prelude::*;
 use std::fs;
 use std::fs::File;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::path::Path;
 
 use std::time::Instant;
 
-use rand::{Rng, prelude::SliceRandom, thread_rng};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use rand::{Rng, prelude::{IteratorRandom, SliceRandom}};
 use serde::{Serialize, Deserialize};
 
 use battle::DamageType;
 use dialogue::DialogueLibrary;
-use display::{Colour, GameUI, SidebarInfo, WHITE};
+use display::{Colour, GameUI, SidebarInfo, UserInterface, WHITE};
 use effects::{HasStatuses, Status};
 use game_obj::{Ability, GameObject, GameObjectDB, GameObjects, Person};
 use items::{GoldPile, IA_CONSUMABLE, IA_IMMOBILE, Item, ItemType};
-use map::{DoorState, ShrineType, Tile};
+use map::{tile_at, DoorState, ShrineType, SpecialSquare, Tile};
 use npc::{Attitude, MA_WEBSLINGER, MonsterFactory, Venue};
-use player::{Player};
-use util::StringUtils;
+use player::{Player, Race};
+use town::{TownOptions, TownSize};
+use util::{GameRng, StringUtils};
 use world::WorldInfo;
 
 const MSG_HISTORY_LENGTH: usize = 50;
 const FOV_WIDTH: usize = 41;
 const FOV_HEIGHT: usize = 21;
 const PLAYER_INV: (i32, i32, i8) = (-999, -999, -128);
+const SCENT_TRAIL_LENGTH: usize = 25;
+const THROW_RANGE: usize = 6;
 
 pub type Map = HashMap<(i32, i32, i8), map::Tile>;
 
@@ -100,14 +113,38 @@ pub enum EventType {
     PlayerKilled,
     LevelUp,
     TrapRevealed,
+    TrapSprung,
     DeathOf(usize),
 }
 
+// Events queued up during a turn to be resolved at the end of it (in check_event_queue)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GameEvent {
+    pub event_type: EventType,
+    pub loc: (i32, i32, i8),
+    pub obj_id: usize,
+    pub msg: Option<String>,
+}
+
+impl GameEvent {
+    pub fn new(event_type: EventType, loc: (i32, i32, i8), obj_id: usize) -> GameEvent {
+        GameEvent { event_type, loc, obj_id, msg: None }
+    }
+
+    pub fn with_msg(event_type: EventType, loc: (i32, i32, i8), obj_id: usize, msg: &str) -> GameEvent {
+        GameEvent { event_type, loc, obj_id, msg: Some(String::from(msg)) }
+    }
+}
+
+#[derive(Clone)]
 pub enum Cmd { 
     Bash((i32, i32, i8)),
+    Cast,
     Chat((i32, i32, i8)),    
     Close((i32, i32, i8)),
+    Craft,
     Down,
+    Drink,
     DropItem,
     Help,    
     Move(String),
@@ -115,21 +152,69 @@ pub enum Cmd {
     Open((i32, i32, i8)),
     Pass,
     PickUp,
+    Pray,
     Quit,
+    Repeat,
+    Run(String),
     Save,
     Search,
+    ShowJournal,
+    WriteNote,
     ShowCharacterSheet,
+    ShowEquipment,
     ShowInventory,
+    Throw,
     ToggleEquipment,
+    Travel,
     Up,
     Use,
     WizardCommand,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AutoPickup {
+    Gold,
+    Item(ItemType),
+}
+
+pub const TURNS_PER_MINUTE: u32 = 6;
+pub const TURNS_PER_HOUR: u32 = TURNS_PER_MINUTE * 60;
+pub const TURNS_PER_DAY: u32 = TURNS_PER_HOUR * 24;
+const DEFAULT_START_HOUR: u32 = 8;
+const DEFAULT_AUTOSAVE_INTERVAL: u32 = 500;
+const MORGUE_MSG_COUNT: usize = 20;
+
 #[derive(Debug)]
 pub struct ConfigOptions {
     font_size: u16,
     sm_font_size: u16,
+    thirst: bool,
+    autopickup: Vec<AutoPickup>,
+    start_hour: u32,
+    town: TownOptions,
+    dungeon_depth: u8,
+    saves: SaveOptions,
+}
+
+// The choices from the options file about how the game is saved. These are
+// settings for the session rather than part of the game itself so they are
+// never written into the save file.
+#[derive(Debug, Clone, Copy)]
+pub struct SaveOptions {
+    autosave_interval: u32, // turns between autosaves, 0 to disable
+    compress: bool, // gzip save files
+}
+
+impl Default for SaveOptions {
+    fn default() -> SaveOptions {
+        SaveOptions { autosave_interval: DEFAULT_AUTOSAVE_INTERVAL, compress: false }
+    }
+}
+
+impl SaveOptions {
+    fn autosave_due(&self, turn: u32) -> bool {
+        self.autosave_interval > 0 && turn.is_multiple_of(self.autosave_interval)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -149,6 +234,66 @@ impl Message {
     pub fn info(text: &str) -> Message {
         Message { obj_id: 0, loc: (-1, -1, -1), text: String::from(text), alt_text: String::from("") }
     }
+
+    // What (if anything) the player should be told. If they can't see where the message came from,
+    // they get the alt text (ie., "You hear a scream.") as long as it happened nearby.
+    // I don't know about the distance calculation for noise. A floodfill like I'm doing
+    // to alert monsters is probably better but more complicated.
+    fn text_for_player(self, visible: &HashSet<(i32, i32, i8)>, player_loc: (i32, i32, i8)) -> Option<String> {
+        if visible.contains(&self.loc) || (self.obj_id == 0 && self.loc == (-1, -1, -1)) {
+            Some(self.text)
+        } else if !self.alt_text.is_empty() && self.loc.2 == player_loc.2
+                    && util::distance(player_loc.0, player_loc.1, self.loc.0, self.loc.1) < 12.0 {
+            Some(self.alt_text)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn monster_hp(&self, hp: u8) -> u8 {
+        let scaled = match self {
+            Difficulty::Easy => hp as u16 * 3 / 4,
+            Difficulty::Normal => hp as u16,
+            Difficulty::Hard => hp as u16 * 4 / 3,
+        };
+
+        scaled.clamp(1, 255) as u8
+    }
+
+    pub fn monster_dmg(&self, dmg: u8) -> u8 {
+        match self {
+            Difficulty::Easy => dmg - dmg / 4,
+            Difficulty::Normal => dmg,
+            Difficulty::Hard => dmg.saturating_add(dmg / 4 + 1),
+        }
+    }
+
+    // Multiplier on the odds of finding items in the dungeon and on monsters
+    pub fn loot_factor(&self) -> f64 {
+        match self {
+            Difficulty::Easy => 1.5,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 0.67,
+        }
+    }
+
+    // How many turns between the player naturally recovering HP
+    pub fn recovery_interval(&self) -> u32 {
+        match self {
+            Difficulty::Easy => 15,
+            Difficulty::Normal => 25,
+            Difficulty::Hard => 40,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -159,10 +304,31 @@ pub struct GameState {
     world_info: WorldInfo,
     tile_memory: HashMap<(i32, i32, i8), Tile>,
     lit_sqs: HashMap<(i32, i32, i8), Colour>, // by light sources independent of player
+    darkness: HashMap<(i32, i32, i8), u32>, // squares under magical darkness and the turn it lifts
+    favour: religion::Favour, // standing with the gods
+    spawn_timers: HashMap<i8, u32>, // the turn at which each dungeon level next gets a wandering monster
+    pending_followers: Vec<usize>, // monsters chasing the player up or down the stairs
+    scent_trail: VecDeque<(i32, i32, i8)>, // the player's most recent positions, freshest first
     aura_sqs: HashSet<(i32, i32, i8)>, // areas of special effects
-    queued_events: VecDeque<(EventType, (i32, i32, i8), usize, Option<String>)>, // events queue during a turn that should be resolved at the end of turn
+    queued_events: VecDeque<GameEvent>, // events queue during a turn that should be resolved at the end of turn
     animation_pause: bool,
     curr_visible: HashSet<(i32, i32, i8)>,
+    #[serde(skip)]
+    fov_cache: fov::FovCache,
+    detected: HashSet<(i32, i32, i8)>, // squares revealed by detection magic
+    detecting: u128,
+    detected_until: u32,
+    thirst: bool, // whether the player needs to drink (set in the options file)
+    difficulty: Difficulty,
+    autopickup: Vec<AutoPickup>,
+    sort_inventory: bool, // group the inventory view by category rather than by slot
+    stats: scores::GameStats,
+    journal: Vec<(String, String)>, // (timestamp, entry)
+    start_hour: u32,
+    #[serde(skip)]
+    last_cmd: Option<Cmd>,
+    #[serde(skip)]
+    rng: util::GameRng,
 }
 
 impl GameState {
@@ -174,10 +340,28 @@ impl GameState {
             world_info: world_info,
             tile_memory: HashMap::new(),
             lit_sqs: HashMap::new(),
+            darkness: HashMap::new(),
+            favour: religion::Favour::new(),
+            spawn_timers: HashMap::new(),
+            pending_followers: Vec::new(),
+            scent_trail: VecDeque::new(),
             aura_sqs: HashSet::new(),
             queued_events: VecDeque::new(),
             animation_pause: false,
             curr_visible: HashSet::new(),
+            fov_cache: fov::FovCache::default(),
+            detected: HashSet::new(),
+            detecting: 0,
+            detected_until: 0,
+            thirst: false,
+            difficulty: Difficulty::Normal,
+            autopickup: Vec::new(),
+            sort_inventory: false,
+            stats: scores::GameStats::new(),
+            journal: Vec::new(),
+            start_hour: DEFAULT_START_HOUR,
+            last_cmd: None,
+            rng: util::GameRng::default(),
         }
     }
 
@@ -193,26 +377,59 @@ impl GameState {
         let confused = player.has_status(Status::Confused);
         let paralyzed = player.has_status(Status::Paralyzed);
         let flying = player.has_status(Status::Flying);
+        let hasted = player.has_status(Status::Hasted);
+        let slowed = player.has_status(Status::Slowed);
+        let blind = player.has_status(Status::Blind);
+        let bleeding = player.has_status(Status::Bleeding);
+        let hungry = player.satiation <= player::SATIATION_HUNGRY;
+        let weak = player.satiation <= player::SATIATION_WEAK;
+        let thirsty = self.thirst && player.hydration <= player::HYDRATION_THIRSTY;
+        let parched = self.thirst && player.hydration <= player::HYDRATION_PARCHED;
         
-        SidebarInfo::new(player.get_fullname(), player.curr_hp, player.max_hp, self.turn, player.ac,
-            player.purse, weapon_name, loc.2 as u8, poisoned, confused, paralyzed, flying)
+        SidebarInfo { name: player.get_fullname(), curr_hp: player.curr_hp, max_hp: player.max_hp, curr_mana: player.curr_mana,
+            max_mana: player.max_mana, turn: self.turn, ac: player.ac, zorkmids: player.purse, weapon: weapon_name, curr_level: loc.2 as u8,
+            poisoned, confused, paralyzed, flying, hasted, slowed, blind, bleeding, hungry, weak, thirsty, parched }
     }
 
-    // I made life difficult for myself by deciding that Turn 0 of the game is 8:00am T_T
-    // 1 turn is 10 seconds (setting aside all concerns about realize and how the amount of stuff one
+    // 1 turn is 10 seconds (setting aside all concerns about realism and how the amount of stuff one
     // can do in 10 seconds will in no way correspond to one action in the game...) so an hour is 
-    // 360 turns
+    // 360 turns. Turn 0 is start_hour o'clock on the first day, so the clock is offset by that much.
+    fn clock_turn(&self) -> u32 {
+        self.turn + self.start_hour * TURNS_PER_HOUR
+    }
+
+    // Days since the game started, counting from 0 and rolling over at midnight
+    pub fn curr_day(&self) -> u32 {
+        self.day_of(self.turn)
+    }
+
+    // Which day a given turn fell on
+    pub fn day_of(&self, turn: u32) -> u32 {
+        (turn + self.start_hour * TURNS_PER_HOUR) / TURNS_PER_DAY
+    }
+
     pub fn curr_time(&self) -> (u16, u16) {
-        let normalized = (self.turn + 2880) % 8640; // 8640 turns per day
-        let hour = normalized / 360;
-        let leftover = normalized - (hour * 360);
-        let minute = leftover / 6;
+        let normalized = self.clock_turn() % TURNS_PER_DAY;
+        let hour = normalized / TURNS_PER_HOUR;
+        let minute = (normalized % TURNS_PER_HOUR) / TURNS_PER_MINUTE;
         
         (hour as u16, minute as u16)
     }
+
+    // Note something memorable in the player's journal, unless it's already in there
+    pub fn add_journal_entry(&mut self, entry: &str) {
+        if self.journal.iter().any(|e| e.1 == entry) {
+            return;
+        }
+
+        let (hour, minute) = self.curr_time();
+        let day = self.curr_day() + 1;
+        let timestamp = format!("Day {}, {:02}:{:02}", day, hour, minute);
+        self.journal.push((timestamp, entry.to_string()));
+    }
 }
 
-fn title_screen(gui: &mut GameUI) {
+fn title_screen(gui: &mut dyn UserInterface) {
     let mut lines = vec!["Welcome to Rogue Village 0.0.1!", ""];
     lines.push("");
     lines.push("");
@@ -233,27 +450,46 @@ fn title_screen(gui: &mut GameUI) {
     gui.write_long_msg(&lines, true);
 }
 
-fn calc_save_filename(player_name: &str) -> String {
-    let s: String = player_name.chars()
+fn file_safe_name(player_name: &str) -> String {
+    player_name.chars()
         .map(|ch| match ch {
             'a'..='z' => ch,
             'A'..='Z' => ch,
             '0'..='9' => ch,
             _ => '_'
-        }).collect();
-    
-    format!("{}.yaml", s)
+        }).collect()
 }
 
-fn serialize_game_data(state: &GameState, game_obj_db: &GameObjectDB) {
-    let player_name = game_obj_db.get(0).unwrap().get_fullname();
+fn calc_save_filename(player_name: &str) -> String {
+    format!("{}.yaml", file_safe_name(player_name))
+}
+
+// The autosave lives alongside the regular save so that a crash mid-write of one
+// can't take out the other
+fn calc_autosave_filename(player_name: &str) -> String {
+    calc_save_filename(player_name).replace(".yaml", ".autosave.yaml")
+}
+
+// gzip streams always begin with these two bytes, which is how we tell compressed saves
+// apart from older plain YAML ones when loading
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+fn serialize_game_data(state: &GameState, game_obj_db: &GameObjectDB, filename: &str, saves: &SaveOptions) {
     let game_data = (state, game_obj_db);
     let serialized = serde_yaml::to_string(&game_data).unwrap();
-    let filename = calc_save_filename(&player_name);
+    let bytes = if saves.compress {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        match encoder.write_all(serialized.as_bytes()) {
+            Ok(_) => encoder.finish().expect("Oh no cannot compress save file!"),
+            Err(_) => panic!("Oh no cannot compress save file!"),
+        }
+    } else {
+        serialized.into_bytes()
+    };
 
-    match File::create(&filename) {
+    match File::create(filename) {
         Ok(mut buffer) => {
-            match buffer.write_all(serialized.as_bytes()) {
+            match buffer.write_all(&bytes) {
                 Ok(_) => { },
                 Err(_) => panic!("Oh no cannot write to file!"),
             }
@@ -262,9 +498,7 @@ fn serialize_game_data(state: &GameState, game_obj_db: &GameObjectDB) {
     }
 }
 
-fn existing_save_file(player_name: &str) -> bool {
-    let save_filename = calc_save_filename(player_name);
-
+fn existing_save_file(save_filename: &str) -> bool {
     let paths = fs::read_dir("./").unwrap();
     for path in paths {
         if save_filename == path.unwrap().path().file_name().unwrap().to_str().unwrap() {
@@ -275,33 +509,84 @@ fn existing_save_file(player_name: &str) -> bool {
     false
 }
 
-fn load_save_game(player_name: &str) -> Result<(GameState, GameObjectDB), serde_yaml::Error> {
-    let filename = calc_save_filename(player_name);
-    let blob = fs::read_to_string(filename).expect("Error reading save file");
+fn load_save_game(filename: &str) -> Result<(GameState, GameObjectDB), Box<dyn Error>> {
+    let bytes = fs::read(filename)?;
+    let blob = if bytes.starts_with(&GZIP_MAGIC) {
+        let mut s = String::new();
+        GzDecoder::new(&bytes[..]).read_to_string(&mut s)?;
+        s
+    } else {
+        String::from_utf8(bytes)?
+    };
     let game_data: (GameState, GameObjectDB) = serde_yaml::from_str(&blob)?;
     
     Ok((game_data.0, game_data.1))
 }
 
-fn fetch_saved_data(player_name: &str) -> Option<(GameState, GameObjectDB)> {    
-    match load_save_game(player_name) {
+fn fetch_saved_data(filename: &str) -> Option<(GameState, GameObjectDB)> {    
+    match load_save_game(filename) {
         Ok(gd) => Some(gd),
         Err(err) => { println!("error in save file {:?}", err); None },
     }
 }
 
-fn save_and_exit(state: &GameState, game_obj_db: &mut GameObjectDB, gui: &mut GameUI) -> Result<(), ExitReason> {
+fn save_and_exit(state: &GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface, saves: &SaveOptions) -> Result<(), ExitReason> {
     let sbi = state.curr_sidebar_info(game_obj_db);
     match gui.query_yes_no("Save and exit? (y/n)", Some(&sbi)) {
         'y' => {
-            serialize_game_data(state, game_obj_db);
+            let player_name = game_obj_db.get(0).unwrap().get_fullname();
+            serialize_game_data(state, game_obj_db, &calc_save_filename(&player_name), saves);
+            // The regular save is now the more recent one
+            remove_autosave(&player_name);
             Err(ExitReason::Save)
         },
         _ => Ok(()),
     }
 }
 
-fn who_are_you(gui: &mut GameUI) -> String {
+fn remove_autosave(player_name: &str) {
+    let filename = calc_autosave_filename(player_name);
+    if existing_save_file(&filename) {
+        let _ = fs::remove_file(filename);
+    }
+}
+
+// Every path that ends a turn for the world (the main loop as well as the multi-step
+// actions like running) comes through here, so the autosave can't be skipped over
+fn advance_turn(state: &mut GameState, game_obj_db: &mut GameObjectDB, saves: &SaveOptions) {
+    let p = game_obj_db.player().unwrap();
+    if state.turn.is_multiple_of(state.difficulty.recovery_interval()) {
+        p.recover();
+    }
+    state.turn += 1;
+
+    if saves.autosave_due(state.turn) {
+        let player_name = game_obj_db.get(0).unwrap().get_fullname();
+        serialize_game_data(state, game_obj_db, &calc_autosave_filename(&player_name), saves);
+    }
+}
+
+// If the regular save is missing or won't load, the player may still have an autosave
+// to fall back on
+fn offer_autosave(player_name: &str, save_found: bool, gui: &mut dyn UserInterface) -> Option<(GameState, GameObjectDB)> {
+    let filename = calc_autosave_filename(player_name);
+    if !existing_save_file(&filename) {
+        return None;
+    }
+
+    let question = if save_found {
+        "Your save file appears to be damaged. Resume from the autosave? (y/n)"
+    } else {
+        "An autosave was found. Resume from it? (y/n)"
+    };
+
+    match gui.query_yes_no(question, None) {
+        'y' => fetch_saved_data(&filename),
+        _ => None,
+    }
+}
+
+fn who_are_you(gui: &mut dyn UserInterface) -> String {
     loop {
         if let Some(name) = gui.query_user("Who are you?", 15, None) {
             if !name.trim().is_empty() {
@@ -311,29 +596,184 @@ fn who_are_you(gui: &mut GameUI) -> String {
     }
 }
 
-fn start_new_game(state: &GameState, game_obj_db: &mut GameObjectDB, gui: &mut GameUI, player_name: String) {
+fn pick_difficulty(gui: &mut dyn UserInterface) -> Difficulty {
+    let mut menu = vec!["How tough an adventure are you looking for?"];
+    menu.push("");
+    menu.push("  (a) Easy - weaker monsters, more loot and quicker healing.");
+    menu.push("");
+    menu.push("  (b) Normal - the adventure as intended.");
+    menu.push("");
+    menu.push("  (c) Hard - tougher monsters, scarcer loot and slower healing.");
+    
+    let answers: HashSet<&char> = ['a', 'b', 'c'].iter().collect();
+    match gui.menu_wordy_picker(&menu, &answers) {
+        Some('a') => Difficulty::Easy,
+        Some('c') => Difficulty::Hard,
+        _ => Difficulty::Normal,
+    }
+}
+
+fn start_new_game(state: &GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface, player_name: String, rng: &mut GameRng) {
     let mut menu = vec!["Welcome adventurer, please choose your role in RogueVillage:"];
     menu.push("");
-    menu.push("  (a) Human Warrior - a doughty fighter who lives by the sword and...well");
-    menu.push("                      hopefully just that first part.");
+    menu.push("  (a) Warrior - a doughty fighter who lives by the sword and...well");
+    menu.push("                hopefully just that first part.");
+    menu.push("");
+    menu.push("  (b) Rogue - a quick, sly adventurer who gets by on their light step");
+    menu.push("              and fast blade.");
     menu.push("");
-    menu.push("  (b) Human Rogue - a quick, sly adventurer who gets by on their light step");
-    menu.push("                    and fast blade.");
+    menu.push("  (c) Mage - a frail student of the arcane who trusts in magic missiles");
+    menu.push("             more than in steel.");
     
-    let answers: HashSet<&char> = ['a', 'b'].iter().collect();
+    let answers: HashSet<&char> = ['a', 'b', 'c'].iter().collect();
     if let Some(answer) = gui.menu_wordy_picker(&menu, &answers) {
+        let race = match pick_race(gui) {
+            Some(race) => race,
+            None => return,
+        };
+
         if answer == 'a' {
-            Player::new_warrior(game_obj_db, &player_name);
+            Player::new_warrior(game_obj_db, &player_name, race, rng);
+        } else if answer == 'c' {
+            Player::new_mage(game_obj_db, &player_name, race, rng);
         } else {
-            Player::new_warrior(game_obj_db, &player_name);
+            Player::new_warrior(game_obj_db, &player_name, race, rng);
             //Player::new_rogue(game_obj_db, player_name);
         }
 
-        game_obj_db.set_to_loc(0, pick_player_start_loc(&state));
+        if !game_obj_db.set_to_loc(0, pick_player_start_loc(&state, rng)) {
+            panic!("Unable to place the player in the world!");
+        }
+    }
+}
+
+fn pick_race(gui: &mut dyn UserInterface) -> Option<Race> {
+    let mut menu = vec!["And what manner of folk are you?"];
+    menu.push("");
+    menu.push("  (a) Human - adaptable and unremarkable, for better or worse.");
+    menu.push("");
+    menu.push("  (b) Dwarf - tough and strong (+1 Str, +2 Con, -1 Dex, -1 Chr) and hardy");
+    menu.push("              enough to shrug off poison.");
+    menu.push("");
+    menu.push("  (c) Elf - nimble and clever (+2 Dex, +1 Apt, -1 Str, -1 Con), with eyes");
+    menu.push("            that see further in dim light.");
+    menu.push("");
+    menu.push("  (d) Halfling - small and quick (+2 Dex, +1 Chr, -2 Str) and very quiet");
+    menu.push("                 on their feet.");
+
+    let answers: HashSet<&char> = ['a', 'b', 'c', 'd'].iter().collect();
+    match gui.menu_wordy_picker(&menu, &answers) {
+        Some('a') => Some(Race::Human),
+        Some('b') => Some(Race::Dwarf),
+        Some('c') => Some(Race::Elf),
+        Some('d') => Some(Race::Halfling),
+        _ => None,
+    }
+}
+
+// Drink from a water source the player is standing on or next to, or failing that, from a
+// waterskin in their pack. Being at a water source also tops up any waterskins.
+fn drink(state: &mut GameState, game_obj_db: &mut GameObjectDB) -> f32 {
+    let player = game_obj_db.player().unwrap();
+    let player_loc = player.get_loc();
+
+    let mut sqs = vec![player_loc];
+    for adj in util::ADJ.iter() {
+        sqs.push((player_loc.0 + adj.0, player_loc.1 + adj.1, player_loc.2));
+    }
+    let source = sqs.iter().find(|sq| matches!(state.map.get(sq), Some(Tile::Well) | Some(Tile::Spring) | Some(Tile::Water)));
+
+    if let Some(&sq) = source {
+        let s = match state.map[&sq] {
+            Tile::Well => "You draw some water from the well and drink.",
+            Tile::Spring => "You drink from the cool, clear spring.",
+            _ => "You drink some water.",
+        };
+        state.msg_queue.push_back(Message::info(s));
+        player.quench(player::HYDRATION_MAX);
+
+        for obj in player.inventory.iter_mut() {
+            if let GameObjects::Item(item) = obj {
+                if item.base_info.name == "waterskin" && item.charges < 2 {
+                    item.charges = 2;
+                    state.msg_queue.push_back(Message::info("You refill your waterskin."));
+                }
+            }
+        }
+
+        if state.map[&sq] == Tile::Spring {
+            drink_from_spring(state, game_obj_db, sq);
+        }
+
+        return 1.0;
+    }
+
+    for obj in player.inventory.iter_mut() {
+        if let GameObjects::Item(item) = obj {
+            // Wine slakes thirst too, just not as well as water
+            let (msg, amt) = match item.base_info.name.as_str() {
+                "waterskin" => ("You take a drink from your waterskin.", 500),
+                "wineskin" => ("You take a swig of wine.", 200),
+                _ => continue,
+            };
+            if item.item_type == ItemType::Bottle && item.charges > 0 {
+                item.charges -= 1;
+                state.msg_queue.push_back(Message::info(msg));
+                player.quench(amt);
+                return 1.0;
+            }
+        }
+    }
+
+    state.msg_queue.push_back(Message::info("You have nothing to drink."));
+    0.0
+}
+
+// The springs found in the dungeon are faintly magical. Usually the water is restorative but
+// sometimes it has stranger effects. Each spring is only good for one drink before it runs dry.
+fn drink_from_spring(state: &mut GameState, game_obj_db: &mut GameObjectDB, sq: (i32, i32, i8)) {
+    let roll = state.rng.gen_range(0.0, 1.0);
+    let effect = if roll < 0.6 {
+        effects::EF_MINOR_HEAL
+    } else if roll < 0.75 {
+        effects::EF_BLINK
+    } else if roll < 0.9 {
+        effects::EF_DETECT_MONSTERS
+    } else {
+        effects::EF_HASTE
+    };
+    effects::apply_effects(state, 0, game_obj_db, effect);
+
+    let dry = if sq.2 == 0 { Tile::Dirt } else { Tile::StoneFloor };
+    state.map.insert(sq, dry);
+    state.msg_queue.push_back(Message::info("The spring sputters and runs dry."));
+}
+
+// Let the player leave a message for themselves (say, to mark where they've stashed something)
+fn write_note(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) -> f32 {
+    let sbi = state.curr_sidebar_info(game_obj_db);
+    let player_loc = game_obj_db.get(0).unwrap().get_loc();
+
+    match gui.query_user("What do you write?", 50, Some(&sbi)) {
+        Some(text) if !text.trim().is_empty() => {
+            let mut note = Item::get_item(game_obj_db, "note", &mut state.rng).unwrap();
+            if let GameObjects::Item(item) = &mut note {
+                item.text = Some(("note in your handwriting".to_string(), text.trim().to_string()));
+            }
+            note.set_loc(player_loc);
+            game_obj_db.add(note);
+            state.msg_queue.push_back(Message::info("You jot down a note and leave it here."));
+            
+            1.0
+        },
+        _ => {
+            state.msg_queue.push_back(Message::info("Never mind."));
+            0.0
+        },
     }
 }
 
-fn drop_zorkmids(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut GameUI) -> f32 {
+fn drop_zorkmids(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) -> f32 {
     let player = game_obj_db.player().unwrap();
     let player_loc = player.get_loc();
     let mut purse = player.purse;
@@ -347,12 +787,15 @@ fn drop_zorkmids(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mu
     if let Some(amt) = gui.query_natural_num("How much?", Some(&sbi)) {
         let tile = &state.map[&player_loc];                        
         let into_well = *tile == Tile::Well;
+        let shrine = religion::shrine_at(state, player_loc);
 
         if amt == 0 {
             state.msg_queue.push_back(Message::info("Never mind."));            
         } else if amt >= purse {
             if into_well {
                 state.msg_queue.push_back(Message::info("You hear faint tinkling splashes."));                
+            } else if let Some(deity) = shrine {
+                religion::offer_gold(state, deity, purse);
             } else {
                 state.msg_queue.push_back(Message::info("You drop all of your money."));
                 let zorkmids = GoldPile::make(game_obj_db, purse, player_loc);
@@ -362,6 +805,8 @@ fn drop_zorkmids(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mu
         } else if amt > 1 {
             if into_well {
                 state.msg_queue.push_back(Message::info("You hear faint tinkling splashes."));
+            } else if let Some(deity) = shrine {
+                religion::offer_gold(state, deity, amt);
             } else {
                 let s = format!("You drop {} gold pieces.", amt);
                 state.msg_queue.push_back(Message::info(&s));                
@@ -372,6 +817,8 @@ fn drop_zorkmids(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mu
         } else {
             if into_well {
                 state.msg_queue.push_back(Message::info("You hear a faint splash."));
+            } else if let Some(deity) = shrine {
+                religion::offer_gold(state, deity, 1);
             } else {
                 state.msg_queue.push_back(Message::info("You drop a gold piece."));
                 let zorkmids = GoldPile::make(game_obj_db, 1, player_loc);
@@ -419,7 +866,7 @@ fn drop_stack(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc: (i32,
     0.0
 }
 
-fn drop_item(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut GameUI) -> f32 {    
+fn drop_item(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) -> f32 {    
     let sbi = state.curr_sidebar_info(game_obj_db);
     let player = game_obj_db.player().unwrap();
     let player_loc = player.get_loc();
@@ -458,8 +905,12 @@ fn drop_item(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut Ga
                     Ok(mut items) => {
                         let obj = items.remove(0);
                         let s = format!("You drop {}.", &obj.get_fullname().with_def_article());
-                        state.msg_queue.push_back(Message::info(&s));                        
-                        item_hits_ground(obj, player_loc, game_obj_db);
+                        state.msg_queue.push_back(Message::info(&s));
+                        // A corpse left on an altar is taken as a sacrifice
+                        match religion::shrine_at(state, player_loc) {
+                            Some(deity) if obj.get_fullname().ends_with("corpse") => religion::offer_sacrifice(state, deity),
+                            _ => item_hits_ground(obj, player_loc, game_obj_db),
+                        }
                         cost = 1.0;
                     },
                     Err(msg) => state.msg_queue.push_back(Message::info(&msg)),
@@ -476,11 +927,11 @@ fn drop_item(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut Ga
     cost    
 }
 
-fn search_loc(state: &mut GameState, roll: u8, loc: (i32, i32, i8), game_obj_db: &mut GameObjectDB) {
+fn search_loc(state: &mut GameState, found: bool, loc: (i32, i32, i8), game_obj_db: &mut GameObjectDB) {
     let things:Vec<usize> = game_obj_db.hidden_at_loc(loc);
     
     for obj_id in &things {
-        if roll >= 15 {
+        if found {
             let t = game_obj_db.get_mut(*obj_id).unwrap();
             let s = format!("You find {}!", t.get_fullname().with_indef_article());  
             state.msg_queue.push_back(Message::info(&s));
@@ -493,17 +944,59 @@ fn search(state: &mut GameState, game_obj_db: &mut GameObjectDB) {
     let player = game_obj_db.player().unwrap();
     let ploc = player.get_loc();
     
-    let roll = player.ability_check(Ability::Apt);
+    let found = player.search_check(&mut state.rng);
+    let radius = player.apt_search_radius();
     
-    search_loc(state, roll, ploc, game_obj_db);
-    for adj in util::ADJ.iter() {
-        let loc = (ploc.0 + adj.0, ploc.1 + adj.1, ploc.2);
-        search_loc(state, roll, loc, game_obj_db);
+    for r in -radius..=radius {
+        for c in -radius..=radius {
+            let loc = (ploc.0 + r, ploc.1 + c, ploc.2);
+            search_loc(state, found, loc, game_obj_db);
+        }
+    }
+}
+
+fn take_obj(state: &mut GameState, game_obj_db: &mut GameObjectDB, obj_id: usize) {
+    let obj = game_obj_db.remove(obj_id);
+    if let GameObjects::GoldPile(zorkmids) = &obj {
+        let amount = zorkmids.amount;
+        if amount == 1 {
+            state.msg_queue.push_back(Message::info("You pick up a single gold piece."));
+        } else {
+            let s = format!("You pick up {} gold pieces.", amount);
+            state.msg_queue.push_back(Message::info(&s));
+        }
+        game_obj_db.player().unwrap().purse += amount;
+    } else {
+        state.stats.items_found += 1;
+        let s = format!("You pick up {}.", obj.get_fullname().with_def_article());
+        state.msg_queue.push_back(Message::info(&s));
+        game_obj_db.player().unwrap().add_to_inv(obj);
+    }
+}
+
+// Scoop up whatever the player has asked to collect automatically in their options file, so
+// long as everything on the square is wanted. Otherwise, leave it to them to pick through it.
+fn auto_pick_up(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8)) {
+    let things = game_obj_db.items_to_pick_up(loc);
+    if things.is_empty() {
+        return;
+    }
+
+    let all_wanted = things.iter().all(|id| match game_obj_db.get(*id) {
+        Some(GameObjects::GoldPile(_)) => state.autopickup.contains(&AutoPickup::Gold),
+        Some(GameObjects::Item(item)) => state.autopickup.contains(&AutoPickup::Item(item.item_type)),
+        _ => false,
+    });
+
+    if all_wanted {
+        for id in things {
+            take_obj(state, game_obj_db, id);
+        }
     }
 }
 
 // Not yet handling when there are no inventory slots yet
-fn pick_up(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut GameUI) -> f32 {
+fn pick_up(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) -> f32 {
     let player_loc = game_obj_db.get(0).unwrap().get_loc();
     let things = game_obj_db.items_to_pick_up(player_loc);
     
@@ -512,8 +1005,6 @@ fn pick_up(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut Game
         return 0.0;
     } else if things.len() == 1 {
         let obj = game_obj_db.get(things[0]).unwrap();
-        let zorkmids = matches!(obj, GameObjects::GoldPile(_));
-
         if let GameObjects::Item(item) = obj {
             if item.attributes & IA_IMMOBILE > 0 {
                 state.msg_queue.push_back(Message::info("You cannot pick that up!"));
@@ -521,76 +1012,38 @@ fn pick_up(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut Game
             }
         }
 
-        if zorkmids {
-            let obj = game_obj_db.remove(things[0]);
-            let amount = if let GameObjects::GoldPile(zorkmids) = &obj {
-                zorkmids.amount
-            } else {
-                0
-            };
-
-            if amount == 1 {
-                state.msg_queue.push_back(Message::info("You pick up a single gold piece."));        
-            } else {
-                let s = format!("You pick up {} gold pieces.", amount);
-                state.msg_queue.push_back(Message::info(&s));                  
-            }
-            
-            let p = game_obj_db.player().unwrap();
-            p.purse += amount;
-        } else {
-            let obj = game_obj_db.remove(things[0]);
-            let s = format!("You pick up {}.", obj.get_fullname().with_def_article());
-            state.msg_queue.push_back(Message::info(&s));
-            let p = game_obj_db.player().unwrap();
-            p.add_to_inv(obj);
-        }
+        take_obj(state, game_obj_db, things[0]);
 
         return 1.0;
     } else {
-        let mut m = game_obj_db.get_pickup_menu(player_loc);
-        let mut answer_key = HashMap::new();
+        // Any gold piles on the square are lumped together under '$' and the items
+        // are lettered in order, so each menu key maps to a fixed set of object ids.
+        let m = game_obj_db.get_pickup_menu(player_loc);
+        let (gold, items): (Vec<_>, Vec<_>) = m.into_iter()
+                                               .partition(|(_, id)| matches!(game_obj_db.get(*id), Some(GameObjects::GoldPile(_))));
+        let mut answer_key: HashMap<char, Vec<usize>> = HashMap::new();
         let mut menu = Vec::new();
-        for (j, item) in m.iter().enumerate() {
-            if item.0.contains("gold piece") {
-                menu.push((item.0.to_string(), '$'));
-                answer_key.insert('$', item.1);
-                m.remove(j);
-                break;
-            }
+        if !gold.is_empty() {
+            let total: u32 = gold.iter()
+                                 .map(|(_, id)| if let Some(GameObjects::GoldPile(z)) = game_obj_db.get(*id) { z.amount } else { 0 })
+                                 .sum();
+            menu.push((format!("{} gold pieces", total), '$'));
+            answer_key.insert('$', gold.iter().map(|(_, id)| *id).collect());
         }
-        for (j, item) in m.iter().enumerate() { // in 0..m.len() {
-            let ch = (j as u8 + b'a') as char;
-            menu.push((item.0.to_string(), ch));
-            answer_key.insert(ch, item.1);
+        let slots = (b'a'..=b'z').chain(b'A'..=b'Z').map(|b| b as char);
+        for ((desc, id), ch) in items.into_iter().zip(slots) {
+            menu.push((desc, ch));
+            answer_key.insert(ch, vec![id]);
         }
         
         if let Some(answers) = gui.side_pane_menu("Pick up what: (* to get everything)".to_string(), &menu, false) {
-            let picks: Vec<usize> = answers.iter().map(|a| answer_key[a]).collect();
+            let picks: Vec<usize> = answers.iter()
+                                           .filter_map(|a| answer_key.get(a))
+                                           .flatten()
+                                           .copied()
+                                           .collect();
             for id in picks {
-                let obj = game_obj_db.get(id).unwrap();
-                let (is_zorkmids, amount) = if let GameObjects::GoldPile(zorkmids) = &obj {
-                    (true, zorkmids.amount)
-                } else {
-                    (false, 0)
-                };
-                
-                if is_zorkmids {
-                    if amount == 1 {
-                        state.msg_queue.push_back(Message::info("You pick up a single gold piece."));
-                    } else {
-                        let s = format!("You pick up {} gold pieces.", amount);
-                        state.msg_queue.push_back(Message::info(&s));                        
-                    }
-                    game_obj_db.remove(id);
-                    game_obj_db.player().unwrap().purse += amount;                   
-                } else {
-                    let obj = game_obj_db.remove(id);
-                    let s = format!("You pick up {}.", obj.get_fullname().with_def_article());
-                    state.msg_queue.push_back(Message::info(&s));
-                    let p = game_obj_db.player().unwrap();
-                    p.add_to_inv(obj);
-                }
+                take_obj(state, game_obj_db, id);
             }
             return 1.0;
         } else {
@@ -636,6 +1089,12 @@ fn toggle_item(state: &mut GameState, slot: char, game_obj_db: &mut GameObjectDB
             state.msg_queue.push_back(Message::info("You're already wearing armour."));
             return 0.0;             
         }
+    } else if item_type == ItemType::Cloak {
+        let readied = player.readied_obj_ids_of_type(ItemType::Cloak);
+        if !readied.is_empty() && readied[0] != obj_id {
+            state.msg_queue.push_back(Message::info("You're already wearing a cloak."));
+            return 0.0;             
+        }
     } else if item_type == ItemType::Shield {
         let readied = player.readied_obj_ids_of_type(ItemType::Shield);
         if !readied.is_empty() && readied[0] != obj_id {
@@ -679,7 +1138,7 @@ fn toggle_item(state: &mut GameState, slot: char, game_obj_db: &mut GameObjectDB
     1.0
 }
 
-fn toggle_equipment(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut GameUI) -> f32 {
+fn toggle_equipment(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) -> f32 {
     let player = game_obj_db.player().unwrap();
     let slots = player.inv_slots_used();
     
@@ -702,10 +1161,11 @@ fn toggle_equipment(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui:
     }
 }
 
-fn use_item(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut GameUI) -> f32 {
+fn use_item(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) -> f32 {
     let sbi = state.curr_sidebar_info(game_obj_db);        
     let player = game_obj_db.player().unwrap();
     let confused = player.has_status(Status::Confused);
+    let blind = player.has_status(Status::Blind);
     let slots = player.inv_slots_used();
     
     if slots.is_empty() {
@@ -722,10 +1182,10 @@ fn use_item(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut Gam
         
         let obj = player.inv_item_in_slot(ch).unwrap();
         let obj_id = obj.obj_id();
-        let (useable, item_type, consumable, effects, equiped) = if let GameObjects::Item(item) = &obj {
-            (item.useable(), item.item_type, item.attributes & IA_CONSUMABLE > 0, item.effects, item.equiped)
+        let (useable, item_type, consumable, effects, equiped, nutrition) = if let GameObjects::Item(item) = &obj {
+            (item.useable(), item.item_type, item.attributes & IA_CONSUMABLE > 0, item.effects, item.equiped, item.nutrition)
         } else {
-            (false, ItemType::Weapon, false, 0, false)
+            (false, ItemType::Weapon, false, 0, false, 0)
         };
         
         let (desc, text) = if let GameObjects::Item(item) = &obj {
@@ -738,8 +1198,18 @@ fn use_item(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut Gam
             ("".to_string(), "".to_string())
         };
         
+        if blind && (item_type == ItemType::Scroll || item_type == ItemType::Note || item_type == ItemType::Spellbook) {
+            state.msg_queue.push_back(Message::info("You can't read while you're blind!"));
+            return 0.0;
+        }
+
         if item_type == ItemType::Wand {
             return use_wand(state, ch, game_obj_db, gui, effects);
+        } else if item_type == ItemType::Trap {
+            return set_trap(state, game_obj_db, gui, obj_id);
+        } else if item_type == ItemType::Spellbook {
+            let name = obj.get_fullname();
+            return magic::read_spellbook(state, game_obj_db, obj_id, &name);
         } else if useable {
             if item_type == ItemType::Light {
                 let (item_id, active) = use_light(state, ch, game_obj_db);
@@ -754,7 +1224,12 @@ fn use_item(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut Gam
             }
 
             if item_type == ItemType::Food {
+                if nutrition == 0 {
+                    state.msg_queue.push_back(Message::info("That isn't fit to eat!"));
+                    return 0.0;
+                }
                 state.msg_queue.push_back(Message::info("Om nom nom."));
+                game_obj_db.player().unwrap().eat(state, nutrition);
             }
 
             if effects > 0 {
@@ -791,7 +1266,111 @@ fn use_item(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut Gam
     0.0
 }
 
-fn use_wand(state: &mut GameState, slot: char, game_obj_db: &mut GameObjectDB, gui: &mut GameUI, effects: u128) -> f32 {
+fn set_trap(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface, obj_id: usize) -> f32 {
+    let loc = if let Some(loc) = gui.select_dir("Set it where?", state, game_obj_db) {
+        loc
+    } else {
+        state.msg_queue.push_back(Message::info("Never mind."));
+        return 0.0;
+    };
+
+    let tile = tile_at(&state.map, &loc);
+    if !tile.passable_dry_land() || game_obj_db.blocking_obj_at(&loc) || !game_obj_db.special_sqs_at_loc(&loc).is_empty() {
+        state.msg_queue.push_back(Message::info("You can't set a trap there."));
+        return 0.0;
+    }
+
+    let player = game_obj_db.player().unwrap();
+    let trap_name = player.inv_remove(obj_id).unwrap().get_fullname();
+    let trap = SpecialSquare::player_trap(&trap_name, loc, game_obj_db);
+    let trap_id = trap.obj_id();
+    game_obj_db.add(trap);
+    game_obj_db.listeners.insert((trap_id, EventType::SteppedOn));
+
+    let s = format!("You set the {}.", trap_name);
+    state.msg_queue.push_back(Message::info(&s));
+
+    2.0
+}
+
+fn throw_item(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) -> f32 {
+    let player = game_obj_db.player().unwrap();
+    let player_loc = player.get_loc();
+    let slots = player.inv_slots_used();
+
+    if slots.is_empty() {
+        state.msg_queue.push_back(Message::info("You are empty handed."));
+        return 0.0;
+    }
+
+    let menu = player.inv_menu(0);
+    let ch = match gui.show_in_side_pane("Throw which?", &menu) {
+        Some(ch) if slots.contains(&ch) => ch,
+        Some(_) => {
+            state.msg_queue.push_back(Message::info("You do not have that item!"));
+            return 0.0;
+        },
+        None => {
+            state.msg_queue.push_back(Message::info("Never mind."));
+            return 0.0;
+        },
+    };
+
+    if let Some(GameObjects::Item(item)) = player.inv_item_in_slot(ch) {
+        if item.equiped {
+            state.msg_queue.push_back(Message::info("You'll have to unequip that first."));
+            return 0.0;
+        }
+    }
+
+    let target = match gui.select_target(state, game_obj_db, "Throw at what?") {
+        Some(loc) => loc,
+        None => {
+            state.msg_queue.push_back(Message::info("Never mind."));
+            return 0.0;
+        },
+    };
+
+    // The item flies until it hits someone or something solid
+    let mut landing = player_loc;
+    let mut victim = None;
+    for pt in util::bresenham(player_loc.0, player_loc.1, target.0, target.1).iter().skip(1).take(THROW_RANGE) {
+        let loc = (pt.0, pt.1, player_loc.2);
+        if !tile_at(&state.map, &loc).passable() {
+            break;
+        }
+        landing = loc;
+        if let Some(id) = game_obj_db.npc_at(&loc) {
+            victim = Some(id);
+            break;
+        }
+    }
+
+    let player = game_obj_db.player().unwrap();
+    let obj = player.inv_remove_from_slot(ch, 1).unwrap().remove(0);
+    player.calc_gear_effects();
+    effects::break_invisibility(state, game_obj_db);
+
+    let breakable = if let GameObjects::Item(item) = &obj {
+        if let Some(id) = victim {
+            battle::player_throws(state, id, item, game_obj_db);
+        }
+        item.item_type == ItemType::Potion || item.item_type == ItemType::Bottle
+    } else {
+        false
+    };
+
+    if breakable {
+        let s = format!("{} shatters!", obj.get_fullname().with_def_article().capitalize());
+        state.msg_queue.push_back(Message::new(0, landing, &s, "You hear something shatter."));
+    } else {
+        item_hits_ground(obj, landing, game_obj_db);
+    }
+
+    1.0
+}
+
+fn use_wand(state: &mut GameState, slot: char, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface, effects: u128) -> f32 {
     let player = game_obj_db.player().unwrap();
     let player_loc = player.get_loc();
     let obj = player.inv_item_in_slot(slot).unwrap();
@@ -814,6 +1393,7 @@ fn use_wand(state: &mut GameState, slot: char, game_obj_db: &mut GameObjectDB, g
     };
 
     state.msg_queue.push_back(Message::info("You zap the wand!"));
+    effects::break_invisibility(state, game_obj_db);
 
     // The player could select a square that is less than the range of the wand, but wands always shoot their
     // beam to their full length, so scale the line out if needed
@@ -844,12 +1424,15 @@ fn use_wand(state: &mut GameState, slot: char, game_obj_db: &mut GameObjectDB, g
     1.0
 }
 
-fn area_of_effect(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut GameUI, sqs_in_area: &[(i32, i32, i8)], effects: u128) {    
+fn area_of_effect(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface, sqs_in_area: &[(i32, i32, i8)], effects: u128) {    
     let mut affected_sqs = Vec::new();
     for sq in sqs_in_area.iter() {
         if effects & effects::EF_FROST > 0 {
             effects::frost(state, game_obj_db, *sq, 0);
         }
+        if effects & effects::EF_SLOW > 0 {
+            effects::slow(state, game_obj_db, *sq);
+        }
 
         affected_sqs.push(*sq);
 
@@ -870,7 +1453,7 @@ fn use_weapon_as_tool(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc
     for oi in obstacle_info.iter() {
         if oi.2 == "web" {
             let p = game_obj_db.player().unwrap();
-            if p.ability_check(Ability::Str) < oi.1 {
+            if p.ability_check(Ability::Str, &mut state.rng) < oi.1 {
                 state.msg_queue.push_back(Message::info("You hack away at the web but it remains intact."));
             } else {
                 state.msg_queue.push_back(Message::info("You clear out the web."));
@@ -883,8 +1466,8 @@ fn use_weapon_as_tool(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc
             let w = p.readied_weapon().unwrap().0;
             if w.dmg_type == DamageType::Slashing {
                 state.msg_queue.push_back(Message::info("You chop the mushroom to pieces."));
-                for _ in 0..rand::thread_rng().gen_range(1, 4) {
-                    let mut m = Item::get_item(game_obj_db, "piece of mushroom").unwrap();
+                for _ in 0..state.rng.gen_range(1, 4) {
+                    let mut m = Item::get_item(game_obj_db, "piece of mushroom", &mut state.rng).unwrap();
                     m.set_loc(loc);
                     game_obj_db.add(m);
                 }
@@ -959,7 +1542,57 @@ fn get_move_tuple(mv: &str) -> (i32, i32) {
     }
 }
 
-fn do_open(state: &mut GameState, loc: (i32, i32, i8)) {
+// Picking a lock is a Dex check. If someone's home and catches you at it, they
+// won't be pleased.
+fn pick_lock(state: &mut GameState, loc: (i32, i32, i8), game_obj_db: &mut GameObjectDB) {
+    let player = game_obj_db.player().unwrap();
+    if player.ability_check(Ability::Dex, &mut state.rng) + player.apt_lockpick_bonus() >= 15 {
+        state.msg_queue.push_back(Message::info("You pick the lock."));
+        state.map.insert(loc, map::Tile::Door(DoorState::Closed));
+        town::trespass(state, game_obj_db, loc);
+    } else {
+        state.msg_queue.push_back(Message::info("You fiddle with the lock but can't open it."));
+    }
+}
+
+// Meta commands (saving, viewing menus, etc) don't count as actions for the purposes
+// of the repeat command
+fn is_repeatable(cmd: &Cmd) -> bool {
+    !matches!(cmd, Cmd::Help | Cmd::MsgHistory | Cmd::Quit | Cmd::Repeat | Cmd::Save | Cmd::ShowCharacterSheet 
+            | Cmd::ShowEquipment | Cmd::ShowInventory | Cmd::ShowJournal | Cmd::Travel | Cmd::WizardCommand)
+}
+
+// Fetch the player's previous command, provided it still makes sense to do it again.
+// If not, we return Cmd::Repeat, which the game loop treats as a no-op.
+fn repeat_cmd(state: &mut GameState, game_obj_db: &mut GameObjectDB) -> Cmd {
+    let cmd = match &state.last_cmd {
+        Some(cmd) => cmd.clone(),
+        None => {
+            state.msg_queue.push_back(Message::info("Nothing to repeat."));
+            return Cmd::Repeat;
+        },
+    };
+
+    let ploc = game_obj_db.get(0).unwrap().get_loc();
+    let valid = match &cmd {
+        Cmd::Bash(loc) | Cmd::Chat(loc) | Cmd::Close(loc) | Cmd::Open(loc) if loc.2 != ploc.2 || util::distance(ploc.0, ploc.1, loc.0, loc.1) > 1.5 => false,
+        Cmd::Bash(loc) => matches!(tile_at(&state.map, loc), Tile::Door(DoorState::Closed) | Tile::Door(DoorState::Locked) | Tile::Window(_)) 
+                            || game_obj_db.blocking_obj_at(loc) || game_obj_db.obstacle_at(loc),
+        Cmd::Chat(loc) => game_obj_db.npc_at(loc).is_some(),
+        Cmd::Close(loc) => tile_at(&state.map, loc) == Tile::Door(DoorState::Open),
+        Cmd::Open(loc) => matches!(tile_at(&state.map, loc), Tile::Door(DoorState::Closed) | Tile::Door(DoorState::Locked)),
+        _ => true,
+    };
+
+    if valid {
+        cmd
+    } else {
+        state.msg_queue.push_back(Message::info("You can't do that again."));
+        Cmd::Repeat
+    }
+}
+
+fn do_open(state: &mut GameState, loc: (i32, i32, i8), game_obj_db: &mut GameObjectDB) {
     let tile = &state.map[&loc];
     match tile {
         Tile::Door(DoorState::Open) | Tile::Door(DoorState::Broken) => state.msg_queue.push_back(Message::info("That door is already open.")),
@@ -967,7 +1600,7 @@ fn do_open(state: &mut GameState, loc: (i32, i32, i8)) {
             state.msg_queue.push_back(Message::info("You open the door."));
             state.map.insert(loc, map::Tile::Door(DoorState::Open));
         },
-        Tile::Door(DoorState::Locked) => state.msg_queue.push_back(Message::info("That door is locked.")),
+        Tile::Door(DoorState::Locked) => pick_lock(state, loc, game_obj_db),
         _ => state.msg_queue.push_back(Message::info("You cannot open that.")),
     }        
 }
@@ -989,35 +1622,107 @@ fn do_close(state: &mut GameState, loc: (i32, i32, i8), game_obj_db: &mut GameOb
     }        
 }
 
+// Monsters hot on the player's heels when they take the stairs may chase them to the next level. 
+// Fast monsters always keep up, and the fearless ones are often determined enough to.
+fn stair_pursuers(state: &mut GameState, game_obj_db: &mut GameObjectDB, player_loc: (i32, i32, i8)) -> Vec<usize> {
+    let mut pursuers = Vec::new();
+    for adj in util::ADJ.iter() {
+        let loc = (player_loc.0 + adj.0, player_loc.1 + adj.1, player_loc.2);
+        if let Some(id) = game_obj_db.npc_at(&loc) {
+            let npc = game_obj_db.npc(id).unwrap();
+            if !npc.is_monster || !npc.alive || !npc.active || npc.attitude != Attitude::Hostile || npc.has_status(Status::Held) {
+                continue;
+            }
+
+            if npc.attributes & npc::MA_FAST > 0 || (npc.attributes & npc::MA_FEARLESS > 0 && state.rng.gen_range(0.0, 1.0) < 0.5) {
+                pursuers.push(id);
+            }
+        }
+    }
+
+    pursuers
+}
+
+// Place any monsters who followed the player through a stairway beside them
+fn check_stair_followers(state: &mut GameState, game_obj_db: &mut GameObjectDB) {
+    if state.pending_followers.is_empty() {
+        return;
+    }
+
+    let player_loc = game_obj_db.get(0).unwrap().get_loc();
+    let followers: Vec<usize> = state.pending_followers.drain(..).collect();
+    for id in followers {
+        if game_obj_db.npc(id).is_none_or(|npc| !npc.alive) {
+            continue;
+        }
+
+        let spot = util::ADJ.iter()
+            .map(|adj| (player_loc.0 + adj.0, player_loc.1 + adj.1, player_loc.2))
+            .find(|loc| state.map.contains_key(loc) && state.map[loc].passable_dry_land() && !game_obj_db.location_occupied(loc));
+
+        if let Some(loc) = spot {
+            if game_obj_db.set_to_loc(id, loc) {
+                let npc = game_obj_db.npc(id).unwrap();
+                npc.plan.clear();
+                let s = format!("{} follows you!", npc.npc_name(false).capitalize());
+                state.msg_queue.push_back(Message::info(&s));
+            }
+        }
+    }
+}
+
 fn take_stairs(state: &mut GameState, game_obj_db: &mut GameObjectDB, down: bool) -> f32 {
     let player_loc = game_obj_db.get(0).unwrap().get_loc();
-    let tile = &state.map[&player_loc];
+    let tile = state.map[&player_loc];
     
     if down {
-        let cost = if *tile == map::Tile::Portal {
+        if tile != map::Tile::Portal && tile != map::Tile::StairsDown {
+            state.msg_queue.push_back(Message::info("You cannot do that here."));            
+            return 0.0;
+        }
+
+        // There's nothing generated below the bottom of the dungeon, so don't let the player
+        // wander off the edge of the map
+        let next_loc = (player_loc.0, player_loc.1, player_loc.2 + 1);
+        if next_loc.2 as u8 > state.world_info.dungeon_depth || !state.map.contains_key(&next_loc) {
+            state.msg_queue.push_back(Message::info("The way down is choked with rubble."));
+            return 0.0;
+        }
+
+        if !game_obj_db.set_to_loc(0, next_loc) {
+            state.msg_queue.push_back(Message::info("Something is blocking the way down."));
+            return 0.0;
+        }
+
+        if tile == map::Tile::Portal {
             state.msg_queue.push_back(Message::info("You enter the beckoning portal."));
-            game_obj_db.set_to_loc(0, (player_loc.0, player_loc.1, player_loc.2 + 1));
-            1.0
-        } else if *tile == map::Tile::StairsDown {
-            state.msg_queue.push_back(Message::info("You brave the stairs downward."));
-            game_obj_db.set_to_loc(0, (player_loc.0, player_loc.1, player_loc.2 + 1));
-            1.0
         } else {
-            state.msg_queue.push_back(Message::info("You cannot do that here."));            
-            0.0
-        };
+            state.msg_queue.push_back(Message::info("You brave the stairs downward."));
+        }
+        state.pending_followers = stair_pursuers(state, game_obj_db, player_loc);
 
         if let Some(GameObjects::Player(p)) = game_obj_db.get_mut(0) {
             if player_loc.2 > p.max_depth as i8 {
                 p.max_depth = player_loc.2 as u8 + 1;
             }            
         }
+        if state.stats.levels_reached.insert(player_loc.2 + 1) {
+            let s = format!("Descended to the {} level of the dungeon.", util::num_to_nth(player_loc.2 as u8 + 1));
+            state.add_journal_entry(&s);
+            for feeling in state.world_info.level_feelings(player_loc.2 + 1) {
+                state.msg_queue.push_back(Message::info(feeling));
+            }
+        }
 
-        return cost;
+        return 1.0;
     } else {
-        if *tile == map::Tile::StairsUp {
+        if tile == map::Tile::StairsUp {
+            if !game_obj_db.set_to_loc(0, (player_loc.0, player_loc.1, player_loc.2 - 1)) {
+                state.msg_queue.push_back(Message::info("Something is blocking the way up."));
+                return 0.0;
+            }
             state.msg_queue.push_back(Message::info("You climb the stairway."));
-            game_obj_db.set_to_loc(0, (player_loc.0, player_loc.1, player_loc.2 - 1));
+            state.pending_followers = stair_pursuers(state, game_obj_db, player_loc);
             
             if player_loc.2 == 1 {
                 state.msg_queue.push_back(Message::info("Fresh air!"));
@@ -1034,33 +1739,32 @@ fn take_stairs(state: &mut GameState, game_obj_db: &mut GameObjectDB, down: bool
 
 fn check_closed_gate(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8)) {
     let player_loc = game_obj_db.get(0).unwrap().get_loc();
-    let mut rng = rand::thread_rng();
     if player_loc == loc {
         let mut options: Vec<usize> = (0..util::ADJ.len()).collect();            
-        options.shuffle(&mut rng);
+        options.shuffle(&mut state.rng);
         while !options.is_empty() {
             let id = options.pop().unwrap();
             let landing_spot = (loc.0 + util::ADJ[id].0, loc.1 + util::ADJ[id].1, loc.2);
-            if !state.map[&landing_spot].passable() {
+            if !tile_at(&state.map, &landing_spot).passable() {
                 continue;
             }
-            if !game_obj_db.location_occupied(&landing_spot) {
+            if !game_obj_db.location_occupied(&landing_spot) && game_obj_db.set_to_loc(0, landing_spot) {
                 state.msg_queue.push_back(Message::info("You are shoved out of the way by the falling gate!"));
-                game_obj_db.set_to_loc(0, landing_spot);
                 return;
             }
         }
 
-        // If we get here there are no available landing spots. What to do?
-        // Just crush the player to death??           
+        // If we get here there are no available landing spots, so rather than crush the player
+        // the gate jams partway down.
+        jam_gate(state, loc, 0, "The gate grinds to a halt on top of you!");
     } else if let Some(obj_id) = game_obj_db.npc_at(&loc) {
         // This is untested because I don't have NPCs aside from villagers in the game...
         let mut options: Vec<usize> = (0..util::ADJ.len()).collect();            
-        options.shuffle(&mut rng);
+        options.shuffle(&mut state.rng);
         while !options.is_empty() {
             let id = options.pop().unwrap();                
             let landing_spot = (loc.0 + util::ADJ[id].0, loc.1 + util::ADJ[id].1, loc.2);
-            if !state.map[&landing_spot].passable() {
+            if !tile_at(&state.map, &landing_spot).passable() {
                 continue;
             }
             if landing_spot != player_loc && !game_obj_db.location_occupied(&landing_spot) {
@@ -1069,20 +1773,31 @@ fn check_closed_gate(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc:
                 let start_loc = npc.get_loc();
                 let npc_id = npc.obj_id();
                 
-                game_obj_db.set_to_loc(npc_id, landing_spot);
+                if !game_obj_db.set_to_loc(npc_id, landing_spot) {
+                    continue;
+                }
                 
                 let s = format!("{} is shoved out of the way by the falling gate!", npc_name.with_def_article());
                 state.msg_queue.push_back(Message::new(npc_id, start_loc, &s, ""));
                 
                 game_obj_db.remove_from_loc(npc_id, start_loc);                    
-                game_obj_db.stepped_on_event(state, landing_spot);
+                game_obj_db.stepped_on_event(state, landing_spot, npc_id);
 
                 return;
             }
         }
+
+        let npc_name = game_obj_db.get(obj_id).unwrap().get_fullname();
+        let s = format!("The gate grinds to a halt on top of {}!", npc_name.with_def_article());
+        jam_gate(state, loc, obj_id, &s);
     }
 }
 
+fn jam_gate(state: &mut GameState, loc: (i32, i32, i8), obj_id: usize, msg: &str) {
+    state.msg_queue.push_back(Message::new(obj_id, loc, msg, "You hear a screech of metal."));
+    state.map.insert(loc, Tile::Gate(DoorState::Open));
+}
+
 fn firepit_msg(num: u8) -> &'static str {
     if num == 0 {
         "An old fire pit -- some previous adventurer?"
@@ -1097,7 +1812,7 @@ fn firepit_msg(num: u8) -> &'static str {
     }
 }
 
-fn maybe_fight(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8), gui: &mut GameUI, confused: bool) -> f32 {
+fn maybe_fight(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8), gui: &mut dyn UserInterface, confused: bool) -> f32 {
     if let Some(npc_id) = game_obj_db.npc_at(&loc) {
         let npc = game_obj_db.get_mut(npc_id).unwrap();
         let (npc_name, attitude) = if let GameObjects::NPC(other) = npc {
@@ -1140,28 +1855,39 @@ fn maybe_fight(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc: (i32,
     0.0
 }
 
-fn random_open_sq(state: &mut GameState, game_obj_db: &GameObjectDB, level: i8) -> (i32, i32, i8) {
-    let mut rng = rand::thread_rng();
+// Random sampling is usually quick but on a level that's mostly (or entirely) blocked it could spin
+// forever, so after a bounded number of tries fall back to checking every square. Returns None if
+// there's nowhere free on the level at all.
+fn random_open_sq(state: &mut GameState, game_obj_db: &GameObjectDB, level: i8) -> Option<(i32, i32, i8)> {
 
     let all_sqs_on_level: Vec<(i32, i32, i8)> = state.map.keys()
         .filter(|k| k.2 == level)
         .map(|k| *k).collect();
+    if all_sqs_on_level.is_empty() {
+        return None;
+    }
 
-    loop {
-        let i = rng.gen_range(0, all_sqs_on_level.len());
-        let loc = all_sqs_on_level[i];
-        if state.map[&loc].passable_dry_land() && !game_obj_db.blocking_obj_at(&loc) {
-            return loc;
+    let map = &state.map;
+    let open = |loc: &(i32, i32, i8)| map[loc].passable_dry_land() && !game_obj_db.blocking_obj_at(loc);
+    for _ in 0..1000 {
+        let loc = all_sqs_on_level[state.rng.gen_range(0, all_sqs_on_level.len())];
+        if open(&loc) {
+            return Some(loc);
         }
     }
+
+    all_sqs_on_level.iter()
+                    .filter(|loc| open(loc))
+                    .copied()
+                    .choose(&mut state.rng)
 }
 
 // Stuff that happens after someone steps on a square. I could probably move a bunch of the code here for
 // stepping on lava, etc. It's a bit awkward right now because Player and NPC are separate types and I can't
 // just pass a reference in, but if I eventually need to, I can sort out who exactly stepped on the square via
 // the obj_id (0 is always the player)
-fn land_on_location(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8), _obj_id: usize) {
-    game_obj_db.stepped_on_event(state, loc);
+fn land_on_location(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8), obj_id: usize) {
+    game_obj_db.stepped_on_event(state, loc, obj_id);
 
     // for special in game_objs.special_sqs_at_loc(&loc) {
     //     if special.special_sq.as_ref().unwrap().get_tile() == Tile::TeleportTrap {
@@ -1186,7 +1912,7 @@ fn check_for_obstacles(state: &mut GameState, game_obj_db: &mut GameObjectDB, ob
                 return 0.0;
             }
 
-            if agent.ability_check(Ability::Str) < oi.1 {
+            if agent.ability_check(Ability::Str, &mut state.rng) < oi.1 {
                 let msg = util::format_msg(obj_id, "to be", "held fast by the web!", game_obj_db);
                 state.msg_queue.push_back(Message::new(obj_id, loc, &msg, ""));
                 return 1.0;
@@ -1205,7 +1931,7 @@ fn check_for_obstacles(state: &mut GameState, game_obj_db: &mut GameObjectDB, ob
             }
         } else if !flying && oi.2 == "rubble" {
             let agent = game_obj_db.as_person(obj_id).unwrap();
-            if agent.ability_check(Ability::Dex) <= 12 {            
+            if agent.ability_check(Ability::Dex, &mut state.rng) <= 12 {            
                 let msg = util::format_msg(obj_id, "stumble", "over the rubble!", game_obj_db);
                 state.msg_queue.push_back(Message::new(obj_id, loc, &msg, ""));
                 return 1.0;
@@ -1217,15 +1943,30 @@ fn check_for_obstacles(state: &mut GameState, game_obj_db: &mut GameObjectDB, ob
 }
 
 pub fn take_step(state: &mut GameState, game_obj_db: &mut GameObjectDB, obj_id: usize, start_loc: (i32, i32, i8), next_loc: (i32, i32, i8), flying: bool) -> (f32, bool) {    
+    // Someone caught in a bear trap can still fight back, but they aren't going anywhere
+    let held = if obj_id == 0 {
+        game_obj_db.player().unwrap().has_status(Status::Held)
+    } else {
+        game_obj_db.npc(obj_id).is_some_and(|npc| npc.has_status(Status::Held))
+    };
+    if held {
+        if obj_id == 0 {
+            state.msg_queue.push_back(Message::info("You are held fast!"));
+        }
+        return (1.0, false);
+    }
+
     let cost = check_for_obstacles(state, game_obj_db, obj_id, start_loc, flying);
     if cost > 0.0 { return (cost, false); }
 
-    game_obj_db.set_to_loc(obj_id, next_loc);
+    if !game_obj_db.set_to_loc(obj_id, next_loc) {
+        return (0.0, false);
+    }
     
     // This whole next section of checking for special floor effects is gross and ugly
     // but I don't know what the final form will look like after I have more kinds of 
     // effects so I'm going to leave it gross until it's more fixed.
-    game_obj_db.stepped_on_event(state, next_loc);
+    game_obj_db.stepped_on_event(state, next_loc, obj_id);
 
     let mut teleport: bool = false;
     for special in game_obj_db.special_sqs_at_loc(&next_loc) {
@@ -1234,8 +1975,14 @@ pub fn take_step(state: &mut GameState, game_obj_db: &mut GameObjectDB, obj_id:
         }
     }
     if teleport {        
-        let sq = random_open_sq(state, game_obj_db, start_loc.2);
-        game_obj_db.set_to_loc(obj_id, sq);                
+        // If there's nowhere to land, the trap just fizzles
+        let sq = match random_open_sq(state, game_obj_db, start_loc.2) {
+            Some(sq) => sq,
+            None => return (1.0, true),
+        };
+        if !game_obj_db.set_to_loc(obj_id, sq) {
+            return (1.0, true);
+        }
         if obj_id == 0 {
             state.msg_queue.push_back(Message::new(0, sq, "You have a feeling of vertigo!", "You have a feeling of vertigo!"));
         } else {
@@ -1248,16 +1995,16 @@ pub fn take_step(state: &mut GameState, game_obj_db: &mut GameObjectDB, obj_id:
     (1.0, true)
 }
 
-fn do_move(state: &mut GameState, game_obj_db: &mut GameObjectDB, dir: &str, gui: &mut GameUI) -> f32 {
+fn do_move(state: &mut GameState, game_obj_db: &mut GameObjectDB, dir: &str, gui: &mut dyn UserInterface) -> f32 {
     let player = game_obj_db.player().unwrap();
     let confused = player.has_status(Status::Confused);
     let flying = player.has_status(Status::Flying);
 
     // if the player is confused, they walk in their intended direction 1/5 of the time, otherwise
     // they stagger in a random direction.
-    let mv = if confused && rand::thread_rng().gen_range(0.0, 1.0) < 0.8 {
+    let mv = if confused && state.rng.gen_range(0.0, 1.0) < 0.8 {
         state.msg_queue.push_back(Message::info("You stagger."));
-        let roll = rand::thread_rng().gen_range(0, 8);
+        let roll = state.rng.gen_range(0, 8);
         match roll {
             0 => get_move_tuple("n"),
             1 => get_move_tuple("e"),
@@ -1273,12 +2020,14 @@ fn do_move(state: &mut GameState, game_obj_db: &mut GameObjectDB, dir: &str, gui
     };
 
     let start_loc = game_obj_db.get(0).unwrap().get_loc();
-    let start_tile = state.map[&start_loc];
+    let start_tile = tile_at(&state.map, &start_loc);
     let next_loc = (start_loc.0 + mv.0, start_loc.1 + mv.1, start_loc.2);
-    let tile = state.map[&next_loc];
+    let tile = tile_at(&state.map, &next_loc);
     
     if game_obj_db.blocking_obj_at(&next_loc) {
         return maybe_fight(state, game_obj_db, next_loc, gui, confused);
+    } else if map::cuts_corner(&state.map, start_loc, next_loc) {
+        state.msg_queue.push_back(Message::new(0, next_loc, "You can't squeeze through there.", "You can't squeeze through there."));
     } else if tile.passable() || (tile.can_be_flown_over() && flying) {
         let (cost, moved) = take_step(state, game_obj_db, 0, start_loc, next_loc, flying);
 
@@ -1295,14 +2044,29 @@ fn do_move(state: &mut GameState, game_obj_db: &mut GameObjectDB, dir: &str, gui
             },
             Tile::Ice => if !flying { state.msg_queue.push_back(Message::info("The ice is slippery!")) },
             Tile::Well => if !flying { state.msg_queue.push_back(Message::info("There is a well here.")) },
+            Tile::Bed => state.msg_queue.push_back(Message::info("You clamber over a bed.")),
+            Tile::Table => state.msg_queue.push_back(Message::info("You clamber over a table.")),
+            Tile::Counter => state.msg_queue.push_back(Message::info("You clamber over the counter.")),
             Tile::Lava => if !flying { state.msg_queue.push_back(Message::info("MOLTEN LAVA!")) },
             Tile::FirePit => if !flying { state.msg_queue.push_back(Message::info("You've stepped in the fire!")) },
             Tile::OldFirePit(n) => state.msg_queue.push_back(Message::new(0, next_loc, firepit_msg(n), "You feel the remains of an old firepit.")),
             Tile::Portal => state.msg_queue.push_back(Message::new(0, next_loc, "Where could this lead?", "")),
+            Tile::BrokenWindow(_) => state.msg_queue.push_back(Message::new(0, next_loc, "You climb through the broken window.", "You clamber over twisted bars.")),
             Tile::Shrine(stype) => {
+                let place = if next_loc.2 == 0 {
+                    "on the surface".to_string()
+                } else {
+                    format!("on the {} level of the dungeon", util::num_to_nth(next_loc.2 as u8))
+                };
                 match stype {
-                    ShrineType::Woden => state.msg_queue.push_back(Message::new(0, next_loc, "A shrine to Woden.", "")),
-                    ShrineType::Crawler => state.msg_queue.push_back(Message::new(0, next_loc, "The misshappen altar makes your skin crawl.", "You have a feeling of unease.")),
+                    ShrineType::Woden => {
+                        state.msg_queue.push_back(Message::new(0, next_loc, "A shrine to Woden.", ""));
+                        state.add_journal_entry(&format!("Found a shrine to Woden {}.", place));
+                    },
+                    ShrineType::Crawler => {
+                        state.msg_queue.push_back(Message::new(0, next_loc, "The misshappen altar makes your skin crawl.", "You have a feeling of unease."));
+                        state.add_journal_entry(&format!("Found a misshappen altar {}.", place));
+                    },
                 }
             },
             _ => {
@@ -1312,6 +2076,10 @@ fn do_move(state: &mut GameState, game_obj_db: &mut GameObjectDB, dir: &str, gui
             },            
         }
 
+        if !state.autopickup.is_empty() && !flying {
+            auto_pick_up(state, game_obj_db, next_loc);
+        }
+
         let items = game_obj_db.descs_at_loc(&next_loc);
         let item_count = items.len();                        
         if item_count == 1 {
@@ -1335,13 +2103,21 @@ fn do_move(state: &mut GameState, game_obj_db: &mut GameObjectDB, dir: &str, gui
         return cost;
     } else if tile == Tile::Door(DoorState::Closed) {
         // Bump to open doors. I might make this an option later
-        do_open(state, next_loc);
+        do_open(state, next_loc, game_obj_db);
         return 1.0;
     } else if tile == Tile::Door(DoorState::Locked) {  
         state.msg_queue.push_back(Message::new(0, next_loc, "You door is locked.", "The door is locked."));
         return 1.0;
+    } else if tile == Tile::Gate(DoorState::Closed) && town::open_town_gate(state, next_loc) {
+        return 1.0;
     } else if tile == Tile::Gate(DoorState::Closed) || tile == Tile::Gate(DoorState::Locked) {
         state.msg_queue.push_back(Message::new(0, next_loc, "A portcullis bars your way.", "A portcullis bars your way."));        
+    } else if let Tile::Window(_) = tile {
+        state.msg_queue.push_back(Message::new(0, next_loc, "The window is barred.", "You feel iron bars."));
+    } else if tile == Tile::Mountain {
+        return climb_mountain(state, game_obj_db, start_loc, next_loc);
+    } else if tile == Tile::SnowPeak {
+        state.msg_queue.push_back(Message::new(0, next_loc, "The icy peak is too sheer to climb.", "You feel a sheer, icy rock face."));
     } else  {
         state.msg_queue.push_back(Message::new(0, next_loc, "You cannot go that way.", "You cannot go that way."));
     }
@@ -1349,69 +2125,124 @@ fn do_move(state: &mut GameState, game_obj_db: &mut GameObjectDB, dir: &str, gui
     0.0
 }
 
-// I don't know how real noise works but when I want to alert monsters to something noisy a player did, I'm
-// going to floodfill out to a certain radius. (Which closed doors muffling the noise)
-// Another semi-duplicate implementation of floodfill but this one does work a little differently than the others.
-fn floodfill_noise(state: &mut GameState, game_obj_db: &mut GameObjectDB, centre: (i32, i32, i8), radius: u8, _actor_id: usize) {
-    // find start point
-    let mut q = VecDeque::new();
-    let mut visited = HashSet::new();
-    let start = (centre, 0);
-
-    q.push_front(start);
-    while !q.is_empty() {
-        let pt = q.pop_front().unwrap();
-        let mut distance = pt.1;
-        if visited.contains(&pt) {
-            continue;
-        }
-        
-        visited.insert(pt);
-
-        match state.map[&pt.0] {
-            Tile::WoodWall | Tile::Wall => { continue; },
-            Tile::Door(DoorState::Closed) | Tile::Door(DoorState::Locked) | Tile::Window(_) => distance += 4,
-            _ => { distance += 1 },
-        }
-        
-        let loc = pt.0;
-        for adj in util::ADJ.iter() {
-            let n = (loc.0 + adj.0, loc.1 + adj.1, loc.2);
-            if distance > radius || !state.map.contains_key(&n) || state.map[&n] == Tile::WoodWall || state.map[&n] == Tile::Wall   {
-                continue;
-            }
+// Mountains aren't passable in the usual sense, but a determined adventurer can scramble over them.
+// It's slow going and a bad slip will hurt. (Snowy peaks are still too sheer to attempt.)
+fn climb_mountain(state: &mut GameState, game_obj_db: &mut GameObjectDB, start_loc: (i32, i32, i8), next_loc: (i32, i32, i8)) -> f32 {
+    let player = game_obj_db.player().unwrap();
+    let check = player.ability_check(Ability::Str, &mut state.rng);
 
-            q.push_back((n, distance));
+    if check >= 13 {
+        let (_, moved) = take_step(state, game_obj_db, 0, start_loc, next_loc, false);
+        if moved {
+            state.msg_queue.push_back(Message::info("You clamber up the rocky slope."));
         }
+    } else if check < 6 {
+        state.msg_queue.push_back(Message::info("You lose your footing and tumble down the slope!"));
+        let dmg = state.rng.gen_range(1, 5);
+        let player = game_obj_db.player().unwrap();
+        player.damaged(state, dmg, battle::DamageType::Bludgeoning, 0, "a fall");
+    } else {
+        state.msg_queue.push_back(Message::info("You can't find a good handhold."));
     }
 
-    // Now we have to alert/wake up any monsters in the visited sqs
-    for loc in visited {
-        if let Some(npc_id) = game_obj_db.npc_at(&loc.0) {
-            npc::heard_noise(npc_id, centre, state, game_obj_db);
+    3.0
+}
+
+// I don't know how real noise works but when I want to alert monsters to something noisy a player did, I'm
+// going to floodfill out to a certain radius. (Which closed doors muffling the noise) Monsters are told how
+// loud the noise still was when it reached them, which is however much of the radius was left over.
+fn floodfill_noise(state: &mut GameState, game_obj_db: &mut GameObjectDB, centre: (i32, i32, i8), radius: u8, _actor_id: usize) {
+    let heard = util::floodfill(centre, radius as u32, |loc| {
+        // Noise passing through a closed door or window is muffled
+        let cost = match tile_at(&state.map, &loc) {
+            Tile::WoodWall | Tile::Wall | Tile::Blank => return Vec::new(),
+            Tile::Door(DoorState::Closed) | Tile::Door(DoorState::Locked) | Tile::Window(_) => 4,
+            _ => 1,
+        };
+
+        util::ADJ.iter()
+                 .map(|adj| (loc.0 + adj.0, loc.1 + adj.1, loc.2))
+                 .filter(|n| !matches!(tile_at(&state.map, n), Tile::WoodWall | Tile::Wall | Tile::Blank))
+                 .map(|n| (n, cost))
+                 .collect()
+    });
+
+    // Now we have to alert/wake up any monsters in the squares the noise reached
+    for (loc, dist) in heard.iter() {
+        if let Some(npc_id) = game_obj_db.npc_at(loc) {
+            let volume = (radius as u32).saturating_sub(*dist) as u8;
+            npc::heard_noise(npc_id, centre, volume, state, game_obj_db);
         }
     }    
 }
 
 fn bash(state: &mut GameState, loc: (i32, i32, i8), game_obj_db: &mut GameObjectDB) -> f32 {
-    let tile = state.map[&loc];
+    let tile = tile_at(&state.map, &loc);
 
     if tile == Tile::Door(DoorState::Locked) || tile == Tile::Door(DoorState::Closed) {
-        floodfill_noise(state, game_obj_db, loc, 10, 0);
+        // A door crashing down is a lot louder than the thud of one that holds
         let player = game_obj_db.player().unwrap();
-        if player.ability_check(Ability::Str) > 17 {
+        if player.ability_check(Ability::Str, &mut state.rng) > 17 {
             state.msg_queue.push_back(Message::info("BAM! You knock down the door!"));
-            state.map.insert(loc, Tile::Door(DoorState::Broken));           
+            state.map.insert(loc, Tile::Door(DoorState::Broken));
+            floodfill_noise(state, game_obj_db, loc, 10, 0);
+            town::trespass(state, game_obj_db, loc);
         } else {
             state.msg_queue.push_back(Message::info("The door holds firm."));
+            floodfill_noise(state, game_obj_db, loc, 4, 0);
         }        
+    } else if let Tile::Window(ch) = tile {
+        // Windows are barred so it takes a fair bit of oomph to break through them, and it's
+        // even noisier than bashing a door.
+        let player = game_obj_db.player().unwrap();
+        if player.ability_check(Ability::Str, &mut state.rng) > 18 {
+            state.msg_queue.push_back(Message::info("CRASH! You smash through the window bars!"));
+            state.map.insert(loc, Tile::BrokenWindow(ch));
+            floodfill_noise(state, game_obj_db, loc, 12, 0);
+        } else {
+            state.msg_queue.push_back(Message::info("The bars rattle but hold."));
+            floodfill_noise(state, game_obj_db, loc, 5, 0);
+        }
+    } else if tile == Tile::GraniteWall {
+        // Granite is the bedrock shell of the dungeon and nothing the player does will dent it
+        state.msg_queue.push_back(Message::info("Ouch! You slam yourself into the solid granite!"));
+        let dmg = state.rng.gen_range(1, 6);
+        let player = game_obj_db.player().unwrap();
+        player.damaged(state, dmg, battle::DamageType::Bludgeoning, 0, "a granite wall");
     } else if tile == Tile::Wall || tile == Tile::WoodWall {
-        state.msg_queue.push_back(Message::info("Ouch! You slam yourself into the wall!"));
+        floodfill_noise(state, game_obj_db, loc, 10, 0);
         let player = game_obj_db.player().unwrap();
-        player.damaged(state, rand::thread_rng().gen_range(1, 6), battle::DamageType::Bludgeoning, 0, "a wall");
+        if tile == Tile::Wall && loc.2 > 0 && player.ability_check(Ability::Str, &mut state.rng) > 21 {
+            // Dungeon walls are old and crumbling and a truly mighty blow can bring one down
+            state.msg_queue.push_back(Message::info("CRUNCH! The wall collapses into rubble!"));
+            state.map.insert(loc, Tile::StoneFloor);
+            let rubble = Item::rubble(game_obj_db, loc);
+            game_obj_db.add(rubble);
+        } else {
+            state.msg_queue.push_back(Message::info("Ouch! You slam yourself into the wall!"));
+            let dmg = state.rng.gen_range(1, 6);
+            player.damaged(state, dmg, battle::DamageType::Bludgeoning, 0, "a wall");
+        }
     } else if  game_obj_db.blocking_obj_at(&loc) {
         // I don't yet have blocking_objs that aren't creatures...
         battle::knock_back(state, game_obj_db, loc);
+    } else if let Some((rubble_id, dc)) = game_obj_db.obstacles_at_loc(loc).iter()
+                                                     .find(|o| o.get_fullname() == "rubble")
+                                                     .map(|o| (o.obj_id(), o.item_dc)) {
+        floodfill_noise(state, game_obj_db, loc, 6, 0);
+        let player = game_obj_db.player().unwrap();
+        if player.ability_check(Ability::Str, &mut state.rng) >= dc {
+            state.msg_queue.push_back(Message::info("You heave the rubble aside and clear a path."));
+            game_obj_db.remove(rubble_id);
+            if state.rng.gen_range(0.0, 1.0) < 0.15 {
+                let mut ore = Item::get_item(game_obj_db, "lump of iron ore", &mut state.rng).unwrap();
+                ore.set_loc(loc);
+                game_obj_db.add(ore);
+                state.msg_queue.push_back(Message::info("You uncover a lump of iron ore."));
+            }
+        } else {
+            state.msg_queue.push_back(Message::info("You shift some of the rubble but the way is still blocked."));
+        }
     } else {
         // I should perhaps move them?
         state.msg_queue.push_back(Message::info("You flail about in a silly fashion."));
@@ -1420,8 +2251,9 @@ fn bash(state: &mut GameState, loc: (i32, i32, i8), game_obj_db: &mut GameObject
     1.0
 }
 
-fn chat_with(state: &mut GameState, gui: &mut GameUI, loc: (i32, i32, i8), game_obj_db: &mut GameObjectDB, dialogue: &DialogueLibrary) -> f32 {
+fn chat_with(state: &mut GameState, gui: &mut dyn UserInterface, loc: (i32, i32, i8), game_obj_db: &mut GameObjectDB, dialogue: &DialogueLibrary) -> f32 {
     let sbi = state.curr_sidebar_info(game_obj_db);
+    let mut energy_used = 1.0;
     if let Some(obj_id) = game_obj_db.npc_at(&loc) {
         let npc = game_obj_db.get_mut(obj_id).unwrap();
 
@@ -1438,7 +2270,10 @@ fn chat_with(state: &mut GameState, gui: &mut GameUI, loc: (i32, i32, i8), game_
                 shops::talk_to_grocer(state, obj_id, game_obj_db, dialogue, gui);
             },
             Some(Venue::Smithy) => {
-                shops::talk_to_smith(state, obj_id, game_obj_db, dialogue, gui);
+                energy_used = shops::talk_to_smith(state, obj_id, game_obj_db, dialogue, gui);
+            },
+            Some(Venue::Caravan(_)) => {
+                shops::talk_to_merchant(state, obj_id, game_obj_db, dialogue, gui);
             },
             _ => {
                 let mut ei = HashMap::new();
@@ -1450,50 +2285,142 @@ fn chat_with(state: &mut GameState, gui: &mut GameUI, loc: (i32, i32, i8), game_
         }           
     } else {
         if let Tile::Door(_) = state.map[&loc] {
-            state.msg_queue.push_back(Message::info("The door is ignoring you."));
+            town::knock(state, game_obj_db, loc);
         } else {
             state.msg_queue.push_back(Message::info("Oh no, talking to yourself?"));            
         } 
     }
 
-    1.0
+    energy_used
 }
 
-fn show_character_sheet(gui: &mut GameUI, player: &Player) {
-    let s = format!("{}, a {} level {}", player.get_fullname(), util::num_to_nth(player.level), player.role.desc());
-    let mut lines: Vec<&str> = vec![&s];
-    lines.push("");
-    let s = format!("Strength: {}", player.str);
-    lines.push(&s);
-    let s = format!("Dexterity: {}", player.dex);
-    lines.push(&s);
-    let s = format!("Constitution: {}", player.con);
-    lines.push(&s);
-    let s = format!("Charisma: {}", player.chr);
-    lines.push(&s);
-    let s = format!("Aptitude: {}", player.apt);
-    lines.push(&s);
-    lines.push("");
-    let s = format!("AC: {}    Hit Points: {}({})", player.ac, player.curr_hp, player.max_hp);
-    lines.push(&s);
-    let s = format!("XP: {}", player.xp);
-    lines.push(&s);
-    lines.push("");
+fn character_sheet_lines(player: &Player, stats: &scores::GameStats) -> Vec<String> {
+    let s = format!("{}, a {} level {} {}", player.get_fullname(), util::num_to_nth(player.level), player.race.desc(), player.role.desc());
+    let mut lines = vec![s];
+    let traits = player.race.traits();
+    if !traits.is_empty() {
+        lines.push(traits.to_string());
+    }
+    lines.push("".to_string());
+    lines.push(format!("Strength: {}", player.str));
+    lines.push(format!("Dexterity: {}", player.dex));
+    lines.push(format!("Constitution: {}", player.con));
+    lines.push(format!("Charisma: {}", player.chr));
+    lines.push(format!("Aptitude: {}", player.apt));
+    lines.push(format!("  ({}% xp, search radius {}, +{} to pick locks, ~{} turns to identify)", player.apt_xp_pct(), 
+        player.apt_search_radius(), player.apt_lockpick_bonus(), player.apt_identify_turns()));
+    lines.push("".to_string());
+    lines.push(format!("AC: {}    Hit Points: {}({})", player.ac, player.curr_hp, player.max_hp));
+    lines.push(format!("XP: {}", player.xp));
+    lines.push("".to_string());
 
     let dungeon_depth = if player.max_depth == 0 {
         String::from("You have not yet ventured into the dungeon.")
     } else {
         format!("You have been as far as the {} level of the dungeon.", util::num_to_nth(player.max_depth))
     };
-    lines.push(&dungeon_depth);
+    lines.push(dungeon_depth);
+    lines.push("".to_string());
+    lines.extend(stats.summary());
+
+    lines
+}
+
+fn show_character_sheet(gui: &mut dyn UserInterface, player: &Player, stats: &scores::GameStats) {
+    let lines = character_sheet_lines(player, stats);
+    let lines: Vec<&str> = lines.iter().map(AsRef::as_ref).collect();
+    gui.write_long_msg(&lines, true);
+}
+
+// What a single piece of readied gear is doing for the player
+fn gear_contribution(player: &Player, item: &Item) -> String {
+    let mut bits = Vec::new();
+    match item.item_type {
+        ItemType::Weapon => {
+            let dmg_type = format!("{:?}", item.dmg_type).to_lowercase();
+            bits.push(format!("{}d{} {}", item.dmg_dice, item.dmg_die, dmg_type));
+            if item.attack_bonus != 0 {
+                bits.push(format!("{:+} to hit", item.attack_bonus));
+            }
+            if item.attributes & items::IA_TWO_HANDED > 0 {
+                bits.push("two-handed".to_string());
+            }
+        },
+        ItemType::Shield => {
+            bits.push(format!("AC {:+}", item.ac_bonus));
+            bits.push(format!("{:.0}% to block", player.block_chance() * 100.0));
+        },
+        _ => {
+            if item.ac_bonus != 0 {
+                bits.push(format!("AC {:+}", item.ac_bonus));
+            }
+        },
+    }
+
+    if item.attributes & items::IA_MED_ARMOUR > 0 {
+        bits.push("Dex bonus capped at +2, -2 stealth".to_string());
+    } else if item.attributes & items::IA_HEAVY_ARMOUR > 0 {
+        bits.push("no Dex bonus, halves stealth".to_string());
+    }
+    if item.attributes & items::IA_WARM > 0 {
+        bits.push("protects from cold".to_string());
+    }
+    if item.attributes & items::IA_FIRE_RESISTANT > 0 {
+        bits.push("protects from heat".to_string());
+    }
+
+    bits.join(", ")
+}
+
+fn equipment_lines(player: &Player) -> Vec<String> {
+    let mut lines = vec!["You have readied:".to_string(), "".to_string()];
+    let slots = [("Weapon", ItemType::Weapon), ("Armour", ItemType::Armour), ("Shield", ItemType::Shield), ("Cloak", ItemType::Cloak)];
+    for (label, item_type) in slots.iter() {
+        let item = player.inventory.iter().find_map(|obj| match obj {
+            GameObjects::Item(i) if i.equiped && i.item_type == *item_type => Some(i),
+            _ => None,
+        });
+        let line = match item {
+            Some(i) => format!("{:<8}{} ({})", label, i.get_fullname(), gear_contribution(player, i)),
+            None => format!("{:<8}nothing", label),
+        };
+        lines.push(line);
+    }
+
+    lines.push("".to_string());
+    lines.push(format!("AC: {}    Stealth: {}", player.ac, player.stealth_score));
+    let failure = player.spell_failure_chance();
+    if failure > 0.0 {
+        lines.push(format!("Your gear gives a {:.0}% chance for spells to fail.", failure * 100.0));
+    }
 
+    lines
+}
+
+fn show_equipment(gui: &mut dyn UserInterface, player: &Player) {
+    let lines = equipment_lines(player);
+    let lines: Vec<&str> = lines.iter().map(AsRef::as_ref).collect();
     gui.write_long_msg(&lines, true);
 }
 
-fn show_inventory(gui: &mut GameUI, state: &mut GameState, game_obj_db: &mut GameObjectDB) {
+fn show_journal(gui: &mut dyn UserInterface, state: &mut GameState) {
+    if state.journal.is_empty() {
+        state.msg_queue.push_back(Message::info("Your journal is empty."));
+        return;
+    }
+
+    let mut lines = vec!["Your journal:".to_string(), "".to_string()];
+    for (timestamp, entry) in state.journal.iter() {
+        lines.push(format!("{} - {}", timestamp, entry));
+    }
+    let lines: Vec<&str> = lines.iter().map(AsRef::as_ref).collect();
+    gui.write_long_msg(&lines, true);
+}
+
+fn show_inventory(gui: &mut dyn UserInterface, state: &mut GameState, game_obj_db: &mut GameObjectDB) {
     let p = game_obj_db.player().unwrap();
-    let menu = p.inv_menu(0);
     let purse = p.purse;
+    let (total_value, total_weight) = p.inv_totals();
 
     let money = if purse == 1 {
         String::from("$) a single zorkmid to your name")
@@ -1502,19 +2429,35 @@ fn show_inventory(gui: &mut GameUI, state: &mut GameState, game_obj_db: &mut Gam
         s
     };
 
-    if menu.is_empty() && purse == 0 {
-        state.msg_queue.push_back(Message::info("You are empty handed."));
-    } else {
+    // Pressing s in the inventory view flips between slot order and grouping by category
+    loop {
+        let menu = p.inv_menu_sorted(0, state.sort_inventory);
+        if menu.is_empty() && purse == 0 {
+            state.msg_queue.push_back(Message::info("You are empty handed."));
+            return;
+        }
+
         let mut m: Vec<(String, bool)> = menu.iter().map(|m| (m.0.to_string(), m.1)).collect();        
         if purse > 0 {
-            m.insert(0, (money, true));
+            m.insert(0, (money.clone(), true));
         }
+        m.push((String::from(""), true));
+        m.push((format!("Total: {}gp, wt {}", total_value, total_weight), true));
         
-        gui.show_in_side_pane("You are carrying:", &m);
+        match gui.show_in_side_pane("You are carrying: (s to sort)", &m) {
+            Some('s') => state.sort_inventory = !state.sort_inventory,
+            _ => return,
+        }
     }
 }
 
 fn dump_level(state: &GameState, level: i8) {
+    for line in level_map_lines(state, level) {
+        println!("{}", line);
+    }
+}
+
+fn level_map_lines(state: &GameState, level: i8) -> Vec<String> {
     let dungeon_sqs:  Vec<(i32, i32, i8)> = state.map.keys()
                                                     .filter(|k| k.2 == level)
                                                     .copied()
@@ -1528,7 +2471,7 @@ fn dump_level(state: &GameState, level: i8) {
         let row = sq.0 - min_row;
         let col = sq.1 - min_col;
         let ch = match state.map[&sq] {
-                Tile::Wall => '#',
+                Tile::Wall | Tile::GraniteWall => '#',
                 Tile::StoneFloor => '.',
                 Tile::Door(_) => '+',
                 Tile::Shrine(_) => '_',
@@ -1544,6 +2487,7 @@ fn dump_level(state: &GameState, level: i8) {
         chars[(row * width + col) as usize] = ch;
     }
 
+    let mut lines = Vec::new();
     let mut c = 0;
     let mut s = String::from("");
     while c < chars.len() {            
@@ -1551,13 +2495,62 @@ fn dump_level(state: &GameState, level: i8) {
         c += 1;
 
         if c % width as usize == 0 {
-            println!("{}", s);
+            lines.push(s);
             s = String::from("");
         }
-    }        
+    }
+
+    lines
+}
+
+// Write a plain text record of the character's final state, named after the character and
+// the time the game ended so one run doesn't overwrite the last
+fn write_morgue_file(state: &GameState, game_obj_db: &mut GameObjectDB, gui: &dyn UserInterface, fate: &str) {
+    let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs(),
+        Err(_) => 0,
+    };
+    let player_name = game_obj_db.get(0).unwrap().get_fullname();
+    let filename = format!("{}-{}.morgue.txt", file_safe_name(&player_name), timestamp);
+    let lines = morgue_lines(state, game_obj_db, gui, fate);
+
+    match File::create(&filename) {
+        Ok(mut buffer) => {
+            if buffer.write_all(lines.join("\n").as_bytes()).is_err() {
+                eprintln!("Unable to write the morgue file.");
+            }
+        },
+        Err(_) => eprintln!("Unable to create the morgue file."),
+    }
 }
 
-fn wiz_command(state: &mut GameState, gui: &mut GameUI, game_obj_db: &mut GameObjectDB, mf: &MonsterFactory)  {
+fn morgue_lines(state: &GameState, game_obj_db: &mut GameObjectDB, gui: &dyn UserInterface, fate: &str) -> Vec<String> {
+    let player = game_obj_db.player().unwrap();
+    let player_loc = player.get_loc();
+    let mut lines = vec![format!("{}: {}, on turn {}.", player.get_fullname(), fate, state.turn), "".to_string()];
+    lines.extend(character_sheet_lines(player, &state.stats));
+    lines.push("".to_string());
+
+    lines.push("Inventory:".to_string());
+    if player.purse > 0 {
+        lines.push(format!("$) {} gold pieces", player.purse));
+    }
+    lines.extend(player.inv_menu(0).into_iter().map(|i| i.0));
+    lines.push("".to_string());
+
+    if player_loc.2 > 0 {
+        lines.push(format!("The {} level of the dungeon:", util::num_to_nth(player_loc.2 as u8)));
+        lines.extend(level_map_lines(state, player_loc.2));
+        lines.push("".to_string());
+    }
+
+    lines.push("Last messages:".to_string());
+    lines.extend(gui.recent_messages(MORGUE_MSG_COUNT));
+
+    lines
+}
+
+fn wiz_command(state: &mut GameState, gui: &mut dyn UserInterface, game_obj_db: &mut GameObjectDB, mf: &MonsterFactory)  {
     let player_loc = game_obj_db.get(0).unwrap().get_loc();
     let sbi = state.curr_sidebar_info(game_obj_db);
     if let Some(result) = gui.query_user(":", 20, Some(&sbi)) {
@@ -1567,12 +2560,12 @@ fn wiz_command(state: &mut GameState, gui: &mut GameUI, game_obj_db: &mut GameOb
             println!("{:?}", player_loc);
         } else if result == "!heal" {
             let loc = (player_loc.0, player_loc.1, player_loc.2);
-            let mut poh = items::Item::get_item(game_obj_db,"potion of healing").unwrap();
+            let mut poh = items::Item::get_item(game_obj_db,"potion of healing", &mut state.rng).unwrap();
             poh.set_loc(loc);
             game_obj_db.add(poh);
         } else if result == "goblin" {
             let loc = (player_loc.0, player_loc.1 - 1, player_loc.2);
-            mf.monster("goblin", loc, game_obj_db);
+            mf.monster("goblin", loc, game_obj_db, &mut state.rng);
         } else if result == "dump level" {
             if player_loc.2 == 0 {
                 state.msg_queue.push_back(Message::info("Uhh the wilderness is too big to dump."));
@@ -1593,7 +2586,238 @@ fn wiz_command(state: &mut GameState, gui: &mut GameUI, game_obj_db: &mut GameOb
     }
 }
 
-fn confirm_quit(state: &GameState, gui: &mut GameUI, game_obj_db: &mut GameObjectDB) -> Result<(), ExitReason> {
+fn monsters_nearby(state: &GameState, game_obj_db: &GameObjectDB, loc: (i32, i32, i8)) -> bool {
+    for sq in state.curr_visible.iter() {
+        if util::distance(loc.0, loc.1, sq.0, sq.1) > 10.0 {
+            continue;
+        }
+
+        if let Some(objs) = game_obj_db.obj_locs.get(sq) {
+            for id in objs.iter() {
+                if let Some(GameObjects::NPC(npc)) = game_obj_db.get(*id) {
+                    if npc.is_monster || npc.attitude == Attitude::Hostile {
+                        return true;
+                    }
+                }
+            }
+        }
+    }
+
+    false
+}
+
+// Everything the rest of the world does once the player has acted. Shared by the main loop
+// and pass_turn() so the two can't drift apart
+fn end_of_turn(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface, monster_fac: &MonsterFactory) -> Result<(), ExitReason> {
+    let p = game_obj_db.player().unwrap();
+    effects::check_statuses(p, state);
+    p.digest(state);
+    p.spoil_food(state);
+    p.study_items(state);
+    if state.thirst {
+        p.dehydrate(state);
+    }
+    effects::check_recall(state, game_obj_db);
+    effects::check_temperature(state, game_obj_db);
+
+    town::check_visitors(state, game_obj_db);
+    town::check_town_gates(state, game_obj_db);
+    check_wandering_monsters(state, game_obj_db, monster_fac);
+    check_stair_followers(state, game_obj_db);
+    record_scent(state, game_obj_db);
+    game_obj_db.do_npc_turns(state);
+    game_obj_db.update_listeners(state, EventType::Update);
+    game_obj_db.update_listeners(state, EventType::EndOfTurn);
+    
+    check_event_queue(state, game_obj_db, gui)
+}
+
+// For multi-step actions like fast travel and running, where each step after the first
+// is a full turn for the rest of the world
+fn pass_turn(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface, monster_fac: &MonsterFactory, saves: &SaveOptions) -> Result<(), ExitReason> {
+    end_of_turn(state, game_obj_db, gui, monster_fac)?;
+    advance_turn(state, game_obj_db, saves);
+
+    Ok(())
+}
+
+fn open_neighbours(state: &GameState, loc: (i32, i32, i8)) -> usize {
+    util::ADJ.iter()
+             .map(|d| (loc.0 + d.0, loc.1 + d.1, loc.2))
+             .filter(|sq| state.map.contains_key(sq) && state.map[sq].passable())
+             .count()
+}
+
+// Classic corridor running: keep stepping in a direction until something interesting happens.
+// We stop at walls, doors, junctions or openings (ie., the number of open squares around the
+// player changes), on squares with items, whenever a monster comes into view or if anything
+// generates a message.
+fn run(state: &mut GameState, game_obj_db: &mut GameObjectDB, dir: &str, gui: &mut dyn UserInterface, monster_fac: &MonsterFactory, saves: &SaveOptions) -> Result<f32, ExitReason> {
+    let player = game_obj_db.player().unwrap();
+    if player.has_status(Status::Confused) {
+        state.msg_queue.push_back(Message::info("You're too confused to run."));
+        return Ok(0.0);
+    }
+
+    let start_loc = player.get_loc();
+    if monsters_nearby(state, game_obj_db, start_loc) {
+        state.msg_queue.push_back(Message::info("Not with enemies nearby!"));
+        return Ok(0.0);
+    }
+
+    // The first step is the player's regular move for this turn
+    let cost = do_move(state, game_obj_db, dir, gui);
+    let mv = get_move_tuple(dir);
+    let mut curr_loc = game_obj_db.get(0).unwrap().get_loc();
+    if curr_loc == start_loc {
+        return Ok(cost);
+    }
+    let mut open_count = open_neighbours(state, curr_loc);
+
+    loop {
+        if !state.msg_queue.is_empty() || !game_obj_db.descs_at_loc(&curr_loc).is_empty() {
+            break;
+        }
+
+        let door_adj = util::ADJ.iter()
+                                .map(|d| (curr_loc.0 + d.0, curr_loc.1 + d.1, curr_loc.2))
+                                .any(|sq| matches!(state.map.get(&sq), Some(Tile::Door(_))));
+        if door_adj {
+            break;
+        }
+
+        let next_loc = (curr_loc.0 + mv.0, curr_loc.1 + mv.1, curr_loc.2);
+        match state.map.get(&next_loc) {
+            Some(tile) if tile.passable() => { },
+            _ => break,
+        }
+        if game_obj_db.blocking_obj_at(&next_loc) {
+            break;
+        }
+
+        pass_turn(state, game_obj_db, gui, monster_fac, saves)?;
+        let interrupted = !state.msg_queue.is_empty();
+        update_view(state, game_obj_db, gui);
+        if interrupted || monsters_nearby(state, game_obj_db, curr_loc) {
+            break;
+        }
+
+        // Things may have changed during the turn so we use do_move() rather than take_step()
+        // to make sure the player gets the usual messages, autopickup, etc.
+        do_move(state, game_obj_db, dir, gui);
+        let loc = game_obj_db.get(0).unwrap().get_loc();
+        if loc == curr_loc {
+            break;
+        }
+        curr_loc = loc;
+        
+        let count = open_neighbours(state, curr_loc);
+        if count != open_count {
+            break;
+        }
+        open_count = count;
+    }
+
+    Ok(cost)
+}
+
+// Walking back and forth between town and the dungeon gets tedious, so on the surface the player
+// can pick a known destination and walk there automatically. Each step is a full turn for the rest
+// of the world, and the trip is interrupted if anything hostile comes into view.
+fn fast_travel(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface, monster_fac: &MonsterFactory, saves: &SaveOptions) -> Result<f32, ExitReason> {
+    let player_loc = game_obj_db.player().unwrap().get_loc();
+    if player_loc.2 != 0 {
+        state.msg_queue.push_back(Message::info("You can only travel quickly on the surface."));
+        return Ok(0.0);
+    }
+
+    if monsters_nearby(state, game_obj_db, player_loc) {
+        state.msg_queue.push_back(Message::info("You can't travel with enemies nearby!"));
+        return Ok(0.0);
+    }
+
+    // Destinations and the route to them are both judged by the same overworld passability rules
+    let passable = pathfinding::overworld_passable();
+    let mut destinations = Vec::new();
+    let town_sq = state.world_info.town_square.iter()
+                                              .filter(|sq| state.map.get(sq).is_some_and(|t| pathfinding::passable_by_me(t, &passable)))
+                                              .copied()
+                                              .next();
+    if let Some(sq) = town_sq {
+        destinations.push((format!("{} town square", state.world_info.town_name), sq));
+    }
+    for fact in state.world_info.facts.iter() {
+        if fact.detail == "dungeon location" {
+            destinations.push(("the dungeon entrance".to_string(), fact.location));
+        }
+    }
+
+    let mut menu = Vec::new();
+    let mut slot = 'a';
+    for d in destinations.iter() {
+        menu.push((d.0.to_string(), slot));
+        slot = (slot as u8 + 1) as char;
+    }
+
+    let dest = match gui.side_pane_menu("Travel where?".to_string(), &menu, true) {
+        Some(answer) => {
+            let ch = *answer.iter().next().unwrap();
+            destinations[(ch as u8 - b'a') as usize].1
+        },
+        None => {
+            state.msg_queue.push_back(Message::info("Never mind."));
+            return Ok(0.0);
+        }
+    };
+
+    let mut path = pathfinding::find_path(&state.map, Some(game_obj_db), false, player_loc.0, player_loc.1, 0, dest.0, dest.1, 400, &passable);
+    if path.is_empty() {
+        state.msg_queue.push_back(Message::info("You can't find a way there from here."));
+        return Ok(0.0);
+    }
+
+    // The path is returned goal-first and includes the square the player is standing on
+    path.reverse();
+    path.remove(0);
+    let final_step = path.pop();
+
+    state.msg_queue.push_back(Message::info("You set off."));
+    for sq in path.iter() {
+        let curr_loc = game_obj_db.player().unwrap().get_loc();
+        let next_loc = (sq.0, sq.1, 0);
+        if game_obj_db.blocking_obj_at(&next_loc) {
+            state.msg_queue.push_back(Message::info("Something is in your way."));
+            return Ok(1.0);
+        }
+
+        let (_, moved) = take_step(state, game_obj_db, 0, curr_loc, next_loc, false);
+        if !moved {
+            return Ok(1.0);
+        }
+
+        // Each step is a full turn for everyone else
+        pass_turn(state, game_obj_db, gui, monster_fac, saves)?;
+
+        update_view(state, game_obj_db, gui);
+        if monsters_nearby(state, game_obj_db, next_loc) {
+            state.msg_queue.push_back(Message::info("You spot danger and stop!"));
+            return Ok(1.0);
+        }
+    }
+
+    // The last step is taken as the player's regular move for this turn
+    if let Some(sq) = final_step {
+        let curr_loc = game_obj_db.player().unwrap().get_loc();
+        let next_loc = (sq.0, sq.1, 0);
+        if !game_obj_db.blocking_obj_at(&next_loc) {
+            take_step(state, game_obj_db, 0, curr_loc, next_loc, false);
+        }
+    }
+
+    Ok(1.0)
+}
+
+fn confirm_quit(state: &GameState, gui: &mut dyn UserInterface, game_obj_db: &mut GameObjectDB) -> Result<(), ExitReason> {
     let sbi = state.curr_sidebar_info(game_obj_db);
     if let 'y' = gui.query_yes_no("Do you really want to Quit? (y/n)", Some(&sbi)) {
         Err(ExitReason::Quit)
@@ -1602,8 +2826,8 @@ fn confirm_quit(state: &GameState, gui: &mut GameUI, game_obj_db: &mut GameObjec
     }
 }
 
-fn pick_player_start_loc(state: &GameState) -> (i32, i32, i8) {
-    let x = thread_rng().gen_range(0, 4);
+fn pick_player_start_loc(state: &GameState, rng: &mut GameRng) -> (i32, i32, i8) {
+    let x = rng.gen_range(0, 4);
     let b = state.world_info.town_boundary;
 
     for fact in &state.world_info.facts {
@@ -1613,13 +2837,42 @@ fn pick_player_start_loc(state: &GameState) -> (i32, i32, i8) {
     }
     
     if x == 0 {
-        (b.0 - 5, thread_rng().gen_range(b.1, b.3), 0)
+        (b.0 - 5, rng.gen_range(b.1, b.3), 0)
     } else if x == 1 {
-        (b.2 + 1, thread_rng().gen_range(b.1, b.3), 0)
+        (b.2 + 1, rng.gen_range(b.1, b.3), 0)
     } else if x == 2 {
-        (thread_rng().gen_range(b.0, b.2), b.1 - 5, 0)
+        (rng.gen_range(b.0, b.2), b.1 - 5, 0)
+    } else {
+        (rng.gen_range(b.0, b.2), b.3 + 5, 0)
+    }
+}
+
+// How a visible square of terrain should be drawn, given any light or aura that's on it.
+fn lit_tile(state: &GameState, loc: (i32, i32, i8)) -> Tile {
+    let tile = tile_at(&state.map, &loc);
+
+    // I will probably eventually get rid of having auras light squares at some point
+    if state.aura_sqs.contains(&loc) && tile == Tile::StoneFloor {
+        Tile::ColourFloor(display::LIGHT_BLUE)
+    } else if let Some(&colour) = state.lit_sqs.get(&loc) {
+        // Fuckery here because I want a torch to colour only floors squares, but for things 
+        // like mushrooms, I later decided I wanted them to light up stone walls as well.
+        if colour == display::WHITE {
+            match tile {
+                Tile::StoneFloor => Tile::ColourFloor(display::YELLOW),
+                Tile::Trigger => Tile::ColourFloor(display::YELLOW_ORANGE),
+                _ => tile,
+            }
+        } else {
+            match tile {
+                Tile::StoneFloor => Tile::ColourFloor(colour),
+                Tile::Trigger => Tile::ColourFloor(colour),
+                Tile::Wall | Tile::GraniteWall => Tile::LitWall(colour),
+                _ => tile,
+            }
+        }
     } else {
-        (thread_rng().gen_range(b.0, b.2), b.3 + 5, 0)
+        tile
     }
 }
 
@@ -1632,8 +2885,10 @@ fn fov_to_tiles(state: &mut GameState, game_obj_db: &GameObjectDB, visible: &[((
     for j in 0..visible.len() {
         let vis = visible[j];
         if vis.0 == player_loc {
-            v_matrix[j] = (map::Tile::Player(WHITE), true);
-        } else if visible[j].1 {     
+            // Draw the player dimmed while they are invisible
+            let colour = if game_obj_db.get(0).unwrap().hidden() { display::GREY } else { WHITE };
+            v_matrix[j] = (map::Tile::Player(colour), true);
+        } else if visible[j].1 && !state.darkness.contains_key(&vis.0) {     
             let tile = if let Some(t) = game_obj_db.tile_at(&vis.0) {
                 if t.1 {
                     state.tile_memory.insert(vis.0, t.0);
@@ -1641,35 +2896,15 @@ fn fov_to_tiles(state: &mut GameState, game_obj_db: &GameObjectDB, visible: &[((
                 t.0
             } else {
                 state.tile_memory.insert(vis.0, state.map[&vis.0]);
-                
-                // I will probably eventually get rid of having auras light squares at some point
-                if state.aura_sqs.contains(&vis.0) && state.map[&vis.0] == Tile::StoneFloor {
-                    Tile::ColourFloor(display::LIGHT_BLUE)
-                } else if state.lit_sqs.contains_key(&vis.0) {
-                    let colour = state.lit_sqs[&vis.0];
-
-                    // Fuckery here because I want a torch to colour only floors squares, but for things 
-                    // like mushrooms, I later decided I wanted them to light up stone walls as well.
-                    if colour == display::WHITE {
-                        match state.map[&vis.0] {
-                            Tile::StoneFloor => Tile::ColourFloor(display::YELLOW),
-                            Tile::Trigger => Tile::ColourFloor(display::YELLOW_ORANGE),
-                            _ => state.map[&vis.0],
-                        }
-                    } else {
-                        match state.map[&vis.0] {
-                            Tile::StoneFloor => Tile::ColourFloor(colour),
-                            Tile::Trigger => Tile::ColourFloor(colour),
-                            Tile::Wall | Tile::GraniteWall => Tile::LitWall(colour),
-                            _ => state.map[&vis.0],
-                        }
-                    }
-                } else {
-                    state.map[&vis.0]
-                }
+                lit_tile(state, vis.0)
             };
             
             v_matrix[j] = (tile, true);
+        } else if state.detected.contains(&vis.0) {
+            // Show things the player has sensed through magic, but don't remember them
+            if let Some(t) = game_obj_db.tile_at(&vis.0) {
+                v_matrix[j] = (t.0, false);
+            }
         } else if state.tile_memory.contains_key(&vis.0) {
             v_matrix[j] = (state.tile_memory[&vis.0], false);            
         }
@@ -1678,7 +2913,7 @@ fn fov_to_tiles(state: &mut GameState, game_obj_db: &GameObjectDB, visible: &[((
     v_matrix
 }
 
-fn kill_screen(state: &mut GameState, gui: &mut GameUI, game_obj_db: &mut GameObjectDB, msg: &str) {
+fn kill_screen(state: &mut GameState, gui: &mut dyn UserInterface, game_obj_db: &mut GameObjectDB, msg: &str) {
     if msg.is_empty() {
         state.msg_queue.push_back(Message::info("Oh no! You have died!"));
     } else {
@@ -1697,10 +2932,25 @@ fn kill_screen(state: &mut GameState, gui: &mut GameUI, game_obj_db: &mut GameOb
     }
     gui.update(&mut msgs, Some(&sbi));
     gui.pause_for_more();
+
+    let mut lines = vec!["Your adventure in brief:".to_string(), "".to_string()];
+    lines.extend(state.stats.summary());
+    let lines: Vec<&str> = lines.iter().map(AsRef::as_ref).collect();
+    gui.write_long_msg(&lines, true);
+
+    let fate = if msg.is_empty() {
+        "died".to_string()
+    } else {
+        format!("killed by {}", msg)
+    };
+    write_morgue_file(state, game_obj_db, gui, &fate);
+    let player = game_obj_db.player().unwrap();
+    let score = scores::Score::new(&player.get_fullname(), player.max_depth, player.purse, state.turn, state.stats.total_kills(), &fate);
+    scores::show_high_scores(gui, score);
 }
 
 // Herein lies the main game loop
-fn run_game_loop(gui: &mut GameUI, state: &mut GameState, game_obj_db: &mut GameObjectDB, dialogue: &DialogueLibrary, monster_fac: &MonsterFactory) -> Result<(), ExitReason> {    
+fn run_game_loop(gui: &mut dyn UserInterface, state: &mut GameState, game_obj_db: &mut GameObjectDB, dialogue: &DialogueLibrary, monster_fac: &MonsterFactory, saves: &SaveOptions) -> Result<(), ExitReason> {    
     update_view(state, game_obj_db, gui);
     
     loop {
@@ -1713,8 +2963,6 @@ fn run_game_loop(gui: &mut GameUI, state: &mut GameState, game_obj_db: &mut Game
         let mut skip_turn = false;
         let mut effects: u128 = 0;
         while curr_energy >= 1.0 {
-            gui.clear_msg_buff();
-            
             // Here we look for any statuses that should have effects at the start of a player's turn.
             // After their turn we'll check to see if the statuses have ended.
             let p = game_obj_db.player().unwrap();
@@ -1744,6 +2992,11 @@ fn run_game_loop(gui: &mut GameUI, state: &mut GameState, game_obj_db: &mut Game
             } else  {
                 gui.get_command(&state, game_obj_db)
             };
+            let cmd = match cmd {
+                Cmd::Repeat => repeat_cmd(state, game_obj_db),
+                _ => cmd,
+            };
+            let repeatable = if is_repeatable(&cmd) { Some(cmd.clone()) } else { None };
 
             let mut energy_cost = 0.0;
             match cmd {
@@ -1753,37 +3006,61 @@ fn run_game_loop(gui: &mut GameUI, state: &mut GameState, game_obj_db: &mut Game
                     do_close(state, loc, game_obj_db);
                     energy_cost = 1.0;
                 },
+                Cmd::Cast => energy_cost = magic::cast(state, game_obj_db, gui),
+                Cmd::Craft => energy_cost = crafting::craft(state, game_obj_db, gui),
                 Cmd::Down => energy_cost = take_stairs(state, game_obj_db, true),
+                Cmd::Drink => energy_cost = drink(state, game_obj_db),
                 Cmd::DropItem => energy_cost = drop_item(state, game_obj_db, gui),  
                 Cmd::Move(dir) => energy_cost = do_move(state, game_obj_db, &dir, gui),
                 Cmd::MsgHistory => gui.show_message_history(),
                 Cmd::Open(loc) => { 
-                    do_open(state, loc);
+                    do_open(state, loc, game_obj_db);
                     energy_cost = 1.0;
                 },
                 Cmd::Pass => {
                     let p = game_obj_db.player().unwrap();
                     energy_cost = p.energy;
+                    // Holding still gives a wound the chance to close up
+                    if p.has_status(Status::Bleeding) && state.rng.gen_range(0, 4) == 0 {
+                        effects::remove_status(p, Status::Bleeding);
+                        state.msg_queue.push_back(Message::info("Your bleeding stops."));
+                    }
                 },
                 Cmd::PickUp => energy_cost = pick_up(state, game_obj_db, gui),
-                Cmd::Save => save_and_exit(state, game_obj_db, gui)?,
+                Cmd::Pray => energy_cost = religion::pray(state, game_obj_db, gui),
+                Cmd::Save => save_and_exit(state, game_obj_db, gui, saves)?,
                 Cmd::Search => {
                     search(state, game_obj_db);
                     energy_cost = 1.0;
                 },
                 Cmd::ShowCharacterSheet => {
                     if let Some(GameObjects::Player(p)) = game_obj_db.get(0) {
-                        show_character_sheet(gui, p);
+                        show_character_sheet(gui, p, &state.stats);
+                    }
+                },
+                Cmd::ShowEquipment => {
+                    if let Some(GameObjects::Player(p)) = game_obj_db.get(0) {
+                        show_equipment(gui, p);
                     }
                 },
                 Cmd::ShowInventory => show_inventory(gui, state, game_obj_db),
+                Cmd::ShowJournal => show_journal(gui, state),
+                Cmd::Throw => energy_cost = throw_item(state, game_obj_db, gui),
                 Cmd::ToggleEquipment => energy_cost = toggle_equipment(state, game_obj_db, gui),
+                Cmd::Travel => energy_cost = fast_travel(state, game_obj_db, gui, monster_fac, saves)?,
                 Cmd::Use => energy_cost = use_item(state, game_obj_db, gui),
                 Cmd::Quit => confirm_quit(state, gui, game_obj_db)?,
+                Cmd::Run(dir) => energy_cost = run(state, game_obj_db, &dir, gui, monster_fac, saves)?,
                 Cmd::Up => energy_cost = take_stairs(state, game_obj_db, false),
                 Cmd::WizardCommand => wiz_command(state, gui, game_obj_db, monster_fac),
+                Cmd::WriteNote => energy_cost = write_note(state, game_obj_db, gui),
+                Cmd::Repeat => { }, // repeat_cmd() couldn't find anything valid to re-issue
                 _ => continue,
             }
+
+            if energy_cost > 0.0 && repeatable.is_some() {
+                state.last_cmd = repeatable;
+            }
             
             let p = game_obj_db.player().unwrap();
             p.energy -= energy_cost;
@@ -1794,7 +3071,7 @@ fn run_game_loop(gui: &mut GameUI, state: &mut GameState, game_obj_db: &mut Game
                 // I should queue an event "Monster killed" and then check the event queue. That way I don't
                 // have to loop over the entire structure of GameObjects looking to see if there are any
                 // dead ones.
-                game_obj_db.check_for_dead_npcs();
+                game_obj_db.check_for_dead_npcs(&mut state.rng);
                 game_obj_db.update_listeners(state, EventType::Update);
                 if !skip_turn || !state.msg_queue.is_empty() {
                     update_view(state, game_obj_db, gui);
@@ -1813,22 +3090,11 @@ fn run_game_loop(gui: &mut GameUI, state: &mut GameState, game_obj_db: &mut Game
             ::std::thread::sleep(Duration::new(0, 75_000_000u32));
         }
 
-        let p = game_obj_db.player().unwrap();
-        effects::check_statuses(p, state);
-
-        game_obj_db.do_npc_turns(state);
-        game_obj_db.update_listeners(state, EventType::Update);
-        game_obj_db.update_listeners(state, EventType::EndOfTurn);
-        
-        check_event_queue(state, game_obj_db, gui)?;
+        end_of_turn(state, game_obj_db, gui, monster_fac)?;
 
         let p = game_obj_db.player().unwrap();
-        p.energy += p.energy_restore;
-        if state.turn % 25 == 0 {
-             p.recover();
-        }
-
-        state.turn += 1;
+        p.energy += p.energy_restore * effects::speed_factor(&p.statuses);
+        advance_turn(state, game_obj_db, saves);
 
         if !skip_turn || !state.msg_queue.is_empty() {
             update_view(state, game_obj_db, gui);
@@ -1841,25 +3107,94 @@ fn run_game_loop(gui: &mut GameUI, state: &mut GameState, game_obj_db: &mut Game
     }
 }
 
-fn check_event_queue(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut GameUI) -> Result<(), ExitReason> {
-    while !state.queued_events.is_empty() {
-        match state.queued_events.pop_front().unwrap() {
-            (EventType::GateClosed, loc, _, _) => {
-                check_closed_gate(state, game_obj_db, loc);
+// Keep track of where the player has recently been, for monsters who hunt by scent
+fn record_scent(state: &mut GameState, game_obj_db: &mut GameObjectDB) {
+    let player_loc = game_obj_db.get(0).unwrap().get_loc();
+    if state.scent_trail.front() == Some(&player_loc) {
+        return;
+    }
+
+    // A flying player leaves no trail to follow
+    if game_obj_db.player().unwrap().has_status(Status::Flying) {
+        return;
+    }
+    
+    state.scent_trail.push_front(player_loc);
+    state.scent_trail.truncate(SCENT_TRAIL_LENGTH);
+}
+
+// Deeper levels restock themselves faster
+fn wandering_monster_interval(level: i8, rng: &mut GameRng) -> u32 {
+    let base = 400 - (level as u32 * 25).min(250);
+    base + rng.gen_range(0, base / 2)
+}
+
+// Dungeon levels slowly restock themselves so the player can't camp or rest in safety forever. 
+// New arrivals turn up out of the player's sight, preferably beside the stairs as though they'd
+// just come from another level. (Not on them, where they'd be in the way of the player using them)
+fn check_wandering_monsters(state: &mut GameState, game_obj_db: &mut GameObjectDB, monster_fac: &MonsterFactory) {
+    let player_loc = game_obj_db.get(0).unwrap().get_loc();
+    let level = player_loc.2;
+    if level <= 0 {
+        return;
+    }
+
+    let next_spawn = *state.spawn_timers.entry(level).or_insert(state.turn + wandering_monster_interval(level, &mut state.rng));
+    if state.turn < next_spawn {
+        return;
+    }
+    state.spawn_timers.insert(level, state.turn + wandering_monster_interval(level, &mut state.rng));
+
+    let out_of_sight: Vec<((i32, i32, i8), Tile)> = state.map.iter()
+        .filter(|(loc, tile)| loc.2 == level && tile.passable_dry_land())
+        .filter(|(loc, _)| !state.curr_visible.contains(loc) && util::distance(loc.0, loc.1, player_loc.0, player_loc.1) > 10.0)
+        .filter(|(loc, _)| !game_obj_db.location_occupied(loc))
+        .map(|(loc, tile)| (*loc, *tile))
+        .collect();
+
+    let is_stairs = |tile: &Tile| *tile == Tile::StairsUp || *tile == Tile::StairsDown;
+    let open_sqs: Vec<(i32, i32, i8)> = out_of_sight.iter()
+        .filter(|(_, tile)| !is_stairs(tile))
+        .map(|(loc, _)| *loc)
+        .collect();
+    let by_stairs: Vec<(i32, i32, i8)> = open_sqs.iter()
+        .filter(|loc| util::ADJ.iter().any(|a| matches!(state.map.get(&(loc.0 + a.0, loc.1 + a.1, loc.2)), Some(t) if is_stairs(t))))
+        .copied()
+        .collect();
+
+    let loc = if let Some(loc) = by_stairs.choose(&mut state.rng) {
+        *loc
+    } else if let Some(loc) = open_sqs.choose(&mut state.rng) {
+        *loc
+    } else {
+        return;
+    };
+
+    monster_fac.monster_for_dungeon(loc, state.world_info.dungeon_depth, game_obj_db, &mut state.rng);
+}
+
+fn check_event_queue(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) -> Result<(), ExitReason> {
+    while let Some(event) = state.queued_events.pop_front() {
+        match event.event_type {
+            EventType::GateClosed => {
+                check_closed_gate(state, game_obj_db, event.loc);
             },
-            (EventType::PlayerKilled, _, _, Some(msg)) => {
+            EventType::PlayerKilled => {
+                let msg = event.msg.unwrap_or_else(|| String::from("something"));
                 kill_screen(state, gui, game_obj_db, &msg);
+                remove_autosave(&game_obj_db.get(0).unwrap().get_fullname());
                 return Err(ExitReason::Death(String::from("Player killed")));
             },
-            (EventType::LevelUp, _, _, _) => {
+            EventType::LevelUp => {
                 let p = game_obj_db.player().unwrap();
-                p.level_up();
+                p.level_up(&mut state.rng);
                 let level = p.level;
                 let s = format!("Welcome to level {}!", level);
                 state.msg_queue.push_back(Message::info(&s));                
             },
-            (EventType::DeathOf(npc_id), _, _, _) => {
+            EventType::DeathOf(npc_id) => {
                 game_obj_db.update_listeners(state, EventType::DeathOf(npc_id));
+                npc::check_pack_morale(state, game_obj_db, npc_id);
             },
             _ => { },
         }                
@@ -1868,36 +3203,45 @@ fn check_event_queue(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui:
     Ok(())
 }
 
-fn update_view(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut GameUI) {    
+fn update_view(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) {    
     let player = game_obj_db.player().unwrap();
     let player_loc = player.get_loc();
     player.calc_vision_radius(state, player_loc);
     let player_vr = player.vision_radius;
+    let telepathic = player.has_status(Status::Telepathic);
     
     //let _fov_start = Instant::now();
-    let visible = fov::visible_sqs(state, player_loc, player_vr, false);
+    let mut fov_cache = std::mem::take(&mut state.fov_cache);
+    let mut visible = fov::visible_sqs(state, &mut fov_cache, player_loc, player_vr, false);
+    state.fov_cache = fov_cache;
+
+    // Even a vision radius of 0 lets the player see adjacent squares and anything lit, so a blind
+    // player is limited to their own square and has to get around by memory
+    if player_vr == 0 {
+        for sq in visible.iter_mut() {
+            sq.1 = sq.0 == player_loc;
+        }
+    }
     state.curr_visible = visible.iter()
-                                .filter(|sq| sq.1)
+                                .filter(|sq| sq.1 && !state.darkness.contains_key(&sq.0))
                                 .map(|sq| sq.0)
                                 .collect();
     
-    gui.v_matrix = fov_to_tiles(state, game_obj_db, &visible, player_loc);        
+    if state.detecting > 0 || telepathic || !state.detected.is_empty() {
+        effects::refresh_detected(state, game_obj_db, player_loc.2, telepathic);
+    }
+
+    gui.set_v_matrix(fov_to_tiles(state, game_obj_db, &visible, player_loc));        
     //let _fov_duration = _fov_start.elapsed();
     //println!("Player fov: {:?}", fov_duration);
     
     //let write_screen_start = Instant::now();
     let sbi = state.curr_sidebar_info(game_obj_db);
 
-    // this is semi-temporary code. Eventually msg_queue will replace msg_buff
-    // I don't know about the distance calculation for noise. A floodfill like I'm doing
-    // to alert monsters is probably better but more complicated.
     let mut msgs = VecDeque::new();
-    while !state.msg_queue.is_empty() {
-        let msg = state.msg_queue.pop_front().unwrap();
-        if state.curr_visible.contains(&msg.loc) || (msg.obj_id == 0 && msg.loc == (-1, -1, -1)) {
-            msgs.push_back(msg.text);
-        } else if !msg.alt_text.is_empty() && util::distance(player_loc.0, player_loc.1, msg.loc.0, msg.loc.1) < 12.0 {
-            msgs.push_back(msg.alt_text);            
+    while let Some(msg) = state.msg_queue.pop_front() {
+        if let Some(text) = msg.text_for_player(&state.curr_visible, player_loc) {
+            msgs.push_back(text);
         }
     }
 
@@ -1909,7 +3253,8 @@ fn update_view(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut
 fn fetch_config_options() -> ConfigOptions {
     match fs::read_to_string("options") {
         Ok(contents) => {
-            let mut co = ConfigOptions { font_size: 24, sm_font_size: 18 };
+            let mut co = ConfigOptions { font_size: 24, sm_font_size: 18, thirst: false, autopickup: Vec::new(), start_hour: DEFAULT_START_HOUR, town: TownOptions::new(),
+                dungeon_depth: world::DEFAULT_DUNGEON_DEPTH, saves: SaveOptions::default() };
             let lines = contents.split('\n').collect::<Vec<&str>>();
 
             for line in lines.iter() {
@@ -1920,18 +3265,144 @@ fn fetch_config_options() -> ConfigOptions {
                 if pieces[0] == "sm_font_size" {
                     co.sm_font_size = pieces[1].parse::<u16>().unwrap();
                 }
+                if pieces[0] == "thirst" {
+                    co.thirst = pieces[1].trim() == "on";
+                }
+                if pieces[0] == "compress_saves" {
+                    co.saves.compress = pieces[1].trim() == "on";
+                }
+                if pieces[0] == "town_walls" {
+                    co.town.walled = pieces[1].trim() == "on";
+                }
+                if pieces[0] == "town_river" {
+                    co.town.riverside = pieces[1].trim() == "on";
+                }
+                if pieces[0] == "town_size" {
+                    co.town.size = match pieces[1].trim() {
+                        "small" => TownSize::Small,
+                        "large" => TownSize::Large,
+                        _ => TownSize::Medium,
+                    };
+                }
+                if pieces[0] == "dungeon_depth" {
+                    if let Ok(depth) = pieces[1].trim().parse::<u8>() {
+                        co.dungeon_depth = depth.clamp(1, world::MAX_DUNGEON_DEPTH);
+                    }
+                }
+                // ie., autosave=250, or autosave=off
+                if pieces[0] == "autosave" {
+                    co.saves.autosave_interval = match pieces[1].trim() {
+                        "off" => 0,
+                        val => val.parse::<u32>().unwrap_or(DEFAULT_AUTOSAVE_INTERVAL),
+                    };
+                }
+                if pieces[0] == "start_hour" {
+                    if let Ok(hour) = pieces[1].trim().parse::<u32>() {
+                        co.start_hour = hour % 24;
+                    }
+                }
+                // ie., autopickup=gold,potions,scrolls
+                if pieces[0] == "autopickup" {
+                    co.autopickup = pieces[1].split(',')
+                                             .filter_map(|t| match t.trim() {
+                                                 "gold" => Some(AutoPickup::Gold),
+                                                 "potions" => Some(AutoPickup::Item(ItemType::Potion)),
+                                                 "scrolls" => Some(AutoPickup::Item(ItemType::Scroll)),
+                                                 "wands" => Some(AutoPickup::Item(ItemType::Wand)),
+                                                 "food" => Some(AutoPickup::Item(ItemType::Food)),
+                                                 "ammunition" => Some(AutoPickup::Item(ItemType::Ammunition)),
+                                                 "notes" => Some(AutoPickup::Item(ItemType::Note)),
+                                                 _ => None,
+                                             })
+                                             .collect();
+                }
             }
 
             co
         },
-        Err(_) => ConfigOptions { font_size: 24, sm_font_size: 18 },
+        Err(_) => ConfigOptions { font_size: 24, sm_font_size: 18, thirst: false, autopickup: Vec::new(), start_hour: DEFAULT_START_HOUR, town: TownOptions::new(),
+                dungeon_depth: world::DEFAULT_DUNGEON_DEPTH, saves: SaveOptions::default() },
     }
     //let contents = fs::read_to_string("options")
     //    .expect("Unable to find building templates file!");
     //let lines = contents.split('\n').collect::<Vec<&str>>();
 }
 
+// Check the data files before starting up so that all of the problems in them get reported
+// together, rather than the game panicking partway through on the first one it trips over.
+// Load the player's saved game if there is one, otherwise create a character and generate a
+// fresh world for them. Returns None if the save file couldn't be loaded.
+fn setup_game(gui: &mut dyn UserInterface, mf: &mut MonsterFactory, opts: &ConfigOptions) -> Option<(GameState, GameObjectDB)> {
+    let player_name = who_are_you(gui);
+    
+    let mut game_obj_db: GameObjectDB;
+    let mut state: GameState;
+    let save_filename = calc_save_filename(&player_name);
+    let save_found = existing_save_file(&save_filename);
+    let saved_game = if save_found {
+        fetch_saved_data(&save_filename)
+    } else {
+        None
+    };
+    let saved_game = saved_game.or_else(|| offer_autosave(&player_name, save_found, gui));
+
+    if let Some(saved_objs) = saved_game {
+        state = saved_objs.0;
+        game_obj_db = saved_objs.1;
+        mf.difficulty = state.difficulty;
+        
+        let msg = format!("Welcome back, {}!", player_name);
+        state.msg_queue.push_back(Message::info(&msg));
+    } else if save_found {
+        // need to dump some sort of message for corrupted game file
+        return None;
+    } else {
+        game_obj_db = GameObjectDB::new();
+        mf.difficulty = pick_difficulty(gui);
+
+        // The world is built with the same rng the game goes on to use so that a seeded one
+        // reproduces everything from the map on
+        let mut rng = GameRng::default();
+        let wg_start = Instant::now();
+        let w = world::generate_world(&mut game_obj_db, mf, &player_name, opts.town, opts.dungeon_depth, &mut rng);
+        state = GameState::init(w.0, w.1);    
+        state.difficulty = mf.difficulty;
+        state.start_hour = opts.start_hour;
+        let wg_dur = wg_start.elapsed();
+        println!("World gen time: {:?}", wg_dur);
+
+        start_new_game(&state, &mut game_obj_db, gui, player_name, &mut rng);
+        state.rng = rng;
+        let s = format!("Arrived in {}, seeking adventure.", state.world_info.town_name);
+        state.add_journal_entry(&s);
+        
+        state.msg_queue.push_back(Message::info("Welcome, adventurer."));        
+    }
+    
+    state.thirst = opts.thirst;
+    state.autopickup = opts.autopickup.clone();
+
+    Some((state, game_obj_db))
+}
+
+fn validate_data_files() -> bool {
+    let mut errors = MonsterFactory::validate_monster_file();
+    errors.extend(town::validate_building_templates());
+    errors.extend(dialogue::validate_dialogue_lib());
+
+    for err in errors.iter() {
+        eprintln!("{}", err);
+    }
+
+    errors.is_empty()
+}
+
 fn main() {
+    if !validate_data_files() {
+        eprintln!("Errors found in the game's data files. Unable to start.");
+        return;
+    }
+
     let opts = fetch_config_options();
     
     // It bugs me aesthetically that I can't move creating the font contexts into the 
@@ -1943,52 +3414,462 @@ fn main() {
         .expect("Error loading game font!");
     let sm_font = ttf_context.load_font(font_path, opts.sm_font_size)
         .expect("Error loading small game font!");
-    let mut gui = GameUI::init(&font, &sm_font)
-        .expect("Error initializing GameUI object.");
+    let (event_pump, canvas) = display::open_window(&font)
+        .expect("Error opening the game window!");
+    let texture_creator = canvas.texture_creator();
+    let mut gui = GameUI::init(&font, &sm_font, event_pump, canvas, &texture_creator);
 
     title_screen(&mut gui);
 
-    let mf = MonsterFactory::init();
+    let mut mf = MonsterFactory::init();
     let dialogue_library = dialogue::read_dialogue_lib();
-    let player_name = who_are_you(&mut gui);
     
-    let mut game_obj_db: GameObjectDB;
-    let mut state: GameState;
-    if existing_save_file(&player_name) {
-        if let Some(saved_objs) = fetch_saved_data(&player_name) {
-            state = saved_objs.0;
-            game_obj_db = saved_objs.1;
-            
-            let msg = format!("Welcome back, {}!", player_name);
-            state.msg_queue.push_back(Message::info(&msg));
-        } else {
-            // need to dump some sort of message for corrupted game file
+    // Once a game ends in death or victory, the player can go straight into a new one
+    loop {
+        let (mut state, mut game_obj_db) = match setup_game(&mut gui, &mut mf, &opts) {
+            Some(game) => game,
+            None => return,
+        };
+
+        // for _ in 0..20 {
+        //     println!("{}", MonsterFactory::pick_monster_level(10));
+        // }
+
+        match run_game_loop(&mut gui, &mut state, &mut game_obj_db, &dialogue_library, &mf, &opts.saves) {
+            Ok(_) => println!("Game over I guess? Probably the player won?!"),
+            Err(ExitReason::Win) => write_morgue_file(&state, &mut game_obj_db, &gui, "won"),
+            //Err(ExitReason::Save) => save_msg(&mut state, &mut gui),
+            //Err(ExitReason::Quit) => quit_msg(&mut state, &mut gui),
+            //Err(ExitReason::Win) => victory_msg(&mut state, &mut gui),
+            //Err(ExitReason::Death(src)) => death(&mut state, src, &mut gui),
+            Err(ExitReason::Death(_)) => { },
+            Err(_) => {
+                println!("okay bye");
+                return;
+            },
+        }
+
+        if gui.query_yes_no("Play again? (y/n)", None) != 'y' {
             return;
         }
-    } else {
-        game_obj_db = GameObjectDB::new();
+        gui.clear_message_history();
+    }
+}
 
-        let wg_start = Instant::now();
-        let w = world::generate_world(&mut game_obj_db, &mf, &player_name);        
-        state = GameState::init(w.0, w.1);    
-        let wg_dur = wg_start.elapsed();
-        println!("World gen time: {:?}", wg_dur);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        start_new_game(&state, &mut game_obj_db, &mut gui, player_name);
-        
-        state.msg_queue.push_back(Message::info("Welcome, adventurer."));        
+    // Seeded so that a failing test fails the same way every time
+    pub fn test_state() -> GameState {
+        let world_info = WorldInfo::new("Testville".to_string(), (0, 0, 10, 10), "The Test Inn".to_string());
+        let mut state = GameState::init(Map::new(), world_info);
+        state.rng = GameRng::seeded(1);
+
+        state
     }
-    
-    // for _ in 0..20 {
-    //     println!("{}", MonsterFactory::pick_monster_level(10));
-    // }
 
-    match run_game_loop(&mut gui, &mut state, &mut game_obj_db, &dialogue_library, &mf) {
-        Ok(_) => println!("Game over I guess? Probably the player won?!"),
-        //Err(ExitReason::Save) => save_msg(&mut state, &mut gui),
-        //Err(ExitReason::Quit) => quit_msg(&mut state, &mut gui),
-        //Err(ExitReason::Win) => victory_msg(&mut state, &mut gui),
-        //Err(ExitReason::Death(src)) => death(&mut state, src, &mut gui),
-        Err(_) => println!("okay bye"),
+    #[test]
+    fn days_roll_over_at_midnight() {
+        let mut state = test_state();
+        assert_eq!(state.curr_time(), (8, 0));
+        assert_eq!(state.curr_day(), 0);
+
+        state.turn = 16 * TURNS_PER_HOUR - 1;
+        assert_eq!(state.curr_day(), 0);
+        state.turn += 1;
+        assert_eq!(state.curr_time(), (0, 0));
+        assert_eq!(state.curr_day(), 1);
+        assert_eq!(state.day_of(TURNS_PER_HOUR), 0);
+    }
+
+    // A small lit room on level 1 with the player standing in the middle of it
+    pub fn test_level(game_obj_db: &mut GameObjectDB) -> GameState {
+        let mut state = test_state();
+        for r in 0..11 {
+            for c in 0..11 {
+                let tile = if r == 0 || r == 10 || c == 0 || c == 10 { Tile::Wall } else { Tile::StoneFloor };
+                state.map.insert((r, c, 1), tile);
+            }
+        }
+        Player::new_warrior(game_obj_db, "Tester", Race::Human, &mut state.rng);
+        assert!(game_obj_db.set_to_loc(0, (5, 5, 1)));
+
+        state
+    }
+
+    #[test]
+    fn writing_a_note_without_a_window() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = test_level(&mut game_obj_db);
+        let mut gui = headless::HeadlessUI::new();
+        gui.queue_keys("Beware the kobolds\n");
+
+        assert_eq!(write_note(&mut state, &mut game_obj_db, &mut gui), 1.0);
+        update_view(&mut state, &mut game_obj_db, &mut gui);
+
+        assert_eq!(gui.screens, vec!["What do you write?".to_string()]);
+        assert_eq!(gui.recent_messages(1), vec!["You jot down a note and leave it here.".to_string()]);
+        assert_eq!(game_obj_db.descs_at_loc(&(5, 5, 1)), vec!["a note".to_string()]);
+    }
+
+    #[test]
+    fn wandering_monsters_arrive_beside_the_stairs_once_their_time_is_up() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = test_state();
+        for r in 0..5 {
+            for c in 0..40 {
+                let tile = if r == 0 || r == 4 || c == 0 || c == 39 { Tile::Wall } else { Tile::StoneFloor };
+                state.map.insert((r, c, 1), tile);
+            }
+        }
+        state.map.insert((2, 35, 1), Tile::StairsDown);
+        state.rng = GameRng::seeded(2215);
+        Player::new_warrior(&mut game_obj_db, "Tester", Race::Human, &mut state.rng);
+        assert!(game_obj_db.set_to_loc(0, (2, 2, 1)));
+        let mf = MonsterFactory::init();
+        let npcs = |db: &GameObjectDB| -> Vec<usize> {
+            db.listeners.iter().filter(|l| l.1 == EventType::TakeTurn).map(|l| l.0).collect()
+        };
+
+        // The first check only starts the level's clock
+        check_wandering_monsters(&mut state, &mut game_obj_db, &mf);
+        let next_spawn = state.spawn_timers[&1];
+        assert!(next_spawn > state.turn);
+        assert!(npcs(&game_obj_db).is_empty());
+
+        state.turn = next_spawn - 1;
+        check_wandering_monsters(&mut state, &mut game_obj_db, &mf);
+        assert!(npcs(&game_obj_db).is_empty());
+
+        state.turn = next_spawn;
+        check_wandering_monsters(&mut state, &mut game_obj_db, &mf);
+        let arrivals: Vec<(i32, i32, i8)> = npcs(&game_obj_db).iter().map(|id| game_obj_db.get(*id).unwrap().get_loc()).collect();
+        assert!(!arrivals.is_empty());
+        assert!(!arrivals.contains(&(2, 35, 1)));
+        assert!(arrivals.iter().any(|loc| util::are_adj(*loc, (2, 35, 1))));
+        assert!(state.spawn_timers[&1] > next_spawn);
+    }
+
+    #[test]
+    fn springs_run_dry_after_one_drink() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = test_level(&mut game_obj_db);
+        let spring = (5, 6, 1);
+        state.map.insert(spring, Tile::Spring);
+
+        drink_from_spring(&mut state, &mut game_obj_db, spring);
+        assert_eq!(state.map[&spring], Tile::StoneFloor);
+        assert!(state.msg_queue.iter().any(|m| m.text == "The spring sputters and runs dry."));
+    }
+
+    #[test]
+    fn autosaves_come_due_on_the_interval() {
+        let saves = SaveOptions { autosave_interval: 250, compress: false };
+        assert!(!saves.autosave_due(249));
+        assert!(saves.autosave_due(250));
+        assert!(saves.autosave_due(500));
+
+        let off = SaveOptions { autosave_interval: 0, compress: false };
+        assert!(!off.autosave_due(0));
+        assert!(!off.autosave_due(500));
+    }
+
+    #[test]
+    fn multi_step_actions_still_autosave() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = test_level(&mut game_obj_db);
+        let mut gui = headless::HeadlessUI::new();
+        let saves = SaveOptions { autosave_interval: 3, compress: true };
+        let mf = MonsterFactory::init();
+        let filename = calc_autosave_filename("Tester");
+        remove_autosave("Tester");
+
+        assert!(pass_turn(&mut state, &mut game_obj_db, &mut gui, &mf, &saves).is_ok());
+        assert!(pass_turn(&mut state, &mut game_obj_db, &mut gui, &mf, &saves).is_ok());
+        assert_eq!(state.turn, 2);
+        assert!(!existing_save_file(&filename));
+
+        assert!(pass_turn(&mut state, &mut game_obj_db, &mut gui, &mf, &saves).is_ok());
+        assert!(existing_save_file(&filename));
+        let (saved_state, _) = load_save_game(&filename).unwrap();
+        remove_autosave("Tester");
+        assert_eq!(saved_state.turn, 3);
+    }
+
+    #[test]
+    fn saves_load_back_whether_compressed_or_not() {
+        for compress in [false, true].iter() {
+            let mut game_obj_db = GameObjectDB::new();
+            let mut state = test_level(&mut game_obj_db);
+            state.turn = 1234;
+            state.add_journal_entry("Saved the game.");
+            let saves = SaveOptions { autosave_interval: 0, compress: *compress };
+            let path = std::env::temp_dir().join(format!("rv_save_test_{}.yaml", compress));
+            let filename = path.to_str().unwrap();
+            serialize_game_data(&state, &game_obj_db, filename, &saves);
+
+            let bytes = fs::read(filename).unwrap();
+            assert_eq!(bytes.starts_with(&GZIP_MAGIC), *compress);
+            let (loaded_state, mut loaded_db) = load_save_game(filename).unwrap();
+            let _ = fs::remove_file(filename);
+
+            assert_eq!(loaded_state.turn, 1234);
+            assert_eq!(loaded_state.map.len(), state.map.len());
+            assert_eq!(loaded_state.journal, state.journal);
+            assert_eq!(loaded_db.get(0).unwrap().get_loc(), (5, 5, 1));
+            assert_eq!(loaded_db.player().unwrap().inv_menu(0), game_obj_db.player().unwrap().inv_menu(0));
+        }
+    }
+
+    #[test]
+    fn morgue_has_the_character_kit_level_and_last_words() {
+        let mut game_obj_db = GameObjectDB::new();
+        let state = test_level(&mut game_obj_db);
+        let mut gui = headless::HeadlessUI::new();
+        let mut msgs = VecDeque::new();
+        msgs.push_back("The kobold hits you.".to_string());
+        gui.update(&mut msgs, None);
+
+        let lines = morgue_lines(&state, &mut game_obj_db, &gui, "killed by a kobold");
+        assert_eq!(lines[0], "Tester: killed by a kobold, on turn 0.");
+        assert!(lines.contains(&"Tester, a 1st level human warrior".to_string()));
+        let inv = lines.iter().position(|l| l == "Inventory:").unwrap();
+        assert_eq!(lines[inv + 1], "$) 20 gold pieces");
+        assert_eq!(lines[inv + 2], "a) a spear (in hand) [2gp, wt 2]");
+
+        let map = lines.iter().position(|l| l == "The 1st level of the dungeon:").unwrap();
+        assert_eq!(lines[map + 1], "###########");
+        assert_eq!(lines[map + 2], "#.........#");
+        assert_eq!(lines.last().unwrap(), "The kobold hits you.");
+    }
+
+    #[test]
+    fn the_player_leaves_a_scent_trail_unless_flying() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = test_level(&mut game_obj_db);
+        record_scent(&mut state, &mut game_obj_db);
+        record_scent(&mut state, &mut game_obj_db);
+        assert_eq!(state.scent_trail.len(), 1);
+
+        for c in 1..10 {
+            for r in 1..10 {
+                assert!(game_obj_db.set_to_loc(0, (r, c, 1)));
+                record_scent(&mut state, &mut game_obj_db);
+            }
+        }
+        assert_eq!(state.scent_trail.len(), SCENT_TRAIL_LENGTH);
+        assert_eq!(state.scent_trail[0], (9, 9, 1));
+
+        effects::add_status(game_obj_db.player().unwrap(), Status::Flying, 100);
+        assert!(game_obj_db.set_to_loc(0, (5, 5, 1)));
+        record_scent(&mut state, &mut game_obj_db);
+        assert_eq!(state.scent_trail[0], (9, 9, 1));
+    }
+
+    #[test]
+    fn fast_monsters_follow_the_player_down_the_stairs() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = test_level(&mut game_obj_db);
+        state.world_info.dungeon_depth = 2;
+        state.map.insert((5, 5, 1), Tile::StairsDown);
+        for r in 0..11 {
+            for c in 0..11 {
+                let tile = if r == 0 || r == 10 || c == 0 || c == 10 { Tile::Wall } else { Tile::StoneFloor };
+                state.map.insert((r, c, 2), tile);
+            }
+        }
+        state.map.insert((5, 5, 2), Tile::StairsUp);
+
+        let mf = MonsterFactory::init();
+        mf.monster("dire rat", (5, 6, 1), &mut game_obj_db, &mut state.rng);
+        mf.monster("kobold", (4, 5, 1), &mut game_obj_db, &mut state.rng);
+        let rat_id = game_obj_db.npc_at(&(5, 6, 1)).unwrap();
+        let kobold_id = game_obj_db.npc_at(&(4, 5, 1)).unwrap();
+        for id in [rat_id, kobold_id].iter() {
+            let npc = game_obj_db.npc(*id).unwrap();
+            npc.active = true;
+            npc.attitude = Attitude::Hostile;
+        }
+
+        assert_eq!(take_stairs(&mut state, &mut game_obj_db, true), 1.0);
+        assert_eq!(state.pending_followers, vec![rat_id]);
+        check_stair_followers(&mut state, &mut game_obj_db);
+
+        let rat_loc = game_obj_db.get(rat_id).unwrap().get_loc();
+        assert!(util::are_adj(rat_loc, (5, 5, 2)));
+        assert_eq!(game_obj_db.get(kobold_id).unwrap().get_loc(), (4, 5, 1));
+        assert!(state.msg_queue.iter().any(|m| m.text.ends_with("follows you!")));
+    }
+
+    #[test]
+    fn followers_arrive_during_multi_step_actions() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = test_level(&mut game_obj_db);
+        let mut gui = headless::HeadlessUI::new();
+        let saves = SaveOptions { autosave_interval: 0, compress: false };
+        state.world_info.dungeon_depth = 2;
+        state.map.insert((5, 5, 1), Tile::StairsDown);
+        for r in 0..11 {
+            for c in 0..11 {
+                let tile = if r == 0 || r == 10 || c == 0 || c == 10 { Tile::Wall } else { Tile::StoneFloor };
+                state.map.insert((r, c, 2), tile);
+            }
+        }
+        state.map.insert((5, 5, 2), Tile::StairsUp);
+
+        let mf = MonsterFactory::init();
+        mf.monster("dire rat", (5, 6, 1), &mut game_obj_db, &mut state.rng);
+        let rat_id = game_obj_db.npc_at(&(5, 6, 1)).unwrap();
+        let rat = game_obj_db.npc(rat_id).unwrap();
+        rat.active = true;
+        rat.attitude = Attitude::Hostile;
+
+        // Running or travelling right after taking the stairs goes through pass_turn(), not the main loop
+        take_stairs(&mut state, &mut game_obj_db, true);
+        assert!(pass_turn(&mut state, &mut game_obj_db, &mut gui, &mf, &saves).is_ok());
+
+        assert!(state.pending_followers.is_empty());
+        assert_eq!(game_obj_db.get(rat_id).unwrap().get_loc().2, 2);
+    }
+
+    #[test]
+    fn a_second_game_starts_from_scratch() {
+        let opts = ConfigOptions { font_size: 24, sm_font_size: 18, thirst: true, autopickup: Vec::new(), start_hour: DEFAULT_START_HOUR, town: TownOptions::new(),
+            dungeon_depth: 2, saves: SaveOptions::default() };
+        let mut mf = MonsterFactory::init();
+        let mut gui = headless::HeadlessUI::new();
+        gui.queue_keys("First Run\nbaa");
+        gui.queue_keys("Second Run\nccb");
+
+        let (mut state, _) = setup_game(&mut gui, &mut mf, &opts).unwrap();
+        state.turn = 5000;
+        assert_eq!(state.difficulty, Difficulty::Normal);
+
+        let (state, mut game_obj_db) = setup_game(&mut gui, &mut mf, &opts).unwrap();
+        assert_eq!(state.turn, 0);
+        assert!(state.thirst);
+        assert_eq!(state.difficulty, Difficulty::Hard);
+        assert_eq!(mf.difficulty, Difficulty::Hard);
+        assert_eq!(state.journal.len(), 1);
+        let player = game_obj_db.player().unwrap();
+        assert_eq!(player.get_fullname(), "Second Run");
+        assert_eq!((player.role.clone(), player.race), (player::Role::Mage, Race::Dwarf));
+        assert!(gui.screens.iter().any(|s| s.starts_with("How tough an adventure are you looking for?")));
+    }
+
+    #[test]
+    fn view_shows_lit_visible_and_remembered_squares() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = test_level(&mut game_obj_db);
+        state.lit_sqs.insert((5, 10, 1), display::LIGHT_PURPLE);
+        state.lit_sqs.insert((5, 9, 1), WHITE);
+        state.aura_sqs.insert((5, 6, 1));
+        state.tile_memory.insert((9, 9, 1), Tile::StoneFloor);
+
+        let mut gui = headless::HeadlessUI::new();
+        update_view(&mut state, &mut game_obj_db, &mut gui);
+
+        let at = |r: i32, c: i32| {
+            let row = (r - 5 + FOV_HEIGHT as i32 / 2) as usize;
+            let col = (c - 5 + FOV_WIDTH as i32 / 2) as usize;
+            gui.v_matrix[row * FOV_WIDTH + col]
+        };
+        assert_eq!(at(5, 5), (Tile::Player(WHITE), true));
+        assert_eq!(at(4, 5), (Tile::StoneFloor, true));
+        assert_eq!(at(5, 6), (Tile::ColourFloor(display::LIGHT_BLUE), true));
+        assert_eq!(at(5, 9), (Tile::ColourFloor(display::YELLOW), true));
+        assert_eq!(at(5, 10), (Tile::LitWall(display::LIGHT_PURPLE), true));
+        assert_eq!(at(9, 9), (Tile::StoneFloor, false));
+        assert_eq!(at(9, 1), (Tile::Blank, false));
+
+        // Lit squares are remembered as the plain tile underneath the light
+        assert_eq!(state.tile_memory[&(5, 10, 1)], Tile::Wall);
+    }
+
+    // A stretch of open grass on the surface with the dungeon entrance off at the far end
+    fn travel_field(game_obj_db: &mut GameObjectDB) -> GameState {
+        let mut state = test_state();
+        for r in 0..5 {
+            for c in 0..40 {
+                state.map.insert((r, c, 0), Tile::Grass);
+            }
+        }
+        state.world_info.facts.push(world::Fact::new("dungeon location".to_string(), 0, (2, 35, 0)));
+        Player::new_warrior(game_obj_db, "Tester", Race::Human, &mut state.rng);
+        assert!(game_obj_db.set_to_loc(0, (2, 2, 0)));
+
+        state
+    }
+
+    #[test]
+    fn fast_travel_takes_the_player_to_the_dungeon() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = travel_field(&mut game_obj_db);
+        let mut gui = headless::HeadlessUI::new();
+        let saves = SaveOptions { autosave_interval: 0, compress: false };
+        let mf = MonsterFactory::init();
+        update_view(&mut state, &mut game_obj_db, &mut gui);
+        gui.queue_keys("a");
+
+        assert_eq!(fast_travel(&mut state, &mut game_obj_db, &mut gui, &mf, &saves).ok(), Some(1.0));
+        assert_eq!(game_obj_db.player().unwrap().get_loc(), (2, 35, 0));
+        // Every step but the last passes a turn for the rest of the world
+        assert_eq!(state.turn, 32);
+    }
+
+    #[test]
+    fn fast_travel_stops_when_a_monster_comes_into_view() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = travel_field(&mut game_obj_db);
+        let mut gui = headless::HeadlessUI::new();
+        let saves = SaveOptions { autosave_interval: 0, compress: false };
+        let mf = MonsterFactory::init();
+        mf.monster("goblin", (4, 25, 0), &mut game_obj_db, &mut state.rng);
+        update_view(&mut state, &mut game_obj_db, &mut gui);
+        gui.queue_keys("a");
+
+        assert_eq!(fast_travel(&mut state, &mut game_obj_db, &mut gui, &mf, &saves).ok(), Some(1.0));
+        update_view(&mut state, &mut game_obj_db, &mut gui);
+        assert_eq!(gui.recent_messages(1), vec!["You spot danger and stop!".to_string()]);
+        let loc = game_obj_db.player().unwrap().get_loc();
+        assert!(loc.1 > 2 && loc.1 < 25);
+    }
+
+    fn inv_slot_of(game_obj_db: &mut GameObjectDB, name: &str) -> char {
+        let player = game_obj_db.player().unwrap();
+        let slots = player.inv_slots_used();
+        *slots.iter().find(|ch| matches!(player.inv_item_in_slot(**ch), Some(GameObjects::Item(i)) if i.base_info.name == name)).unwrap()
+    }
+
+    #[test]
+    fn readying_armour_and_shields_changes_ac() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = test_level(&mut game_obj_db);
+        let shield = Item::get_item(&mut game_obj_db, "shield", &mut state.rng).unwrap();
+        let player = game_obj_db.player().unwrap();
+        player.add_to_inv(shield);
+        // A +3 Dex bonus, which ringmail caps at +2
+        player.dex = 16;
+        player.calc_gear_effects();
+        assert_eq!(player.ac, 15);
+
+        let slot = inv_slot_of(&mut game_obj_db, "shield");
+        toggle_item(&mut state, slot, &mut game_obj_db);
+        let player = game_obj_db.player().unwrap();
+        assert_eq!(player.ac, 16);
+        let lines = equipment_lines(player);
+        assert!(lines.contains(&"Armour  ringmail (being worn) (AC +3, Dex bonus capped at +2, -2 stealth)".to_string()));
+        assert!(lines.iter().any(|l| l.starts_with("Shield  shield (on your arm) (AC +1, ")));
+        assert!(lines.iter().any(|l| l.starts_with("AC: 16 ")));
+
+        // Out of armour, the full Dex bonus applies again
+        let slot = inv_slot_of(&mut game_obj_db, "ringmail");
+        toggle_item(&mut state, slot, &mut game_obj_db);
+        assert_eq!(game_obj_db.player().unwrap().ac, 14);
+
+        let slot = inv_slot_of(&mut game_obj_db, "shield");
+        toggle_item(&mut state, slot, &mut game_obj_db);
+        assert_eq!(game_obj_db.player().unwrap().ac, 13);
     }
 }
@@ -19,7 +19,7 @@ extern crate serde;
 
 use serde::{Serialize, Deserialize};
 
-use super::{EventResponse, EventType, GameState, Map, Message};
+use super::{EventResponse, EventType, GameEvent, GameState, Map, Message};
 
 use crate::display;
 use crate::display::Colour;
@@ -72,6 +72,7 @@ pub enum Tile {
 	OldFirePit(u8),
 	Floor,
 	Window(char),
+	BrokenWindow(char),
 	Spring,
     Portal,
     Fog,
@@ -84,6 +85,9 @@ pub enum Tile {
 	Well,
 	Highlight(Colour, Colour, char),
 	Ice,
+	Bed,
+	Table,
+	Counter,
 }
 
 impl Tile {
@@ -119,7 +123,7 @@ impl Tile {
 	}
 
 	pub fn indoors(&self) -> bool {
-		matches!(self, Tile::Floor | Tile::StoneFloor | Tile::StairsUp | Tile::StairsDown)
+		matches!(self, Tile::Floor | Tile::StoneFloor | Tile::StairsUp | Tile::StairsDown | Tile::Bed | Tile::Table | Tile::Counter)
 	}
 }
 
@@ -147,6 +151,19 @@ impl SpecialSquare {
 		GameObjects::SpecialSquare(sq)		
 	}
 
+	// Traps the player has set out. They're visible (the player knows where they put them) and
+	// only go off when a monster steps on them.
+	pub fn player_trap(name: &str, location: (i32, i32, i8), game_obj_db: &mut GameObjectDB) -> GameObjects {
+		let sq = SpecialSquare { base_info: GameObjectBase::new(game_obj_db.next_id(), location, false, '^', display::GREY,
+			display::DARK_GREY, false, name), tile: Tile::Thing(display::GREY, display::DARK_GREY, '^'), radius: 0, target: None, active: true };
+
+		GameObjects::SpecialSquare(sq)
+	}
+
+	pub fn is_player_trap(&self) -> bool {
+		self.base_info.name == "bear trap" || self.base_info.name == "caltrops"
+	}
+
 	fn mark_aura(&self, state: &mut GameState, loc: (i32, i32, i8)) {
 		if self.active {
 			let in_aura = fov::calc_fov(state, loc, self.radius, true);
@@ -162,11 +179,11 @@ impl SpecialSquare {
 			self.active = !self.active;
 			state.msg_queue.push_back(Message::new(obj_id, loc, "You hear a metallic grinding.", "You hear a metallic grinding."));
 			if self.active {
-				state.queued_events.push_back((EventType::GateClosed, loc, obj_id, None));
+				state.queued_events.push_back(GameEvent::new(EventType::GateClosed, loc, obj_id));
 				state.map.insert(loc, Tile::Gate(DoorState::Closed));
 			} else {
 				state.map.insert(loc, Tile::Gate(DoorState::Open));
-				state.queued_events.push_back((EventType::GateOpened, loc, obj_id, None));
+				state.queued_events.push_back(GameEvent::new(EventType::GateOpened, loc, obj_id));
 			}
 		}
 	}
@@ -184,7 +201,9 @@ impl SpecialSquare {
 	fn stepped_on(&mut self, state: &mut GameState, obj_id: usize) -> Option<EventResponse> {
 		if self.tile == Tile::TeleportTrap {
 			return Some(EventResponse::new(obj_id, EventType::TrapRevealed));
-		}  else {
+		} else if self.is_player_trap() {
+			return Some(EventResponse::new(obj_id, EventType::TrapSprung));
+		} else {
 			state.msg_queue.push_back(Message::new(obj_id, self.get_loc(), "Click.", "Click."));
 			self.active = !self.active;
 
@@ -257,6 +276,23 @@ impl GameObject for SpecialSquare {
 	}
 }
 
+// Squares off the edge of the map (or on a level that doesn't exist) are treated as Blank, which
+// nothing can enter or see through. Use this instead of indexing the map directly anywhere
+// the location might not be on the map.
+pub fn tile_at(map: &Map, loc: &(i32, i32, i8)) -> Tile {
+	map.get(loc).copied().unwrap_or(Tile::Blank)
+}
+
+// Moving diagonally between two walls (or doors, etc) would mean squeezing through the corner 
+// where they meet, which nothing is allowed to do.
+pub fn cuts_corner(map: &Map, from: (i32, i32, i8), to: (i32, i32, i8)) -> bool {
+	if from.0 == to.0 || from.1 == to.1 {
+		return false;
+	}
+
+	tile_at(map, &(to.0, from.1, from.2)).solid() && tile_at(map, &(from.0, to.1, from.2)).solid()
+}
+
 pub fn adjacent_door(map: &Map, loc: (i32, i32, i8), door_state: DoorState) -> Option<(i32, i32, i8)> {
 	let mut doors = 0;
 	let mut door: (i32, i32, i8) = (0, 0, 0);
@@ -20,11 +20,12 @@ use std::fs;
 use std::u128;
 //use std::time::Instant;
 
-use rand::thread_rng;
+use crate::util::GameRng;
 use rand::Rng;
+use rand::seq::SliceRandom;
 use serde::{Serialize, Deserialize};
 
-use super::{EventResponse, EventType, GameState, Message, Status};
+use super::{Difficulty, EventResponse, EventType, GameEvent, GameState, Message, Status};
 
 use crate::battle;
 use crate::battle::DamageType;
@@ -32,14 +33,15 @@ use crate::dialogue;
 use crate::dialogue::DialogueLibrary;
 use crate::display;
 use crate::effects;
-use crate::effects::{AB_CREATE_PHANTASM, HasStatuses};
+use crate::effects::{AB_CREATE_PHANTASM, AB_RAISE_ALARM, AB_REMARK_ON_WOUNDS, HasStatuses};
 use crate::game_obj::{Ability, GameObject, GameObjectBase, GameObjectDB, GameObjects, Person};
 use crate::items::{GoldPile, Item};
-use crate::map::{Tile, DoorState};
-use crate::pathfinding::find_path;
+use crate::map::{tile_at, Tile, DoorState};
+use crate::pathfinding::{find_path, monster_passable};
 use crate::util;
 use crate::util::StringUtils;
 use crate::fov;
+use crate::world;
 
 // Loot categories from monsters.txt
 pub const LOOT_NONE: u128       = 0x00000001;
@@ -64,6 +66,16 @@ pub const MA_CONFUSION: u128         = 0x00001000;
 pub const MA_LEAVE_CORPSE: u128      = 0x00002000;
 pub const MA_PARALYZE: u128          = 0x00004000;
 pub const MA_SMASH_DOORS: u128       = 0x00008000;
+pub const MA_FAST: u128              = 0x00010000;
+pub const MA_SLOW: u128              = 0x00020000;
+pub const MA_SKITTISH: u128          = 0x00040000;
+pub const MA_CAN_BE_UNIQUE: u128     = 0x00080000;
+pub const MA_TRACKER: u128           = 0x00100000;
+pub const MA_REND: u128              = 0x00200000;
+pub const MA_SPORES: u128            = 0x00400000; // rolls which spores it releases when it's spawned
+
+// How loud a noise needs to be to rouse a monster that's dozing
+const WAKE_VOLUME: u8 = 5;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Venue {
@@ -71,10 +83,11 @@ pub enum Venue {
     Tavern,
     Shrine,
     Favourite((i32, i32, i8)),
-    Visit(i32),
+    Visit(usize), // another villager's home, by index into the town's homes
     Home(usize),
     Market,
     Smithy,
+    Caravan(i32), // days between a travelling merchant's stops in town
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -147,6 +160,9 @@ pub struct NPC {
     pub home: Option<Venue>,
     pub plan: VecDeque<Action>,
     pub voice: String,
+    pub is_monster: bool, // monsters are referred to generically ("the goblin") rather than by name
+    pub unique_name: Option<String>, // for the rare monster who has made a name for themselves
+    pub pack: Option<(usize, u8)>, // (obj_id of the pack leader, how many were in the pack when it spawned)
     pub schedule: Vec<AgendaItem>,
     pub mode: NPCPersonality,
     pub attack_mod: u8,
@@ -168,27 +184,31 @@ pub struct NPC {
     pub pronouns: Pronouns,
     pub rarity: u8,
     pub statuses: Vec<(Status, u32)>,
+    pub energy: f32,
+    pub energy_restore: f32,
+    #[serde(skip)]
+    pub fov_cache: fov::FovCache,
 }
 
 impl NPC {
-    pub fn villager(name: String, location: (i32, i32, i8), home: Option<Venue>, voice: &str, game_obj_db: &mut GameObjectDB) -> GameObjects {            
+    pub fn villager(name: String, location: (i32, i32, i8), home: Option<Venue>, voice: &str, game_obj_db: &mut GameObjectDB, rng: &mut GameRng) -> GameObjects {            
         let npc = NPC { base_info: GameObjectBase::new(game_obj_db.next_id(), location, false, '@', display::LIGHT_GREY, 
             display::LIGHT_GREY, true, &name), ac: 10, curr_hp: 8, max_hp: 8, attitude: Attitude::Stranger, facts_known: Vec::new(), home, plan: VecDeque::new(), 
-            voice: String::from(voice), schedule: Vec::new(), mode: NPCPersonality::Villager, attack_mod: 2, dmg_dice: 1, dmg_die: 3, dmg_bonus: 0, edc: 12,
+            voice: String::from(voice), is_monster: false, unique_name: None, pack: None, schedule: Vec::new(), mode: NPCPersonality::Villager, attack_mod: 2, dmg_dice: 1, dmg_die: 3, dmg_bonus: 0, edc: 12,
             attributes: MA_OPEN_DOORS | MA_UNLOCK_DOORS, alive: true, xp_value: 0, inventory: Vec::new(), active: true, active_behaviour: Behaviour::Idle, 
-            inactive_behaviour: Behaviour::Idle, level: 0, last_inventory: 0, recently_saw_player: false, size: 2, pronouns: pick_pronouns(), rarity: 0,
-            statuses: Vec::new(),
+            inactive_behaviour: Behaviour::Idle, level: 0, last_inventory: 0, recently_saw_player: false, fov_cache: fov::FovCache::default(), size: 2, pronouns: pick_pronouns(rng), rarity: 0,
+            statuses: Vec::new(), energy: 0.0, energy_restore: 1.0,
         };
 
 		GameObjects::NPC(npc)
     }
     
-    pub fn phantasm(name: String, location: (i32, i32, i8), sym: char, colour: (u8, u8, u8), game_obj_db: &mut GameObjectDB) -> GameObjects {
+    pub fn phantasm(name: String, location: (i32, i32, i8), sym: char, colour: (u8, u8, u8), game_obj_db: &mut GameObjectDB, rng: &mut GameRng) -> GameObjects {
         let phantasm = NPC { base_info: GameObjectBase::new(game_obj_db.next_id(), location, false, sym, colour, colour, true, &name), ac: 10, curr_hp: 0, max_hp: 0, 
-            attitude: Attitude::Hostile, facts_known: Vec::new(), home: None, plan: VecDeque::new(), voice: String::from("monster"), schedule: Vec::new(), 
+            attitude: Attitude::Hostile, facts_known: Vec::new(), home: None, plan: VecDeque::new(), voice: String::from("monster"), is_monster: true, unique_name: None, pack: None, schedule: Vec::new(), 
             mode: NPCPersonality::SimpleMonster, attack_mod: 0, dmg_dice: 0, dmg_die: 0, dmg_bonus: 0, edc: 10, attributes: MA_FEARLESS | MA_ILLUSION, alive: true, 
-            xp_value: 0, inventory: Vec::new(), active: true, active_behaviour: Behaviour::Hunt, inactive_behaviour: Behaviour::Hunt, level: 0, last_inventory: 0, recently_saw_player: false, 
-            size: 2, pronouns: pick_pronouns(), rarity: 0, statuses: Vec::new(),
+            xp_value: 0, inventory: Vec::new(), active: true, active_behaviour: Behaviour::Hunt, inactive_behaviour: Behaviour::Hunt, level: 0, last_inventory: 0, recently_saw_player: false, fov_cache: fov::FovCache::default(), 
+            size: 2, pronouns: pick_pronouns(rng), rarity: 0, statuses: Vec::new(), energy: 0.0, energy_restore: 1.0,
         };
 
 		GameObjects::NPC(phantasm)
@@ -206,6 +226,10 @@ impl NPC {
     //     }        
     // }
 
+    pub fn has_status(&self, status: Status) -> bool {
+        self.statuses.iter().any(|s| s.0 == status)
+    }
+
     // Select the current, highest priority agenda item from the schedule
     pub fn curr_agenda_item(&self, state: &GameState) -> Option<AgendaItem> {
         let ct = state.curr_time();
@@ -224,8 +248,8 @@ impl NPC {
     }
 
     pub fn talk_to(&mut self, state: &mut GameState, dialogue: &DialogueLibrary, extra_info: &mut HashMap<String, String>) -> String {
-        if self.voice == "monster" {
-            let s = format!("{} growls.", self.base_info.name.with_def_article().capitalize());
+        if self.is_monster {
+            let s = format!("{} growls.", self.npc_name(false).capitalize());
             return s;
         }
 
@@ -243,7 +267,7 @@ impl NPC {
             "".to_string()
         };
 
-        let line = dialogue::parse_voice_line(&dialogue::pick_voice_line(dialogue, &self.voice, self.attitude, &context), &state,
+        let line = dialogue::parse_voice_line(&dialogue::pick_voice_line(dialogue, &self.voice, self.attitude, &context, &mut state.rng), &state,
             &self.base_info.name, self.get_loc(), extra_info);
         if self.attitude == Attitude::Stranger {
             // Perhaps a charisma check to possibly jump straight to friendly?
@@ -253,23 +277,31 @@ impl NPC {
         line
     }
 
-    pub fn get_corpse(&self, game_obj_db: &mut GameObjectDB) -> Vec<GameObjects> {
+    pub fn get_corpse(&self, game_obj_db: &mut GameObjectDB, rng: &mut GameRng) -> Vec<GameObjects> {
         let mut pieces = Vec::new();
         if self.get_fullname() == "fungal growth" {
-            for _ in 0..rand::thread_rng().gen_range(1, 4) {
-                let mut m = Item::get_item(game_obj_db, "piece of mushroom").unwrap();
+            for _ in 0..rng.gen_range(1, 4) {
+                let mut m = Item::get_item(game_obj_db, "piece of mushroom", rng).unwrap();
                 m.set_loc(self.get_loc());
                 pieces.push(m);
             }
+        } else if rng.gen_range(0, 2) == 0 {
+            let mut corpse = Item::corpse(game_obj_db, &self.get_fullname(), self.size);
+            corpse.set_loc(self.get_loc());
+            pieces.push(corpse);
         }
     
         pieces
     }
 
-    // At the moment, just using the voice to determine the name, although maybe
-    // I'll later need a bit for anonymous vs named
+    pub fn is_named(&self) -> bool {
+        !self.is_monster || self.unique_name.is_some()
+    }
+
     pub fn npc_name(&self, indef: bool) -> String {
-        if self.voice != "monster" {
+        if let Some(name) = &self.unique_name {
+            name.clone()
+        } else if self.is_named() {
             self.base_info.name.clone()
         } else if indef {
             self.base_info.name.with_indef_article()
@@ -342,7 +374,7 @@ impl GameObject for NPC {
 impl Person for NPC {    
     fn damaged(&mut self, state: &mut GameState, amount: u8, dmg_type: DamageType, assailant_id: usize, _assailant_name: &str) {
         if self.attributes & MA_ILLUSION > 0 {
-            if rand::thread_rng().gen_range(0.0, 1.0) <= 0.75 {
+            if state.rng.gen_range(0.0, 1.0) <= 0.75 {
                 let msg = Message::new(self.base_info.object_id, self.get_loc(), "Your weapon seems to pass right through them!", "");
                 state.msg_queue.push_back(msg);
             } else {
@@ -350,7 +382,7 @@ impl Person for NPC {
                 let msg = Message::new(self.base_info.object_id, self.get_loc(), &s, "");
                 state.msg_queue.push_back(msg);
                 self.alive = false;
-                state.queued_events.push_back((EventType::DeathOf(self.base_info.object_id), self.get_loc(), self.base_info.object_id, None));
+                state.queued_events.push_back(GameEvent::new(EventType::DeathOf(self.base_info.object_id), self.get_loc(), self.base_info.object_id));
             }
             return;
         }
@@ -374,7 +406,7 @@ impl Person for NPC {
             let msg = Message::new(self.base_info.object_id, self.get_loc(), &self.death_msg(assailant_id), "You think you've landed a fatal blow!");
             state.msg_queue.push_back(msg);
             
-            state.queued_events.push_back((EventType::DeathOf(self.base_info.object_id), self.get_loc(), self.base_info.object_id, None));
+            state.queued_events.push_back(GameEvent::new(EventType::DeathOf(self.base_info.object_id), self.get_loc(), self.base_info.object_id));
         } else {
             self.curr_hp -= adjusted_dmg;
         }
@@ -390,8 +422,8 @@ impl Person for NPC {
 
     // I'm not (yet) giving monsters individual stats yet, so for ability checks 
     // just use their attack mod for now
-    fn ability_check(&self, _ability: Ability) -> u8 {
-        rand::thread_rng().gen_range(1, 21) + self.attack_mod
+    fn ability_check(&self, _ability: Ability, rng: &mut GameRng) -> u8 {
+        rng.gen_range(1, 21) + self.attack_mod
     }
 
     fn attributes(&self) -> u128 {
@@ -421,8 +453,33 @@ impl HasStatuses for NPC {
     }
 }
 
-fn pick_pronouns() -> Pronouns {
-    let roll = rand::thread_rng().gen_range(0, 3);
+// Every so often, the player will run into a monster who has risen above the rank-and-file
+// of their kind. They have a name and title to go with it and are a bit tougher.
+fn make_unique(npc: &mut NPC, rng: &mut GameRng) {
+    let starts = ["Gr", "Sn", "Kr", "Bl", "Ug", "Th", "Zog", "Murg", "Sk", "Dr"];
+    let ends = ["ukk", "ag", "ash", "orz", "ikk", "um", "ath", "og", "uz", "ak"];
+    let ranks = ["Chief", "Butcher", "Warlord", "Reaver", "Despoiler", "Skullsplitter"];
+
+    let proper = format!("{}{}", starts.choose(rng).unwrap(), ends.choose(rng).unwrap());
+    let rank = ranks.choose(rng).unwrap();
+    let species = &npc.base_info.name;
+    let title = if species.contains(' ') {
+        rank.to_string()
+    } else {
+        format!("{} {}", species.capitalize(), rank)
+    };
+    npc.unique_name = Some(format!("{} the {}", proper, title));
+
+    npc.max_hp = npc.max_hp.saturating_add(npc.max_hp / 2);
+    npc.curr_hp = npc.max_hp;
+    npc.ac += 1;
+    npc.attack_mod += 2;
+    npc.dmg_bonus += 1;
+    npc.xp_value *= 2;
+}
+
+fn pick_pronouns(rng: &mut GameRng) -> Pronouns {
+    let roll = rng.gen_range(0, 3);
     if roll == 0 {
         Pronouns::Masculine
     } else if roll == 1 {
@@ -432,6 +489,17 @@ fn pick_pronouns() -> Pronouns {
     }
 }
 
+// Spend a turn's worth of energy if the NPC (who is still alive) has enough
+pub fn ready_to_act(npc_id: usize, game_obj_db: &mut GameObjectDB) -> bool {
+    match game_obj_db.npc(npc_id) {
+        Some(npc) if npc.alive && npc.energy >= 1.0 => {
+            npc.energy -= 1.0;
+            true
+        },
+        _ => false,
+    }
+}
+
 pub fn take_turn(npc_id: usize, state: &mut GameState, game_obj_db: &mut GameObjectDB) {  
     let npc = game_obj_db.npc(npc_id).unwrap();
     let npc_loc = npc.get_loc();
@@ -447,7 +515,9 @@ pub fn take_turn(npc_id: usize, state: &mut GameState, game_obj_db: &mut GameObj
         Behaviour::Wander => wander(npc_id, state, game_obj_db, npc_loc),
         Behaviour::Idle => {
             if npc_mode == NPCPersonality::Villager {
-                villager_schedule(npc_id, state, game_obj_db, npc_loc);
+                if !villager_reacts_to_threats(npc_id, state, game_obj_db, npc_loc) {
+                    villager_schedule(npc_id, state, game_obj_db, npc_loc);
+                }
                 follow_plan(npc_id, state, game_obj_db);
             } else {
                 idle_monster(npc_id, state, game_obj_db, npc_loc);
@@ -486,11 +556,10 @@ fn wander(npc_id: usize, state: &mut GameState, game_obj_db: &mut GameObjectDB,
     let npc = game_obj_db.npc(npc_id).unwrap();
     let no_plan = npc.plan.is_empty();
     if no_plan {
-        let mut rng = rand::thread_rng();
         // try a bunch of times to find a new plae to move to.
         for _ in 0..50 {
-            let r = rng.gen_range(-10, 11);
-            let c = rng.gen_range(-10, 11);
+            let r = state.rng.gen_range(-10, 11);
+            let c = state.rng.gen_range(-10, 11);
             let n = (npc_loc.0 + r, npc_loc.1 + c, npc_loc.2);
             if state.map.contains_key(&n) && state.map[&n].passable_dry_land() {
                 calc_plan_to_move(npc_id, state, game_obj_db, n, false);
@@ -514,27 +583,114 @@ fn idle_monster(npc_id: usize, state: &mut GameState, game_obj_db: &mut GameObje
     }
 
     // just pick a random adjacent square
-    random_adj_sq(npc_id, state, game_obj_db, npc_loc);
+    let mut rng = std::mem::take(&mut state.rng);
+    random_adj_sq(npc_id, state, game_obj_db, npc_loc, &mut rng);
+    state.rng = rng;
     follow_plan(npc_id, state, game_obj_db);
 }
 
-pub fn heard_noise(npc_id: usize, loc: (i32, i32, i8), state: &mut GameState, game_obj_db: &mut GameObjectDB) {
+// Monsters that are already hunting head for the noise. Otherwise how a monster reacts depends on
+// what sort of creature it is: skittish ones run away from it, wanderers come to have a look, and
+// ones that are dozing only wake up if it's loud enough.
+pub fn heard_noise(npc_id: usize, loc: (i32, i32, i8), volume: u8, state: &mut GameState, game_obj_db: &mut GameObjectDB) {
     let player_loc = game_obj_db.player().unwrap().get_loc();
     let npc = game_obj_db.npc(npc_id).unwrap();
     // I need to make a better way to differentiate between monsters and villagers
-    if npc.voice == "monster" {
-        npc.attitude = Attitude::Hostile;
-        npc.active = true;
-        let npc_loc = npc.get_loc();
+    if !npc.is_monster || matches!(npc.inactive_behaviour, Behaviour::Plant) {
+        return;
+    }
 
-        if !can_see_player(state, game_obj_db, npc_loc, player_loc, npc_id) {
-            calc_plan_to_move(npc_id, state, game_obj_db, loc, false);
+    let npc_loc = npc.get_loc();
+    if !npc.active {
+        if npc.attributes & MA_SKITTISH > 0 {
+            flee_from(npc_id, state, game_obj_db, npc_loc, loc);
+            return;
+        } else if matches!(npc.inactive_behaviour, Behaviour::Wander) {
+            // Curious, but not yet looking for a fight. (The noise is often coming from whoever's
+            // standing on that square, so the monster can only get as far as next to it)
+            calc_plan_to_move(npc_id, state, game_obj_db, loc, true);
+            return;
+        } else if volume < WAKE_VOLUME {
+            return;
+        }
+    }
+
+    let npc = game_obj_db.npc(npc_id).unwrap();
+    npc.attitude = Attitude::Hostile;
+    npc.active = true;
+
+    if !can_see_player(state, game_obj_db, npc_loc, player_loc, npc_id) {
+        calc_plan_to_move(npc_id, state, game_obj_db, loc, true);
+    }
+}
+
+// Called whenever something dies. A pack that has lost its leader, or half of its number, may
+// break and scatter. Fearless monsters hold their ground. Either way the pack is dissolved afterwards
+// so that its survivors aren't checked again on every subsequent death.
+pub fn check_pack_morale(state: &mut GameState, game_obj_db: &mut GameObjectDB, dead_id: usize) {
+    let mut packs: HashMap<usize, (u8, Vec<usize>)> = HashMap::new();
+    let npcs: Vec<usize> = game_obj_db.listeners.iter()
+                        .filter(|l| l.1 == EventType::TakeTurn)
+                        .map(|l| l.0).collect();
+    for id in npcs {
+        if let Some(GameObjects::NPC(npc)) = game_obj_db.get(id) {
+            if let Some((leader, size)) = npc.pack {
+                if npc.alive {
+                    packs.entry(leader).or_insert((size, Vec::new())).1.push(id);
+                }
+            }
+        }
+    }
+
+    for (leader, (size, survivors)) in packs {
+        let leader_lost = leader == dead_id || !survivors.contains(&leader);
+        if !leader_lost && survivors.len() * 2 > size as usize {
+            continue;
+        }
+
+        for id in survivors {
+            let npc = game_obj_db.npc(id).unwrap();
+            npc.pack = None;
+            if npc.attributes & MA_FEARLESS > 0 {
+                continue;
+            }
+            let npc_loc = npc.get_loc();
+            let s = format!("{} flees in panic!", npc.npc_name(false).capitalize());
+            npc.plan.clear();
+            effects::add_status(npc, Status::Routed, state.turn + state.rng.gen_range(10, 21));
+            state.msg_queue.push_back(Message::new(id, npc_loc, &s, ""));
+        }
+    }
+}
+
+// Try to put some distance between the monster and something that frightened it
+fn flee_from(npc_id: usize, state: &mut GameState, game_obj_db: &mut GameObjectDB, npc_loc: (i32, i32, i8), threat: (i32, i32, i8)) {
+    let dr = (npc_loc.0 - threat.0).signum();
+    let dc = (npc_loc.1 - threat.1).signum();
+    for _ in 0..20 {
+        let r = npc_loc.0 + dr * state.rng.gen_range(3, 9) + state.rng.gen_range(-2, 3);
+        let c = npc_loc.1 + dc * state.rng.gen_range(3, 9) + state.rng.gen_range(-2, 3);
+        let n = (r, c, npc_loc.2);
+        if state.map.contains_key(&n) && state.map[&n].passable_dry_land() && !game_obj_db.blocking_obj_at(&n) {
+            calc_plan_to_move(npc_id, state, game_obj_db, n, false);
+            return;
         }
     }
 }
 
 fn hunt_player(npc_id: usize, npc_loc: (i32, i32, i8), state: &mut GameState, game_obj_db: &mut GameObjectDB) {
     let player_loc = game_obj_db.get(0).unwrap().get_loc();
+
+    // A routed monster wants nothing more than to be elsewhere
+    let npc = game_obj_db.npc(npc_id).unwrap();
+    if npc.has_status(Status::Routed) {
+        if npc.plan.is_empty() {
+            flee_from(npc_id, state, game_obj_db, npc_loc, player_loc);
+        }
+        follow_plan(npc_id, state, game_obj_db);
+        return;
+    }
+
     let sees = can_see_player(state, game_obj_db, npc_loc, player_loc, npc_id);
     let adj = util::are_adj(npc_loc, player_loc);
 
@@ -543,10 +699,13 @@ fn hunt_player(npc_id: usize, npc_loc: (i32, i32, i8), state: &mut GameState, ga
     }
     
     let npc = game_obj_db.npc(npc_id).unwrap();    
+    let scent = if npc.attributes & MA_TRACKER > 0 { freshest_scent(state, npc_loc) } else { None };
     if adj {        
         npc.plan.push_front(Action::Attack(player_loc));
     } else if sees {
         calc_plan_to_move(npc_id, state, game_obj_db, player_loc, true);
+    } else if let Some(sq) = scent {
+        calc_plan_to_move(npc_id, state, game_obj_db, sq, false);
     } else if npc.plan.is_empty() {
         let guess = best_guess_toward_player(state, npc_loc, player_loc);
         calc_plan_to_move(npc_id, state, game_obj_db, guess, true);
@@ -571,7 +730,7 @@ fn unlock_door(npc_id: usize, loc: (i32, i32, i8), npc_loc: (i32, i32, i8), stat
 
 fn smash_door(npc_id: usize, loc: (i32, i32, i8), npc_loc: (i32, i32, i8), state: &mut GameState, npc_name: String, game_obj_db: &mut GameObjectDB) {    
     let npc = game_obj_db.npc(npc_id).unwrap();
-    if npc.ability_check(Ability::Str) > 17 {
+    if npc.ability_check(Ability::Str, &mut state.rng) > 17 {
         state.map.insert(loc, Tile::Door(DoorState::Broken));
         let s = format!("{} smashes down the door.", npc_name);
         let msg = Message::new(npc_id, npc_loc, &s, "Wham! You hear wood rending!");
@@ -592,7 +751,7 @@ fn close_door(loc: (i32, i32, i8), state: &mut GameState, game_obj_db: &mut Game
         let npc = game_obj_db.npc(npc_id).unwrap();
         npc.plan.push_front(Action::CloseDoor(loc));
     } else {
-        if let Tile::Door(DoorState::Open) = state.map[&loc] {
+        if let Tile::Door(DoorState::Open) = tile_at(&state.map, &loc) {
             state.map.insert(loc, Tile::Door(DoorState::Closed));
             let npc = game_obj_db.npc(npc_id).unwrap();
             if npc.attitude == Attitude::Stranger {
@@ -620,7 +779,11 @@ fn follow_plan(npc_id: usize, state: &mut GameState, game_obj_db: &mut GameObjec
             Action::CloseDoor(loc) => close_door(loc, state, game_obj_db, npc_id, npc_loc, npc_name),
             Action::UnlockDoor(loc) => unlock_door(npc_id, loc, npc_loc, state, npc_name),
             Action::SmashDoor(loc) => smash_door(npc_id, loc, npc_loc, state, npc_name, game_obj_db),
-            Action::Attack(_loc) => battle::monster_attacks_player(state, npc_id, game_obj_db),
+            Action::Attack(_loc) => {
+                if battle::monster_attacks_player(state, npc_id, game_obj_db) == battle::AttackOutcome::Missed {
+                    battle::player_reacts_to_miss(state, npc_id, game_obj_db);
+                }
+            },
         }
     }
 }
@@ -643,18 +806,18 @@ fn try_to_move_to_loc(npc_id: usize, goal_loc: (i32, i32, i8), state: &mut GameS
         // if someone/something is blocking path, clear the current plan which should trigger 
         // creating a new plan
         npc.plan.clear();
-    } else if state.map[&goal_loc] == Tile::Door(DoorState::Closed) {
+    } else if tile_at(&state.map, &goal_loc) == Tile::Door(DoorState::Closed) {
         npc.plan.push_front(Action::Move(goal_loc));
         open_door(npc_id, goal_loc, npc_loc, state, npc_name);
-    } else if state.map[&goal_loc] == Tile::Door(DoorState::Locked) && attributes & MA_UNLOCK_DOORS > 0 {
+    } else if tile_at(&state.map, &goal_loc) == Tile::Door(DoorState::Locked) && attributes & MA_UNLOCK_DOORS > 0 {
         npc.plan.push_front(Action::Move(goal_loc));
         unlock_door(npc_id, goal_loc, npc_loc, state, npc_name);
-    } else if state.map[&goal_loc] == Tile::Door(DoorState::Locked) && attributes & MA_SMASH_DOORS > 0 {
+    } else if tile_at(&state.map, &goal_loc) == Tile::Door(DoorState::Locked) && attributes & MA_SMASH_DOORS > 0 {
         smash_door(npc_id, goal_loc, npc_loc, state, npc_name, game_obj_db);
     } else {
         // Villagers will close doors after they pass through them
         if npc_mode == NPCPersonality::Villager {
-            if let Tile::Door(DoorState::Open) = state.map[&npc_loc] {
+            if let Tile::Door(DoorState::Open) = tile_at(&state.map, &npc_loc) {
                 npc.plan.push_front(Action::CloseDoor(npc_loc));                
             }
         }
@@ -663,7 +826,119 @@ fn try_to_move_to_loc(npc_id: usize, goal_loc: (i32, i32, i8), state: &mut GameS
     }
 }
 
-fn villager_schedule(npc_id: usize, state: &GameState, game_obj_db: &mut GameObjectDB, npc_loc: (i32, i32, i8)) {
+// Villagers only keep an eye out for monsters when the player is close enough for it to matter.
+// Computing a field of view for every villager every turn is otherwise a lot of wasted work.
+const THREAT_WATCH_RANGE: f64 = 30.0;
+
+// I'm not bothering with a perception check for villagers spotting monsters the way
+// monsters have to spot the player. Something big and toothy wandering through town
+// is going to get noticed.
+fn nearest_threat(state: &GameState, game_obj_db: &mut GameObjectDB, npc_id: usize, npc_loc: (i32, i32, i8)) -> Option<usize> {
+    let player_loc = game_obj_db.get(0).unwrap().get_loc();
+    if player_loc.2 != npc_loc.2 || util::distance(npc_loc.0, npc_loc.1, player_loc.0, player_loc.1) > THREAT_WATCH_RANGE {
+        return None;
+    }
+
+    let mut nearest = None;
+    let mut best = f64::MAX;
+    for sq in fov::calc_fov(state, npc_loc, 8, true) {
+        if let Some(other_id) = game_obj_db.npc_at(&sq) {
+            if other_id == npc_id {
+                continue;
+            }
+
+            let other = game_obj_db.npc(other_id).unwrap();
+            if other.is_monster && other.attitude == Attitude::Hostile && other.alive && !other.hidden() {
+                let d = util::distance(npc_loc.0, npc_loc.1, sq.0, sq.1);
+                if d < best {
+                    best = d;
+                    nearest = Some(other_id);
+                }
+            }
+        }
+    }
+
+    nearest
+}
+
+fn flee_indoors(npc_id: usize, state: &GameState, game_obj_db: &mut GameObjectDB, npc_loc: (i32, i32, i8), rng: &mut GameRng) {
+    let tb = state.world_info.town_buildings.as_ref().unwrap();
+    let npc = game_obj_db.npc(npc_id).unwrap();
+    let refuge = match npc.home {
+        Some(Venue::Home(home_id)) => &tb.homes[home_id],
+        Some(Venue::Market) => &tb.market,
+        Some(Venue::Smithy) => &tb.smithy,
+        _ => &tb.tavern,
+    };
+
+    if refuge.is_empty() || in_location(state, npc_loc, refuge, true) {
+        // Already safe inside so just stay put
+        npc.plan.clear();
+    } else {
+        go_to_place(npc_id, state, game_obj_db, refuge, rng);
+    }
+}
+
+fn remark_on_wounds(npc_id: usize, state: &mut GameState, game_obj_db: &mut GameObjectDB, npc_loc: (i32, i32, i8)) {
+    let player = game_obj_db.player().unwrap();
+    let player_loc = player.get_loc();
+    let badly_hurt = (player.curr_hp as f32 / player.max_hp as f32) < 0.33;
+
+    if badly_hurt && util::distance(npc_loc.0, npc_loc.1, player_loc.0, player_loc.1) < 4.0 && state.curr_visible.contains(&npc_loc) {
+        let npc = game_obj_db.npc(npc_id).unwrap();
+        if !npc.has_status(Status::CoolingDown(AB_REMARK_ON_WOUNDS)) {
+            let s = format!("{} says, \"You look terrible! You should get some rest.\"", npc.npc_name(false).capitalize());
+            state.msg_queue.push_back(Message::new(npc_id, npc_loc, &s, ""));
+            effects::add_status(npc, Status::CoolingDown(AB_REMARK_ON_WOUNDS), state.turn + 100);
+        }
+    }
+}
+
+// If a villager spots a monster in town, they shout for help and run for cover. 
+// Returns true if the villager's turn is taken up reacting to a threat.
+fn villager_reacts_to_threats(npc_id: usize, state: &mut GameState, game_obj_db: &mut GameObjectDB, npc_loc: (i32, i32, i8)) -> bool {
+    if let Some(threat_id) = nearest_threat(state, game_obj_db, npc_id, npc_loc) {
+        let threat_name = game_obj_db.npc(threat_id).unwrap().npc_name(true);
+        let npc = game_obj_db.npc(npc_id).unwrap();
+        if !npc.has_status(Status::CoolingDown(AB_RAISE_ALARM)) {
+            let s = format!("{} shouts, \"Help! There's {} in town!\"", npc.npc_name(false).capitalize(), threat_name);
+            state.msg_queue.push_back(Message::new(npc_id, npc_loc, &s, "You hear someone shouting for help!"));
+            effects::add_status(npc, Status::CoolingDown(AB_RAISE_ALARM), state.turn + 20);
+            super::floodfill_noise(state, game_obj_db, npc_loc, 15, npc_id);
+        }
+
+        let mut rng = std::mem::take(&mut state.rng);
+        flee_indoors(npc_id, state, game_obj_db, npc_loc, &mut rng);
+        state.rng = rng;
+        return true;
+    }
+
+    remark_on_wounds(npc_id, state, game_obj_db, npc_loc);
+
+    false
+}
+
+pub fn night_time(state: &GameState) -> bool {
+    let (hour, _) = state.curr_time();
+    !(6..22).contains(&hour)
+}
+
+// Villagers who are home for the night lock up behind them
+fn lock_up_for_night(npc_id: usize, state: &mut GameState, game_obj_db: &mut GameObjectDB, home_id: usize) {
+    let doors: Vec<(i32, i32, i8)> = state.world_info.town_buildings.as_ref().unwrap().homes[home_id].iter()
+        .filter(|sq| state.map[*sq] == Tile::Door(DoorState::Closed))
+        .copied()
+        .collect();
+
+    let npc = game_obj_db.npc(npc_id).unwrap();
+    for door in doors {
+        state.map.insert(door, Tile::Door(DoorState::Locked));
+        let s = format!("{} locks the door.", npc.npc_name(false).capitalize());
+        state.msg_queue.push_back(Message::new(npc_id, door, &s, "You hear a lock click."));
+    }
+}
+
+fn villager_schedule(npc_id: usize, state: &mut GameState, game_obj_db: &mut GameObjectDB, npc_loc: (i32, i32, i8)) {
     let npc = game_obj_db.npc(npc_id).unwrap();
     let npc_home_id = if let Some(Venue::Home(home_id)) = npc.home {
         home_id as i32
@@ -671,49 +946,86 @@ fn villager_schedule(npc_id: usize, state: &GameState, game_obj_db: &mut GameObj
         -1
     };
 
+    // Homes are indexed from 0; -1 means the villager has no home of their own
+    let at_home = npc_home_id >= 0
+        && in_location(state, npc_loc, &state.world_info.town_buildings.as_ref().unwrap().homes[npc_home_id as usize], true);
+
+    // Whether they've gone home because there's nothing on their agenda or they've turned in for
+    // the night, villagers who are home at night lock up behind them
+    if at_home && night_time(state) {
+        lock_up_for_night(npc_id, state, game_obj_db, npc_home_id as usize);
+    }
+
+    // Picking a destination borrows the town's buildings from the state, so the rng is set aside
+    // while the villager makes plans
+    let mut rng = std::mem::take(&mut state.rng);
+    let npc = game_obj_db.npc(npc_id).unwrap();
     if let Some(curr_item) = npc.curr_agenda_item(state) {
-        check_agenda_item(npc_id, state, game_obj_db, &curr_item, npc_loc);
-    } else {
+        check_agenda_item(npc_id, state, game_obj_db, &curr_item, npc_loc, &mut rng);
+    } else if npc_home_id >= 0 && !at_home {
         // The default behaviour is to go home if nothing on the agenda.
         let b = &state.world_info.town_buildings.as_ref().unwrap();
-        
-        if npc_home_id > 0 {
-            if !in_location(state, npc_loc, &b.homes[npc_home_id as usize], true) {
-                go_to_place(npc_id, state, game_obj_db, &b.homes[npc_home_id as usize]);
-            } else {
-                random_adj_sq(npc_id, state, game_obj_db, npc_loc);                    
-            }
-        } else {
-            random_adj_sq(npc_id, state, game_obj_db, npc_loc);
-        }
-    } 
+        go_to_place(npc_id, state, game_obj_db, &b.homes[npc_home_id as usize], &mut rng);
+    } else {
+        random_adj_sq(npc_id, state, game_obj_db, npc_loc, &mut rng);
+    }
+    state.rng = rng;
 }
 
-fn check_agenda_item(npc_id: usize, state: &GameState, game_obj_db: &mut GameObjectDB, item: &AgendaItem, npc_loc: (i32, i32, i8)) {        
+fn check_agenda_item(npc_id: usize, state: &GameState, game_obj_db: &mut GameObjectDB, item: &AgendaItem, npc_loc: (i32, i32, i8), rng: &mut GameRng) {        
+    let tb = state.world_info.town_buildings.as_ref().unwrap();
     let venue =
         match item.place {
-            Venue::Tavern => &state.world_info.town_buildings.as_ref().unwrap().tavern,
-            Venue::Market => &state.world_info.town_buildings.as_ref().unwrap().market,
-            Venue::Smithy => &state.world_info.town_buildings.as_ref().unwrap().smithy,
+            Venue::Tavern => &tb.tavern,
+            Venue::Market => &tb.market,
+            Venue::Smithy => &tb.smithy,
+            Venue::Shrine => &tb.shrine,
             Venue::TownSquare => &state.world_info.town_square,
-            _ => panic!("Haven't implemented that venue yet!"),
+            Venue::Home(home_id) => &tb.homes[home_id],
+            Venue::Visit(home_id) => &tb.homes[home_id],
+            // A caravan sets up in the town square on the days it's in town
+            Venue::Caravan(_) => &state.world_info.town_square,
+            Venue::Favourite(spot) => {
+                // A favourite spot is a single square so the NPC wants to be right on it (or 
+                // beside it if someone else has taken it) rather than just somewhere inside
+                if npc_loc != spot && !(util::are_adj(npc_loc, spot) && game_obj_db.blocking_obj_at(&spot)) {
+                    calc_plan_to_move(npc_id, state, game_obj_db, spot, false);
+                }
+                return;
+            },
         };
 
     if !venue.is_empty() && !in_location(state, npc_loc, &venue, true) {
-        go_to_place(npc_id, state, game_obj_db, &venue);
+        go_to_place(npc_id, state, game_obj_db, &venue, rng);
     } else {
-        random_adj_sq(npc_id, state, game_obj_db, npc_loc);
+        random_adj_sq(npc_id, state, game_obj_db, npc_loc, rng);
     }
 }
 
 // Generally, when I have an NPC go a building/place, I assume it doesn't matter too much if 
 // they go to specific square inside it, so just pick any one of them.
-fn go_to_place(npc_id: usize, state: &GameState, game_obj_db: &mut GameObjectDB, sqs: &HashSet<(i32, i32, i8)>) {
-    let j = thread_rng().gen_range(0, &sqs.len());
+fn go_to_place(npc_id: usize, state: &GameState, game_obj_db: &mut GameObjectDB, sqs: &HashSet<(i32, i32, i8)>, rng: &mut GameRng) {
+    let j = rng.gen_range(0, &sqs.len());
     let goal_loc = &sqs.iter().nth(j).unwrap().clone(); // Clone prevents a compiler warning...
     calc_plan_to_move(npc_id, state, game_obj_db, *goal_loc, false);
 }
 
+// Trackers don't need to see the player to chase them. If they are next to the player's recent 
+// trail, they follow it to its freshest point.
+fn freshest_scent(state: &GameState, loc: (i32, i32, i8)) -> Option<(i32, i32, i8)> {
+    for sq in state.scent_trail.iter() {
+        if *sq == loc {
+            // The npc is already standing on any fresher part of the trail it could reach
+            return None;
+        }
+        if util::are_adj(*sq, loc) {
+            return Some(*sq);
+        }
+    }
+
+    None
+}
+
 // Quick, dirty guess of which adjacent, open square is closest to the player
 fn best_guess_toward_player(state: &GameState, loc: (i32, i32, i8), player_loc: (i32, i32, i8)) -> (i32, i32, i8) {
     let mut nearest = i32::MAX;
@@ -722,7 +1034,7 @@ fn best_guess_toward_player(state: &GameState, loc: (i32, i32, i8), player_loc:
         let a = (loc.0 + adj.0, loc.1 + adj.1, loc.2);
 
         // This will need to be updated when I add aquatic creatures
-        if !state.map[&a].passable_dry_land() {
+        if !tile_at(&state.map, &a).passable_dry_land() {
             continue;
         }
 
@@ -736,12 +1048,12 @@ fn best_guess_toward_player(state: &GameState, loc: (i32, i32, i8), player_loc:
     best
 }
 
-fn random_adj_sq(npc_id: usize, state: &GameState , game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8)) {
-    if thread_rng().gen_range(0.0, 1.0) < 0.33 {
-        let j = thread_rng().gen_range(0, util::ADJ.len()) as usize;
+fn random_adj_sq(npc_id: usize, state: &GameState , game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8), rng: &mut GameRng) {
+    if rng.gen_range(0.0, 1.0) < 0.33 {
+        let j = rng.gen_range(0, util::ADJ.len());
         let d = util::ADJ[j];
         let adj = (loc.0 + d.0, loc.1 + d.1, loc.2);
-        if !game_obj_db.blocking_obj_at(&adj) && state.map[&adj].passable_dry_land() {
+        if !game_obj_db.blocking_obj_at(&adj) && tile_at(&state.map, &adj).passable_dry_land() {
             calc_plan_to_move(npc_id, state, game_obj_db, adj, false);
         }
     }
@@ -754,24 +1066,7 @@ fn calc_plan_to_move(npc_id: usize, state: &GameState, game_obj_db: &mut GameObj
     let npc = game_obj_db.npc(npc_id).unwrap();
     npc.plan.clear();
 
-    let mut passable = HashMap::new();
-    passable.insert(Tile::Grass, 1.0);
-    passable.insert(Tile::Dirt, 1.0);
-    passable.insert(Tile::Tree, 1.0);
-    passable.insert(Tile::Bridge, 1.0);
-    passable.insert(Tile::Door(DoorState::Open), 1.0);
-    passable.insert(Tile::Door(DoorState::Broken), 1.0);
-    passable.insert(Tile::Gate(DoorState::Open), 1.0);
-    passable.insert(Tile::Gate(DoorState::Broken), 1.0);
-    if npc.attributes & MA_OPEN_DOORS > 0 {
-        passable.insert(Tile::Door(DoorState::Closed), 2.0);
-    }
-    if npc.attributes & (MA_UNLOCK_DOORS | MA_SMASH_DOORS) > 0 {
-        passable.insert(Tile::Door(DoorState::Locked), 2.5);
-    }
-    passable.insert(Tile::StoneFloor, 1.0);
-    passable.insert(Tile::Floor, 1.0);
-    passable.insert(Tile::Trigger, 1.0);
+    let passable = monster_passable(npc.attributes);
     
     let npc_loc = npc.get_loc();
     let mut path = find_path(&state.map, Some(game_obj_db), stop_before, npc_loc.0, npc_loc.1, 
@@ -792,7 +1087,7 @@ fn spin_webs(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc: (i32, i
 
     for adj in util::ADJ.iter() {
         let adj_loc = (loc.0 + adj.0, loc.1 + adj.1, loc.2);
-        if state.map[&adj_loc].passable() && rand::thread_rng().gen_range(0.0, 1.0) < 0.66 {
+        if tile_at(&state.map, &adj_loc).passable() && state.rng.gen_range(0.0, 1.0) < 0.66 {
             let mut web = Item::web(game_obj_db, difficulty);
             web.set_loc(adj_loc);
             game_obj_db.add(web);
@@ -811,28 +1106,41 @@ fn minor_black_magic(npc_id: usize, state: &mut GameState, game_obj_db: &mut Gam
     let npc_hp = npc.get_hp();
     
     // if they are injured and near the player, they will blink away 50% of the time    
-    if  (npc_hp.0 as f32 / npc_hp.1 as f32) < 0.33 && distance <= 3.0 && rand::thread_rng().gen_range(0.0, 1.0) < 0.5 {
+    if  (npc_hp.0 as f32 / npc_hp.1 as f32) < 0.33 && distance <= 3.0 && state.rng.gen_range(0.0, 1.0) < 0.5 {
         let s = format!("{} blinks away!", npc_name.capitalize());
         state.msg_queue.push_back(Message::new(npc_id, npc_loc, &s, "You hear a poof."));
         effects::apply_effects(state, npc_id, game_obj_db, effects::EF_BLINK);
         return true;
     }
 
-    if sees_player && distance <= 3.0 && rand::thread_rng().gen_range(0.0, 1.0) < 0.33 {
+    // The curses need a clear path to the player; seeing them through the bars of a window isn't enough
+    let sees_player = sees_player && util::line_of_effect(&state.map, npc_loc, player_loc);
+
+    if sees_player && distance <= 3.0 && state.rng.gen_range(0.0, 1.0) < 0.33 {
         let s = format!("{} mumbles.", npc_name.capitalize());
         state.msg_queue.push_back(Message::new(npc_id, npc_loc, &s, "You hear mumbling."));
         state.msg_queue.push_back(Message::new(npc_id, player_loc, "A shroud falls over your eyes!", "A shroud falls over your eyes!"));
         let player = game_obj_db.player().unwrap();
-        effects::add_status(player, Status::Blind, state.turn + rand::thread_rng().gen_range(3, 6));
+        effects::add_status(player, Status::Blind, state.turn + state.rng.gen_range(3, 6));
+        return true;
+    }
+
+    // Snuffing out the player's light is a good way to even the odds
+    if sees_player && distance <= 6.0 && !state.darkness.contains_key(&player_loc) && state.rng.gen_range(0.0, 1.0) < 0.2 {
+        let s = format!("{} mumbles.", npc_name.capitalize());
+        state.msg_queue.push_back(Message::new(npc_id, npc_loc, &s, "You hear mumbling."));
+        state.msg_queue.push_back(Message::new(npc_id, player_loc, "Darkness swallows the area!", "Darkness swallows the area!"));
+        let until = state.turn + state.rng.gen_range(8, 13);
+        effects::cast_darkness(state, player_loc, 3, until);
         return true;
     }
 
-    if sees_player && distance <= 3.0 && rand::thread_rng().gen_range(0.0, 1.0) < 0.33 {
+    if sees_player && distance <= 3.0 && state.rng.gen_range(0.0, 1.0) < 0.33 {
         let s = format!("{} mumbles.", npc_name.capitalize());
         state.msg_queue.push_back(Message::new(npc_id, npc_loc, &s, "You hear mumbling."));
         state.msg_queue.push_back(Message::new(npc_id, npc_loc, "You have been cursed!", "You have been cursed!"));
         let player = game_obj_db.player().unwrap();
-        effects::add_status(player, Status::Bane, state.turn + rand::thread_rng().gen_range(3, 6));
+        effects::add_status(player, Status::Bane, state.turn + state.rng.gen_range(3, 6));
         return true;
     }
 
@@ -843,19 +1151,19 @@ fn create_phantasm(npc_id: usize, state: &mut GameState, game_obj_db: &mut GameO
     let mut options = Vec::new();
     for adj in util::ADJ.iter() {
         let loc = (centre.0 + adj.0, centre.1 + adj.1, centre.2);
-        if !game_obj_db.location_occupied(&loc) && state.map[&loc].passable() {
+        if !game_obj_db.location_occupied(&loc) && tile_at(&state.map, &loc).passable() {
             options.push(loc);
         }
     }
 
     if !options.is_empty() {
-        let j = rand::thread_rng().gen_range(0, options.len());
+        let j = state.rng.gen_range(0, options.len());
         let npc = game_obj_db.npc(npc_id).unwrap();
         let ch = npc.base_info.symbol;
         let colour = npc.base_info.lit_colour;
         let name = &npc.base_info.name.to_string();
         let phantasm_loc = options[j];
-        let phantasm = NPC::phantasm(name.to_string(), phantasm_loc, ch, colour, game_obj_db);
+        let phantasm = NPC::phantasm(name.to_string(), phantasm_loc, ch, colour, game_obj_db, &mut state.rng);
         let pid = phantasm.obj_id();
         
         game_obj_db.add(phantasm);
@@ -869,11 +1177,8 @@ fn create_phantasm(npc_id: usize, state: &mut GameState, game_obj_db: &mut GameO
         state.msg_queue.push_back(Message::new(pid, phantasm_loc, &s, ""));
         
         // The caster sometimes swaps places with the newly summoned phantasm
-        if rand::thread_rng().gen_range(0.0, 1.0) < 0.33 {
-            let npc = game_obj_db.get_mut(npc_id).unwrap();
-            let npc_loc = npc.get_loc();
-            game_obj_db.set_to_loc(npc_id, phantasm_loc);
-            game_obj_db.set_to_loc(pid, npc_loc);
+        if state.rng.gen_range(0.0, 1.0) < 0.33 {
+            game_obj_db.swap_locs(npc_id, pid);
         }
     }
 }
@@ -899,21 +1204,21 @@ fn minor_trickery(npc_id: usize, state: &mut GameState, game_obj_db: &mut GameOb
     }
 
     // if they are injured and near the player, they will blink away 50% of the time (this check is cut-n-pasted from minor_black_magic...)
-    if  (npc_hp.0 as f32 / npc_hp.1 as f32) < 0.33 && distance <= 3.0 && rand::thread_rng().gen_range(0.0, 1.0) < 0.5 {
+    if  (npc_hp.0 as f32 / npc_hp.1 as f32) < 0.33 && distance <= 3.0 && state.rng.gen_range(0.0, 1.0) < 0.5 {
         let s = format!("{} blinks away!", npc_name.capitalize());
         state.msg_queue.push_back(Message::new(npc_id, npc_loc, &s, "You hear a poof."));
         effects::apply_effects(state, npc_id, game_obj_db, effects::EF_BLINK);
         return true;
     }
 
-    if sees_player && !invisible && rand::thread_rng().gen_range(0.0, 1.0) < 0.33 {
+    if sees_player && !invisible && state.rng.gen_range(0.0, 1.0) < 0.33 {
         let s = format!("{} disappears!", npc_name.capitalize());
         state.msg_queue.push_back(Message::new(npc_id, npc_loc, &s, ""));
-        effects::add_status(npc, Status::Invisible, state.turn + rand::thread_rng().gen_range(5, 8));
+        effects::add_status(npc, Status::Invisible, state.turn + state.rng.gen_range(5, 8));
         return true;
     }
 
-    if !cast_phantasm && adj && !invisible && rand::thread_rng().gen_range(0.0, 1.0) < 0.33 {
+    if !cast_phantasm && adj && !invisible && state.rng.gen_range(0.0, 1.0) < 0.33 {
         // create three phantasm duplicates
         create_phantasm(npc_id, state, game_obj_db, player_loc);
         create_phantasm(npc_id, state, game_obj_db, player_loc);
@@ -936,7 +1241,7 @@ fn special_move(npc_id: usize, state: &mut GameState, game_obj_db: &mut GameObje
     
     if attributes & MA_WEBSLINGER > 0 && sees_player && !adj {
         let d = util::distance(npc_loc.0, npc_loc.1, player_loc.0, player_loc.1);
-        if d < 5.0 && rand::thread_rng().gen_range(0.0, 1.0) < 0.33 {
+        if d < 5.0 && util::line_of_effect(&state.map, npc_loc, player_loc) && state.rng.gen_range(0.0, 1.0) < 0.33 {
             spin_webs(state, game_obj_db, player_loc, npc_id, npc_name, difficulty);
             return true;
         }
@@ -953,11 +1258,19 @@ fn special_move(npc_id: usize, state: &mut GameState, game_obj_db: &mut GameObje
     false
 }
 
-fn can_see_player(state: &GameState, game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8), player_loc: (i32, i32, i8), npc_id: usize) -> bool {
+fn can_see_player(state: &mut GameState, game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8), player_loc: (i32, i32, i8), npc_id: usize) -> bool {
     let dr = loc.0 - player_loc.0;
     let dc = loc.1 - player_loc.1;
     let d = dr * dr + dc * dc;
 
+    // An invisible player can only be noticed by monsters right next to them
+    if d > 2 && game_obj_db.get(0).unwrap().hidden() {
+        if let Some(GameObjects::NPC(npc)) = game_obj_db.get_mut(npc_id) {
+            npc.recently_saw_player = false;
+        }
+        return false;
+    }
+
     // This distance check may be premature optimization. If monster fov turns out to not be a bottleneck
     // I can ditch it. But my first ever attempt at a roguelike was in Python in 2002 and you had to be
     // careful about speed...
@@ -966,8 +1279,11 @@ fn can_see_player(state: &GameState, game_obj_db: &mut GameObjectDB, loc: (i32,
         // then they can see the player. Otherwise, not. If they player passes out of the FOV, flip the 
         // recently saw player bit so that they have to make a new perception check if they loose track
         // of player
-        let visible_sqs = fov::calc_fov(state, loc, 12, true);
-        let in_fov = visible_sqs.contains(&player_loc);
+        let in_fov = if let Some(GameObjects::NPC(npc)) = game_obj_db.get_mut(npc_id) {
+            fov::calc_fov_cached(state, &mut npc.fov_cache, loc, 12, true).contains(&player_loc)
+        } else {
+            fov::calc_fov(state, loc, 12, true).contains(&player_loc)
+        };
         if !in_fov {
             if let Some(GameObjects::NPC(npc)) = game_obj_db.get_mut(npc_id) {
                 npc.recently_saw_player = false;
@@ -985,8 +1301,7 @@ fn can_see_player(state: &GameState, game_obj_db: &mut GameObjectDB, loc: (i32,
             return true;
         }
 
-        let mut rng = rand::thread_rng();
-        let percept = rng.gen_range(1, 21) + npc_level;
+        let percept = state.rng.gen_range(1, 21) + npc_level;
         let player_stealth = game_obj_db.player().unwrap().stealth_score;
         if percept >= player_stealth {
             if let Some(GameObjects::NPC(npc)) = game_obj_db.get_mut(npc_id) {
@@ -1016,12 +1331,12 @@ fn in_location(state: &GameState, loc: (i32, i32, i8), sqs: &HashSet<(i32, i32,
     }
 }
 
-pub fn pick_villager_name(used_names: &HashSet<String>) -> String {
+pub fn pick_villager_name(used_names: &HashSet<String>, rng: &mut GameRng) -> String {
     let names: [&str; 12] = ["Galleren", "Jaquette", "Aalis", "Martin", "Brida", "Cecillia",
         "Gotleib", "Ulrich", "Magda", "Sofiya", "Milivoj", "Velimer"];
 
     loop {
-        let n = thread_rng().gen_range(0, names.len());
+        let n = rng.gen_range(0, names.len());
         if !used_names.contains(names[n]) {
             return String::from(names[n]);
         }
@@ -1033,62 +1348,57 @@ pub struct MonsterFactory {
     // active_behaviour, inactive_behaviour, size,
     table: HashMap<String, (u8, u8, char, (u8, u8, u8), NPCPersonality, u8, u8, u8, u8, u8, u128, u32, bool, Behaviour, Behaviour, u8, u8, u128)>,
     index_by_lvl: HashMap<u8, Vec<String>>,
+    pub difficulty: Difficulty,
 }
 
 impl MonsterFactory {
-    fn to_personality(text: &str) -> NPCPersonality {
+    fn to_personality(text: &str) -> Result<NPCPersonality, String> {
         match text {
-            "SimpleMonster" => NPCPersonality::SimpleMonster,
-            "BasicUndead" => NPCPersonality::BasicUndead,
-            "Plant" => NPCPersonality::Plant,
-            _ => {
-                panic!("{}", format!("Unknown personality: {}", text));
-            }
+            "SimpleMonster" => Ok(NPCPersonality::SimpleMonster),
+            "BasicUndead" => Ok(NPCPersonality::BasicUndead),
+            "Plant" => Ok(NPCPersonality::Plant),
+            _ => Err(format!("Unknown personality: {}", text)),
         }
     }
 
-    fn to_colour(text: &str) -> (u8, u8, u8) {
+    fn to_colour(text: &str) -> Result<(u8, u8, u8), String> {
         match text {    
-            "BEIGE" => display::BEIGE,        
-            "BLACK" => display::BLACK,
-            "BLUE" => display::BLUE,
-            "BRIGHT_RED" => display::BRIGHT_RED,
-            "BROWN" => display::BROWN,
-            "DARK_BLUE" => display::DARK_BROWN,
-            "DARK_BROWN" => display::DARK_BROWN,
-            "DARK_GREEN" => display::DARK_GREEN,
-            "DARK_GREY" => display::DARK_GREY,
-            "DULL_RED" => display::DULL_RED,
-            "GOLD" => display::GOLD,
-            "GREEN" => display::GREEN,
-            "GREY" => display::GREY,
-            "LIGHT_BLUE" => display::LIGHT_BLUE,
-            "LIGHT_BROWN" => display::LIGHT_BROWN,
-            "LIGHT_GREY" => display::LIGHT_GREY,
-            "PINK" => display::PINK,
-            "PURPLE" => display::PURPLE,
-            "WHITE" => display::WHITE,
-            "YELLOW" => display::YELLOW,
-            "YELLOW_ORANGE" => display::YELLOW_ORANGE,
-            _ => {
-                panic!("{}", format!("Unknown colour: {}!", text));
-            }
+            "BEIGE" => Ok(display::BEIGE),        
+            "BLACK" => Ok(display::BLACK),
+            "BLUE" => Ok(display::BLUE),
+            "BRIGHT_RED" => Ok(display::BRIGHT_RED),
+            "BROWN" => Ok(display::BROWN),
+            "DARK_BLUE" => Ok(display::DARK_BROWN),
+            "DARK_BROWN" => Ok(display::DARK_BROWN),
+            "DARK_GREEN" => Ok(display::DARK_GREEN),
+            "DARK_GREY" => Ok(display::DARK_GREY),
+            "DULL_RED" => Ok(display::DULL_RED),
+            "GOLD" => Ok(display::GOLD),
+            "GREEN" => Ok(display::GREEN),
+            "GREY" => Ok(display::GREY),
+            "LIGHT_BLUE" => Ok(display::LIGHT_BLUE),
+            "LIGHT_BROWN" => Ok(display::LIGHT_BROWN),
+            "LIGHT_GREY" => Ok(display::LIGHT_GREY),
+            "PINK" => Ok(display::PINK),
+            "PURPLE" => Ok(display::PURPLE),
+            "WHITE" => Ok(display::WHITE),
+            "YELLOW" => Ok(display::YELLOW),
+            "YELLOW_ORANGE" => Ok(display::YELLOW_ORANGE),
+            _ => Err(format!("Unknown colour: {}", text)),
         }
     }
 
-    fn to_behaviour(text: &str) -> Behaviour {
+    fn to_behaviour(text: &str) -> Result<Behaviour, String> {
         match text {
-            "hunt" => Behaviour::Hunt,
-            "idle" => Behaviour::Idle,
-            "wander" => Behaviour::Wander,
-            "plant" => Behaviour::Plant,
-            _ => {
-                panic!("{}", format!("Unknown behaviour: {}!", text));
-            }
+            "hunt" => Ok(Behaviour::Hunt),
+            "idle" => Ok(Behaviour::Idle),
+            "wander" => Ok(Behaviour::Wander),
+            "plant" => Ok(Behaviour::Plant),
+            _ => Err(format!("Unknown behaviour: {}", text)),
         }
     }
 
-    fn parse_loot_field(text: &str) -> u128 {
+    fn parse_loot_field(text: &str) -> Result<u128, String> {
         let mut loot = 0;
 
         let fields = text.split('|').map(|l| l.trim()).collect::<Vec<&str>>();
@@ -1098,15 +1408,13 @@ impl MonsterFactory {
                 "PITTANCE" => LOOT_PITTANCE,
                 "MINOR_GEAR" => LOOT_MINOR_GEAR,
                 "MINOR_ITEM" => LOOT_MINOR_ITEM,
-                _ => {
-                    panic!("{}", format!("Unknown loot type: {}", field));
-                }
+                _ => return Err(format!("Unknown loot type: {}", field)),
             }
         }
-        loot
+        Ok(loot)
     }
 
-    fn parse_attributes(text: &str) -> u128 {
+    fn parse_attributes(text: &str) -> Result<u128, String> {
         let mut attributes = 0;
 
         let attrs = text.split('|').map(|a| a.trim()).collect::<Vec<&str>>();
@@ -1126,57 +1434,78 @@ impl MonsterFactory {
                 "MA_LEAVE_CORPSE" => MA_LEAVE_CORPSE,
                 "MA_PARALYZE" => MA_PARALYZE,
                 "MA_SMASH_DOORS" => MA_SMASH_DOORS,
-                "SPORES" => {
-                    let roll = rand::thread_rng().gen_range(0.0, 1.0);
-                    if roll < 0.4 {
-                        MA_WEAK_VENOMOUS
-                    } else if roll < 0.8 {
-                        MA_CONFUSION
-                    } else {
-                        MA_WEAK_VENOMOUS | MA_CONFUSION
-                    }
-                },
+                "MA_FAST" => MA_FAST,
+                "MA_SLOW" => MA_SLOW,
+                "MA_SKITTISH" => MA_SKITTISH,
+                "MA_CAN_BE_UNIQUE" => MA_CAN_BE_UNIQUE,
+                "MA_TRACKER" => MA_TRACKER,
+                "MA_REND" => MA_REND,
+                "SPORES" => MA_SPORES,
                 "NONE" => 0,
-                _ => {
-                    panic!("{}", format!("Unknown attribute: {}!", a));
-                }
+                _ => return Err(format!("Unknown attribute: {}", a)),
             }
         }
-        attributes
+        Ok(attributes)
+    }
+
+    fn parse_num<T: std::str::FromStr>(field: &str, name: &str) -> Result<T, String> {
+        field.trim().parse::<T>().map_err(|_| format!("Bad value for {}: {}", name, field.trim()))
     }
 
-    fn parse_line(line: &str) -> (String, (u8, u8, char, (u8, u8, u8), NPCPersonality, u8, u8, u8, u8, u8, u128, u32, bool, Behaviour, Behaviour, u8, u8, u128)) {
+    fn parse_line(line: &str) -> Result<(String, (u8, u8, char, (u8, u8, u8), NPCPersonality, u8, u8, u8, u8, u8, u128, u32, bool, Behaviour, Behaviour, u8, u8, u128)), String> {
         let pieces = line.split(',').collect::<Vec<&str>>();
+        if pieces.len() != 18 {
+            return Err(format!("Expected 18 fields but found {}", pieces.len()));
+        }
         let name = pieces[0].trim();
-        let level = pieces[1].trim().parse::<u8>().expect("Incorrectly formatted line in monster file!");
-        let ac = pieces[2].trim().parse::<u8>().expect("Incorrectly formatted line in monster file!");
-        let hp = pieces[3].trim().parse::<u8>().expect("Incorrectly formatted line in monster file!");
-        let ch = pieces[4].trim().chars().next().unwrap();
-        let colour = MonsterFactory::to_colour(pieces[5].trim());
-        let personality = MonsterFactory::to_personality(pieces[6].trim());
-        let attack_mod = pieces[7].trim().parse::<u8>().expect("Incorrectly formatted line in monster file!");
-        let dmg_dice = pieces[8].trim().parse::<u8>().expect("Incorrectly formatted line in monster file!");
-        let dmg_die = pieces[9].trim().parse::<u8>().expect("Incorrectly formatted line in monster file!");
-        let dmg_bonus = pieces[10].trim().parse::<u8>().expect("Incorrectly formatted line in monster file!");
-        let xp_value = pieces[11].trim().parse::<u32>().expect("Incorrectly formatted line in monster file!");
-        let active_behaviour = MonsterFactory::to_behaviour(pieces[12].trim());
-        let inactive_behaviour = MonsterFactory::to_behaviour(pieces[13].trim());
-        let size = pieces[14].trim().parse::<u8>().expect("Incorrectly formatted line in monster file!");
-        let rarity = pieces[15].trim().parse::<u8>().expect("Incorrectly formatted line in monster file!");
-        let loot = MonsterFactory::parse_loot_field(pieces[16]);
-        let attributes = MonsterFactory::parse_attributes(pieces[17]);
-
-        (name.to_string(), (ac, hp, ch, colour, personality, attack_mod, dmg_dice, dmg_die, dmg_bonus, level, attributes, xp_value, false, active_behaviour, inactive_behaviour, size, rarity, loot))
+        let level = MonsterFactory::parse_num::<u8>(pieces[1], "level")?;
+        let ac = MonsterFactory::parse_num::<u8>(pieces[2], "AC")?;
+        let hp = MonsterFactory::parse_num::<u8>(pieces[3], "HP")?;
+        let ch = pieces[4].trim().chars().next().ok_or("Missing ch")?;
+        let colour = MonsterFactory::to_colour(pieces[5].trim())?;
+        let personality = MonsterFactory::to_personality(pieces[6].trim())?;
+        let attack_mod = MonsterFactory::parse_num::<u8>(pieces[7], "attack_mod")?;
+        let dmg_dice = MonsterFactory::parse_num::<u8>(pieces[8], "dmg_dice")?;
+        let dmg_die = MonsterFactory::parse_num::<u8>(pieces[9], "dmg_die")?;
+        let dmg_bonus = MonsterFactory::parse_num::<u8>(pieces[10], "dmg_bonus")?;
+        let xp_value = MonsterFactory::parse_num::<u32>(pieces[11], "xp_value")?;
+        let active_behaviour = MonsterFactory::to_behaviour(pieces[12].trim())?;
+        let inactive_behaviour = MonsterFactory::to_behaviour(pieces[13].trim())?;
+        let size = MonsterFactory::parse_num::<u8>(pieces[14], "size")?;
+        let rarity = MonsterFactory::parse_num::<u8>(pieces[15], "rarity")?;
+        let loot = MonsterFactory::parse_loot_field(pieces[16])?;
+        let attributes = MonsterFactory::parse_attributes(pieces[17])?;
+
+        Ok((name.to_string(), (ac, hp, ch, colour, personality, attack_mod, dmg_dice, dmg_die, dmg_bonus, level, attributes, xp_value, false, active_behaviour, inactive_behaviour, size, rarity, loot)))
+    }
+
+    // Check every line in the monster file up front so that a typo is reported (along with any others)
+    // when the game starts instead of blowing up whenever that monster happens to be generated.
+    pub fn validate_monster_file() -> Vec<String> {
+        let contents = match fs::read_to_string("monsters.txt") {
+            Ok(contents) => contents,
+            Err(_) => return vec![String::from("monsters.txt: unable to read file")],
+        };
+
+        contents.lines()
+                .enumerate()
+                .skip(1)
+                .filter(|(_, line)| !line.trim().is_empty())
+                .filter_map(|(j, line)| MonsterFactory::parse_line(line).err().map(|e| format!("monsters.txt line {}: {}", j + 1, e)))
+                .collect()
     }
 
     pub fn init() -> MonsterFactory {
-        let mut mf = MonsterFactory { table: HashMap::new(), index_by_lvl: HashMap::new(), };
+        let mut mf = MonsterFactory { table: HashMap::new(), index_by_lvl: HashMap::new(), difficulty: Difficulty::Normal, };
 
         let contents = fs::read_to_string("monsters.txt")
             .expect("Unable to find building templates file!");
         let lines = contents.split('\n').collect::<Vec<&str>>();
         for line in lines.iter().skip(1) {
-            let entry = MonsterFactory::parse_line(line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = MonsterFactory::parse_line(line).expect("Incorrectly formatted line in monster file!");
 
             let name = entry.0;
             let level = entry.1.9;
@@ -1209,40 +1538,40 @@ impl MonsterFactory {
         }
     }
 
-    fn set_loot(&self, loot_fields: u128, game_obj_db: &mut GameObjectDB) -> Vec<GameObjects> {
-        let mut rng = rand::thread_rng();
+    fn set_loot(&self, loot_fields: u128, game_obj_db: &mut GameObjectDB, rng: &mut GameRng) -> Vec<GameObjects> {
         let mut items = Vec::new();
+        let lf = self.difficulty.loot_factor();
 
-        if loot_fields & LOOT_PITTANCE > 0 && rng.gen_range(0.0, 1.0) < 0.33 {   
+        if loot_fields & LOOT_PITTANCE > 0 && rng.gen_range(0.0, 1.0) < 0.33 * lf {   
             let amt = rng.gen_range(3, 6);
             let gold = GoldPile::make(game_obj_db, amt, (-1, -1, -1));
             items.push(gold);            
         }
 
         if loot_fields & LOOT_MINOR_GEAR > 0 {
-            if rng.gen_range(0.0, 1.0) < 0.1 {
+            if rng.gen_range(0.0, 1.0) < 0.1 * lf {
                 for _ in 3..6 {
-                    items.push(Item::get_item(game_obj_db, "arrow").unwrap());
+                    items.push(Item::get_item(game_obj_db, "arrow", rng).unwrap());
                 }
             }
-            if rng.gen_range(0.0, 1.0) < 0.1 {
-                items.push(Item::get_item(game_obj_db, "shortsword").unwrap());
+            if rng.gen_range(0.0, 1.0) < 0.1 * lf {
+                items.push(Item::get_item(game_obj_db, "shortsword", rng).unwrap());
             }
 
         }
 
-        if loot_fields & LOOT_MINOR_ITEM > 0 && rng.gen_range(0.0, 1.0) < 0.5 {
+        if loot_fields & LOOT_MINOR_ITEM > 0 && rng.gen_range(0.0, 1.0) < 0.5 * lf {
             if rng.gen_range(0.0, 1.0) < 0.5 {
-                items.push(Item::get_item(game_obj_db, "potion of healing").unwrap());
+                items.push(Item::get_item(game_obj_db, "potion of healing", rng).unwrap());
             } else {
-                items.push(Item::get_item(game_obj_db, "scroll of blink").unwrap());
+                items.push(Item::get_item(game_obj_db, "scroll of blink", rng).unwrap());
             }
         }
 
         items
     }
 
-    pub fn monster(&self, name: &str, loc: (i32, i32, i8), game_obj_db: &mut GameObjectDB) {
+    pub fn monster(&self, name: &str, loc: (i32, i32, i8), game_obj_db: &mut GameObjectDB, rng: &mut GameRng) {
         if !self.table.contains_key(name) {
             panic!("{}", format!("Unknown monster: {}!!", name));
         }
@@ -1250,37 +1579,88 @@ impl MonsterFactory {
         let stats = self.table.get(name).unwrap();
 
         let sym = stats.2;
+        let hp = self.difficulty.monster_hp(stats.1);
         let mut npc = NPC { base_info: GameObjectBase::new(game_obj_db.next_id(), loc, false, sym, stats.3,  stats.3, true, name),
-            ac: stats.0, curr_hp: stats.1, max_hp: stats.1, attitude: Attitude::Indifferent, facts_known: Vec::new(), home: None, plan: VecDeque::new(), voice: String::from("monster"), 
-            schedule: Vec::new(), mode: stats.4, attack_mod: stats.5, dmg_dice: stats.6, dmg_die: stats.7, dmg_bonus: stats.8, edc: self.calc_dc(stats.9), attributes: stats.10, 
+            ac: stats.0, curr_hp: hp, max_hp: hp, attitude: Attitude::Indifferent, facts_known: Vec::new(), home: None, plan: VecDeque::new(), voice: String::from("monster"), 
+            is_monster: true, unique_name: None, pack: None, schedule: Vec::new(), mode: stats.4, attack_mod: stats.5, dmg_dice: stats.6, dmg_die: stats.7, dmg_bonus: stats.8, edc: self.calc_dc(stats.9), attributes: stats.10, 
             alive: true, xp_value: stats.11, inventory: Vec::new(), active: stats.12, active_behaviour: stats.13, inactive_behaviour: stats.14, level: stats.9, last_inventory: 0,
-            recently_saw_player: false, size: stats.15, pronouns: pick_pronouns(), rarity: stats.16, statuses: Vec::new(),
+            recently_saw_player: false, fov_cache: fov::FovCache::default(), size: stats.15, pronouns: pick_pronouns(rng), rarity: stats.16, statuses: Vec::new(),
+            energy: 0.0, energy_restore: 1.0,
         };
+        if npc.attributes & MA_CAN_BE_UNIQUE > 0 && rng.gen_range(0.0, 1.0) < 0.04 {
+            make_unique(&mut npc, rng);
+        }
+        if npc.attributes & MA_SPORES > 0 {
+            let roll = rng.gen_range(0.0, 1.0);
+            npc.attributes |= if roll < 0.4 {
+                MA_WEAK_VENOMOUS
+            } else if roll < 0.8 {
+                MA_CONFUSION
+            } else {
+                MA_WEAK_VENOMOUS | MA_CONFUSION
+            };
+        }
+        if npc.attributes & MA_FAST > 0 {
+            npc.energy_restore = 2.0;
+        } else if npc.attributes & MA_SLOW > 0 {
+            npc.energy_restore = 0.5;
+        }
 
-        let items = self.set_loot(stats.17, game_obj_db);
+        let items = self.set_loot(stats.17, game_obj_db, rng);
         for item in items {
             npc.inventory.push(item);
         }
 
+        // Some of the monsters who carry gear will be lighting their way with a torch, which
+        // makes them easier to spot coming down a dark corridor
+        if stats.17 & LOOT_MINOR_GEAR > 0 && loc.2 > 0 && rng.gen_range(0.0, 1.0) < 0.2 {
+            let mut torch = Item::get_item(game_obj_db, "torch", rng).unwrap();
+            let torch_id = torch.obj_id();
+            if let GameObjects::Item(t) = &mut torch {
+                t.active = true;
+                t.stackable = false;
+                t.charges = rng.gen_range(250, 1000);
+            }
+            torch.set_loc(loc);
+            npc.inventory.push(torch);
+            game_obj_db.listeners.insert((torch_id, EventType::Update));
+            game_obj_db.listeners.insert((torch_id, EventType::EndOfTurn));
+        }
+
         let obj_id = GameObject::obj_id(&npc);
         game_obj_db.add(GameObjects::NPC(npc));
         game_obj_db.listeners.insert((obj_id, EventType::TakeTurn));
     }
 
-    pub fn monster_for_dungeon(&self, loc: (i32, i32, i8), game_obj_db: &mut GameObjectDB) {
-        let monster_level = MonsterFactory::rnd_monster_level(loc.2 as u8);
+    // Monster levels are balanced around a dungeon of the default depth, so in a deeper (or shallower)
+    // dungeon the player's depth is stretched (or squashed) onto that scale
+    pub fn monster_for_dungeon(&self, loc: (i32, i32, i8), dungeon_depth: u8, game_obj_db: &mut GameObjectDB, rng: &mut GameRng) -> String {
+        let scaled = (loc.2 as f32 * world::DEFAULT_DUNGEON_DEPTH as f32 / dungeon_depth.max(1) as f32).ceil().max(1.0);
+        let monster_level = self.rnd_monster_level(scaled as u8, rng);
         let options = self.index_by_lvl[&monster_level].len();
-        let choice = rand::thread_rng().gen_range(0, options);
+        let choice = rng.gen_range(0, options);
         let name = &self.index_by_lvl[&monster_level][choice];
-        self.monster(name, loc, game_obj_db);
+        self.monster(name, loc, game_obj_db, rng);
+
+        name.clone()
     }
 
-    fn rnd_monster_level(dungeon_level: u8) -> u8 {
+    // Monsters with pack tactics don't wander the dungeon on their own
+    pub fn travels_in_pack(&self, name: &str) -> bool {
+        match self.table.get(name) {
+            Some(stats) => stats.10 & MA_PACK_TACTICS > 0,
+            None => false,
+        }
+    }
+
+    fn rnd_monster_level(&self, dungeon_level: u8, rng: &mut GameRng) -> u8 {
         if dungeon_level == 1 {
             return 1;
         }
 
-        let mut guass = util::general_guassian(dungeon_level as f32, 1.15).round();
+        let max_level = self.index_by_lvl.keys().max().copied().unwrap_or(1);
+
+        let mut guass = util::general_guassian(dungeon_level as f32, 1.15, rng).round();
         if guass < 1.0 {
             guass = 1.0;
         }
@@ -1289,10 +1669,273 @@ impl MonsterFactory {
             guass = dungeon_level as f32 - 3.0;
         }
 
-        if guass > 4.0 {
-            guass = 4.0;
+        if guass > max_level as f32 {
+            guass = max_level as f32;
+        }
+
+        // There may be gaps in the monster table so drop down to the nearest level that has something
+        let mut lvl = guass as u8;
+        while lvl > 1 && !self.index_by_lvl.contains_key(&lvl) {
+            lvl -= 1;
+        }
+
+        lvl
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::UserInterface;
+    use crate::headless::HeadlessUI;
+
+    #[test]
+    fn villager_opening_a_door_is_reported_without_a_window() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = crate::tests::test_level(&mut game_obj_db);
+        // The level's unlit, so the villager stands right next to the player
+        state.map.insert((5, 6, 1), Tile::Door(DoorState::Closed));
+        let villager = NPC::villager("Alice".to_string(), (6, 6, 1), None, "villager1", &mut game_obj_db, &mut state.rng);
+        let villager_id = villager.obj_id();
+        game_obj_db.add(villager);
+        game_obj_db.npc(villager_id).unwrap().plan.push_back(Action::Move((5, 6, 1)));
+
+        follow_plan(villager_id, &mut state, &mut game_obj_db);
+        assert_eq!(state.map[&(5, 6, 1)], Tile::Door(DoorState::Open));
+
+        let mut gui = HeadlessUI::new();
+        crate::update_view(&mut state, &mut game_obj_db, &mut gui);
+        assert_eq!(gui.recent_messages(1), vec!["Alice opens the door.".to_string()]);
+    }
+
+    #[test]
+    fn dozy_wanderers_come_to_investigate_noises() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = crate::tests::test_level(&mut game_obj_db);
+        // A wall between the player and the monster with a gap at the bottom
+        for r in 1..9 {
+            state.map.insert((r, 7, 1), Tile::Wall);
+        }
+        let mf = MonsterFactory::init();
+        mf.monster("skeleton", (2, 9, 1), &mut game_obj_db, &mut state.rng);
+        let npc_id = game_obj_db.npc_at(&(2, 9, 1)).unwrap();
+        assert!(!game_obj_db.npc(npc_id).unwrap().active);
+
+        heard_noise(npc_id, (5, 5, 1), 5, &mut state, &mut game_obj_db);
+        assert!(!game_obj_db.npc(npc_id).unwrap().plan.is_empty());
+
+        for _ in 0..4 {
+            take_turn(npc_id, &mut state, &mut game_obj_db);
+        }
+        let loc = game_obj_db.npc(npc_id).unwrap().get_loc();
+        assert!(util::distance(loc.0, loc.1, 5, 5) < util::distance(2, 9, 5, 5));
+    }
+
+    #[test]
+    fn villagers_in_bed_have_locked_up() {
+        let mut state = crate::tests::test_state();
+        let mut home = HashSet::new();
+        for r in 1..4 {
+            for c in 1..4 {
+                state.map.insert((r, c, 0), Tile::Floor);
+                home.insert((r, c, 0));
+            }
+        }
+        state.map.insert((2, 4, 0), Tile::Door(DoorState::Closed));
+        home.insert((2, 4, 0));
+        let mut tb = crate::town::TownBuildings::new();
+        tb.homes.push(home);
+        state.world_info.town_buildings = Some(tb);
+
+        let mut game_obj_db = GameObjectDB::new();
+        let bed = (2, 2, 0);
+        let mut villager = NPC::villager("Alice".to_string(), bed, Some(Venue::Home(0)), "villager1", &mut game_obj_db, &mut state.rng);
+        if let GameObjects::NPC(npc) = &mut villager {
+            npc.schedule.push(AgendaItem::new((22, 0), (23, 59), 5, Venue::Favourite(bed), "sleeping".to_string()));
+        }
+        let villager_id = villager.obj_id();
+        game_obj_db.add(villager);
+
+        // 9pm, so not yet bed time
+        state.turn = 13 * crate::TURNS_PER_HOUR;
+        villager_schedule(villager_id, &mut state, &mut game_obj_db, bed);
+        assert_eq!(state.map[&(2, 4, 0)], Tile::Door(DoorState::Closed));
+
+        state.turn = 15 * crate::TURNS_PER_HOUR;
+        villager_schedule(villager_id, &mut state, &mut game_obj_db, bed);
+        assert_eq!(state.map[&(2, 4, 0)], Tile::Door(DoorState::Locked));
+    }
+
+    #[test]
+    fn visiting_villagers_head_for_their_neighbours_home() {
+        let mut state = crate::tests::test_state();
+        let mut tb = crate::town::TownBuildings::new();
+        for c0 in &[1, 11] {
+            let mut home = HashSet::new();
+            for r in 1..4 {
+                for c in *c0..*c0 + 3 {
+                    home.insert((r, c, 0));
+                }
+            }
+            tb.homes.push(home);
+        }
+        for r in 0..6 {
+            for c in 0..16 {
+                state.map.insert((r, c, 0), Tile::Floor);
+            }
+        }
+        state.world_info.town_buildings = Some(tb);
+
+        let mut game_obj_db = GameObjectDB::new();
+        let start = (2, 2, 0);
+        let mut villager = NPC::villager("Alice".to_string(), start, Some(Venue::Home(0)), "villager1", &mut game_obj_db, &mut state.rng);
+        if let GameObjects::NPC(npc) = &mut villager {
+            npc.schedule.push(AgendaItem::new((15, 0), (16, 30), 5, Venue::Visit(1), "visiting".to_string()));
+        }
+        let villager_id = villager.obj_id();
+        game_obj_db.add(villager);
+
+        // 3:30pm
+        state.turn = 7 * crate::TURNS_PER_HOUR + 30 * crate::TURNS_PER_MINUTE;
+        villager_schedule(villager_id, &mut state, &mut game_obj_db, start);
+        let plan = &game_obj_db.npc(villager_id).unwrap().plan;
+        let dest = match plan.back() {
+            Some(Action::Move(loc)) => *loc,
+            _ => panic!("Alice should be heading somewhere"),
+        };
+        assert!(state.world_info.town_buildings.as_ref().unwrap().homes[1].contains(&dest));
+    }
+
+    fn spawn_with_seed(seed: u64) -> Vec<(u128, Option<String>, Vec<String>)> {
+        let mf = MonsterFactory::init();
+        let mut game_obj_db = GameObjectDB::new();
+        let mut rng = GameRng::seeded(seed);
+        let mut spawned = Vec::new();
+        for j in 0..20 {
+            let name = if j % 2 == 0 { "fungal growth" } else { "goblin" };
+            let loc = (1, j, 1);
+            mf.monster(name, loc, &mut game_obj_db, &mut rng);
+            let obj_id = game_obj_db.obj_locs[&loc][0];
+            let npc = game_obj_db.npc(obj_id).unwrap();
+            let carrying = npc.inventory.iter().map(|i| i.get_fullname()).collect();
+            spawned.push((npc.attributes, npc.unique_name.clone(), carrying));
+        }
+
+        spawned
+    }
+
+    #[test]
+    fn same_seed_spawns_the_same_monsters() {
+        let spawned = spawn_with_seed(2181);
+        assert_eq!(spawned, spawn_with_seed(2181));
+
+        // Fungal growths roll their spores when they're spawned rather than when monsters.txt is read
+        assert!(spawned.iter().step_by(2).all(|m| m.0 & MA_SPORES > 0 && m.0 & (MA_WEAK_VENOMOUS | MA_CONFUSION) > 0));
+    }
+
+    fn spawn_pack(name: &str, state: &mut GameState, game_obj_db: &mut GameObjectDB) -> Vec<usize> {
+        let mf = MonsterFactory::init();
+        let mut ids = Vec::new();
+        for c in 2..6 {
+            mf.monster(name, (2, c, 1), game_obj_db, &mut state.rng);
+            ids.push(game_obj_db.npc_at(&(2, c, 1)).unwrap());
+        }
+        for id in ids.iter() {
+            game_obj_db.npc(*id).unwrap().pack = Some((ids[0], 4));
+        }
+
+        ids
+    }
+
+    #[test]
+    fn packs_break_when_they_lose_their_leader() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = crate::tests::test_level(&mut game_obj_db);
+        let pack = spawn_pack("kobold", &mut state, &mut game_obj_db);
+
+        // Losing one of the rank and file isn't enough to shake them
+        game_obj_db.npc(pack[3]).unwrap().alive = false;
+        check_pack_morale(&mut state, &mut game_obj_db, pack[3]);
+        assert!(!game_obj_db.npc(pack[1]).unwrap().has_status(Status::Routed));
+
+        game_obj_db.npc(pack[0]).unwrap().alive = false;
+        check_pack_morale(&mut state, &mut game_obj_db, pack[0]);
+        for id in pack[1..3].iter() {
+            let npc = game_obj_db.npc(*id).unwrap();
+            assert!(npc.has_status(Status::Routed));
+            assert!(npc.pack.is_none());
+        }
+        assert!(state.msg_queue.iter().any(|m| m.text.ends_with("flees in panic!")));
+    }
+
+    #[test]
+    fn fearless_packs_hold_their_ground() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = crate::tests::test_level(&mut game_obj_db);
+        let pack = spawn_pack("skeleton", &mut state, &mut game_obj_db);
+
+        game_obj_db.npc(pack[0]).unwrap().alive = false;
+        check_pack_morale(&mut state, &mut game_obj_db, pack[0]);
+        for id in pack[1..].iter() {
+            let npc = game_obj_db.npc(*id).unwrap();
+            assert!(!npc.has_status(Status::Routed));
+            assert!(npc.pack.is_none());
+        }
+    }
+
+    #[test]
+    fn monsters_go_by_their_kind_and_villagers_by_name() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = crate::tests::test_level(&mut game_obj_db);
+        let mf = MonsterFactory::init();
+        mf.monster("kobold", (3, 3, 1), &mut game_obj_db, &mut state.rng);
+        let kobold_id = game_obj_db.npc_at(&(3, 3, 1)).unwrap();
+        let kobold = game_obj_db.npc(kobold_id).unwrap();
+        kobold.unique_name = None;
+        assert!(!kobold.is_named());
+        assert_eq!(kobold.npc_name(true), "a kobold");
+        assert_eq!(kobold.npc_name(false), "the kobold");
+
+        let villager = NPC::villager("Alice".to_string(), (6, 6, 1), None, "villager1", &mut game_obj_db, &mut state.rng);
+        if let GameObjects::NPC(npc) = villager {
+            assert!(npc.is_named());
+            assert_eq!(npc.npc_name(true), "Alice");
+        }
+    }
+
+    #[test]
+    fn uniques_have_a_title_and_are_tougher() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = crate::tests::test_level(&mut game_obj_db);
+        let mf = MonsterFactory::init();
+        mf.monster("kobold", (3, 3, 1), &mut game_obj_db, &mut state.rng);
+        let kobold_id = game_obj_db.npc_at(&(3, 3, 1)).unwrap();
+        let kobold = game_obj_db.npc(kobold_id).unwrap();
+        kobold.unique_name = None;
+        kobold.max_hp = 10;
+        let (attack_mod, xp) = (kobold.attack_mod, kobold.xp_value);
+
+        make_unique(kobold, &mut GameRng::seeded(2213));
+        let name = kobold.unique_name.clone().unwrap();
+        assert!(name.contains(" the Kobold "));
+        assert!(kobold.is_named());
+        assert_eq!(kobold.npc_name(true), name);
+        assert_eq!((kobold.max_hp, kobold.curr_hp), (15, 15));
+        assert_eq!(kobold.attack_mod, attack_mod + 2);
+        assert_eq!(kobold.xp_value, xp * 2);
+    }
+
+    #[test]
+    fn trackers_pick_up_the_freshest_scent_nearby() {
+        let mut state = crate::tests::test_state();
+        for c in 1..6 {
+            state.scent_trail.push_front((2, c, 1));
         }
 
-        guass as u8
+        // The trail runs from (2, 5) (freshest) back to (2, 1)
+        assert_eq!(freshest_scent(&state, (3, 1, 1)), Some((2, 2, 1)));
+        assert_eq!(freshest_scent(&state, (3, 5, 1)), Some((2, 5, 1)));
+        assert_eq!(freshest_scent(&state, (2, 5, 1)), None);
+        assert_eq!(freshest_scent(&state, (7, 7, 1)), None);
     }
 }
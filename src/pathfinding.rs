@@ -21,8 +21,103 @@ use std::cmp::Ordering;
 use super::Map;
 use crate::game_obj::GameObjectDB;
 use crate::map;
+use crate::map::{DoorState, Tile};
+use crate::npc::{MA_OPEN_DOORS, MA_SMASH_DOORS, MA_UNLOCK_DOORS};
 use crate::util;
 
+const DIAGONAL_COST: f64 = 1.41;
+// Extra cost for squares with an obstacle (rubble, webs) on them. They can be crossed but
+// it's slow going so it's usually worth walking around them.
+const OBSTACLE_COST: f64 = 4.0;
+
+// The sets of tiles (and what they cost to step on) for the different things that use find_path().
+// Keeping them all here so they don't drift apart.
+
+// For the player's fast travel on the surface
+pub fn overworld_passable() -> HashMap<Tile, f64> {
+	let mut passable = HashMap::new();
+	passable.insert(Tile::Grass, 1.0);
+	passable.insert(Tile::Dirt, 1.0);
+	passable.insert(Tile::Tree, 1.0);
+	passable.insert(Tile::Bridge, 1.0);
+	passable.insert(Tile::Sand, 1.0);
+	passable.insert(Tile::StoneFloor, 1.0);
+	passable.insert(Tile::Water, 2.0);
+	passable.insert(Tile::Portal, 1.0);
+	passable.insert(Tile::Door(DoorState::Open), 1.0);
+	passable.insert(Tile::Door(DoorState::Broken), 1.0);
+
+	passable
+}
+
+// Where any NPC can walk, in town or in the dungeon
+pub fn villager_passable() -> HashMap<Tile, f64> {
+	let mut passable = HashMap::new();
+	passable.insert(Tile::Grass, 1.0);
+	passable.insert(Tile::Dirt, 1.0);
+	passable.insert(Tile::Tree, 1.0);
+	passable.insert(Tile::Bridge, 1.0);
+	passable.insert(Tile::Sand, 1.0);
+	passable.insert(Tile::Water, 2.0);
+	passable.insert(Tile::Door(DoorState::Open), 1.0);
+	passable.insert(Tile::Door(DoorState::Broken), 1.0);
+	passable.insert(Tile::Gate(DoorState::Open), 1.0);
+	passable.insert(Tile::Gate(DoorState::Broken), 1.0);
+	passable.insert(Tile::StoneFloor, 1.0);
+	passable.insert(Tile::Floor, 1.0);
+	// Folks will clamber over the furniture if they have to but would rather walk around it
+	passable.insert(Tile::Bed, 3.0);
+	passable.insert(Tile::Table, 3.0);
+	passable.insert(Tile::Counter, 3.0);
+	passable.insert(Tile::Trigger, 1.0);
+	passable.insert(Tile::Fog, 1.0);
+
+	passable
+}
+
+// The base NPC set plus doors, depending on whether the creature can deal with them
+pub fn monster_passable(attributes: u128) -> HashMap<Tile, f64> {
+	let mut passable = villager_passable();
+	if attributes & MA_OPEN_DOORS > 0 {
+		passable.insert(Tile::Door(DoorState::Closed), 2.0);
+	}
+	if attributes & (MA_UNLOCK_DOORS | MA_SMASH_DOORS) > 0 {
+		passable.insert(Tile::Door(DoorState::Locked), 2.5);
+	}
+
+	passable
+}
+
+// For laying out the paths between buildings in town. Paths prefer to go around trees and water
+// but will cut through (and bridges get built) if need be. Building a new bridge is expensive
+// enough that paths will detour a fair ways to use one that's already there.
+pub fn road_builder_passable() -> HashMap<Tile, f64> {
+	let mut passable = HashMap::new();
+	passable.insert(Tile::Grass, 1.0);
+	passable.insert(Tile::Dirt, 1.0);
+	passable.insert(Tile::Bridge, 1.0);
+	passable.insert(Tile::Sand, 1.0);
+	passable.insert(Tile::Tree, 2.0);
+	passable.insert(Tile::Water, 3.0);
+	passable.insert(Tile::DeepWater, 6.0);
+
+	passable
+}
+
+// The old road leading away from the dungeon entrance
+pub fn old_road_passable() -> HashMap<Tile, f64> {
+	let mut passable = HashMap::new();
+	passable.insert(Tile::Grass, 1.0);
+	passable.insert(Tile::Dirt, 1.0);
+	passable.insert(Tile::Tree, 1.0);
+	passable.insert(Tile::StoneFloor, 1.0);
+	passable.insert(Tile::Sand, 1.0);
+	passable.insert(Tile::Water, 1.0);
+	passable.insert(Tile::DeepWater, 1.0);
+
+	passable
+}
+
 #[derive(Debug)]
 struct ASQueueItem {
 	loc: (i32, i32),
@@ -128,24 +223,34 @@ fn backtrace_path(goal_r: i32, goal_c: i32, parents: &HashMap<(i32, i32), (i32,
 // }
 
 // This is based straight-up on the algorithm description on Wikipedia.
-// For now, I'm limiting pathfinding to being on the same level
+// For now, I'm limiting pathfinding to being on the same level. The weights in passable_tiles are
+// the cost of stepping onto that kind of tile, so paths will detour around expensive terrain if
+// the detour is cheaper overall.
 fn astar(
 		map: &Map, game_obj_db: Option<&GameObjectDB>, stop_before: bool, start_r: i32, start_c: i32, 
 		level: i8, end_r: i32, end_c: i32, max_distance: i32,
 		passable_tiles: &HashMap<map::Tile, f64>) -> Vec<(i32, i32)> {
 	let mut queue = BinaryHeap::new();
-	let mut in_queue = HashSet::new();
+	let mut done = HashSet::new();
 	let mut parents = HashMap::new();
-	let mut g_scores = HashMap::new();
-	g_scores.insert((start_r, start_c), 0);
+	let mut g_scores: HashMap<(i32, i32), f64> = HashMap::new();
+	g_scores.insert((start_r, start_c), 0.0);
 	let goal = (end_r, end_c);
 
+	// The heuristic needs to never overestimate the cost or we'll miss cheaper paths,
+	// so it assumes every step costs as little as the cheapest tile
+	let min_cost = passable_tiles.values().copied().fold(f64::MAX, f64::min).min(1.0);
+
 	queue.push(ASQueueItem::new((start_r, start_c), 0.0)); 
-	in_queue.insert((start_r, start_c));
 
 	while !queue.is_empty() {
 		let node = queue.pop().unwrap();
 		let curr = node.loc;
+		if !done.insert(curr) {
+			// we already found a cheaper way to this square
+			continue;
+		}
+
 		if stop_before && util::distance(curr.0, curr.1, end_r, end_c) < 1.5 {
 			return backtrace_path(curr.0, curr.1, &parents);
 		} else if curr == goal {
@@ -166,40 +271,46 @@ fn astar(
 			}
 
 			let n_loc = (nr, nc);
+			if done.contains(&n_loc) { continue; }
 			let tile = map[&(n_loc.0, n_loc.1, level)];
 			if !passable_by_me(&tile, passable_tiles) { continue; }
+			if map::cuts_corner(map, (curr.0, curr.1, level), (nr, nc, level)) { continue; }
 			
-			let tentative_score = *g_scores.get(&curr).unwrap() + 1;
-			let mut g = std::u32::MAX;
-			if g_scores.contains_key(&n_loc) {
-				g = *g_scores.get(&n_loc).unwrap();
+			let step = if adj.0 != 0 && adj.1 != 0 { DIAGONAL_COST } else { 1.0 };
+			let mut cost = passable_tiles[&tile] * step;
+			if let Some(gdb) = game_obj_db {
+				if gdb.obstacle_at(&(nr, nc, level)) {
+					cost += OBSTACLE_COST;
+				}
 			}
-
-			if tentative_score < g {
-				g_scores.entry(n_loc)
-						.and_modify(|v| { *v = tentative_score } )
-						.or_insert(tentative_score);
-
-				let mut d_to_goal = util::distance(nr, nc, end_r, end_c);
+			let tentative_score = g_scores[&curr] + cost;
+			if tentative_score < *g_scores.get(&n_loc).unwrap_or(&f64::MAX) {
+				let d_to_goal = util::distance(nr, nc, end_r, end_c);
 				if d_to_goal as i32 > max_distance {
 					continue;
 				}
 
-				let tile_cost = passable_tiles[&tile];
-				d_to_goal += tentative_score as f64 + tile_cost;
-
-				if !in_queue.contains(&n_loc) {
-					let p = parents.entry(n_loc).or_insert(curr);
-					*p = curr;
-					queue.push(ASQueueItem::new(n_loc, -d_to_goal)); 
-					in_queue.insert(n_loc);
-				}				
+				g_scores.insert(n_loc, tentative_score);
+				parents.insert(n_loc, curr);
+				let f = tentative_score + octile(n_loc, goal) * min_cost;
+				queue.push(ASQueueItem::new(n_loc, -f)); 
 			}
 		}
 	}
 	
 	Vec::new()
 }
+
+// Cheapest possible cost of travelling between two squares with 8-way movement when
+// diagonal steps cost DIAGONAL_COST
+fn octile(a: (i32, i32), b: (i32, i32)) -> f64 {
+	let dr = (a.0 - b.0).abs();
+	let dc = (a.1 - b.1).abs();
+	let diagonals = i32::min(dr, dc) as f64;
+	let straights = (i32::max(dr, dc) - i32::min(dr, dc)) as f64;
+
+	straights + diagonals * DIAGONAL_COST
+}
 	
 pub fn passable_by_me(tile: &map::Tile, valid: &HashMap<map::Tile, f64>) -> bool {
 	valid.contains_key(&tile)
@@ -235,3 +346,75 @@ pub fn find_path(
 
 	astar(map, game_obj_db, stop_before, start_r, start_c, level, goal_r, goal_c, max_distance, passable_tiles)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// '.' is grass, '~' water, '#' wall and '+' a closed door
+	fn grid_map(rows: &[&str]) -> Map {
+		let mut map = Map::new();
+		for (r, row) in rows.iter().enumerate() {
+			for (c, ch) in row.chars().enumerate() {
+				let tile = match ch {
+					'~' => Tile::Water,
+					'#' => Tile::Wall,
+					'+' => Tile::Door(DoorState::Closed),
+					_ => Tile::Grass,
+				};
+				map.insert((r as i32, c as i32, 0), tile);
+			}
+		}
+
+		map
+	}
+
+	#[test]
+	fn only_door_openers_path_through_closed_doors() {
+		let map = grid_map(&["#####",
+		                     "#.+.#",
+		                     "#####"]);
+
+		let path = find_path(&map, None, false, 1, 1, 0, 1, 3, 50, &monster_passable(MA_OPEN_DOORS));
+		assert_eq!(path, vec![(1, 3), (1, 2), (1, 1)]);
+
+		let path = find_path(&map, None, false, 1, 1, 0, 1, 3, 50, &monster_passable(0));
+		assert!(path.is_empty());
+	}
+
+	#[test]
+	fn paths_detour_around_costly_water() {
+		let map = grid_map(&[".......",
+		                     "...~...",
+		                     "...~...",
+		                     "...~..."]);
+		let wet = |path: &Vec<(i32, i32)>| path.iter().any(|sq| map[&(sq.0, sq.1, 0)] == Tile::Water);
+
+		// Wading is three times the cost of walking for road builders, so they go around
+		let path = find_path(&map, None, false, 2, 0, 0, 2, 6, 50, &road_builder_passable());
+		assert_eq!(path.first(), Some(&(2, 6)));
+		assert!(!wet(&path));
+
+		// The old road doesn't mind getting its feet wet, so it goes straight across
+		let path = find_path(&map, None, false, 2, 0, 0, 2, 6, 50, &old_road_passable());
+		assert_eq!(path.first(), Some(&(2, 6)));
+		assert!(wet(&path));
+	}
+
+	#[test]
+	fn diagonals_cost_more_and_corners_cant_be_cut() {
+		let map = grid_map(&[".....",
+		                     ".....",
+		                     "....."]);
+		// Zig-zagging through the middle row would be just as short if diagonals were free
+		let path = find_path(&map, None, false, 0, 0, 0, 0, 4, 50, &villager_passable());
+		assert_eq!(path, vec![(0, 4), (0, 3), (0, 2), (0, 1), (0, 0)]);
+		assert!(octile((0, 0), (1, 1)) > octile((0, 0), (0, 1)));
+
+		let map = grid_map(&[".#.",
+		                     "#..",
+		                     "..."]);
+		let path = find_path(&map, None, false, 0, 0, 0, 1, 1, 50, &villager_passable());
+		assert!(path.is_empty());
+	}
+}
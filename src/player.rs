@@ -19,15 +19,18 @@ use std::collections::{HashMap, HashSet};
 use rand::Rng;
 use serde::{Serialize, Deserialize};
 
-use super::{EventResponse, EventType, GameState, Message, Status};
+use super::{EventResponse, EventType, GameEvent, GameState, Message, Status, PLAYER_INV};
 use crate::battle::DamageType;
 use crate::display;
 use crate::effects::HasStatuses;
 use crate::items;
 use crate::game_obj::{Ability, GameObject, GameObjectDB, GameObjectBase, GameObjects, Person};
 use crate::items::{Item, ItemType};
+use crate::magic::Spell;
 use crate::map::Tile;
-use crate::util::StringUtils;
+use crate::util::{GameRng, StringUtils};
+
+pub const SEARCH_DC: u8 = 15;
 
 const XP_CHART: [u32; 19] = [20, 40, 80, 160, 320, 640, 1280, 2560, 5210, 10_000, 15_000, 21_000, 28_000, 36_000, 44_000, 52_000, 60_000, 68_000, 76_000];
 
@@ -35,17 +38,61 @@ const XP_CHART: [u32; 19] = [20, 40, 80, 160, 320, 640, 1280, 2560, 5210, 10_000
 pub enum Role {
     Warrior,
     Rogue,
+    Mage,
 }
 
 impl Role {
     pub fn desc(&self) -> &str {
         match self {
-            Role::Warrior => "human warrior",
-            Role::Rogue => "human rogue",
+            Role::Warrior => "warrior",
+            Role::Rogue => "rogue",
+            Role::Mage => "mage",
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Race {
+    Human,
+    Dwarf,
+    Elf,
+    Halfling,
+}
+
+impl Race {
+    pub fn desc(&self) -> &str {
+        match self {
+            Race::Human => "human",
+            Race::Dwarf => "dwarf",
+            Race::Elf => "elf",
+            Race::Halfling => "halfling",
+        }
+    }
+
+    // (str, dex, con, chr, apt)
+    pub fn stat_mods(&self) -> (i8, i8, i8, i8, i8) {
+        match self {
+            Race::Human => (0, 0, 0, 0, 0),
+            Race::Dwarf => (1, -1, 2, -1, 0),
+            Race::Elf => (-1, 2, -1, 0, 1),
+            Race::Halfling => (-2, 2, 0, 1, 0),
+        }
+    }
+
+    pub fn traits(&self) -> &str {
+        match self {
+            Race::Human => "",
+            Race::Dwarf => "Your dwarven constitution halves the harm poison does you.",
+            Race::Elf => "Your elven eyes see further in dim light.",
+            Race::Halfling => "You move with a halfling's quiet step.",
+        }
+    }
+}
+
+fn adjust_stat(stat: u8, modifier: i8) -> u8 {
+    (stat as i8 + modifier).max(3) as u8
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Player {
     pub base_info: GameObjectBase,
@@ -58,6 +105,7 @@ pub struct Player {
     pub chr: u8,
     pub apt: u8,
     pub role: Role,
+    pub race: Race,
     pub xp: u32,
     pub level: u8,
     pub max_depth: u8,
@@ -72,11 +120,31 @@ pub struct Player {
     pub stealth_score: u8,
     pub statuses: Vec<(Status, u32)>,
     pub size: u8,
+    pub satiation: u16,
+    pub hydration: u16,
+    pub curr_mana: u8,
+    pub max_mana: u8,
+    pub known_spells: Vec<Spell>,
 }
 
+pub const SATIATION_START: u16 = 2000;
+pub const SATIATION_MAX: u16 = 3000;
+pub const SATIATION_HUNGRY: u16 = 500;
+pub const SATIATION_WEAK: u16 = 150;
+pub const HYDRATION_START: u16 = 1500;
+pub const HYDRATION_MAX: u16 = 2000;
+pub const HYDRATION_THIRSTY: u16 = 300;
+pub const HYDRATION_PARCHED: u16 = 100;
+
 impl Player {
     pub fn calc_vision_radius(&mut self, state: &mut GameState, loc: (i32, i32, i8)) {
-        let prev_vr = self.vision_radius;
+        // Work back to how far the light alone let the player see last time, so that the elven
+        // bonus doesn't throw off the sunrise/sunset messages below
+        let prev_vr = if self.race == Race::Elf && self.vision_radius < 99 {
+            self.vision_radius.saturating_sub(2)
+        } else {
+            self.vision_radius
+        };
         let (hour, _) = state.curr_time();
 
         if loc.2 == 0 {
@@ -96,14 +164,20 @@ impl Player {
             };
         } else {
             // indoors
-            // Eventually roles who are dwarves, elves, etc will see in the dark better than
-            // humans
             self.vision_radius = match self.role {
                 Role::Rogue => 1,
                 Role::Warrior => 1,
+                Role::Mage => 1,
             }
         }
 
+        let light_vr = self.vision_radius;
+
+        // Elves make the most of whatever light there is
+        if self.race == Race::Elf && self.vision_radius < 99 {
+            self.vision_radius += 2;
+        }
+
         if self.has_status(Status::Blind) {
             self.vision_radius = 0;           
         }
@@ -111,19 +185,18 @@ impl Player {
         // Announce sunrise and sunset if the player is on the surface
         // This should be here and is a dumb calculation because vision radius will be
         // affected by say torches. It should be moved to end-of-turn stuff in the gameloop
-        if prev_vr == 99 && self.vision_radius == 9 && loc.2 == 0 {
+        if prev_vr == 99 && light_vr == 8 && loc.2 == 0 {
             state.msg_queue.push_back(Message::new(0, loc, "The sun is beginning to set.", ""));            
         }
-        if prev_vr == 5 && self.vision_radius == 7 && loc.2 == 0 {
+        if prev_vr == 5 && light_vr == 7 && loc.2 == 0 {
             state.msg_queue.push_back(Message::new(0, loc, "Sunrise soon.", ""));            
         }
     }
 
-    pub fn new_warrior(game_obj_db: &mut GameObjectDB, name: &str) {
+    pub fn new_warrior(game_obj_db: &mut GameObjectDB, name: &str, race: Race, rng: &mut GameRng) {
         let default_vision_radius = 99;
-        let stats = roll_stats();
+        let stats = roll_stats(rng);
         
-        let mut rng = rand::thread_rng();
         let (chr, apt) = if rng.gen_range(0.0, 1.0) < 0.5 {
             (stats[3], stats[4])
         } else {
@@ -132,23 +205,25 @@ impl Player {
 
         let mut p = Player { base_info: GameObjectBase::new(0, (-1, -1, -1), false, '@', display::WHITE, display::WHITE, true, name),
                 max_hp: (15 + stat_to_mod(stats[1])) as u8, curr_hp: (15 + stat_to_mod(stats[1])) as u8,
-                vision_radius: default_vision_radius, str: stats[0], con: stats[1], dex: stats[2], chr, apt, role: Role::Warrior, xp: 0, level: 1, max_depth: 0, 
+                vision_radius: default_vision_radius, str: stats[0], con: stats[1], dex: stats[2], chr, apt, role: Role::Warrior, race, xp: 0, level: 1, max_depth: 0, 
                 ac: 10, purse: 20, readied_weapon: "".to_string(), energy: 1.0, energy_restore: 1.0, inventory: Vec::new(), next_slot: 'a', hit_die: 10,
-                stealth_score: 10, statuses: Vec::new(), size: 2,
+                stealth_score: 10, statuses: Vec::new(), size: 2, satiation: SATIATION_START,
+                hydration: HYDRATION_START, curr_mana: 0, max_mana: 0, known_spells: Vec::new(),
         };
+        p.apply_race_mods();
         
         // Warrior starting equipment
 
-        if let Some(GameObjects::Item(mut spear)) = Item::get_item(game_obj_db, "spear") {
+        if let Some(GameObjects::Item(mut spear)) = Item::get_item(game_obj_db, "spear", rng) {
             spear.equiped = true;
             p.add_to_inv(GameObjects::Item(spear));
         }
 
-        if let Some(GameObjects::Item(mut armour)) = Item::get_item(game_obj_db, "ringmail") {
+        if let Some(GameObjects::Item(mut armour)) = Item::get_item(game_obj_db, "ringmail", rng) {
             armour.equiped = true;
 
             // All this for a dumb joke...
-            let r = rand::thread_rng().gen_range(0, 3);
+            let r = rng.gen_range(0, 3);
             let s = if r == 0 {
                 "Made in Middle-Earth.".to_string()
             } else if r == 1 {
@@ -160,36 +235,108 @@ impl Player {
             p.add_to_inv(GameObjects::Item(armour));
         }
 
-        if let Some(GameObjects::Item(dagger)) = Item::get_item(game_obj_db, "dagger") {
+        if let Some(GameObjects::Item(dagger)) = Item::get_item(game_obj_db, "dagger", rng) {
             p.add_to_inv(GameObjects::Item(dagger));
         }
                 
         for _ in 0..5 {
-            if let Some(GameObjects::Item(torch)) = Item::get_item(game_obj_db, "torch") {
+            if let Some(GameObjects::Item(torch)) = Item::get_item(game_obj_db, "torch", rng) {
                 p.add_to_inv(GameObjects::Item(torch));
             }
         }
 
         for _ in 0..3 {
-            if let Some(GameObjects::Item(scroll)) = Item::get_item(game_obj_db, "scroll of blink") {
+            if let Some(GameObjects::Item(scroll)) = Item::get_item(game_obj_db, "scroll of blink", rng) {
                 p.add_to_inv(GameObjects::Item(scroll));
             }
         }
         
-        // if let Some(GameObjects::Item(wand)) = Item::get_item(game_obj_db, "wand of frost") {
+        // if let Some(GameObjects::Item(wand)) = Item::get_item(game_obj_db, "wand of frost", rng) {
         //     p.add_to_inv(GameObjects::Item(wand));
         // }
 
-        if let Some(GameObjects::Item(potion)) = Item::get_item(game_obj_db, "potion of levitation") {
+        if let Some(GameObjects::Item(potion)) = Item::get_item(game_obj_db, "potion of levitation", rng) {
             p.add_to_inv(GameObjects::Item(potion));
         }
 
         for _ in 0..2 {
-            if let Some(GameObjects::Item(s)) = Item::get_item(game_obj_db, "scroll of protection") {
+            if let Some(GameObjects::Item(s)) = Item::get_item(game_obj_db, "scroll of protection", rng) {
                 p.add_to_inv(GameObjects::Item(s));
             }
         }
 
+        for _ in 0..2 {
+            if let Some(GameObjects::Item(r)) = Item::get_item(game_obj_db, "ration", rng) {
+                p.add_to_inv(GameObjects::Item(r));
+            }
+        }
+
+        if let Some(GameObjects::Item(w)) = Item::get_item(game_obj_db, "waterskin", rng) {
+            p.add_to_inv(GameObjects::Item(w));
+        }
+
+        p.calc_gear_effects();
+
+        game_obj_db.add(GameObjects::Player(p));
+    }
+
+    pub fn new_mage(game_obj_db: &mut GameObjectDB, name: &str, race: Race, rng: &mut GameRng) {
+        let default_vision_radius = 99;
+        let stats = roll_stats(rng);
+        
+        let (str, chr) = if rng.gen_range(0.0, 1.0) < 0.5 {
+            (stats[3], stats[4])
+        } else {
+            (stats[4], stats[3])
+        };
+
+        let mut p = Player { base_info: GameObjectBase::new(0, (-1, -1, -1), false, '@', display::WHITE, display::WHITE, true, name),
+                max_hp: (10 + stat_to_mod(stats[2])) as u8, curr_hp: (10 + stat_to_mod(stats[2])) as u8,
+                vision_radius: default_vision_radius, str, con: stats[2], dex: stats[1], chr, apt: stats[0], role: Role::Mage, race, xp: 0, level: 1, max_depth: 0, 
+                ac: 10, purse: 20, readied_weapon: "".to_string(), energy: 1.0, energy_restore: 1.0, inventory: Vec::new(), next_slot: 'a', hit_die: 6,
+                stealth_score: 10, statuses: Vec::new(), size: 2, satiation: SATIATION_START,
+                hydration: HYDRATION_START, curr_mana: 0, max_mana: 0, known_spells: vec![Spell::MagicMissile, Spell::Blink],
+        };
+        p.apply_race_mods();
+        p.max_mana = (6 + 2 * stat_to_mod(p.apt)).max(2) as u8;
+        p.curr_mana = p.max_mana;
+
+        // Mage starting equipment
+        if let Some(GameObjects::Item(mut dagger)) = Item::get_item(game_obj_db, "dagger", rng) {
+            dagger.equiped = true;
+            p.add_to_inv(GameObjects::Item(dagger));
+        }
+
+        if let Some(GameObjects::Item(cloak)) = Item::get_item(game_obj_db, "warm cloak", rng) {
+            p.add_to_inv(GameObjects::Item(cloak));
+        }
+
+        if let Some(GameObjects::Item(book)) = Item::get_item(game_obj_db, "spellbook of shield", rng) {
+            p.add_to_inv(GameObjects::Item(book));
+        }
+
+        for _ in 0..5 {
+            if let Some(GameObjects::Item(torch)) = Item::get_item(game_obj_db, "torch", rng) {
+                p.add_to_inv(GameObjects::Item(torch));
+            }
+        }
+
+        for _ in 0..2 {
+            if let Some(GameObjects::Item(potion)) = Item::get_item(game_obj_db, "potion of healing", rng) {
+                p.add_to_inv(GameObjects::Item(potion));
+            }
+        }
+
+        for _ in 0..2 {
+            if let Some(GameObjects::Item(r)) = Item::get_item(game_obj_db, "ration", rng) {
+                p.add_to_inv(GameObjects::Item(r));
+            }
+        }
+
+        if let Some(GameObjects::Item(w)) = Item::get_item(game_obj_db, "waterskin", rng) {
+            p.add_to_inv(GameObjects::Item(w));
+        }
+
         p.calc_gear_effects();
 
         game_obj_db.add(GameObjects::Player(p));
@@ -222,6 +369,26 @@ impl Player {
     }
     */
 
+    // Adjust the rolled stats for the player's race. Hit points were based on the unmodified
+    // constitution so they get topped up (or docked) to match.
+    fn apply_race_mods(&mut self) {
+        let (str_mod, dex_mod, con_mod, chr_mod, apt_mod) = self.race.stat_mods();
+        let prev_con_mod = stat_to_mod(self.con);
+        self.str = adjust_stat(self.str, str_mod);
+        self.dex = adjust_stat(self.dex, dex_mod);
+        self.con = adjust_stat(self.con, con_mod);
+        self.chr = adjust_stat(self.chr, chr_mod);
+        self.apt = adjust_stat(self.apt, apt_mod);
+
+        let hp = (self.max_hp as i8 + stat_to_mod(self.con) - prev_con_mod).max(1) as u8;
+        self.max_hp = hp;
+        self.curr_hp = hp;
+
+        if self.race == Race::Halfling {
+            self.size = 1;
+        }
+    }
+
     pub fn has_status(&self, status: Status) -> bool {
         for s in self.statuses.iter() {
             if s.0 == status {
@@ -397,6 +564,12 @@ impl Player {
 
     // highlight: 0 is everything, 1 is useable, 2 is equipable
     pub fn inv_menu(&self, highlight: u8) -> Vec<(String, bool)> {
+        self.inv_menu_sorted(highlight, false)
+    }
+
+    // When sorting, items are grouped under category headings and alphabetized within
+    // them. Either way each item keeps its slot letter.
+    pub fn inv_menu_sorted(&self, highlight: u8, by_category: bool) -> Vec<(String, bool)> {
         let mut items = Vec::new();
         for obj in self.inventory.iter() {
             if let GameObjects::Item(i) = obj {
@@ -410,7 +583,7 @@ impl Player {
                 } else {
                     false
                 };
-                items.push((i.slot, name, h));
+                items.push((i.slot, name, h, i.item_type.category(), i.value as u32, i.weight as u32));
             }            
         }
         
@@ -420,11 +593,31 @@ impl Player {
         slots.dedup();
         let mut menu_items = HashMap::new();
         for s in items {
-            let counter = menu_items.entry(s.0).or_insert((s.1, 0, s.2));
+            let counter = menu_items.entry(s.0).or_insert((s.1, 0, s.2, s.3, 0, 0));
             counter.1 += 1;
+            counter.4 += s.4;
+            counter.5 += s.5;
         }
         
+        if by_category {
+            slots.sort_by(|a, b| {
+                let item_a = &menu_items[a];
+                let item_b = &menu_items[b];
+                (item_a.3, &item_a.0).cmp(&(item_b.3, &item_b.0))
+            });
+        }
+
+        let mut curr_category = None;
         for slot in slots {
+            let category = menu_items[&slot].3;
+            if by_category && curr_category != Some(category) {
+                if curr_category.is_some() {
+                    menu.push((String::from(""), true));
+                }
+                menu.push((format!("{}:", category.1), true));
+                curr_category = Some(category);
+            }
+
             let mut s = String::from(slot);
             s.push_str(") ");
 
@@ -434,12 +627,21 @@ impl Player {
             } else {
                 s.push_str(&format!("{} {}", i.1.to_string(), i.0.pluralize()));
             }
+            s.push(' ');
+            s.push_str(&Item::value_and_weight(i.4, i.5));
             menu.push((s, i.2));
         }
         
         menu
     }
 
+    // Total (value, weight) of everything the player is carrying
+    pub fn inv_totals(&self) -> (u32, u32) {
+        self.inventory.iter()
+            .filter_map(|obj| if let GameObjects::Item(i) = obj { Some((i.value as u32, i.weight as u32)) } else { None })
+            .fold((0, 0), |acc, i| (acc.0 + i.0, acc.1 + i.1))
+    }
+
     pub fn ac_mods_from_gear(&self) -> (i8, u128) {
         let mut sum = 0;
         let mut attributes = 0;
@@ -455,6 +657,19 @@ impl Player {
         (sum, attributes)
     }
 
+    pub fn gear_attributes(&self) -> u128 {
+        let mut attributes = 0;
+        for obj in self.inventory.iter() {
+            if let GameObjects::Item(item) = obj {
+                if item.equiped {
+                    attributes |= item.attributes;
+                }
+            }
+        }
+
+        attributes
+    }
+
     pub fn readied_weapon(&self) -> Option<(&Item, String)> {
         for j in 0..self.inventory.len() {
             if let GameObjects::Item(item) = &self.inventory[j] {
@@ -468,6 +683,59 @@ impl Player {
         None
     }
 
+    // A readied shield gives a chance to turn aside a blow entirely, and the heavier the shield
+    // the better the odds. There's no blocking while wielding a two-handed weapon or when
+    // the player can't move.
+    pub fn block_chance(&self) -> f32 {
+        if self.has_status(Status::Paralyzed) {
+            return 0.0;
+        }
+
+        let mut chance = 0.0;
+        for obj in self.inventory.iter() {
+            if let GameObjects::Item(item) = obj {
+                if !item.equiped {
+                    continue;
+                }
+                if item.item_type == ItemType::Weapon && item.attributes & items::IA_TWO_HANDED > 0 {
+                    return 0.0;
+                }
+                if item.item_type == ItemType::Shield {
+                    chance = 0.05 + 0.02 * item.weight as f32;
+                }
+            }
+        }
+
+        chance
+    }
+
+    // Warriors get a chance to counterattack when a foe's swing misses them, which improves as
+    // they gain levels. They need a weapon in hand and to be able to see the opening.
+    pub fn riposte_chance(&self) -> f32 {
+        if self.role != Role::Warrior || self.readied_weapon().is_none() 
+                || self.has_status(Status::Paralyzed) || self.has_status(Status::Blind) {
+            return 0.0;
+        }
+
+        (0.05 + 0.02 * self.level as f32).min(0.35)
+    }
+
+    // Armour gets in the way of the precise gestures spellcasting needs
+    pub fn spell_failure_chance(&self) -> f32 {
+        let mut chance: f32 = 0.0;
+        let (_, attributes) = self.ac_mods_from_gear();
+        if attributes & items::IA_HEAVY_ARMOUR > 0 {
+            chance += 0.4;
+        } else if attributes & items::IA_MED_ARMOUR > 0 {
+            chance += 0.15;
+        }
+        if !self.readied_obj_ids_of_type(ItemType::Shield).is_empty() {
+            chance += 0.1;
+        }
+
+        chance.min(0.9)
+    }
+
     pub fn calc_gear_effects(&mut self) {
         self.calc_ac();
         self.calc_stealth();
@@ -479,12 +747,17 @@ impl Player {
         if self.role == Role::Rogue {
             score += 1 + self.level as i8 / 4;
         }
+        if self.race == Race::Halfling {
+            score += 2;
+        }
 
         // I feel like having a lit torch should also have a big
         // penalty to stealth but that might nerf Rogues too much?
         let (_, attributes) = self.ac_mods_from_gear();
         if attributes & items::IA_HEAVY_ARMOUR > 0 {
             score /= 2;
+        } else if attributes & items::IA_MED_ARMOUR > 0 {
+            score -= 2;
         }
 
         self.stealth_score = if score < 0 {
@@ -497,8 +770,7 @@ impl Player {
     // My idea is that the roles will have differing bonuses to attack rolls. Ie.,
     // a warrior might get an extra 1d6, a rogue an extra 1d4, wizard-types no 
     // extra dice, and they get more dice as they level up.
-    pub fn attack_bonus(&mut self) -> i8 {
-        let mut rng = rand::thread_rng();
+    pub fn attack_bonus(&mut self, rng: &mut GameRng) -> i8 {
         let die;
         let mut num_of_dice = 1;
         match self.role {
@@ -522,6 +794,10 @@ impl Player {
                     num_of_dice = 4;
                 }
             },
+            Role::Mage => {
+                die = 4;
+                num_of_dice = 0;
+            },
         }
 
         let roll: i8 = (0..num_of_dice).map(|_| rng.gen_range(1, die + 1)).sum();
@@ -535,10 +811,103 @@ impl Player {
         if self.curr_hp < self.max_hp {
             self.curr_hp += 1;
         }
+        if self.curr_mana < self.max_mana {
+            self.curr_mana += 1;
+        }
+    }
+
+    // Aptitude modifiers. Quick-witted characters learn faster from experience, spot hidden
+    // things further away and have an easier time working out how a lock is put together.
+    pub fn apt_xp_pct(&self) -> u32 {
+        (100 + 5 * stat_to_mod(self.apt) as i32).max(50) as u32
+    }
+
+    pub fn apt_search_radius(&self) -> i32 {
+        if stat_to_mod(self.apt) >= 2 { 2 } else { 1 }
+    }
+
+    pub fn apt_lockpick_bonus(&self) -> u8 {
+        stat_to_mod(self.apt).max(0) as u8
+    }
+
+    // Roughly how many turns it takes to work out an unfamiliar item (like how many
+    // charges are left in a wand) just by carrying it around.
+    pub fn apt_identify_turns(&self) -> u32 {
+        (200 - 40 * stat_to_mod(self.apt) as i32).clamp(40, 400) as u32
+    }
+
+    pub fn search_check(&self, rng: &mut GameRng) -> bool {
+        self.ability_check(Ability::Apt, rng) >= SEARCH_DC
+    }
+
+    pub fn study_items(&mut self, state: &mut GameState) {
+        let turns = self.apt_identify_turns();
+        for obj in self.inventory.iter_mut() {
+            if let GameObjects::Item(item) = obj {
+                if item.attributes & items::IA_UNIDENTIFIED > 0 && state.rng.gen_range(0, turns) == 0 {
+                    item.attributes &= !items::IA_UNIDENTIFIED;
+                    let s = format!("You figure out your {} has {} charges left.", item.base_info.name, item.charges);
+                    state.msg_queue.push_back(Message::info(&s));
+                }
+            }
+        }
+    }
+
+    // The hunger clock. Called once per turn.
+    pub fn digest(&mut self, state: &mut GameState) {
+        if self.satiation > 0 {
+            self.satiation -= 1;
+            if self.satiation == SATIATION_HUNGRY {
+                state.msg_queue.push_back(Message::info("You are getting hungry."));
+            } else if self.satiation == SATIATION_WEAK {
+                state.msg_queue.push_back(Message::info("You are weak from hunger!"));
+            } else if self.satiation == 0 {
+                state.msg_queue.push_back(Message::info("You are starving!"));
+            }
+        } else if state.turn.is_multiple_of(10) {
+            self.damaged(state, 1, DamageType::Bludgeoning, 0, "starvation");
+        }
+    }
+
+    pub fn spoil_food(&mut self, state: &mut GameState) {
+        for obj in self.inventory.iter_mut() {
+            if let GameObjects::Item(item) = obj {
+                if item.item_type == ItemType::Food {
+                    item.spoil(state, PLAYER_INV);
+                }
+            }
+        }
+    }
+
+    pub fn eat(&mut self, state: &mut GameState, nutrition: u16) {
+        self.satiation = u16::min(self.satiation + nutrition, SATIATION_MAX);
+        if self.satiation > SATIATION_MAX - 500 {
+            state.msg_queue.push_back(Message::info("You're stuffed."));
+        }
+    }
+
+    // Only called when thirst is turned on in the options file
+    pub fn dehydrate(&mut self, state: &mut GameState) {
+        if self.hydration > 0 {
+            self.hydration -= 1;
+            if self.hydration == HYDRATION_THIRSTY {
+                state.msg_queue.push_back(Message::info("You are getting thirsty."));
+            } else if self.hydration == HYDRATION_PARCHED {
+                state.msg_queue.push_back(Message::info("Your throat is parched!"));
+            } else if self.hydration == 0 {
+                state.msg_queue.push_back(Message::info("You are dying of thirst!"));
+            }
+        } else if state.turn.is_multiple_of(5) {
+            self.damaged(state, 1, DamageType::Bludgeoning, 0, "dehydration");
+        }
+    }
+
+    pub fn quench(&mut self, amt: u16) {
+        self.hydration = u16::min(self.hydration + amt, HYDRATION_MAX);
     }
 
     pub fn add_xp(&mut self, xp: u32, state: &mut GameState, loc: (i32, i32, i8)) {
-        self.xp += xp;
+        self.xp += (xp * self.apt_xp_pct() + 50) / 100;
 
         // If the player is less than max level, check to see if they've leveled up.
         // Also, regardless of XP gained, the player won't gain two levels at once and
@@ -548,7 +917,7 @@ impl Player {
         if self.level < 20 {
             let next_level_xp = XP_CHART[self.level as usize - 1];
             if self.xp >= next_level_xp {
-                state.queued_events.push_back((EventType::LevelUp, loc, 0, None));
+                state.queued_events.push_back(GameEvent::new(EventType::LevelUp, loc, 0));
             }
 
             if self.level < 19 && self.xp >= XP_CHART[self.level as usize] {
@@ -557,11 +926,10 @@ impl Player {
         }
     }
 
-    pub fn level_up(&mut self) {
+    pub fn level_up(&mut self, rng: &mut GameRng) {
         self.level += 1;
         
         // Other stuff needs to happen like more hit points, etc
-        let mut rng = rand::thread_rng();
         let mut hp_roll = rng.gen_range(1, self.hit_die + 1) as i8 + stat_to_mod(self.con);
         if hp_roll < 1 {
             hp_roll = 1;
@@ -571,15 +939,29 @@ impl Player {
         if self.curr_hp > self.max_hp {
             self.curr_hp = self.max_hp;
         }        
+
+        if self.role == Role::Mage {
+            let mana_gain = (2 + stat_to_mod(self.apt)).max(1) as u8;
+            self.max_mana += mana_gain;
+            self.curr_mana += mana_gain;
+        }
     }
 }
 
 impl Person for Player {
     fn damaged(&mut self, state: &mut GameState, amount: u8, dmg_type: DamageType, _assailant_id: usize, assailant_name: &str) {
+        // Dwarves are hardy enough that poison only does them half as much harm
+        let amount = if dmg_type == DamageType::Poison && self.race == Race::Dwarf {
+            (amount / 2).max(1)
+        } else {
+            amount
+        };
+
+        state.stats.dmg_taken += amount as u32;
         if amount >= self.curr_hp {
             // Oh no the player has been killed :O
             self.curr_hp = 0;
-            state.queued_events.push_front((EventType::PlayerKilled, (0, 0, 0), 0, Some(String::from(assailant_name))));
+            state.queued_events.push_front(GameEvent::with_msg(EventType::PlayerKilled, (0, 0, 0), 0, assailant_name));
         } else {
             self.curr_hp -= amount;
         }
@@ -603,8 +985,7 @@ impl Person for Player {
         self.curr_hp += amt;
     }
 
-    fn ability_check(&self, ability: Ability) -> u8 {
-        let mut rng = rand::thread_rng();
+    fn ability_check(&self, ability: Ability, rng: &mut GameRng) -> u8 {
         let roll = rng.gen_range(1, 21) + 
             match ability {
                 Ability::Str => stat_to_mod(self.str),
@@ -724,18 +1105,213 @@ pub fn stat_to_mod(stat: u8) -> i8 {
 
 // Classic D&D roll 4d6 and drop lowest. (Or classic in the sense that's how 
 // we did it in 2e)
-fn four_d6_drop_one() -> u8 {
-    let mut rng = rand::thread_rng();
+fn four_d6_drop_one(rng: &mut GameRng) -> u8 {
     let mut rolls = vec![rng.gen_range(1, 7), rng.gen_range(1, 7), rng.gen_range(1, 7), rng.gen_range(1, 7)];
     rolls.sort_unstable();
 
     rolls[1..].iter().sum()
 }
 
-fn roll_stats() -> Vec<u8> {
-    let mut stats = vec![four_d6_drop_one(), four_d6_drop_one(), four_d6_drop_one(), four_d6_drop_one(), four_d6_drop_one()];
+fn roll_stats(rng: &mut GameRng) -> Vec<u8> {
+    let mut stats = vec![four_d6_drop_one(rng), four_d6_drop_one(rng), four_d6_drop_one(rng), four_d6_drop_one(rng), four_d6_drop_one(rng)];
     stats.sort_unstable();
     stats.reverse();
 
     stats
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_db(apt: u8) -> GameObjectDB {
+        let mut game_obj_db = GameObjectDB::new();
+        Player::new_warrior(&mut game_obj_db, "Tester", Race::Human, &mut GameRng::seeded(1));
+        game_obj_db.player().unwrap().apt = apt;
+        game_obj_db
+    }
+
+    #[test]
+    fn higher_aptitude_finds_hidden_things_more_often() {
+        let mut dim_db = test_db(6);
+        let mut sharp_db = test_db(16);
+        let dim = dim_db.player().unwrap();
+        let sharp = sharp_db.player().unwrap();
+        let mut rng = GameRng::seeded(2181);
+        let dim_finds = (0..2000).filter(|_| dim.search_check(&mut rng)).count();
+        let sharp_finds = (0..2000).filter(|_| sharp.search_check(&mut rng)).count();
+
+        assert!(sharp_finds > dim_finds);
+        assert!(sharp.apt_search_radius() > dim.apt_search_radius());
+    }
+
+    fn dusk_and_dawn_messages(race: Race) -> Vec<String> {
+        let mut game_obj_db = GameObjectDB::new();
+        Player::new_warrior(&mut game_obj_db, "Tester", race, &mut GameRng::seeded(1));
+        let mut state = crate::tests::test_state();
+        let player = game_obj_db.player().unwrap();
+        let loc = (10, 10, 0);
+
+        // 19:59 to 20:00, then 3:59 to 4:00 the next morning
+        let minutes = [11 * 60 + 59, 12 * 60, 19 * 60 + 59, 20 * 60];
+        for m in minutes.iter() {
+            state.turn = m * crate::TURNS_PER_MINUTE;
+            player.calc_vision_radius(&mut state, loc);
+        }
+
+        state.msg_queue.iter().map(|m| m.text.clone()).collect()
+    }
+
+    #[test]
+    fn elves_still_see_the_sun_rise_and_set() {
+        let expected = vec!["The sun is beginning to set.".to_string(), "Sunrise soon.".to_string()];
+        assert_eq!(dusk_and_dawn_messages(Race::Human), expected);
+        assert_eq!(dusk_and_dawn_messages(Race::Elf), expected);
+    }
+
+    #[test]
+    fn dwarves_take_half_damage_from_poison() {
+        let mut game_obj_db = GameObjectDB::new();
+        Player::new_warrior(&mut game_obj_db, "Tester", Race::Dwarf, &mut GameRng::seeded(1));
+        let mut state = crate::tests::test_state();
+        let player = game_obj_db.player().unwrap();
+        let hp = player.curr_hp;
+
+        player.damaged(&mut state, 4, DamageType::Poison, 0, "poison");
+        assert_eq!(player.curr_hp, hp - 2);
+        player.damaged(&mut state, 1, DamageType::Poison, 0, "poison");
+        assert_eq!(player.curr_hp, hp - 3);
+    }
+
+    #[test]
+    fn higher_aptitude_identifies_items_faster() {
+        let turns: Vec<u32> = [4, 10, 16].iter()
+            .map(|apt| test_db(*apt).player().unwrap().apt_identify_turns())
+            .collect();
+
+        assert!(turns[0] > turns[1]);
+        assert!(turns[1] > turns[2]);
+    }
+
+    #[test]
+    fn sorted_inventory_groups_items_but_keeps_their_slots() {
+        let mut game_obj_db = GameObjectDB::new();
+        Player::new_warrior(&mut game_obj_db, "Tester", Race::Human, &mut GameRng::seeded(1));
+        let player = game_obj_db.player().unwrap();
+
+        let menu: Vec<String> = player.inv_menu_sorted(0, true).into_iter().map(|(s, _)| s).collect();
+        assert_eq!(menu[0], "Weapons:");
+        assert_eq!(menu[1], "c) a dagger [2gp, wt 1]");
+        assert_eq!(menu[2], "a) a spear (in hand) [2gp, wt 2]");
+        assert_eq!(menu[3], "");
+        assert_eq!(menu[4], "Armour:");
+        assert!(menu.contains(&"e) 3 scrolls of blink [60gp, wt 3]".to_string()));
+        let food = menu.iter().position(|s| s == "Food:").unwrap();
+        let misc = menu.iter().position(|s| s == "Miscellaneous:").unwrap();
+        assert!(food < misc);
+        assert_eq!(menu.last().unwrap(), "i) a waterskin (full) [2gp, wt 1]");
+
+        // Unsorted, it's just the slots in order with no headings
+        let plain = player.inv_menu_sorted(2, false);
+        assert_eq!(plain.len(), 9);
+        assert_eq!(plain[0], ("a) a spear (in hand) [2gp, wt 2]".to_string(), true));
+        assert_eq!(plain[3], ("d) 5 torches [5gp, wt 5]".to_string(), false));
+    }
+
+    #[test]
+    fn race_adjusts_starting_stats() {
+        let mut game_obj_db = GameObjectDB::new();
+        Player::new_warrior(&mut game_obj_db, "Tester", Race::Human, &mut GameRng::seeded(1));
+        let player = game_obj_db.player().unwrap();
+        player.str = 10;
+        player.dex = 10;
+        player.con = 10;
+        player.chr = 10;
+        player.apt = 3;
+        player.max_hp = 15;
+        player.race = Race::Dwarf;
+        player.apply_race_mods();
+
+        assert_eq!(Race::Dwarf.stat_mods(), (1, -1, 2, -1, 0));
+        assert_eq!((player.str, player.dex, player.con, player.chr, player.apt), (11, 9, 12, 9, 3));
+        // The hardier constitution carries through to hit points
+        assert_eq!(player.max_hp, 16);
+        assert_eq!(player.curr_hp, 16);
+
+        // No stat drops below 3, however poorly it was rolled
+        player.race = Race::Halfling;
+        player.str = 4;
+        player.apply_race_mods();
+        assert_eq!(player.str, 3);
+        assert_eq!(Race::Human.stat_mods(), (0, 0, 0, 0, 0));
+    }
+
+    fn give(player: &mut Player, game_obj_db: &mut GameObjectDB, name: &str) {
+        if let Some(GameObjects::Item(mut item)) = Item::get_item(game_obj_db, name, &mut GameRng::seeded(1)) {
+            item.equiped = true;
+            player.add_to_inv(GameObjects::Item(item));
+        }
+        player.calc_gear_effects();
+    }
+
+    #[test]
+    fn heavier_armour_costs_stealth_and_spellcasting() {
+        let mut game_obj_db = GameObjectDB::new();
+        Player::new_warrior(&mut game_obj_db, "Tester", Race::Human, &mut GameRng::seeded(1));
+        let mut player = match game_obj_db.remove(0) {
+            GameObjects::Player(p) => p,
+            _ => panic!("no player"),
+        };
+        player.dex = 10;
+        player.calc_gear_effects();
+
+        // Starting ringmail is medium armour
+        assert_eq!(player.stealth_score, 8);
+        assert!((player.spell_failure_chance() - 0.15).abs() < 0.001);
+
+        player.inventory.retain(|obj| !matches!(obj, GameObjects::Item(i) if i.item_type == ItemType::Armour));
+        give(&mut player, &mut game_obj_db, "plate armour");
+        assert_eq!(player.stealth_score, 5);
+        assert!((player.spell_failure_chance() - 0.4).abs() < 0.001);
+
+        give(&mut player, &mut game_obj_db, "shield");
+        assert!((player.spell_failure_chance() - 0.5).abs() < 0.001);
+    }
+
+    #[test]
+    fn shields_block_unless_both_hands_are_on_a_weapon() {
+        let mut game_obj_db = GameObjectDB::new();
+        Player::new_warrior(&mut game_obj_db, "Tester", Race::Human, &mut GameRng::seeded(1));
+        let mut player = match game_obj_db.remove(0) {
+            GameObjects::Player(p) => p,
+            _ => panic!("no player"),
+        };
+        assert_eq!(player.block_chance(), 0.0);
+
+        give(&mut player, &mut game_obj_db, "buckler");
+        assert!((player.block_chance() - 0.09).abs() < 0.001);
+
+        crate::effects::add_status(&mut player, Status::Paralyzed, 5);
+        assert_eq!(player.block_chance(), 0.0);
+        player.statuses.clear();
+
+        give(&mut player, &mut game_obj_db, "two-handed sword");
+        assert_eq!(player.block_chance(), 0.0);
+    }
+
+    #[test]
+    fn only_warriors_who_can_see_get_to_riposte() {
+        let mut game_obj_db = GameObjectDB::new();
+        Player::new_warrior(&mut game_obj_db, "Tester", Race::Human, &mut GameRng::seeded(1));
+        let player = game_obj_db.player().unwrap();
+        assert!((player.riposte_chance() - 0.07).abs() < 0.001);
+        player.level = 20;
+        assert!((player.riposte_chance() - 0.35).abs() < 0.001);
+
+        crate::effects::add_status(player, Status::Blind, 100);
+        assert_eq!(player.riposte_chance(), 0.0);
+
+        let mut mage_db = GameObjectDB::new();
+        Player::new_mage(&mut mage_db, "Tester", Race::Human, &mut GameRng::seeded(1));
+        assert_eq!(mage_db.player().unwrap().riposte_chance(), 0.0);
+    }
+}
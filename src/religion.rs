@@ -0,0 +1,304 @@
+// This file is part of RogueVillage, a roguelike game.
+//
+// RogueVillage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RogueVillage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RogueVillage.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use super::{GameState, Message};
+use crate::display::UserInterface;
+use crate::effects;
+use crate::effects::Status;
+use crate::game_obj::{GameObject, GameObjectDB};
+use crate::map::{ShrineType, Tile};
+use crate::npc::Attitude;
+
+const MAX_FAVOUR: i32 = 100;
+const MIN_FAVOUR: i32 = -100;
+const PRAYER_COOLDOWN: u32 = 200;
+const SMITE_RANGE: i32 = 6;
+
+// How well regarded the player is by each of the gods. Favour is earned with offerings at a god's
+// shrine and lost through sacrilege, and is spent when a prayer is answered.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Favour {
+    woden: i32,
+    crawler: i32,
+}
+
+impl Favour {
+    pub fn new() -> Favour {
+        Favour { woden: 0, crawler: 0 }
+    }
+
+    pub fn get(&self, deity: ShrineType) -> i32 {
+        match deity {
+            ShrineType::Woden => self.woden,
+            ShrineType::Crawler => self.crawler,
+        }
+    }
+
+    pub fn adjust(&mut self, deity: ShrineType, delta: i32) {
+        let favour = match deity {
+            ShrineType::Woden => &mut self.woden,
+            ShrineType::Crawler => &mut self.crawler,
+        };
+        *favour = (*favour + delta).clamp(MIN_FAVOUR, MAX_FAVOUR);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Boon {
+    MinorHeal,
+    RemoveCurse,
+    Smite,
+}
+
+impl Boon {
+    pub fn desc(&self) -> &str {
+        match self {
+            Boon::MinorHeal => "ask for healing",
+            Boon::RemoveCurse => "ask for a curse to be lifted",
+            Boon::Smite => "call down wrath on your foes",
+        }
+    }
+
+    // The favour needed before the god will grant the boon
+    pub fn threshold(&self) -> i32 {
+        match self {
+            Boon::MinorHeal => 10,
+            Boon::RemoveCurse => 25,
+            Boon::Smite => 50,
+        }
+    }
+
+    // What it costs in favour when the boon is granted
+    pub fn cost(&self) -> i32 {
+        match self {
+            Boon::MinorHeal => 5,
+            Boon::RemoveCurse => 10,
+            Boon::Smite => 20,
+        }
+    }
+}
+
+pub fn boons_unlocked(favour: i32) -> Vec<Boon> {
+    [Boon::MinorHeal, Boon::RemoveCurse, Boon::Smite].iter()
+        .filter(|b| favour >= b.threshold())
+        .copied()
+        .collect()
+}
+
+fn deity_name(deity: ShrineType) -> &'static str {
+    match deity {
+        ShrineType::Woden => "Woden",
+        ShrineType::Crawler => "the Crawler",
+    }
+}
+
+fn rival(deity: ShrineType) -> ShrineType {
+    match deity {
+        ShrineType::Woden => ShrineType::Crawler,
+        ShrineType::Crawler => ShrineType::Woden,
+    }
+}
+
+pub fn shrine_at(state: &GameState, loc: (i32, i32, i8)) -> Option<ShrineType> {
+    if let Some(Tile::Shrine(stype)) = state.map.get(&loc) {
+        Some(*stype)
+    } else {
+        None
+    }
+}
+
+// Let the player know when their standing with a god has crossed into a new tier of boons
+fn adjust_favour(state: &mut GameState, deity: ShrineType, delta: i32) {
+    let prev_tier = boons_unlocked(state.favour.get(deity)).len();
+    state.favour.adjust(deity, delta);
+    let curr_tier = boons_unlocked(state.favour.get(deity)).len();
+
+    if curr_tier > prev_tier {
+        let s = format!("You feel that {} looks upon you with greater favour.", deity_name(deity));
+        state.msg_queue.push_back(Message::info(&s));
+    } else if curr_tier < prev_tier {
+        let s = format!("You feel that {} has grown cool toward you.", deity_name(deity));
+        state.msg_queue.push_back(Message::info(&s));
+    }
+}
+
+// Gold left on an altar is accepted by its god, but the other one takes note of the player's
+// divided loyalties
+pub fn offer_gold(state: &mut GameState, deity: ShrineType, amt: u32) {
+    let gain = (amt / 10).max(1) as i32;
+    let s = format!("The gold vanishes from the altar of {}.", deity_name(deity));
+    state.msg_queue.push_back(Message::info(&s));
+    adjust_favour(state, deity, gain);
+    adjust_favour(state, rival(deity), -(gain / 2));
+}
+
+// Woden has no use for blood on his altar, but the Crawler relishes it
+pub fn offer_sacrifice(state: &mut GameState, deity: ShrineType) {
+    match deity {
+        ShrineType::Woden => {
+            state.msg_queue.push_back(Message::info("You have defiled Woden's altar!"));
+            adjust_favour(state, ShrineType::Woden, -10);
+        },
+        ShrineType::Crawler => {
+            state.msg_queue.push_back(Message::info("The corpse is consumed in a flash of sickly green flame."));
+            adjust_favour(state, ShrineType::Crawler, 8);
+            adjust_favour(state, ShrineType::Woden, -4);
+        },
+    }
+}
+
+// Called when the player spills blood inside Woden's sanctuary
+pub fn sacrilege(state: &mut GameState) {
+    state.msg_queue.push_back(Message::info("You feel Woden's displeasure."));
+    adjust_favour(state, ShrineType::Woden, -10);
+}
+
+fn smite(state: &mut GameState, game_obj_db: &mut GameObjectDB, deity: ShrineType) {
+    let player_loc = game_obj_db.get(0).unwrap().get_loc();
+    let nearby: Vec<(i32, i32, i8)> = state.curr_visible.iter()
+        .filter(|loc| loc.2 == player_loc.2 && **loc != player_loc)
+        .filter(|loc| (loc.0 - player_loc.0).abs() <= SMITE_RANGE && (loc.1 - player_loc.1).abs() <= SMITE_RANGE)
+        .copied()
+        .collect();
+
+    let mut targets = Vec::new();
+    for loc in nearby {
+        if let Some(id) = game_obj_db.person_at(loc) {
+            if let Some(npc) = game_obj_db.npc(id) {
+                if npc.attitude == Attitude::Hostile {
+                    targets.push(id);
+                }
+            }
+        }
+    }
+
+    if targets.is_empty() {
+        state.msg_queue.push_back(Message::info("Thunder rumbles, but there is no one to smite."));
+        return;
+    }
+
+    let s = match deity {
+        ShrineType::Woden => "Lightning crashes down upon your foes!",
+        ShrineType::Crawler => "Black tendrils lash out at your foes!",
+    };
+    state.msg_queue.push_back(Message::info(s));
+    for id in targets {
+        effects::smite(state, game_obj_db, id);
+    }
+}
+
+pub fn pray(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) -> f32 {
+    let player_loc = game_obj_db.get(0).unwrap().get_loc();
+    let deity = match shrine_at(state, player_loc) {
+        Some(deity) => deity,
+        None => {
+            state.msg_queue.push_back(Message::info("You mumble a prayer, but no one seems to be listening."));
+            return 1.0;
+        },
+    };
+
+    let player = game_obj_db.player().unwrap();
+    if player.has_status(Status::CoolingDown(effects::AB_PRAYER)) {
+        let s = format!("{} will not be troubled again so soon.", deity_name(deity));
+        state.msg_queue.push_back(Message::info(&s));
+        return 1.0;
+    }
+
+    let favour = state.favour.get(deity);
+    let boons = boons_unlocked(favour);
+    if boons.is_empty() {
+        state.msg_queue.push_back(Message::info("Your prayers go unanswered."));
+        return 1.0;
+    }
+
+    let sbi = state.curr_sidebar_info(game_obj_db);
+    let mut text = format!("You kneel before the altar of {}.\n", deity_name(deity));
+    let mut options = HashSet::new();
+    let mut slot = 'a';
+    for boon in boons.iter() {
+        let s = format!("\n{}) {}", slot, boon.desc());
+        text.push_str(&s);
+        options.insert(slot);
+        slot = (slot as u8 + 1) as char;
+    }
+
+    let boon = match gui.popup_menu("Prayer", &text, &options, Some(&sbi)) {
+        Some(ch) => boons[(ch as u8 - b'a') as usize],
+        None => {
+            state.msg_queue.push_back(Message::info("Never mind."));
+            return 0.0;
+        },
+    };
+
+    match boon {
+        Boon::MinorHeal => effects::apply_effects(state, 0, game_obj_db, effects::EF_MINOR_HEAL),
+        Boon::RemoveCurse => {
+            let player = game_obj_db.player().unwrap();
+            if player.has_status(Status::Bane) {
+                effects::remove_status(player, Status::Bane);
+                state.msg_queue.push_back(Message::info("You feel a weight lift from your shoulders."));
+            } else {
+                state.msg_queue.push_back(Message::info("You feel reassured."));
+            }
+        },
+        Boon::Smite => smite(state, game_obj_db, deity),
+    }
+
+    adjust_favour(state, deity, -boon.cost());
+    let player = game_obj_db.player().unwrap();
+    effects::add_status(player, Status::CoolingDown(effects::AB_PRAYER), state.turn + PRAYER_COOLDOWN);
+
+    1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boons_unlock_as_favour_grows() {
+        assert!(boons_unlocked(-20).is_empty());
+        assert!(boons_unlocked(9).is_empty());
+        assert_eq!(boons_unlocked(10), vec![Boon::MinorHeal]);
+        assert_eq!(boons_unlocked(49), vec![Boon::MinorHeal, Boon::RemoveCurse]);
+        assert_eq!(boons_unlocked(MAX_FAVOUR), vec![Boon::MinorHeal, Boon::RemoveCurse, Boon::Smite]);
+    }
+
+    #[test]
+    fn favour_stays_within_bounds() {
+        let mut favour = Favour::new();
+        favour.adjust(ShrineType::Woden, 500);
+        favour.adjust(ShrineType::Crawler, -500);
+        assert_eq!(favour.get(ShrineType::Woden), MAX_FAVOUR);
+        assert_eq!(favour.get(ShrineType::Crawler), MIN_FAVOUR);
+    }
+
+    #[test]
+    fn gold_offerings_please_one_god_and_irk_the_other() {
+        let mut state = crate::tests::test_state();
+        state.favour.adjust(ShrineType::Crawler, 12);
+        offer_gold(&mut state, ShrineType::Woden, 100);
+
+        assert_eq!(state.favour.get(ShrineType::Woden), 10);
+        assert_eq!(state.favour.get(ShrineType::Crawler), 7);
+        let msgs: Vec<&str> = state.msg_queue.iter().map(|m| m.text.as_str()).collect();
+        assert!(msgs.contains(&"You feel that Woden looks upon you with greater favour."));
+        assert!(msgs.contains(&"You feel that the Crawler has grown cool toward you."));
+    }
+}
@@ -0,0 +1,195 @@
+// This file is part of RogueVillage, a roguelike game.
+//
+// RogueVillage is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// RogueVillage is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with RogueVillage.  If not, see <https://www.gnu.org/licenses/>.
+
+extern crate serde;
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
+
+use serde::{Serialize, Deserialize};
+
+use crate::display::UserInterface;
+
+const HIGH_SCORES_FILE: &str = "highscores.yaml";
+const MAX_SCORES: usize = 20;
+
+// Running tallies of what the player has gotten up to during the game
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct GameStats {
+    pub kills: BTreeMap<String, u32>,
+    pub items_found: u32,
+    pub levels_reached: HashSet<i8>,
+    pub dmg_dealt: u32,
+    pub dmg_taken: u32,
+}
+
+impl GameStats {
+    pub fn new() -> GameStats {
+        GameStats::default()
+    }
+
+    pub fn record_kill(&mut self, monster: &str) {
+        *self.kills.entry(monster.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn total_kills(&self) -> u32 {
+        self.kills.values().sum()
+    }
+
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        let s = format!("Damage dealt: {}    Damage taken: {}", self.dmg_dealt, self.dmg_taken);
+        lines.push(s);
+        let s = format!("Items found: {}    Dungeon levels explored: {}", self.items_found, 
+            self.levels_reached.iter().filter(|l| **l > 0).count());
+        lines.push(s);
+
+        if self.kills.is_empty() {
+            lines.push("You have not slain any monsters.".to_string());
+        } else {
+            let s = format!("Monsters slain: {}", self.total_kills());
+            lines.push(s);
+            for (name, count) in self.kills.iter() {
+                let s = format!("  {} x {}", name, count);
+                lines.push(s);
+            }
+        }
+
+        lines
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Score {
+    pub name: String,
+    pub points: u32,
+    pub depth: u8,
+    pub gold: u32,
+    pub turns: u32,
+    pub kills: u32,
+    pub fate: String,
+}
+
+impl Score {
+    pub fn new(name: &str, depth: u8, gold: u32, turns: u32, kills: u32, fate: &str) -> Score {
+        // Diving deep is worth the most, but a little credit for simply surviving a while
+        let points = depth as u32 * 250 + gold + kills * 10 + turns / 100;
+        
+        Score { name: name.to_string(), points, depth, gold, turns, kills, fate: fate.to_string() }
+    }
+}
+
+fn read_scores() -> Vec<Score> {
+    match fs::read_to_string(HIGH_SCORES_FILE) {
+        Ok(blob) => serde_yaml::from_str(&blob).unwrap_or_default(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn write_scores(scores: &[Score]) {
+    let serialized = serde_yaml::to_string(scores).unwrap();
+    match File::create(HIGH_SCORES_FILE) {
+        Ok(mut buffer) => {
+            if buffer.write_all(serialized.as_bytes()).is_err() {
+                eprintln!("Unable to write the high scores file.");
+            }
+        },
+        Err(_) => eprintln!("Unable to create the high scores file."),
+    }
+}
+
+// Returns the new score's rank, or None if it didn't make the table
+pub fn record_score(scores: &mut Vec<Score>, score: Score) -> Option<usize> {
+    let rank = scores.iter()
+                     .position(|s| score.points > s.points)
+                     .unwrap_or(scores.len());
+    scores.insert(rank, score);
+    scores.truncate(MAX_SCORES);
+
+    if rank < MAX_SCORES {
+        Some(rank)
+    } else {
+        None
+    }
+}
+
+pub fn show_high_scores(gui: &mut dyn UserInterface, score: Score) {
+    let mut scores = read_scores();
+    let rank = record_score(&mut scores, score);
+    write_scores(&scores);
+
+    let mut lines = vec!["High scores".to_string(), "".to_string()];
+    for (j, s) in scores.iter().enumerate() {
+        // mark the game that just ended
+        let marker = if rank == Some(j) { '*' } else { ' ' };
+        let line = format!("{}{:>3}. {:>7}  {}, {} having reached level {} after {} turns.", marker, j + 1, s.points, s.name, s.fate, s.depth, s.turns);
+        lines.push(line);
+    }
+
+    let lines: Vec<&str> = lines.iter().map(AsRef::as_ref).collect();
+    gui.write_long_msg(&lines, true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(points: u32) -> Score {
+        Score { name: "Tester".to_string(), points, depth: 1, gold: 0, turns: 0, kills: 0, fate: "died".to_string() }
+    }
+
+    #[test]
+    fn scores_are_ranked_highest_first() {
+        let mut scores = Vec::new();
+        assert_eq!(record_score(&mut scores, score(100)), Some(0));
+        assert_eq!(record_score(&mut scores, score(300)), Some(0));
+        assert_eq!(record_score(&mut scores, score(200)), Some(1));
+        // A tie doesn't bump the earlier game down the table
+        assert_eq!(record_score(&mut scores, score(200)), Some(2));
+
+        let points: Vec<u32> = scores.iter().map(|s| s.points).collect();
+        assert_eq!(points, vec![300, 200, 200, 100]);
+    }
+
+    #[test]
+    fn low_scores_fall_off_a_full_table() {
+        let mut scores: Vec<Score> = (0..MAX_SCORES as u32).map(|j| score(1000 - j)).collect();
+        assert_eq!(record_score(&mut scores, score(1)), None);
+        assert_eq!(record_score(&mut scores, score(5000)), Some(0));
+        assert_eq!(scores.len(), MAX_SCORES);
+    }
+
+    #[test]
+    fn stats_tally_kills_and_skip_the_surface() {
+        let mut stats = GameStats::new();
+        assert_eq!(stats.summary()[2], "You have not slain any monsters.");
+
+        stats.record_kill("kobold");
+        stats.record_kill("zombie");
+        stats.record_kill("kobold");
+        stats.levels_reached.insert(0);
+        stats.levels_reached.insert(1);
+        stats.items_found = 3;
+
+        assert_eq!(stats.total_kills(), 3);
+        let summary = stats.summary();
+        assert_eq!(summary[1], "Items found: 3    Dungeon levels explored: 1");
+        assert_eq!(summary[2], "Monsters slain: 3");
+        assert_eq!(summary[3], "  kobold x 2");
+        assert_eq!(summary[4], "  zombie x 1");
+    }
+}
@@ -17,14 +17,16 @@ use std::collections::{HashMap, HashSet};
 
 use rand::Rng;
 
-use super::{GameState, Message, Status};
+use super::{GameState, Message, Status, TURNS_PER_HOUR};
+use crate::crafting;
 use crate::effects;
-use crate::npc::Attitude;
+use crate::npc::{AgendaItem, Attitude, NPC};
 use crate::game_obj::{GameObject, Person};
 use crate::dialogue::DialogueLibrary;
-use crate::display::GameUI;
+use crate::display::UserInterface;
 use crate::game_obj::{Ability, GameObjectDB, GameObjects};
 use crate::items::{Item, ItemType};
+use crate::player;
 use crate::util::StringUtils;
 
 // This is similar to but not quite the same as the player inventory tool
@@ -62,6 +64,105 @@ fn inventory_menu(inventory: &Vec<GameObjects>) -> Vec<(String, char, u8, u16)>
     items
 }
 
+// A charming player can talk the shopkeeper down a bit (and an abrasive one will pay 
+// extra). Each point of Charisma modifier is worth 5% either way.
+fn barter_price(value: u16, chr: u8) -> u16 {
+    let pct = 100 - 5 * player::stat_to_mod(chr) as i32;
+    let price = (value as i32 * pct + 50) / 100;
+
+    price.max(1) as u16
+}
+
+// Shopkeepers buy at half of what they'd sell for, and Charisma sways that the other way
+fn sell_price(value: u16, chr: u8) -> u16 {
+    let pct = 50 + 5 * player::stat_to_mod(chr) as i32;
+    let price = (value as i32 * pct + 50) / 100;
+
+    price.max(1) as u16
+}
+
+fn grocer_buys(item: &Item) -> bool {
+    matches!(item.item_type, ItemType::Food | ItemType::Light | ItemType::Bottle | ItemType::Bandage)
+}
+
+fn smith_buys(item: &Item) -> bool {
+    matches!(item.item_type, ItemType::Weapon | ItemType::Armour | ItemType::Shield | ItemType::Ammunition | ItemType::Material)
+}
+
+fn merchant_buys(item: &Item) -> bool {
+    matches!(item.item_type, ItemType::Potion | ItemType::Scroll | ItemType::Wand | ItemType::Cloak | ItemType::Spellbook)
+}
+
+// Returns true if the player sold anything
+fn sell_to_vendor(state: &mut GameState, vendor_id: usize, name: &str, buys: fn(&Item) -> bool, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) -> bool {
+    let mut made_sale = false;
+    loop {
+        let sbi = state.curr_sidebar_info(game_obj_db);
+        let player = game_obj_db.player().unwrap();
+        let chr = player.chr;
+
+        // Readied gear isn't for sale; the player has to take it off first
+        let mut offers: Vec<(char, String, u16)> = Vec::new();
+        for obj in player.inventory.iter() {
+            if let GameObjects::Item(item) = obj {
+                if item.equiped || item.value == 0 || !buys(item) || offers.iter().any(|o| o.0 == item.slot) {
+                    continue;
+                }
+                offers.push((item.slot, item.get_fullname(), sell_price(item.value, chr)));
+            }
+        }
+        offers.sort_by_key(|o| o.0);
+
+        if offers.is_empty() {
+            gui.popup_msg(name, "\"You've nothing I'd be interested in.\"", Some(&sbi));
+            break;
+        }
+
+        let mut menu = String::from("\"I'd give you...\"\n");
+        for offer in offers.iter() {
+            let s = format!("\n{}) {} for {}$", offer.0, offer.1.with_indef_article(), offer.2);
+            menu.push_str(&s);
+        }
+        let options: HashSet<char> = offers.iter().map(|o| o.0).collect();
+
+        match gui.popup_menu(name, &menu, &options, Some(&sbi)) {
+            Some(ch) => {
+                let price = offers.iter().find(|o| o.0 == ch).unwrap().2;
+                let player = game_obj_db.player().unwrap();
+                if let Ok(mut sold) = player.inv_remove_from_slot(ch, 1) {
+                    player.purse += price as u32;
+                    if let Some(GameObjects::NPC(npc)) = game_obj_db.get_mut(vendor_id) {
+                        npc.inventory.append(&mut sold);
+                    }
+                    made_sale = true;
+                }
+            },
+            None => break,
+        }
+    }
+
+    made_sale
+}
+
+// Shopkeepers will only trade if they're scheduled to be working and they're actually
+// behind their counter (and not, say, still walking back from lunch)
+fn open_for_business(state: &GameState, npc: &NPC, shop: &HashSet<(i32, i32, i8)>) -> bool {
+    match npc.curr_agenda_item(state) {
+        Some(agenda) => agenda.label == "working" && shop.contains(&npc.get_loc()),
+        None => false,
+    }
+}
+
+fn business_hours(npc: &NPC) -> String {
+    let working: Vec<&AgendaItem> = npc.schedule.iter().filter(|i| i.label == "working").collect();
+    let open = working.iter().map(|i| i.from).min().unwrap_or((0, 0));
+    let close = working.iter().map(|i| i.to).max().unwrap_or((0, 0));
+    // Schedules run to the last minute of the hour (ie., 18:59) so round up for the sign on the door
+    let close = (close.0 * 60 + close.1 + 1) % 1440;
+
+    format!("\"Come back during business hours, {}:{:02} to {}:{:02}.\"", open.0, open.1, close / 60, close % 60)
+}
+
 // Is it worth preventing the character from renting a room if it's early in the day?
 // Check in isn't until 3:00pm?
 fn rent_room(state: &mut GameState, game_obj_db: &mut GameObjectDB) {
@@ -73,7 +174,7 @@ fn rent_room(state: &mut GameState, game_obj_db: &mut GameObjectDB) {
 
         player.purse -= 10;
         
-        let checkout = state.turn + 2880; // renting a room is basically passing for 8 hours
+        let checkout = state.turn + 8 * TURNS_PER_HOUR; // renting a room is basically passing for 8 hours
         effects::add_status(player, Status::RestAtInn, checkout);
 
         state.msg_queue.push_back(Message::info("You check in."));
@@ -88,6 +189,7 @@ fn buy_drink(state: &mut GameState, game_obj_db: &mut GameObjectDB) {
             state.msg_queue.push_back(Message::info("\"Hey this isn't a charity!\""));
         } else {
             p.purse -= 1;
+            p.quench(300);
             // more drink types eventually?
             state.msg_queue.push_back(Message::info("You drink a refreshing ale."));            
         }
@@ -109,7 +211,7 @@ fn fill_flask(state: &mut GameState, game_obj_db: &mut GameObjectDB) {
                 } else {
                     item.charges = 2;
                     player.purse -= 2;
-                    let s = if rand::thread_rng().gen_range(0, 2) == 0 {
+                    let s = if state.rng.gen_range(0, 2) == 0 {
                         "\"There you are! Enjoy!\""
                     } else {
                         "\"Please drink and adventure responsibly!\""
@@ -144,7 +246,7 @@ fn buy_round(state: &mut GameState, game_obj_db: &mut GameObjectDB, patrons: &Ve
         
     for npc_id in patrons {
         let p = game_obj_db.player().unwrap();
-        let persuasion = p.ability_check(Ability::Chr);
+        let persuasion = p.ability_check(Ability::Chr, &mut state.rng);
 
         if persuasion >= 13 {
             let patron = game_obj_db.get_mut(*npc_id).unwrap();
@@ -175,8 +277,77 @@ fn inn_patrons(state: &GameState, game_obj_db: &mut GameObjectDB, innkeeper_id:
     patrons
 }
 
+// The innkeeper will hang on to items for the player between delves
+fn stash_item(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) {
+    let player = game_obj_db.player().unwrap();
+    if player.inventory.is_empty() {
+        state.msg_queue.push_back(Message::info("You have nothing to leave with the innkeeper."));
+        return;
+    }
+
+    let menu = player.inv_menu(0);
+    if let Some(ch) = gui.show_in_side_pane("Leave which item?", &menu) {
+        let count = player.inv_count_in_slot(ch) as u32;
+        if count == 0 {
+            state.msg_queue.push_back(Message::info("You do not have that item."));
+            return;
+        }
+
+        match player.inv_remove_from_slot(ch, count) {
+            Ok(items) => {
+                for mut obj in items {
+                    if let GameObjects::Item(item) = &mut obj {
+                        item.equiped = false;
+                    }
+                    state.world_info.stash.push(obj);
+                }
+                player.calc_gear_effects();
+                state.msg_queue.push_back(Message::info("\"I'll keep it safe in the back room for you.\""));
+            },
+            Err(msg) => state.msg_queue.push_back(Message::info(&msg)),
+        }
+    } else {
+        state.msg_queue.push_back(Message::info("Never mind."));
+    }
+}
+
+fn retrieve_item(state: &mut GameState, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface, name: &str) {
+    let sbi = state.curr_sidebar_info(game_obj_db);
+    let menu_items = inventory_menu(&state.world_info.stash);
+    let options: HashSet<char> = menu_items.iter().map(|i| i.1).collect();
+
+    let mut menu = "Which item would you like back?\n".to_string();
+    for item in &menu_items {
+        menu.push('\n');
+        let mut s = format!("{}) {}", item.1, item.0);
+        if item.2 > 1 {
+            s.push_str(&format!(" ({})", item.2));
+        }
+        menu.push_str(&s);
+    }
+
+    if let Some(answer) = gui.popup_menu(name, &menu, &options, Some(&sbi)) {
+        if let Some(item) = menu_items.iter().find(|i| i.1 == answer) {
+            // Stacks are returned all at once
+            let p = game_obj_db.player().unwrap();
+            let mut j = 0;
+            while j < state.world_info.stash.len() {
+                if state.world_info.stash[j].get_fullname() == item.0 {
+                    let obj = state.world_info.stash.remove(j);
+                    p.add_to_inv(obj);
+                } else {
+                    j += 1;
+                }
+            }
+            state.msg_queue.push_back(Message::info("\"Here you are.\""));
+        }
+    } else {
+        state.msg_queue.push_back(Message::info("Never mind."));
+    }
+}
+
 pub fn talk_to_innkeeper(state: &mut GameState, innkeeper_id: usize, game_obj_db: &mut GameObjectDB, 
-        dialogue: &DialogueLibrary, gui: &mut GameUI) {
+        dialogue: &DialogueLibrary, gui: &mut dyn UserInterface) {
     let sbi = state.curr_sidebar_info(game_obj_db);
     let patrons = inn_patrons(state, game_obj_db, innkeeper_id);
     let npc = game_obj_db.get_mut(innkeeper_id).unwrap();
@@ -193,10 +364,16 @@ pub fn talk_to_innkeeper(state: &mut GameState, innkeeper_id: usize, game_obj_db
     msg.push_str("b) rent a room (10$)\n");
     msg.push_str("c) fill a wineskin (2$)\n");
     if !patrons.is_empty() {
-        let s = format!("d) buy a round for the bar ({}$)", patrons.len());
+        let s = format!("d) buy a round for the bar ({}$)\n", patrons.len());
         msg.push_str(&s);
         options.insert('d');
     }
+    msg.push_str("e) leave something in storage\n");
+    options.insert('e');
+    if !state.world_info.stash.is_empty() {
+        msg.push_str("f) collect something from storage\n");
+        options.insert('f');
+    }
 
     let name = format!("{}, the innkeeper", npc.get_fullname().capitalize());
     
@@ -210,9 +387,13 @@ pub fn talk_to_innkeeper(state: &mut GameState, innkeeper_id: usize, game_obj_db
             fill_flask(state, game_obj_db);
         } else if ch == 'd' {
             buy_round(state, game_obj_db, &patrons);
+        } else if ch == 'e' {
+            stash_item(state, game_obj_db, gui);
+        } else if ch == 'f' {
+            retrieve_item(state, game_obj_db, gui, &name);
         }
     } else {
-        let x = rand::thread_rng().gen_range(0, 3);
+        let x = state.rng.gen_range(0, 3);
         let s = if x == 0 {
             "\"Nevermind.\""
         } else if x == 1 {
@@ -225,10 +406,10 @@ pub fn talk_to_innkeeper(state: &mut GameState, innkeeper_id: usize, game_obj_db
 }
 
 fn check_smith_inventory(state: &mut GameState, smith_id: usize, game_obj_db: &mut GameObjectDB, ) {
-    let curr_day = state.turn / 8640;
+    let curr_day = state.curr_day();
     let smith = game_obj_db.get_mut(smith_id).unwrap();
     let (first_inventory, last_inventory_day) = if let GameObjects::NPC(npc) = smith {
-        (npc.attitude == Attitude::Stranger, npc.last_inventory / 8640)
+        (npc.attitude == Attitude::Stranger, state.day_of(npc.last_inventory))
     } else {
         (true, 0)
     };
@@ -236,34 +417,49 @@ fn check_smith_inventory(state: &mut GameState, smith_id: usize, game_obj_db: &m
     let mut objs = Vec::new();
     if first_inventory {
         // The initial inventory when the player first meets the shopkeeper        
-        let ls = Item::get_item(game_obj_db, "longsword").unwrap();
+        let ls = Item::get_item(game_obj_db, "longsword", &mut state.rng).unwrap();
         objs.push(ls);
         
-        for _ in 0..rand::thread_rng().gen_range(1, 4) {
-            let d = Item::get_item(game_obj_db, "dagger").unwrap();
+        for _ in 0..state.rng.gen_range(1, 4) {
+            let d = Item::get_item(game_obj_db, "dagger", &mut state.rng).unwrap();
             objs.push(d);
         }
 
-        if rand::thread_rng().gen_range(0, 3) == 0 {
-            let ts = Item::get_item(game_obj_db, "two-handed sword").unwrap();
+        if state.rng.gen_range(0, 3) == 0 {
+            let ts = Item::get_item(game_obj_db, "two-handed sword", &mut state.rng).unwrap();
             objs.push(ts);
         }
         
-        if rand::thread_rng().gen_range(0, 2) == 0 {
-            let s = Item::get_item(game_obj_db, "spear").unwrap();
+        if state.rng.gen_range(0, 2) == 0 {
+            let s = Item::get_item(game_obj_db, "spear", &mut state.rng).unwrap();
             objs.push(s);
         }
 
-        if rand::thread_rng().gen_range(0, 2) == 0 {
-            let ch = Item::get_item(game_obj_db, "chainmail").unwrap();
+        if state.rng.gen_range(0, 2) == 0 {
+            let ch = Item::get_item(game_obj_db, "chainmail", &mut state.rng).unwrap();
             objs.push(ch);
         }
 
-        if rand::thread_rng().gen_range(0, 2) == 0 {
-            let sh = Item::get_item(game_obj_db, "shield").unwrap();
+        if state.rng.gen_range(0, 2) == 0 {
+            let sh = Item::get_item(game_obj_db, "shield", &mut state.rng).unwrap();
+            objs.push(sh);
+        }
+
+        if state.rng.gen_range(0, 3) == 0 {
+            let sh = Item::get_item(game_obj_db, "buckler", &mut state.rng).unwrap();
+            objs.push(sh);
+        }
+
+        if state.rng.gen_range(0, 4) == 0 {
+            let sh = Item::get_item(game_obj_db, "tower shield", &mut state.rng).unwrap();
             objs.push(sh);
         }
 
+        if state.rng.gen_range(0, 5) == 0 {
+            let pa = Item::get_item(game_obj_db, "plate armour", &mut state.rng).unwrap();
+            objs.push(pa);
+        }
+
         let smith = game_obj_db.get_mut(smith_id).unwrap();
 
         if let GameObjects::NPC(npc) = smith {
@@ -276,43 +472,58 @@ fn check_smith_inventory(state: &mut GameState, smith_id: usize, game_obj_db: &m
         // First, generate the new stock
         let mut new_stock = Vec::new();
 
-        if rand::thread_rng().gen_range(0, 2) == 0 {
-            let ls = Item::get_item(game_obj_db, "longsword").unwrap();
+        if state.rng.gen_range(0, 2) == 0 {
+            let ls = Item::get_item(game_obj_db, "longsword", &mut state.rng).unwrap();
             new_stock.push(ls);
         }
 
-        if rand::thread_rng().gen_range(0, 2) == 0 {
-            let d = Item::get_item(game_obj_db, "dagger").unwrap();
+        if state.rng.gen_range(0, 2) == 0 {
+            let d = Item::get_item(game_obj_db, "dagger", &mut state.rng).unwrap();
             new_stock.push(d);
         }
 
-        if rand::thread_rng().gen_range(0, 3) == 0 {
-            let ts = Item::get_item(game_obj_db, "two-handed sword").unwrap();
+        if state.rng.gen_range(0, 3) == 0 {
+            let ts = Item::get_item(game_obj_db, "two-handed sword", &mut state.rng).unwrap();
             new_stock.push(ts);
         }
         
-        if rand::thread_rng().gen_range(0, 2) == 0 {
-            let s = Item::get_item(game_obj_db, "spear").unwrap();
+        if state.rng.gen_range(0, 2) == 0 {
+            let s = Item::get_item(game_obj_db, "spear", &mut state.rng).unwrap();
             new_stock.push(s);
         }
 
-        if rand::thread_rng().gen_range(0, 2) == 0 {
-            let ch = Item::get_item(game_obj_db, "chainmail").unwrap();
+        if state.rng.gen_range(0, 2) == 0 {
+            let ch = Item::get_item(game_obj_db, "chainmail", &mut state.rng).unwrap();
             new_stock.push(ch);
         }
 
-        if rand::thread_rng().gen_range(0, 2) == 0 {
-            let sh = Item::get_item(game_obj_db, "shield").unwrap();
+        if state.rng.gen_range(0, 2) == 0 {
+            let sh = Item::get_item(game_obj_db, "shield", &mut state.rng).unwrap();
+            new_stock.push(sh);
+        }
+
+        if state.rng.gen_range(0, 3) == 0 {
+            let sh = Item::get_item(game_obj_db, "buckler", &mut state.rng).unwrap();
+            new_stock.push(sh);
+        }
+
+        if state.rng.gen_range(0, 4) == 0 {
+            let sh = Item::get_item(game_obj_db, "tower shield", &mut state.rng).unwrap();
             new_stock.push(sh);
         }
 
+        if state.rng.gen_range(0, 5) == 0 {
+            let pa = Item::get_item(game_obj_db, "plate armour", &mut state.rng).unwrap();
+            new_stock.push(pa);
+        }
+
         // For any item in their current inventory, there's a 25% chance it's been purchases while the 
         // player's been away
         let smith = game_obj_db.get_mut(smith_id).unwrap();
         if let GameObjects::NPC(npc) = smith {        
             let mut to_remove = Vec::new();
             for j in 0..npc.inventory.len() {
-                if rand::thread_rng().gen_range(0.0, 1.0) <= 0.2 {
+                if state.rng.gen_range(0.0, 1.0) <= 0.2 {
                     to_remove.push(j);
                 }
             }
@@ -331,10 +542,10 @@ fn check_smith_inventory(state: &mut GameState, smith_id: usize, game_obj_db: &m
 }
 
 fn check_grocer_inventory(state: &mut GameState, grocer_id: usize, game_obj_db: &mut GameObjectDB, ) {
-    let curr_day = state.turn / 8640;
+    let curr_day = state.curr_day();
     let grocer = game_obj_db.get_mut(grocer_id).unwrap();
     let (first_inventory, last_inventory_day) = if let GameObjects::NPC(npc) = grocer {
-        (npc.attitude == Attitude::Stranger, npc.last_inventory / 8640)
+        (npc.attitude == Attitude::Stranger, state.day_of(npc.last_inventory))
     } else {
         (true, 0)
     };
@@ -342,18 +553,44 @@ fn check_grocer_inventory(state: &mut GameState, grocer_id: usize, game_obj_db:
     let mut objs = Vec::new();
     if first_inventory {
         // The initial inventory when the player first meets the shopkeeper
-        for _ in 0..rand::thread_rng().gen_range(3, 6) {
-            let t = Item::get_item(game_obj_db, "torch").unwrap();
+        for _ in 0..state.rng.gen_range(3, 6) {
+            let t = Item::get_item(game_obj_db, "torch", &mut state.rng).unwrap();
             objs.push(t);
         }
-        for _ in 0..rand::thread_rng().gen_range(1, 3) {
-            let w = Item::get_item(game_obj_db, "wineskin").unwrap();
+        for _ in 0..state.rng.gen_range(1, 3) {
+            let w = Item::get_item(game_obj_db, "wineskin", &mut state.rng).unwrap();
+            objs.push(w);
+        }
+        for _ in 0..state.rng.gen_range(1, 3) {
+            let w = Item::get_item(game_obj_db, "waterskin", &mut state.rng).unwrap();
             objs.push(w);
         }
-        for _ in 0..rand::thread_rng().gen_range(1, 4) {
-            let p = Item::get_item(game_obj_db, "potion of healing").unwrap();
+        let c = Item::get_item(game_obj_db, "warm cloak", &mut state.rng).unwrap();
+        objs.push(c);
+        for _ in 0..state.rng.gen_range(1, 4) {
+            let p = Item::get_item(game_obj_db, "potion of healing", &mut state.rng).unwrap();
             objs.push(p);
         }
+        for _ in 0..state.rng.gen_range(2, 6) {
+            let r = Item::get_item(game_obj_db, "ration", &mut state.rng).unwrap();
+            objs.push(r);
+        }
+        for _ in 0..state.rng.gen_range(0, 4) {
+            let a = Item::get_item(game_obj_db, "apple", &mut state.rng).unwrap();
+            objs.push(a);
+        }
+        for _ in 0..state.rng.gen_range(0, 3) {
+            let c = Item::get_item(game_obj_db, "caltrops", &mut state.rng).unwrap();
+            objs.push(c);
+        }
+        if state.rng.gen_range(0.0, 1.0) < 0.5 {
+            let b = Item::get_item(game_obj_db, "bear trap", &mut state.rng).unwrap();
+            objs.push(b);
+        }
+        for _ in 0..state.rng.gen_range(1, 4) {
+            let b = Item::get_item(game_obj_db, "bandage", &mut state.rng).unwrap();
+            objs.push(b);
+        }
         let grocer = game_obj_db.get_mut(grocer_id).unwrap();
         if let GameObjects::NPC(npc) = grocer {
             npc.inventory = objs;
@@ -364,18 +601,26 @@ fn check_grocer_inventory(state: &mut GameState, grocer_id: usize, game_obj_db:
         
         // Generate some new stock for the shopkeeper
         let mut new_stock = Vec::new();
-        for _ in 0..rand::thread_rng().gen_range(0, 4) {
-            let t = Item::get_item(game_obj_db, "torch").unwrap();
+        for _ in 0..state.rng.gen_range(0, 4) {
+            let t = Item::get_item(game_obj_db, "torch", &mut state.rng).unwrap();
             new_stock.push(t);
         }
-        for _ in 0..rand::thread_rng().gen_range(0, 2) {
-            let w = Item::get_item(game_obj_db, "wineskin").unwrap();
+        for _ in 0..state.rng.gen_range(0, 2) {
+            let w = Item::get_item(game_obj_db, "wineskin", &mut state.rng).unwrap();
             new_stock.push(w);
         }
-        for _ in 0..rand::thread_rng().gen_range(1, 4) {
-            let p = Item::get_item(game_obj_db, "potion of healing").unwrap();
+        for _ in 0..state.rng.gen_range(1, 4) {
+            let p = Item::get_item(game_obj_db, "potion of healing", &mut state.rng).unwrap();
             new_stock.push(p);
         }
+        for _ in 0..state.rng.gen_range(1, 4) {
+            let r = Item::get_item(game_obj_db, "ration", &mut state.rng).unwrap();
+            new_stock.push(r);
+        }
+        for _ in 0..state.rng.gen_range(0, 4) {
+            let a = Item::get_item(game_obj_db, "apple", &mut state.rng).unwrap();
+            new_stock.push(a);
+        }
         
         // For any item in their current inventory, there's a 25% chance it's been purchases while the 
         // player's been away
@@ -383,7 +628,7 @@ fn check_grocer_inventory(state: &mut GameState, grocer_id: usize, game_obj_db:
         if let GameObjects::NPC(npc) = grocer {
             let mut to_remove = Vec::new();
             for j in 0..npc.inventory.len() {
-                if rand::thread_rng().gen_range(0.0, 1.0) <= 0.2 {
+                if state.rng.gen_range(0.0, 1.0) <= 0.2 {
                     to_remove.push(j);
                 }
             }
@@ -415,7 +660,7 @@ fn get_item_from_invetory(npc_id: usize, name: &str, game_obj_db: &mut GameObjec
     None
 }
 
-pub fn talk_to_grocer(state: &mut GameState, grocer_id: usize, game_obj_db: &mut GameObjectDB, dialogue: &DialogueLibrary, gui: &mut GameUI) {
+pub fn talk_to_grocer(state: &mut GameState, grocer_id: usize, game_obj_db: &mut GameObjectDB, dialogue: &DialogueLibrary, gui: &mut dyn UserInterface) {
     let sbi = state.curr_sidebar_info(game_obj_db);
     check_grocer_inventory(state, grocer_id, game_obj_db);
     let grocer = game_obj_db.get_mut(grocer_id).unwrap();
@@ -426,73 +671,29 @@ pub fn talk_to_grocer(state: &mut GameState, grocer_id: usize, game_obj_db: &mut
         extra_info.insert("#goods#".to_string(), "adventuring supply".to_string());
         msg = npc.talk_to(state, dialogue, &mut extra_info);
 
-        if let Some(agenda) = npc.curr_agenda_item(state) {
-            if agenda.label != "working" {
-                let name = format!("{}, the grocer", npc.npc_name(true).capitalize());
-                gui.popup_msg(&name, &msg, Some(&sbi));
-                return;
-            }
+        let market = &state.world_info.town_buildings.as_ref().unwrap().market;
+        if !open_for_business(state, npc, market) {
+            let name = format!("{}, the grocer", npc.npc_name(true).capitalize());
+            let closed = format!("{}\n\n{}", msg, business_hours(npc));
+            gui.popup_msg(&name, &closed, Some(&sbi));
+            return;
         }
     }
     
+    let name = format!("{}, the grocer", game_obj_db.get(grocer_id).unwrap().get_fullname().capitalize());
+    let mut menu = msg.clone();
+    menu.push_str("\n\na) see my wares\nb) sell some supplies\n");
+    let options: HashSet<char> = vec!['a', 'b'].into_iter().collect();
+    let sbi = state.curr_sidebar_info(game_obj_db);
     let mut made_purchase = false;
-    loop {
-        let sbi = state.curr_sidebar_info(game_obj_db);
-        let grocer = game_obj_db.get_mut(grocer_id).unwrap();
-        let menu_items = if let GameObjects::NPC(npc) = grocer {
-            inventory_menu(&npc.inventory)
-        } else {
-            Vec::new()
-        };
-        
-        let options: HashSet<char> = menu_items.iter().map(|i| i.1).collect();
-        if menu_items.is_empty() {
-            msg.push_str("\n\nI seem to be all out of stock!");
-
-            let name = format!("{}, the grocer", grocer.get_fullname().capitalize());
-            gui.popup_msg(&name, &msg, Some(&sbi));
-            break;
-        }
-            
-        let mut store_menu = msg.clone();
-        store_menu.push_str("\n\nWhat would you like:\n");        
-        for item in &menu_items {
-            store_menu.push('\n');
-            let mut s = format!("{}) {}", item.1, item.0);
-            if item.2 > 1 {
-                s.push_str(" (");
-                s.push_str(&item.2.to_string());
-                s.push_str("), ");
-                s.push_str(&item.3.to_string());
-                s.push_str("$ each")
+    match gui.popup_menu(&name, &menu, &options, Some(&sbi)) {
+        Some('a') => made_purchase = purchase_from_vendor(state, grocer_id, name, &msg, game_obj_db, gui),
+        Some(_) => {
+            if sell_to_vendor(state, grocer_id, &name, grocer_buys, game_obj_db, gui) {
+                state.msg_queue.push_back(Message::info("\"Pleasure doing business.\""));
             }
-            else {
-                s.push_str(", ");
-                s.push_str(&item.3.to_string());
-                s.push_str("$");
-            }
-            store_menu.push_str(&s);            
-        }
-        
-        let name = format!("{}, the grocer", grocer.get_fullname().capitalize());
-        if let Some(answer) = gui.popup_menu(&name, &store_menu, &options, Some(&sbi)) {
-            for item in &menu_items {
-                if item.1 == answer {                    
-                    let p = game_obj_db.player().unwrap();
-                    if p.purse < item.3 as u32 {
-                        gui.popup_msg(&name, "Hey! You can't afford that!", Some(&sbi));
-                    } else {
-                        let obj = get_item_from_invetory(grocer_id, &item.0, game_obj_db).unwrap();
-                        let p = game_obj_db.player().unwrap();
-                        p.purse -= item.3 as u32;
-                        p.add_to_inv(obj);
-                        made_purchase = true;
-                    }
-                }
-            }          
-        } else {
-            break;
-        }                
+        },
+        None => state.msg_queue.push_back(Message::info("Never mind.")),
     }
 
     if made_purchase {
@@ -501,18 +702,21 @@ pub fn talk_to_grocer(state: &mut GameState, grocer_id: usize, game_obj_db: &mut
 }
 
 // For when I implement rust/corrosion
-fn repair_gear(state: &mut GameState, _game_obj_db: &mut GameObjectDB, _gui: &mut GameUI) {
+fn repair_gear(state: &mut GameState, _game_obj_db: &mut GameObjectDB, _gui: &mut dyn UserInterface) {
     state.msg_queue.push_back(Message::info("\"Hmm none of your equipment needs fixing right now.\""));      
 }
 
-fn purchase_from_smith(state: &mut GameState, smith_id: usize, name: String, preamble: &str, game_obj_db: &mut GameObjectDB, gui: &mut GameUI) -> bool {
+fn purchase_from_vendor(state: &mut GameState, vendor_id: usize, name: String, preamble: &str, game_obj_db: &mut GameObjectDB, gui: &mut dyn UserInterface) -> bool {
     let mut msg = preamble.to_string();
     let mut made_purchase = false;
     loop {
         let sbi = state.curr_sidebar_info(game_obj_db);
-        let smith = game_obj_db.get_mut(smith_id).unwrap();
-        let menu_items = if let GameObjects::NPC(npc) = smith {
-            inventory_menu(&npc.inventory)
+        let chr = game_obj_db.player().unwrap().chr;
+        let vendor = game_obj_db.get_mut(vendor_id).unwrap();
+        let menu_items: Vec<(String, char, u8, u16)> = if let GameObjects::NPC(npc) = vendor {
+            inventory_menu(&npc.inventory).into_iter()
+                .map(|(name, slot, count, value)| (name, slot, count, barter_price(value, chr)))
+                .collect()
         } else {
             Vec::new()
         };
@@ -520,7 +724,6 @@ fn purchase_from_smith(state: &mut GameState, smith_id: usize, name: String, pre
         if menu_items.is_empty() {
             msg.push_str("\n\nI seem to be all out of stock!");
 
-            let name = format!("{}, the smith", name);
             gui.popup_msg(&name, &msg, Some(&sbi));
             break;
         } 
@@ -552,7 +755,7 @@ fn purchase_from_smith(state: &mut GameState, smith_id: usize, name: String, pre
                     if p.purse < item.3 as u32 {
                         gui.popup_msg(&name, "Hey! You can't afford that!", Some(&sbi));
                     } else {
-                        let obj = get_item_from_invetory(smith_id, &item.0, game_obj_db).unwrap();
+                        let obj = get_item_from_invetory(vendor_id, &item.0, game_obj_db).unwrap();
                         let p = game_obj_db.player().unwrap();
                         p.purse -= item.3 as u32;
                         p.add_to_inv(obj);
@@ -568,7 +771,8 @@ fn purchase_from_smith(state: &mut GameState, smith_id: usize, name: String, pre
     made_purchase
 }
 
-pub fn talk_to_smith(state: &mut GameState, smith_id: usize, game_obj_db: &mut GameObjectDB, dialogue: &DialogueLibrary, gui: &mut GameUI) {
+// Returns how long the visit took, since having the smith work on your gear isn't instantaneous
+pub fn talk_to_smith(state: &mut GameState, smith_id: usize, game_obj_db: &mut GameObjectDB, dialogue: &DialogueLibrary, gui: &mut dyn UserInterface) -> f32 {
     let sbi = state.curr_sidebar_info(game_obj_db);
     check_smith_inventory(state, smith_id, game_obj_db);
     let smith = game_obj_db.get_mut(smith_id).unwrap();
@@ -579,12 +783,11 @@ pub fn talk_to_smith(state: &mut GameState, smith_id: usize, game_obj_db: &mut G
         let mut extra_info = HashMap::new();
         msg = npc.talk_to(state, dialogue, &mut extra_info);
 
-        if let Some(agenda) = npc.curr_agenda_item(state) {
-            if agenda.label != "working" {            
-                let name = format!("{}, the smith", name);
-                gui.popup_msg(&name, &msg, Some(&sbi));
-                return;
-            }
+        let smithy = &state.world_info.town_buildings.as_ref().unwrap().smithy;
+        if !open_for_business(state, npc, smithy) {
+            let closed = format!("{}\n\n{}", msg, business_hours(npc));
+            gui.popup_msg(&name, &closed, Some(&sbi));
+            return 1.0;
         }
     }
     let preamble = msg.clone();
@@ -594,16 +797,23 @@ pub fn talk_to_smith(state: &mut GameState, smith_id: usize, game_obj_db: &mut G
     msg.push('\n');
     msg.push_str("a) see my wares\n");
     msg.push_str("b) repair your gear\n");
+    msg.push_str("c) work some ore into your gear\n");
+    msg.push_str("d) sell arms and armour\n");
     
-    let options: HashSet<char> = vec!['a', 'b'].into_iter().collect();
+    let options: HashSet<char> = vec!['a', 'b', 'c', 'd'].into_iter().collect();
     let mut made_purchase = false;
+    let mut energy_used = 1.0;
     let answer = gui.popup_menu(&name, &msg, &options, Some(&sbi));
     if let Some(ch) = answer {
         if ch == 'a' {
-            made_purchase = purchase_from_smith(state, smith_id, name.clone(), &preamble, game_obj_db, gui);
+            made_purchase = purchase_from_vendor(state, smith_id, name.clone(), &preamble, game_obj_db, gui);
         } else if ch == 'b' {
             repair_gear(state, game_obj_db, gui);
-        } 
+        } else if ch == 'c' {
+            energy_used = crafting::forge_work(state, game_obj_db, gui, true).max(1.0);
+        } else if ch == 'd' && sell_to_vendor(state, smith_id, &name, smith_buys, game_obj_db, gui) {
+            state.msg_queue.push_back(Message::info("\"I can always use more metal.\""));
+        }
     } else {
         state.msg_queue.push_back(Message::info("Never mind."));
     }
@@ -611,4 +821,137 @@ pub fn talk_to_smith(state: &mut GameState, smith_id: usize, game_obj_db: &mut G
     if made_purchase {
         state.msg_queue.push_back(Message::info("\"I hope that serves you well!\""));
     }
-}
\ No newline at end of file
+
+    energy_used
+}
+// The merchant is only in town for a day at a time so they arrive with a fresh
+// load of stock each visit
+fn check_merchant_inventory(state: &mut GameState, merchant_id: usize, game_obj_db: &mut GameObjectDB) {
+    let curr_day = state.curr_day();
+    let merchant = game_obj_db.get_mut(merchant_id).unwrap();
+    if let GameObjects::NPC(npc) = merchant {
+        if npc.last_inventory > 0 && state.day_of(npc.last_inventory) == curr_day {
+            return;
+        }
+    }
+
+    let mut stock = Vec::new();
+    let wares = ["potion of levitation", "scroll of protection", "scroll of blink", "scroll of recall", "wand of frost", "two-handed sword",
+                    "cloak of fire resistance"];
+    for ware in wares.iter() {
+        if state.rng.gen_range(0, 3) > 0 {
+            let w = Item::get_item(game_obj_db, ware, &mut state.rng).unwrap();
+            stock.push(w);
+        }
+    }
+    for _ in 0..state.rng.gen_range(0, 3) {
+        let p = Item::get_item(game_obj_db, "potion of healing", &mut state.rng).unwrap();
+        stock.push(p);
+    }
+
+    let merchant = game_obj_db.get_mut(merchant_id).unwrap();
+    if let GameObjects::NPC(npc) = merchant {
+        npc.inventory = stock;
+        npc.last_inventory = state.turn;
+    }
+}
+
+pub fn talk_to_merchant(state: &mut GameState, merchant_id: usize, game_obj_db: &mut GameObjectDB, dialogue: &DialogueLibrary, gui: &mut dyn UserInterface) {
+    let sbi = state.curr_sidebar_info(game_obj_db);
+    check_merchant_inventory(state, merchant_id, game_obj_db);
+    let merchant = game_obj_db.get_mut(merchant_id).unwrap();
+    let mut msg = "".to_string();
+    let mut name = "".to_string();
+    if let GameObjects::NPC(npc) = merchant {
+        name = format!("{}, the merchant", npc.npc_name(true).capitalize());
+        let mut extra_info = HashMap::new();
+        extra_info.insert("#goods#".to_string(), "Curiosities from distant lands".to_string());
+        msg = npc.talk_to(state, dialogue, &mut extra_info);
+
+        if !open_for_business(state, npc, &state.world_info.town_square) {
+            gui.popup_msg(&name, &msg, Some(&sbi));
+            return;
+        }
+    }
+
+    let mut menu = msg.clone();
+    menu.push_str("\n\na) see my wares\nb) sell me your curiosities\n");
+    let options: HashSet<char> = vec!['a', 'b'].into_iter().collect();
+    match gui.popup_menu(&name, &menu, &options, Some(&sbi)) {
+        Some('a') => {
+            if purchase_from_vendor(state, merchant_id, name, &msg, game_obj_db, gui) {
+                state.msg_queue.push_back(Message::info("\"A fine choice! You won't find that around here.\""));
+            }
+        },
+        Some(_) => {
+            if sell_to_vendor(state, merchant_id, &name, merchant_buys, game_obj_db, gui) {
+                state.msg_queue.push_back(Message::info("\"These will fetch a good price back home.\""));
+            }
+        },
+        None => state.msg_queue.push_back(Message::info("Never mind.")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charming_players_pay_less() {
+        assert!(barter_price(100, 18) < barter_price(100, 8));
+        assert_eq!(barter_price(100, 10), 100);
+    }
+
+    #[test]
+    fn charming_players_sell_for_more() {
+        assert!(sell_price(100, 18) > sell_price(100, 8));
+        assert_eq!(sell_price(100, 10), 50);
+    }
+
+    #[test]
+    fn smith_improves_gear_and_the_visit_takes_time() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = crate::tests::test_level(&mut game_obj_db);
+        let mut gui = crate::headless::HeadlessUI::new();
+        let dialogue = crate::dialogue::read_dialogue_lib();
+        let smithy_loc = (5, 6, 1);
+        let mut tb = crate::town::TownBuildings::new();
+        tb.smithy.insert(smithy_loc);
+        state.world_info.town_buildings = Some(tb);
+
+        let mut smith = NPC::villager("Bob".to_string(), smithy_loc, Some(crate::npc::Venue::Smithy), "smith1", &mut game_obj_db, &mut state.rng);
+        if let GameObjects::NPC(npc) = &mut smith {
+            npc.schedule.push(AgendaItem::new((0, 0), (23, 59), 0, crate::npc::Venue::Smithy, "working".to_string()));
+        }
+        let smith_id = smith.obj_id();
+        game_obj_db.add(smith);
+
+        for _ in 0..2 {
+            let ore = Item::get_item(&mut game_obj_db, "lump of iron ore", &mut state.rng).unwrap();
+            game_obj_db.player().unwrap().add_to_inv(ore);
+        }
+        let player = game_obj_db.player().unwrap();
+        player.purse = crafting::SMITH_FEE;
+        let weapon_slot = player.inventory.iter()
+            .find_map(|obj| match obj {
+                GameObjects::Item(item) if item.item_type == ItemType::Weapon => Some(item.slot),
+                _ => None,
+            }).unwrap();
+        gui.queue_keys(&format!("c{}", weapon_slot));
+
+        assert!(talk_to_smith(&mut state, smith_id, &mut game_obj_db, &dialogue, &mut gui) >= 1.0);
+        let player = game_obj_db.player().unwrap();
+        assert_eq!(player.purse, 0);
+        match player.inv_item_in_slot(weapon_slot) {
+            Some(GameObjects::Item(item)) => assert_eq!(item.attack_bonus, 1),
+            _ => panic!("the player's weapon went missing"),
+        }
+    }
+
+    #[test]
+    fn shopkeepers_never_pay_more_than_they_charge() {
+        for chr in 3..=18 {
+            assert!(sell_price(40, chr) < barter_price(40, chr));
+        }
+    }
+}
@@ -23,20 +23,71 @@ use rand::{Rng, prelude::SliceRandom};
 use rand::seq::IteratorRandom;
 use serde::{Serialize, Deserialize};
 
-use super::{EventType, Map};
+use super::{EventType, GameState, Map, Message};
 
 use crate::npc;
-use crate::npc::{AgendaItem, Venue, NPC};
+use crate::npc::{AgendaItem, Attitude, Venue, NPC};
 use crate::dialogue;
 use crate::game_obj::{GameObject, GameObjects, GameObjectDB};
 use crate::map::{DoorState, Tile};
 use crate::pathfinding;
 use crate::util;
+use crate::util::{GameRng, StringUtils};
 use crate::world::WILDERNESS_SIZE;
 use crate::world::WorldInfo;
 
-const TOWN_WIDTH: i32 = 60;
-const TOWN_HEIGHT: i32 = 36;
+const MERCHANT_VISIT_DAYS: i32 = 5;
+const MAX_BRIDGE_LENGTH: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TownSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl TownSize {
+    // (height, width) of the area the town is built in
+    fn dimensions(&self) -> (i32, i32) {
+        match self {
+            TownSize::Small => (30, 48),
+            TownSize::Medium => (36, 60),
+            TownSize::Large => (44, 72),
+        }
+    }
+
+    // How many cottages to try to fit in, not counting the market and smithy
+    fn max_homes(&self) -> usize {
+        match self {
+            TownSize::Small => 4,
+            TownSize::Medium => 6,
+            TownSize::Large => 9,
+        }
+    }
+}
+
+// The choices from the options file about what sort of town to build
+#[derive(Debug, Clone, Copy)]
+pub struct TownOptions {
+    pub size: TownSize,
+    pub walled: bool,
+    pub riverside: bool,
+}
+
+impl TownOptions {
+    pub fn new() -> TownOptions {
+        TownOptions { size: TownSize::Medium, walled: false, riverside: false }
+    }
+}
+
+// Where the town sits in the wilderness and how much room it has
+#[derive(Debug, Clone, Copy)]
+struct TownLayout {
+    r: i32,
+    c: i32,
+    height: i32,
+    width: i32,
+}
 
 #[derive(Debug)]
 enum BuildingType {
@@ -47,7 +98,7 @@ enum BuildingType {
     Smithy,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Template {
     pub sqs: Vec<char>,
     pub width: usize,
@@ -77,7 +128,7 @@ impl TownBuildings {
             market: HashSet::new(), smithy: HashSet::new(), }
     }
 
-    pub fn vacant_home(&self) -> Option<usize> {
+    pub fn vacant_home(&self, rng: &mut GameRng) -> Option<usize> {
         if self.taken_homes.len() == self.homes.len() {
             None
         } else {
@@ -86,8 +137,7 @@ impl TownBuildings {
                 available.remove(*x);
             }
             
-            let mut rng = rand::thread_rng();
-            let n = available.iter().choose(&mut rng).unwrap();
+            let n = available.iter().choose(rng).unwrap();
             Some(*n)
         }
     }
@@ -106,73 +156,72 @@ fn count_water_sqs(map: &Map, start_r: i32, start_c: i32, lot_r: i32, lot_c: i32
     sum
 }
 
-// This requires that templates be squares. I think I understand how I could
-// modify that but it seems much simpler to just make all my templates 
-// squares (even if the building within is not)
-fn rotate(building: &[char], width: usize) -> Vec<char> {
-    let mut indices = vec![0; width * width];
-    let mut rotated = vec!['`'; width * width];
+// Turn a template 90 degrees clockwise. Windows are drawn as | or - depending on which
+// way the wall runs so they need to be flipped. For non-square templates the width and
+// height trade places.
+fn rotate(template: &Template) -> Template {
+    let (width, height) = (template.width, template.height);
+    let mut rotated = Template::new(height, width, template.no_rotate);
+    rotated.sqs = vec!['`'; width * height];
+
+    for r in 0..height {
+        for c in 0..width {
+            rotated.sqs[c * height + (height - 1 - r)] = match template.sqs[r * width + c] {
+                '|' => '-',
+                '-' => '|',
+                ch => ch,
+            };
+        }
+    }
 
-    for i in 0..width * width {
-        indices[i] = if i < width {
-             i * width + width - 1
-        } else {
-            indices[i - width] - 1
-        }        
+    rotated
+}
+
+// Templates are drawn with their doors facing south, so rotate buildings depending on where 
+// in town they are so that their doors (mostly) face the centre of town
+fn orient_building(template: &Template, loc: (i32, i32), town: &TownLayout) -> Template {
+    if template.no_rotate {
+        return template.clone();
+    }
+
+    let centre_row = loc.0 + template.height as i32 / 2;
+    let centre_col = loc.1 + template.width as i32 / 2;
+    
+    let quarter = town.height / 4;
+    let north_quarter = town.r + quarter;
+    let south_quarter = town.r + quarter + quarter;
+    let mid = town.c + town.width / 2;
+
+    let turns = if centre_row >= south_quarter { 
+        // rotate doors to face north
+        2
+    } else if centre_row > north_quarter && centre_col < mid {
+        // rotate doors to face east
+        3
+    } else if centre_row > north_quarter && centre_col > mid {
+        // rotate doors to face west
+        1
+    } else {
+        0
     };
 
-    for i in indices.iter().map(|i| *i) {
-        rotated[indices[i]] = if building[i] == '|' {
-            '-'
-        } else if building[i] == '-' {
-            '|'
-        } else {
-            building[i]
-        };
+    let mut oriented = template.clone();
+    for _ in 0..turns {
+        oriented = rotate(&oriented);
     }
 
-    rotated
+    oriented
 }
 
-fn draw_building(map: &mut Map, loc: (i32, i32), town: (i32, i32), template: &Template,
-        buildings: &mut TownBuildings, cat: &BuildingType) {
-    let mut rng = rand::thread_rng();    
+fn draw_building(map: &mut Map, loc: (i32, i32), template: &Template,
+        buildings: &mut TownBuildings, cat: &BuildingType, rng: &mut GameRng) {
     let mut building_sqs = HashSet::new();
     let is_wood = rng.gen_range(0.0, 1.0) < 0.7;
-
-    let building = if template.no_rotate {
-        template.sqs.clone()
-    } else {
-        let centre_row = loc.0 + template.height as i32 / 2;
-        let centre_col = loc.1 + template.width as i32 / 2;
-        let mut sqs = template.sqs.clone();
-        
-        let quarter = TOWN_HEIGHT / 4;
-        let north_quarter = town.0 + quarter;
-        let south_quarter = town.0 + quarter + quarter;
-        let mid = town.1 + TOWN_WIDTH / 2;
-
-        if centre_row >= south_quarter { 
-            // rotate doors to face north
-            sqs = rotate(&sqs, template.width);
-            sqs = rotate(&sqs, template.width);
-        } else if centre_row > north_quarter && centre_col < mid {
-            // rotate doors to face east
-            sqs = rotate(&sqs, template.width);
-            sqs = rotate(&sqs, template.width);
-            sqs = rotate(&sqs, template.width);
-        } else if centre_row > north_quarter && centre_col > mid {
-            // rotate doors to face west
-            sqs = rotate(&sqs, template.width);            
-        }
-
-        sqs
-    };
     
     for r in 0..template.height {
         for c in 0..template.width {
             let coord = (loc.0 + r as i32, loc.1 + c as i32, 0);
-            let tile = match building[r as usize * template.width + c as usize] {
+            let tile = match template.sqs[r as usize * template.width + c as usize] {
                 '#' if is_wood => Tile::WoodWall,
                 '#' => Tile::Wall,
                 '`' => Tile::Grass,
@@ -181,6 +230,9 @@ fn draw_building(map: &mut Map, loc: (i32, i32), town: (i32, i32), template: &Te
                 '-' => Tile::Window('-'),
                 'T' => Tile::Tree,
                 '.' => Tile::StoneFloor,
+                'b' => Tile::Bed,
+                't' => Tile::Table,
+                'c' => Tile::Counter,
                 _ => panic!("Illegal character in building template!"),
             };
             map.insert(coord, tile);
@@ -189,6 +241,7 @@ fn draw_building(map: &mut Map, loc: (i32, i32), town: (i32, i32), template: &Te
                 Tile::Door(_) => { building_sqs.insert(coord); },
                 Tile::Floor => { building_sqs.insert(coord); },
                 Tile::StoneFloor => { building_sqs.insert(coord); },
+                Tile::Bed | Tile::Table | Tile::Counter => { building_sqs.insert(coord); },
                 _ => { },
             }
         }
@@ -208,8 +261,8 @@ fn building_fits(map: &mut Map, nw_r: i32, nw_c: i32, template: &Template) -> bo
         for c in 0..template.width {
             let loc = (nw_r + r as i32, nw_c + c as i32, 0);
             match &map[&loc] {
-                Tile::DeepWater | Tile::Wall | Tile::WoodWall | Tile::Window(_) |
-                Tile::Floor | Tile::StoneFloor | Tile::Door(_) => { return false; }
+                Tile::DeepWater | Tile::Bridge | Tile::Wall | Tile::WoodWall | Tile::Window(_) |
+                Tile::Floor | Tile::StoneFloor | Tile::Door(_) | Tile::Bed | Tile::Table | Tile::Counter => { return false; }
                 _ => { continue; }
             }
         }
@@ -241,14 +294,18 @@ fn building_fits(map: &mut Map, nw_r: i32, nw_c: i32, template: &Template) -> bo
     true
 }
 
-fn check_along_col(map: &mut Map, start: (i32, i32), delta: i32, town: (i32, i32), template: &Template, buildings: &mut TownBuildings, cat: &BuildingType) -> bool {
+// Slide the building (its template and what sort of building it is) along from start, delta
+// squares at a time, until it fits somewhere
+fn check_along_col(map: &mut Map, start: (i32, i32), delta: i32, town: &TownLayout, building: (&Template, &BuildingType), buildings: &mut TownBuildings, rng: &mut GameRng) -> bool {
+    let (template, cat) = building;
     let height = template.height as i32;
 
     if delta > 0 {
         let mut row = start.0;
-        while row + height < town.0 + TOWN_HEIGHT {
-            if building_fits(map, row, start.1, template) {
-                draw_building(map, (row, start.1), town, template, buildings, cat);
+        while row + height < town.r + town.height {
+            let oriented = orient_building(template, (row, start.1), town);
+            if building_fits(map, row, start.1, &oriented) {
+                draw_building(map, (row, start.1), &oriented, buildings, cat, rng);
                 return true;
             }
 
@@ -256,9 +313,10 @@ fn check_along_col(map: &mut Map, start: (i32, i32), delta: i32, town: (i32, i32
         }
     } else {
         let mut row = start.0 - height;
-        while row > town.0 {
-            if building_fits(map, row, start.1, template) {
-                draw_building(map, (row, start.1), town, template, buildings, cat);
+        while row > town.r {
+            let oriented = orient_building(template, (row, start.1), town);
+            if building_fits(map, row, start.1, &oriented) {
+                draw_building(map, (row, start.1), &oriented, buildings, cat, rng);
                 return true;
             }
 
@@ -269,23 +327,26 @@ fn check_along_col(map: &mut Map, start: (i32, i32), delta: i32, town: (i32, i32
     false
 }
 
-fn check_along_row(map: &mut Map, start: (i32, i32), delta: i32, town: (i32, i32), template: &Template, buildings: &mut TownBuildings, cat: &BuildingType) -> bool {
+fn check_along_row(map: &mut Map, start: (i32, i32), delta: i32, town: &TownLayout, building: (&Template, &BuildingType), buildings: &mut TownBuildings, rng: &mut GameRng) -> bool {
+    let (template, cat) = building;
     let width = template.width as i32;
 
     if delta > 0 {
         let mut col = start.1;
-        while col + width < town.1 + TOWN_WIDTH {
-            if building_fits(map, start.0, col, template) {
-                draw_building(map, (start.0, col), town, template, buildings, cat);
+        while col + width < town.c + town.width {
+            let oriented = orient_building(template, (start.0, col), town);
+            if building_fits(map, start.0, col, &oriented) {
+                draw_building(map, (start.0, col), &oriented, buildings, cat, rng);
                 return true;
             }
             col += delta;
         }
     } else {
-        let mut col = town.1 + TOWN_WIDTH - width - 1;
-        while col > town.1 {
-            if building_fits(map, start.0, col, template) {
-                draw_building(map, (start.0, col), town, template, buildings, cat);
+        let mut col = town.c + town.width - width - 1;
+        while col > town.c {
+            let oriented = orient_building(template, (start.0, col), town);
+            if building_fits(map, start.0, col, &oriented) {
+                draw_building(map, (start.0, col), &oriented, buildings, cat, rng);
                 return true;
             }
             col += delta;
@@ -296,10 +357,9 @@ fn check_along_row(map: &mut Map, start: (i32, i32), delta: i32, town: (i32, i32
 }
 
 // The inn is placed on the outside of town
-fn place_tavern(map: &mut Map, town_r: i32, town_c: i32, templates: &HashMap<String, Template>, buildings: &mut TownBuildings) {
-    let mut rng = rand::thread_rng();
+fn place_tavern(map: &mut Map, town: &TownLayout, templates: &HashMap<String, Template>, buildings: &mut TownBuildings, rng: &mut GameRng) {
     let mut options = vec![1, 2, 3, 4];
-    options.shuffle(&mut rng);
+    options.shuffle(rng);
 
     while !options.is_empty() {
         let choice = options.pop().unwrap();
@@ -308,145 +368,144 @@ fn place_tavern(map: &mut Map, town_r: i32, town_c: i32, templates: &HashMap<Str
             // east facting tavern
             let template = templates.get("tavern 1").unwrap();
             let (start_r, delta) = if rng.gen_range(0.0, 1.0) < 0.5 {
-                (town_r, 1)
+                (town.r, 1)
             } else {
-                (town_r + TOWN_HEIGHT, -1)
+                (town.r + town.height, -1)
             };
-            if check_along_col(map, (start_r, town_c), delta, (town_r, town_c), template, buildings, &BuildingType::Tavern) {
+            if check_along_col(map, (start_r, town.c), delta, town, (template, &BuildingType::Tavern), buildings, rng) {
                 break;
             }
         } else if choice == 2 {
             // south facing tavern
             let template = templates.get("tavern 2").unwrap();
             let (start_c, delta) = if rng.gen_range(0.0, 1.0) < 0.5 {
-                (town_c, 1)
+                (town.c, 1)
             } else {
-                (town_c + TOWN_WIDTH - template.width as i32, - 1)
+                (town.c + town.width - template.width as i32, - 1)
             };
-            if check_along_row(map, (town_r, start_c), delta, (town_r, town_c), template, buildings, &BuildingType::Tavern) {
+            if check_along_row(map, (town.r, start_c), delta, town, (template, &BuildingType::Tavern), buildings, rng) {
                 break;
             }
         } else if choice == 3 {
             // north facing tavern
             let template = templates.get("tavern 3").unwrap();
             let (start_c, delta) = if rng.gen_range(0.0, 1.0) < 0.5 {
-                (town_c, 1)
+                (town.c, 1)
             } else {
-                (town_c + TOWN_WIDTH - template.width as i32, - 1)
+                (town.c + town.width - template.width as i32, - 1)
             };
-            if check_along_row(map, (town_r + TOWN_HEIGHT - template.height as i32 - 1, start_c), delta, (town_r, town_c), template, buildings, &BuildingType::Tavern) {
+            if check_along_row(map, (town.r + town.height - template.height as i32 - 1, start_c), delta, town, (template, &BuildingType::Tavern), buildings, rng) {
                 break;
             }
         } else {
             // west facing tavern
             let template = templates.get("tavern 4").unwrap();
             let (start_r, delta) = if rng.gen_range(0.0, 1.0) < 0.5 {
-                (town_r, 1)
+                (town.r, 1)
             } else {
-                (town_r + TOWN_HEIGHT, -1)
+                (town.r + town.height, -1)
             };
-            if check_along_col(map, (start_r, town_c + TOWN_WIDTH - template.width as i32 - 1), delta, (town_r, town_c), template, buildings, &BuildingType::Tavern) {
+            if check_along_col(map, (start_r, town.c + town.width - template.width as i32 - 1), delta, town, (template, &BuildingType::Tavern), buildings, rng) {
                 break;
             }
         }
     }
 }
 
-fn place_building(map: &mut Map, town_r: i32, town_c: i32, template: &Template, buildings: &mut TownBuildings, cat: BuildingType) -> bool {
-    let mut rng = rand::thread_rng();
+fn place_building(map: &mut Map, town: &TownLayout, template: &Template, buildings: &mut TownBuildings, cat: BuildingType, rng: &mut GameRng) -> bool {
     let mut options = vec![1, 2, 3, 4];
-    options.shuffle(&mut rng);
+    options.shuffle(rng);
     
     while !options.is_empty() {
         let pick = options.pop().unwrap();
 
         if pick == 1 {
             // Start at the top left
-            let (mut row, mut col, delta_r, delta_c) = (town_r, town_c, 2, 2);
+            let (mut row, mut col, delta_r, delta_c) = (town.r, town.c, 2, 2);
 
             // stagger the buildings a bit
             row += rng.gen_range(0, 6);
             col += rng.gen_range(0, 6);
 
             loop {
-                if check_along_row(map, (row, col), delta_c, (town_r, town_c), template, buildings, &cat) {
+                if check_along_row(map, (row, col), delta_c, town, (template, &cat), buildings, rng) {
                     return true;
                 }
                 row += delta_r;
                 col += delta_c;
-                if col + template.width as i32 > town_c + TOWN_WIDTH {
-                    col = town_c;
+                if col + template.width as i32 > town.c + town.width {
+                    col = town.c;
                 }
 
-                if row < town_r || row + template.height as i32 > town_r + TOWN_HEIGHT {
+                if row < town.r || row + template.height as i32 > town.r + town.height {
                     break;
                 }
             }
         } else if pick == 2 {
             // Start at the bottom left
-            let (mut row, mut col, delta_r, delta_c) = (town_r + TOWN_HEIGHT as i32 - template.height as i32 - 1, 
-                    town_c, -2, 2);
+            let (mut row, mut col, delta_r, delta_c) = (town.r + town.height - template.height as i32 - 1, 
+                    town.c, -2, 2);
 
             // stagger the buildings a bit
             row -= rng.gen_range(0, 6);
             col += rng.gen_range(0, 6);
 
             loop {
-                if check_along_row(map, (row, col), delta_c, (town_r, town_c), template, buildings, &cat) {
+                if check_along_row(map, (row, col), delta_c, town, (template, &cat), buildings, rng) {
                     return true;
                 }
                 row += delta_r;
                 col += delta_c;
-                if col + template.width as i32 > town_c + TOWN_WIDTH {
-                    col = town_c;
+                if col + template.width as i32 > town.c + town.width {
+                    col = town.c;
                 }
 
-                if row < town_r || row + template.height as i32 > town_r + TOWN_HEIGHT {
+                if row < town.r || row + template.height as i32 > town.r + town.height {
                     break;
                 }
             }
         } else if pick == 3 {
             // Start at the top right
-            let (mut row, mut col, delta_r, delta_c) = (town_r, town_c + TOWN_WIDTH - template.width as i32 - 1, 2, -2);
+            let (mut row, mut col, delta_r, delta_c) = (town.r, town.c + town.width - template.width as i32 - 1, 2, -2);
 
             // stagger the buildings a bit
             row += rng.gen_range(0, 6);
             col -= rng.gen_range(0, 6);
 
             loop {
-                if check_along_row(map, (row, col), delta_c, (town_r, town_c), template, buildings, &cat) {
+                if check_along_row(map, (row, col), delta_c, town, (template, &cat), buildings, rng) {
                     return true;
                 }
                 row += delta_r;
                 col += delta_c;
-                if col < town_c {
-                    col = town_c + TOWN_WIDTH - template.width as i32 - 1;
+                if col < town.c {
+                    col = town.c + town.width - template.width as i32 - 1;
                 }
 
-                if row < town_r || row + template.height as i32 > town_r + TOWN_HEIGHT {
+                if row < town.r || row + template.height as i32 > town.r + town.height {
                     break;
                 }
             } 
         } else {
             // Start at bottom right
-            let (mut row, mut col, delta_r, delta_c) = (town_r + TOWN_HEIGHT - template.height as i32 - 1, 
-                town_c + TOWN_WIDTH - template.width as i32 - 1, -2, -2);
+            let (mut row, mut col, delta_r, delta_c) = (town.r + town.height - template.height as i32 - 1, 
+                town.c + town.width - template.width as i32 - 1, -2, -2);
 
             // stagger the buildings a bit
             row -= rng.gen_range(0, 6);
             col -= rng.gen_range(0, 6);
 
             loop {
-                if check_along_row(map, (row, col), delta_c, (town_r, town_c), template, buildings, &cat) {
+                if check_along_row(map, (row, col), delta_c, town, (template, &cat), buildings, rng) {
                     return true;
                 }
                 row += delta_r;
                 col += delta_c;
-                if col < town_c {
-                    col = town_c + TOWN_WIDTH - template.width as i32 - 1;
+                if col < town.c {
+                    col = town.c + town.width - template.width as i32 - 1;
                 }
 
-                if row < town_r || row + template.height as i32 > town_r + TOWN_HEIGHT {
+                if row < town.r || row + template.height as i32 > town.r + town.height {
                     break;
                 }
             }
@@ -472,22 +531,24 @@ fn good_spot_for_forge(map: &Map, loc: &(i32, i32, i8)) -> bool {
     true
 }
 
-// Town is laid out with 5x3 lots, each lot being 12x12 squares
-fn  place_town_buildings(map: &mut Map, town_r: i32, town_c: i32, 
-            templates: &HashMap<String, Template>, buildings: &mut TownBuildings) {   
-    let mut rng = rand::thread_rng();
+fn place_town_buildings(map: &mut Map, town: &TownLayout, 
+            templates: &HashMap<String, Template>, buildings: &mut TownBuildings, max_homes: usize, rng: &mut GameRng) {   
 
     // Step one, get rid of most but not all of the trees in town and replace with grass.
-	for r in town_r..town_r + TOWN_HEIGHT {
-		for c in town_c..town_c + TOWN_WIDTH {
-            if map[&(r, c, 0)] == Tile::Tree && rng.gen_range(0.0, 1.0) < 0.85 {
-                map.insert((r, c, 0), Tile::Grass);
+    // Likewise, townsfolk have tamped down any sand and drained the marshy bits.
+	for r in town.r..town.r + town.height {
+		for c in town.c..town.c + town.width {
+            match map[&(r, c, 0)] {
+                Tile::Tree if rng.gen_range(0.0, 1.0) < 0.85 => { map.insert((r, c, 0), Tile::Grass); },
+                Tile::Sand => { map.insert((r, c, 0), Tile::Dirt); },
+                Tile::Water => { map.insert((r, c, 0), Tile::Grass); },
+                _ => { },
             }
         }
     }
 
     // Start by placing the tavern since it's the largest building and the hardest to fit
-    place_tavern(map, town_r, town_c, templates, buildings);
+    place_tavern(map, town, templates, buildings, rng);
 
     let cottages: Vec<String> = templates.keys()
         .filter(|k| k.starts_with("cottage"))
@@ -495,13 +556,13 @@ fn  place_town_buildings(map: &mut Map, town_r: i32, town_c: i32,
 
     // create the town's market
     let j = rng.gen_range(0, cottages.len());
-    place_building(map, town_r, town_c, &templates[&cottages[j]], buildings, BuildingType::Market);
+    place_building(map, town, &templates[&cottages[j]], buildings, BuildingType::Market, rng);
 
     // and the smithy
     let j = rng.gen_range(0, cottages.len());
-    place_building(map, town_r, town_c, &templates[&cottages[j]], buildings, BuildingType::Smithy);
+    place_building(map, town, &templates[&cottages[j]], buildings, BuildingType::Smithy, rng);
     loop {
-        let loc = buildings.smithy.iter().choose(&mut rng).unwrap();
+        let loc = buildings.smithy.iter().choose(rng).unwrap();
         if good_spot_for_forge(map, &loc) {
             map.insert(*loc, Tile::Forge);
             break;
@@ -509,23 +570,132 @@ fn  place_town_buildings(map: &mut Map, town_r: i32, town_c: i32,
     }
     // The town will have only 1 shrine. (Maybe in the future I can implement religious rivalries...)
     if rng.gen_range(0, 2) == 0 {
-        place_building(map, town_r, town_c, &templates["shrine 1"], buildings, BuildingType::Shrine);
+        place_building(map, town, &templates["shrine 1"], buildings, BuildingType::Shrine, rng);
     } else {
-        place_building(map, town_r, town_c, &templates["shrine 2"], buildings, BuildingType::Shrine);
+        place_building(map, town, &templates["shrine 2"], buildings, BuildingType::Shrine, rng);
     }
 
-    for _ in 0..6 {
+    for _ in 0..max_homes {
         let j = rng.gen_range(0, cottages.len());
-        if !place_building(map, town_r, town_c, &templates[&cottages[j]], buildings, BuildingType::Home) {
+        if !place_building(map, town, &templates[&cottages[j]], buildings, BuildingType::Home, rng) {
             break;
         }
     }
 }
 
+// Surround the town with a palisade, just outside the area buildings are placed in, with a gate 
+// in the middle of each side. Water and mountains are left alone since they're barrier enough.
+fn build_town_wall(map: &mut Map, town: &TownLayout) -> Vec<(i32, i32, i8)> {
+    let top = town.r - 1;
+    let bottom = town.r + town.height;
+    let left = town.c - 1;
+    let right = town.c + town.width;
+
+    let mut perimeter = Vec::new();
+    for c in left..=right {
+        perimeter.push((top, c, 0));
+        perimeter.push((bottom, c, 0));
+    }
+    for r in top + 1..bottom {
+        perimeter.push((r, left, 0));
+        perimeter.push((r, right, 0));
+    }
+
+    for sq in perimeter.iter() {
+        if map[sq].passable_dry_land() {
+            map.insert(*sq, Tile::WoodWall);
+        }
+    }
+
+    // Each gate starts at the middle of its wall and slides along until it finds a spot
+    // that has open ground on either side of it
+    let sides = [
+        ((top, (left + right) / 2), (0, 1), (1, 0)),
+        ((bottom, (left + right) / 2), (0, 1), (1, 0)),
+        (((top + bottom) / 2, left), (1, 0), (0, 1)),
+        (((top + bottom) / 2, right), (1, 0), (0, 1)),
+    ];
+    let mut gates = Vec::new();
+    for (mid, along, across) in sides.iter() {
+        let len = if along.0 == 0 { town.width / 2 } else { town.height / 2 };
+        for offset in (0..len).flat_map(|d| vec![d, -d]) {
+            let loc = (mid.0 + along.0 * offset, mid.1 + along.1 * offset, 0);
+            let inside = (loc.0 + across.0, loc.1 + across.1, 0);
+            let outside = (loc.0 - across.0, loc.1 - across.1, 0);
+            if map[&loc] == Tile::WoodWall && map[&inside].passable_dry_land() && map[&outside].passable_dry_land() {
+                map.insert(loc, Tile::Gate(DoorState::Open));
+                gates.push(loc);
+                break;
+            }
+        }
+    }
+
+    gates
+}
+
+// A riverside town has a river running north to south through it, off to one side of the town
+// square. It runs a little past the edges of town so that the roads have to bridge it rather than
+// just walking around the ends. There's always a bridge in line with the town square.
+fn draw_river_through_town(map: &mut Map, town: &TownLayout, rng: &mut GameRng) {
+    let (lo, hi) = if rng.gen_range(0, 2) == 0 {
+        (town.c + town.width / 6, town.c + town.width / 3)
+    } else {
+        (town.c + town.width - town.width / 3, town.c + town.width - town.width / 6)
+    };
+
+    let bridge_row = town.r + town.height / 2;
+    let mut col = rng.gen_range(lo, hi);
+    for r in town.r - 6..town.r + town.height + 6 {
+        let breadth = rng.gen_range(2, 4);
+        let tile = if r == bridge_row { Tile::Bridge } else { Tile::DeepWater };
+        for c in col..col + breadth {
+            if map.contains_key(&(r, c, 0)) {
+                map.insert((r, c, 0), tile);
+            }
+        }
+        col = (col + rng.gen_range(-1, 2)).max(lo).min(hi);
+    }
+}
+
+// Walled towns shut their gates overnight. (Unless someone is standing in the way.) The gates are
+// never barred though: the night watch will open up for anyone who knocks. See open_town_gate()
+pub fn check_town_gates(state: &mut GameState, game_obj_db: &GameObjectDB) {
+    if state.world_info.town_gates.is_empty() {
+        return;
+    }
+
+    let night = npc::night_time(state);
+    for gate in state.world_info.town_gates.clone() {
+        match state.map.get(&gate) {
+            Some(Tile::Gate(DoorState::Open)) if night && !game_obj_db.blocking_obj_at(&gate) => {
+                state.map.insert(gate, Tile::Gate(DoorState::Closed));
+                state.msg_queue.push_back(Message::new(0, gate, "The town gate rumbles shut for the night.", "You hear a gate rumble shut."));
+            },
+            Some(Tile::Gate(DoorState::Closed)) if !night => {
+                state.map.insert(gate, Tile::Gate(DoorState::Open));
+                state.msg_queue.push_back(Message::new(0, gate, "The town gate creaks open.", "You hear a gate creak open."));
+            },
+            _ => { },
+        }
+    }
+}
+
+// The player bumped into a gate that's been shut for the night. The watch lets them through and
+// the gate will be shut again once they're clear of it.
+pub fn open_town_gate(state: &mut GameState, gate: (i32, i32, i8)) -> bool {
+    if !state.world_info.town_gates.contains(&gate) || state.map.get(&gate) != Some(&Tile::Gate(DoorState::Closed)) {
+        return false;
+    }
+
+    state.map.insert(gate, Tile::Gate(DoorState::Open));
+    state.msg_queue.push_back(Message::new(0, gate, "The night watch cranks open the gate for you.", "You hear a gate creak open."));
+
+    true
+}
+
 // Draw paths in town. For now they just converge on the town square but I might in the future have
 // some of them move from one neighbour to another
-fn draw_paths_in_town(map: &mut Map, world_info: &WorldInfo) {
-    let mut rng = rand::thread_rng();
+fn draw_paths_in_town(map: &mut Map, world_info: &WorldInfo, rng: &mut GameRng) {
     let mut doors = HashSet::new();
 
     let adj: [(i32, i32); 4] = [(0, -1), (0, 1), (-1, 0), (1, 0)];
@@ -545,71 +715,103 @@ fn draw_paths_in_town(map: &mut Map, world_info: &WorldInfo) {
         }
     }
 
+    // Roads also run from the town gates (if there are any) in to the square
+    for gate in world_info.town_gates.iter() {
+        doors.insert(*gate);
+    }
+
     // pick random spot in the town square for paths to converge on
-    let mut passable = HashMap::new();
-    passable.insert(Tile::Grass, 1.0);
-    passable.insert(Tile::Dirt, 1.0);
-    passable.insert(Tile::Bridge, 1.0);
-    passable.insert(Tile::Tree, 2.0);
-    passable.insert(Tile::Water, 3.0);
-    passable.insert(Tile::DeepWater, 3.0);
+    let passable = pathfinding::road_builder_passable();
     let j = rng.gen_range(0, world_info.town_square.len());
     let centre = world_info.town_square.iter().nth(j).unwrap();
-    for door in doors {
+    for door in doors.iter() {
         let path = pathfinding::find_path(map, None, false, door.0, door.1, 0, centre.0, centre.1, 150, &passable);
-        if !path.is_empty() {
-            for sq in path {
-                let loc = (sq.0, sq.1, 0);
-                if let Tile::Grass = map[&loc] {
-                    map.insert(loc, Tile::Dirt);
-                } else if let Tile::DeepWater = map[&loc] {
-                    map.insert(loc, Tile::Bridge);
-                    let mut col = loc.1 + 1;
-                    while map[&(loc.0, col, loc.2)] == Tile::DeepWater {
-                         map.insert((loc.0, col, loc.2), Tile::Bridge);
-                         col += 1;
+        lay_road(map, &path);
+    }
+
+    // A road that had to cross water can come up short: find_path may have given up before
+    // reaching the square, or lay_road may have refused to bridge a crossing wider than
+    // MAX_BRIDGE_LENGTH. Doors left cut off from the square get a second, longer search (which can
+    // find a narrower crossing further along).
+    let reachable = reachable_from_square(map, world_info, *centre);
+    for door in doors.iter().filter(|d| !reachable.contains_key(d)) {
+        let path = pathfinding::find_path(map, None, false, door.0, door.1, 0, centre.0, centre.1, 400, &passable);
+        lay_road(map, &path);
+    }
+}
+
+// Everywhere in town someone can walk to from the given spot in the square (without swimming)
+fn reachable_from_square(map: &Map, world_info: &WorldInfo, centre: (i32, i32, i8)) -> HashMap<(i32, i32, i8), u32> {
+    let b = world_info.town_boundary;
+    util::floodfill(centre, 500, |sq| {
+        util::ADJ.iter()
+                 .map(|d| (sq.0 + d.0, sq.1 + d.1, 0))
+                 .filter(|n| n.0 >= b.0 - 1 && n.0 <= b.2 + 1 && n.1 >= b.1 - 1 && n.1 <= b.3 + 1)
+                 .filter(|n| matches!(map.get(n), Some(t) if t.passable_dry_land() || matches!(t, Tile::Door(_))))
+                 .map(|n| (n, 1))
+                 .collect()
+    })
+}
+
+// Turn a path into dirt road, bridging any deep water along the way. When the road crosses a river
+// the bridge is widened out across the channel, so long as that doesn't make for an absurdly long 
+// bridge (or run off the edge of the map).
+fn lay_road(map: &mut Map, path: &[(i32, i32)]) {
+    for sq in path.iter() {
+        let loc = (sq.0, sq.1, 0);
+        match map.get(&loc) {
+            Some(Tile::Grass) => { map.insert(loc, Tile::Dirt); },
+            Some(Tile::DeepWater) => {
+                map.insert(loc, Tile::Bridge);
+
+                let mut span = Vec::new();
+                for delta in [-1, 1].iter() {
+                    let mut col = loc.1 + delta;
+                    while map.get(&(loc.0, col, 0)) == Some(&Tile::DeepWater) && span.len() < MAX_BRIDGE_LENGTH {
+                        span.push((loc.0, col, 0));
+                        col += delta;
                     }
-                    let mut col = loc.1 - 1;
-                    while map[&(loc.0, col, loc.2)] == Tile::DeepWater {
-                         map.insert((loc.0, col, loc.2), Tile::Bridge);
-                         col -= 1;
+                }
+                if span.len() < MAX_BRIDGE_LENGTH {
+                    for b in span {
+                        map.insert(b, Tile::Bridge);
                     }
                 }
-            }
+            },
+            _ => { },
         }
     }
 }
 
 // eventually to be fancier
-fn random_tavern_name() -> String {
-    let mut rng = rand::thread_rng();
+fn random_tavern_name(rng: &mut GameRng) -> String {
     let nouns = ["Arms", "Boar", "Cup", "Axe", "Bow", "Elf", "Stag"];
     let adjective = ["Black", "Golden", "Broken", "Jeweled", "Lost", "Pickled"];
 
-    let noun = String::from(*nouns.iter().choose(&mut rng).unwrap());
-    let adj = String::from(*adjective.iter().choose(&mut rng).unwrap());
+    let noun = String::from(*nouns.iter().choose(rng).unwrap());
+    let adj = String::from(*adjective.iter().choose(rng).unwrap());
 
     let tavern = format!("the {} {}", adj, noun);
 
     tavern
 }
 
-fn random_town_name() -> String {
-    let mut rng = rand::thread_rng();
+fn random_town_name(rng: &mut GameRng) -> String {
 
     // This will one day be more fancy and expansive...
     let names = ["Skara Brae", "Jhelom", "Yew", "Moonglow", "Magincia", "Antioch"];
 
-    String::from(*names.iter().choose(&mut rng).unwrap())
+    String::from(*names.iter().choose(rng).unwrap())
 }
 
-fn create_villager(voice: &str, tb: &mut TownBuildings, used_names: &HashSet<String>, game_obj_db: &mut GameObjectDB) -> GameObjects {
-    let home_id = tb.vacant_home().unwrap();
+fn create_villager(map: &Map, voice: &str, tb: &mut TownBuildings, used_names: &HashSet<String>, game_obj_db: &mut GameObjectDB, rng: &mut GameRng) -> GameObjects {
+    let home_id = tb.vacant_home(rng).unwrap();
     let home_sqs = &tb.homes[home_id];
-    let j = rand::thread_rng().gen_range(0, home_sqs.len());    
+    let j = rng.gen_range(0, home_sqs.len());    
     let loc = home_sqs.iter().nth(j).unwrap();
+    let bed = home_sqs.iter().find(|sq| map[sq] == Tile::Bed).copied();
     let home = Some(Venue::Home(home_id));
-    let mut villager = NPC::villager(npc::pick_villager_name(used_names), *loc, home, voice, game_obj_db);
+    let mut villager = NPC::villager(npc::pick_villager_name(used_names, rng), *loc, home, voice, game_obj_db, rng);
     tb.taken_homes.push(home_id);
     
     if voice.starts_with("mayor") {
@@ -619,21 +821,36 @@ fn create_villager(voice: &str, tb: &mut TownBuildings, used_names: &HashSet<Str
         }
     } else {
         if let GameObjects::NPC(npc) = &mut villager {
+            npc.schedule.push(AgendaItem::new((8, 0), (9, 0), 5, Venue::Shrine, "prayer".to_string()));
             npc.schedule.push(AgendaItem::new((11, 0), (14, 0), 10, Venue::Tavern, "lunch".to_string()));
             npc.schedule.push(AgendaItem::new((18, 0), (22, 0), 10, Venue::Tavern, "supper".to_string()));
+
+            // Drop in on one of the neighbours in the afternoon
+            let neighbours: Vec<usize> = tb.taken_homes.iter().filter(|h| **h != home_id).copied().collect();
+            if let Some(neighbour) = neighbours.choose(rng) {
+                npc.schedule.push(AgendaItem::new((15, 0), (16, 30), 5, Venue::Visit(*neighbour), "visiting".to_string()));
+            }
+        }
+    }
+
+    // If their home has a bed, that's where they'll be overnight
+    if let Some(bed) = bed {
+        if let GameObjects::NPC(npc) = &mut villager {
+            npc.schedule.push(AgendaItem::new((22, 0), (23, 59), 5, Venue::Favourite(bed), "sleeping".to_string()));
+            npc.schedule.push(AgendaItem::new((0, 0), (6, 30), 5, Venue::Favourite(bed), "sleeping".to_string()));
         }
     }
 
     villager
 }
 
-fn create_innkeeper(tb: &TownBuildings, used_names: &HashSet<String>, game_obj_db: &mut GameObjectDB) -> GameObjects {
+fn create_innkeeper(tb: &TownBuildings, used_names: &HashSet<String>, game_obj_db: &mut GameObjectDB, rng: &mut GameRng) -> GameObjects {
     let inn_sqs: Vec<(i32, i32, i8)> = tb.tavern.iter().map(|s| *s).collect();
-    let j = rand::thread_rng().gen_range(0, inn_sqs.len());    
+    let j = rng.gen_range(0, inn_sqs.len());    
     let loc = inn_sqs.get(j).unwrap();
 
-    let voice = dialogue::rnd_innkeeper_voice();
-    let mut innkeeper = NPC::villager(npc::pick_villager_name(used_names), *loc, Some(Venue::Tavern), &voice, game_obj_db);
+    let voice = dialogue::rnd_innkeeper_voice(rng);
+    let mut innkeeper = NPC::villager(npc::pick_villager_name(used_names, rng), *loc, Some(Venue::Tavern), &voice, game_obj_db, rng);
     if let GameObjects::NPC(npc) = &mut innkeeper {
         npc.schedule.push(AgendaItem::new((0, 0), (23, 59), 0, Venue::Tavern, "inn".to_string()));
     }
@@ -641,12 +858,12 @@ fn create_innkeeper(tb: &TownBuildings, used_names: &HashSet<String>, game_obj_d
     innkeeper
 }
 
-fn create_grocer(tb: &TownBuildings, used_names: &HashSet<String>, game_obj_db: &mut GameObjectDB) -> GameObjects {
+fn create_grocer(tb: &TownBuildings, used_names: &HashSet<String>, game_obj_db: &mut GameObjectDB, rng: &mut GameRng) -> GameObjects {
     let market_sqs: Vec<(i32, i32, i8)> = tb.market.iter().map(|s| *s).collect();
-    let j = rand::thread_rng().gen_range(0, market_sqs.len());
+    let j = rng.gen_range(0, market_sqs.len());
     let loc = market_sqs.get(j).unwrap();
 
-    let mut grocer = NPC::villager(npc::pick_villager_name(used_names), *loc, Some(Venue::Market), "shopkeeper1", game_obj_db);
+    let mut grocer = NPC::villager(npc::pick_villager_name(used_names, rng), *loc, Some(Venue::Market), "shopkeeper1", game_obj_db, rng);
     if let GameObjects::NPC(npc) = &mut grocer {
         npc.schedule.push(AgendaItem::new((8, 0), (12, 29), 0, Venue::Market, "working".to_string()));
         npc.schedule.push(AgendaItem::new((12, 30), (12, 59), 0, Venue::Tavern, "lunch".to_string()));
@@ -657,12 +874,12 @@ fn create_grocer(tb: &TownBuildings, used_names: &HashSet<String>, game_obj_db:
     grocer
 }
 
-fn create_smith(tb: &TownBuildings, used_names: &HashSet<String>, game_obj_db: &mut GameObjectDB) -> GameObjects {
+fn create_smith(tb: &TownBuildings, used_names: &HashSet<String>, game_obj_db: &mut GameObjectDB, rng: &mut GameRng) -> GameObjects {
     let smith_sqs: Vec<(i32, i32, i8)> = tb.smithy.iter().map(|s| *s).collect();
-    let j = rand::thread_rng().gen_range(0, smith_sqs.len());
+    let j = rng.gen_range(0, smith_sqs.len());
     let loc = smith_sqs.get(j).unwrap();
 
-    let mut smith = NPC::villager(npc::pick_villager_name(used_names), *loc, Some(Venue::Smithy), "smith1", game_obj_db);
+    let mut smith = NPC::villager(npc::pick_villager_name(used_names, rng), *loc, Some(Venue::Smithy), "smith1", game_obj_db, rng);
     if let GameObjects::NPC(npc) = &mut smith {
         npc.schedule.push(AgendaItem::new((8, 0), (11, 59), 0, Venue::Smithy, "working".to_string()));
         npc.schedule.push(AgendaItem::new((12, 0), (12, 59), 0, Venue::Tavern, "lunch".to_string()));
@@ -673,10 +890,162 @@ fn create_smith(tb: &TownBuildings, used_names: &HashSet<String>, game_obj_db: &
     smith
 }
 
-fn add_well(map: &mut Map, world_info: &WorldInfo) {
-    let mut rng = rand::thread_rng();
+// The merchant doesn't live in town. They show up in the town square every few days,
+// sell their wares for the day and then move on.
+fn create_merchant(used_names: &HashSet<String>, game_obj_db: &mut GameObjectDB, rng: &mut GameRng) -> GameObjects {
+    let mut merchant = NPC::villager(npc::pick_villager_name(used_names, rng), (-1, -1, 0), Some(Venue::Caravan(MERCHANT_VISIT_DAYS)), "merchant1", game_obj_db, rng);
+    if let GameObjects::NPC(npc) = &mut merchant {
+        npc.schedule.push(AgendaItem::new((8, 0), (18, 59), 0, Venue::TownSquare, "working".to_string()));
+    }
+
+    merchant
+}
+
+fn visit_day(state: &GameState, days_between: i32) -> bool {
+    let day = state.curr_day();
+
+    day as i32 % days_between == days_between / 2
+}
+
+fn visitor_arrives(state: &mut GameState, game_obj_db: &mut GameObjectDB, j: usize) -> bool {
+    let open_sqs: Vec<(i32, i32, i8)> = state.world_info.town_square.iter()
+        .filter(|sq| state.map[*sq].passable_dry_land() && !game_obj_db.location_occupied(sq))
+        .copied()
+        .collect();
+    let loc = match open_sqs.iter().choose(&mut state.rng) {
+        Some(loc) => *loc,
+        None => return false, // no room in the square, so they'll try again next turn
+    };
+
+    let mut visitor = state.world_info.visitors.remove(j);
+    if let GameObjects::NPC(npc) = &mut visitor {
+        npc.plan.clear();
+    }
+    visitor.set_loc(loc);
+    let obj_id = visitor.obj_id();
+    game_obj_db.add(visitor);
+    game_obj_db.listeners.insert((obj_id, EventType::TakeTurn));
+    state.world_info.visiting.push(obj_id);
+
+    state.msg_queue.push_back(Message::new(obj_id, loc, "A merchant's wagon rolls into the town square.", ""));
+
+    true
+}
+
+fn visitor_leaves(state: &mut GameState, game_obj_db: &mut GameObjectDB, obj_id: usize) {
+    state.world_info.visiting.retain(|id| *id != obj_id);
+
+    // If the player has murdered the visitor, they aren't coming back
+    if let Some(GameObjects::NPC(npc)) = game_obj_db.get(obj_id) {
+        if npc.alive {
+            let loc = npc.get_loc();
+            let s = format!("{} packs up and leaves town.", npc.npc_name(false));
+            state.msg_queue.push_back(Message::new(obj_id, loc, &s, ""));
+            let visitor = game_obj_db.remove(obj_id);
+            state.world_info.visitors.push(visitor);
+        }
+    }
+}
+
+// Check to see if anyone who visits town periodically should be arriving or departing
+pub fn check_visitors(state: &mut GameState, game_obj_db: &mut GameObjectDB) {
+    let leaving: Vec<usize> = state.world_info.visiting.iter()
+        .filter(|id| {
+            match game_obj_db.get(**id) {
+                Some(GameObjects::NPC(npc)) => !npc_should_be_visiting(state, npc),
+                _ => true,
+            }
+        })
+        .copied()
+        .collect();
+    for obj_id in leaving {
+        visitor_leaves(state, game_obj_db, obj_id);
+    }
+
+    let mut j = 0;
+    while j < state.world_info.visitors.len() {
+        let arriving = match &state.world_info.visitors[j] {
+            GameObjects::NPC(npc) => npc_should_be_visiting(state, npc),
+            _ => false,
+        };
+
+        if !(arriving && visitor_arrives(state, game_obj_db, j)) {
+            j += 1;
+        }
+    }
+}
+
+fn npc_should_be_visiting(state: &GameState, npc: &NPC) -> bool {
+    match npc.home {
+        Some(Venue::Caravan(days_between)) => visit_day(state, days_between) && npc.curr_agenda_item(state).is_some(),
+        _ => true,
+    }
+}
+
+fn home_with_door(state: &GameState, door: (i32, i32, i8)) -> Option<usize> {
+    let tb = state.world_info.town_buildings.as_ref()?;
+    tb.homes.iter().position(|h| h.contains(&door))
+}
+
+// Which of a home's residents are currently inside?
+fn residents_in(state: &GameState, game_obj_db: &GameObjectDB, home_id: usize) -> Vec<usize> {
+    let home_sqs = &state.world_info.town_buildings.as_ref().unwrap().homes[home_id];
+    game_obj_db.objects.values()
+        .filter_map(|obj| match obj {
+            GameObjects::NPC(npc) => Some(npc),
+            _ => None,
+        })
+        .filter(|npc| matches!(npc.home, Some(Venue::Home(id)) if id == home_id) && home_sqs.contains(&npc.get_loc()))
+        .map(|npc| npc.obj_id())
+        .collect()
+}
+
+pub fn knock(state: &mut GameState, game_obj_db: &mut GameObjectDB, door: (i32, i32, i8)) {
+    let home_id = match home_with_door(state, door) {
+        Some(home_id) => home_id,
+        None => {
+            state.msg_queue.push_back(Message::info("The door is ignoring you."));
+            return;
+        }
+    };
+
+    state.msg_queue.push_back(Message::info("You knock on the door."));
+    let residents = residents_in(state, game_obj_db, home_id);
+    if residents.is_empty() {
+        state.msg_queue.push_back(Message::info("No one answers."));
+        return;
+    }
+
+    let night = npc::night_time(state);
+    let npc = game_obj_db.npc(residents[0]).unwrap();
+    if npc.attitude == Attitude::Hostile {
+        state.msg_queue.push_back(Message::info("\"Go away!\""));
+    } else if night {
+        state.msg_queue.push_back(Message::info("\"Do you know what time it is? Come back in the morning!\""));
+    } else if state.map[&door] == Tile::Door(DoorState::Locked) {
+        let s = format!("{} unlocks the door.", npc.npc_name(false).capitalize());
+        state.msg_queue.push_back(Message::info(&s));
+        state.map.insert(door, Tile::Door(DoorState::Closed));
+    } else {
+        state.msg_queue.push_back(Message::info("\"Come in, it's open!\""));
+    }
+}
+
+// Breaking into someone's home is a good way to get on their bad side
+pub fn trespass(state: &mut GameState, game_obj_db: &mut GameObjectDB, door: (i32, i32, i8)) {
+    if let Some(home_id) = home_with_door(state, door) {
+        for npc_id in residents_in(state, game_obj_db, home_id) {
+            let npc = game_obj_db.npc(npc_id).unwrap();
+            npc.attitude = Attitude::Hostile;
+            let loc = npc.get_loc();
+            state.msg_queue.push_back(Message::new(npc_id, loc, "\"Hey! Get out of my house!\"", "\"Hey! Get out of my house!\""));
+        }
+    }
+}
+
+fn add_well(map: &mut Map, world_info: &WorldInfo, rng: &mut GameRng) {
     let mut sqs = world_info.town_square.iter().map(|s| *s).collect::<Vec<(i32, i32, i8)>>();
-    sqs.shuffle(&mut rng);
+    sqs.shuffle(rng);
 
     while !sqs.is_empty() {
         let sq = sqs.pop().unwrap();
@@ -705,63 +1074,88 @@ fn add_well(map: &mut Map, world_info: &WorldInfo) {
     }
 }
 
-pub fn create_town(map: &mut Map, game_obj_db: &mut GameObjectDB) -> WorldInfo {
-    // load the building templates
-    let mut buildings = HashMap::new();
+const BUILDING_CHARS: &str = "#`+|-T.btc";
+
+fn read_building_templates() -> (HashMap<String, Template>, Vec<String>) {
     let contents = fs::read_to_string("buildings.txt")
         .expect("Unable to find building templates file!");
-    let lines = contents
-        .lines()
-        .collect::<Vec<&str>>();
+
+    parse_building_templates(&contents)
+}
+
+// Parse the building templates, along with a list of any problems found with them. Every row of a
+// template has to be the same length and may only use the characters draw_building() knows how 
+// to turn into tiles.
+fn parse_building_templates(contents: &str) -> (HashMap<String, Template>, Vec<String>) {
+    let mut buildings = HashMap::new();
+    let mut errors = Vec::new();
     let mut curr_building: String = "".to_string();
-    let mut width: usize = 0;
-    let mut sqs: Vec<char> = Vec::new();
-    let mut rows: usize = 0;
+    let mut rows: Vec<&str> = Vec::new();
     let mut no_rotate = false;
-    for line in lines {
+    for line in contents.lines().chain(std::iter::once("%")) {
         if line.starts_with('%') {            
             if !curr_building.is_empty() {
-                let mut template = Template::new(width, rows, no_rotate);
-                template.sqs = sqs.clone();
+                let width = rows.iter().map(|r| r.chars().count()).max().unwrap_or(0);
+                if rows.iter().any(|r| r.chars().count() != width) {
+                    errors.push(format!("buildings.txt: {} has rows of different lengths", curr_building));
+                }
+                for ch in rows.iter().flat_map(|r| r.chars()) {
+                    if !BUILDING_CHARS.contains(ch) {
+                        errors.push(format!("buildings.txt: {} contains illegal character '{}'", curr_building, ch));
+                        break;
+                    }
+                }
+
+                let mut template = Template::new(width, rows.len(), no_rotate);
+                template.sqs = rows.iter().flat_map(|r| r.chars()).collect();
                 buildings.insert(curr_building.to_string(), template);
             }
 
             curr_building = line[1..].to_string();
-            rows = 0;
-            sqs = Vec::new();
+            rows = Vec::new();
             no_rotate = false;
         } else if line == "no rotate" {            
             no_rotate = true;
         } else {
-            width = line.len();
-            sqs.extend(line.chars());
-            rows += 1;
+            rows.push(line);
         }
     }
-    let mut template = Template::new(width, rows, no_rotate);
-    template.sqs = sqs;
-    buildings.insert(curr_building, template);
 
-    let mut rng = rand::thread_rng();
+    (buildings, errors)
+}
+
+pub fn validate_building_templates() -> Vec<String> {
+    read_building_templates().1
+}
+
+pub fn create_town(map: &mut Map, game_obj_db: &mut GameObjectDB, opts: TownOptions, rng: &mut GameRng) -> WorldInfo {
+    let buildings = read_building_templates().0;
+
     // // pick starting co-ordinates that are in the centre-ish part of the map
 	let start_r = rng.gen_range(WILDERNESS_SIZE /4 , WILDERNESS_SIZE / 2);
 	let start_c = rng.gen_range(WILDERNESS_SIZE /4 , WILDERNESS_SIZE / 2);
+    let (height, width) = opts.size.dimensions();
+    let town = TownLayout { r: start_r as i32, c: start_c as i32, height, width };
+
+    if opts.riverside {
+        draw_river_through_town(map, &town, rng);
+    }
 
     let mut tb = TownBuildings::new();
     let place_buildings_start = Instant::now();
-    place_town_buildings(map, start_r as i32, start_c as i32, &buildings, &mut tb);
+    place_town_buildings(map, &town, &buildings, &mut tb, opts.size.max_homes(), rng);
     let place_buildings_elapsed = place_buildings_start.elapsed();
     println!("Time to place buildings: {:?}", place_buildings_elapsed);
 
-    let tavern_name = random_tavern_name();
-    let town_name = random_town_name();
+    let tavern_name = random_tavern_name(rng);
+    let town_name = random_town_name(rng);
     let mut world_info = WorldInfo::new(town_name,
-        (start_r as i32, start_c as i32, start_r as i32 + 35, start_c as i32 + 60),
+        (town.r, town.c, town.r + town.height - 1, town.c + town.width),
         tavern_name);    
     
     // Mark the town square
-    let centre_row = start_r as i32 + TOWN_HEIGHT / 2;
-    let centre_col = start_c as i32 + TOWN_WIDTH / 2;
+    let centre_row = town.r + town.height / 2;
+    let centre_col = town.c + town.width / 2;
     for r in centre_row - 5 .. centre_row + 5 {
         for c in centre_col - 5 .. centre_col  + 5 {
             let loc = (r, c, 0);
@@ -771,42 +1165,156 @@ pub fn create_town(map: &mut Map, game_obj_db: &mut GameObjectDB) -> WorldInfo {
         }
     }
 
+    if opts.walled {
+        world_info.town_gates = build_town_wall(map, &town);
+    }
+
     let draw_paths_start = Instant::now();
-    draw_paths_in_town(map, &world_info);
+    draw_paths_in_town(map, &world_info, rng);
     let draw_paths_elapsed = draw_paths_start.elapsed();
     println!("Time to draw paths: {:?}", draw_paths_elapsed);
 
-    add_well(map, &world_info);
+    add_well(map, &world_info, rng);
     
     let mut used_names = HashSet::new();
-    let v = create_villager("mayor1", &mut tb, &used_names, game_obj_db);
+    let v = create_villager(map, "mayor1", &mut tb, &used_names, game_obj_db, rng);
     used_names.insert(v.get_fullname());
     let obj_id = v.obj_id();    
     game_obj_db.add(v);
     game_obj_db.listeners.insert((obj_id, EventType::TakeTurn));
 
-    let v = create_villager("villager1", &mut tb, &used_names, game_obj_db);
+    let v = create_villager(map, "villager1", &mut tb, &used_names, game_obj_db, rng);
     used_names.insert(v.get_fullname());
     let obj_id = v.obj_id();
     game_obj_db.add(v);
     game_obj_db.listeners.insert((obj_id, EventType::TakeTurn));
     
-    let ik = create_innkeeper(&tb, &used_names, game_obj_db);
+    let ik = create_innkeeper(&tb, &used_names, game_obj_db, rng);
     let obj_id = ik.obj_id();
     game_obj_db.add(ik);
     game_obj_db.listeners.insert((obj_id, EventType::TakeTurn));
 
-    let g = create_grocer(&tb, &used_names, game_obj_db);
+    let g = create_grocer(&tb, &used_names, game_obj_db, rng);
     let obj_id = g.obj_id();
     game_obj_db.add(g);
     game_obj_db.listeners.insert((obj_id, EventType::TakeTurn));
 
-    let s = create_smith(&tb, &used_names, game_obj_db);
+    let s = create_smith(&tb, &used_names, game_obj_db, rng);
     let obj_id = s.obj_id();
     game_obj_db.add(s);
     game_obj_db.listeners.insert((obj_id, EventType::TakeTurn));
 
+    let m = create_merchant(&used_names, game_obj_db, rng);
+    world_info.visitors.push(m);
+
     world_info.town_buildings = Some(tb);
 
     world_info
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_country() -> Map {
+        let mut map = Map::new();
+        for r in 0..WILDERNESS_SIZE as i32 {
+            for c in 0..WILDERNESS_SIZE as i32 {
+                map.insert((r, c, 0), Tile::Grass);
+            }
+        }
+
+        map
+    }
+
+    fn homes_in_town(size: TownSize, seed: u64) -> usize {
+        let mut map = open_country();
+        let mut game_obj_db = GameObjectDB::new();
+        let opts = TownOptions { size, ..TownOptions::new() };
+        let world_info = create_town(&mut map, &mut game_obj_db, opts, &mut GameRng::seeded(seed));
+
+        world_info.town_buildings.unwrap().homes.len()
+    }
+
+    #[test]
+    fn larger_towns_have_more_homes() {
+        for seed in 0..3 {
+            assert!(homes_in_town(TownSize::Large, seed) > homes_in_town(TownSize::Small, seed));
+        }
+    }
+
+    #[test]
+    fn night_watch_lets_the_player_through_the_gate() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut state = crate::tests::test_level(&mut game_obj_db);
+        let mut gui = crate::headless::HeadlessUI::new();
+        let gate = (5, 6, 1);
+        state.map.insert(gate, Tile::Gate(DoorState::Open));
+        state.world_info.town_gates = vec![gate];
+
+        state.turn = 15 * crate::TURNS_PER_HOUR;
+        check_town_gates(&mut state, &game_obj_db);
+        assert_eq!(state.map[&gate], Tile::Gate(DoorState::Closed));
+
+        assert_eq!(crate::do_move(&mut state, &mut game_obj_db, "E", &mut gui), 1.0);
+        assert_eq!(state.map[&gate], Tile::Gate(DoorState::Open));
+        assert_eq!(game_obj_db.get(0).unwrap().get_loc(), (5, 5, 1));
+
+        crate::do_move(&mut state, &mut game_obj_db, "E", &mut gui);
+        assert_eq!(game_obj_db.get(0).unwrap().get_loc(), gate);
+        check_town_gates(&mut state, &game_obj_db);
+        assert_eq!(state.map[&gate], Tile::Gate(DoorState::Open));
+
+        crate::do_move(&mut state, &mut game_obj_db, "E", &mut gui);
+        check_town_gates(&mut state, &game_obj_db);
+        assert_eq!(state.map[&gate], Tile::Gate(DoorState::Closed));
+    }
+
+    #[test]
+    fn riverside_town_is_bridged() {
+        let mut map = open_country();
+        let mut game_obj_db = GameObjectDB::new();
+        let world_info = create_town(&mut map, &mut game_obj_db, TownOptions { riverside: true, ..TownOptions::new() }, &mut GameRng::seeded(2186));
+        let b = world_info.town_boundary;
+
+        let in_town: Vec<Tile> = (b.0..=b.2).flat_map(|r| (b.1..=b.3).map(move |c| (r, c, 0)))
+                                             .map(|loc| map[&loc])
+                                             .collect();
+        assert!(in_town.contains(&Tile::DeepWater));
+        assert!(in_town.contains(&Tile::Bridge));
+
+        let square = *world_info.town_square.iter().next().unwrap();
+        let reachable = reachable_from_square(&map, &world_info, square);
+        for r in b.0..=b.2 {
+            for c in b.1..=b.3 {
+                if let Tile::Door(_) = map[&(r, c, 0)] {
+                    assert!(reachable.contains_key(&(r, c, 0)), "door at {:?} is cut off", (r, c));
+                }
+            }
+        }
+        assert!(!world_info.town_buildings.unwrap().homes.is_empty());
+    }
+
+    #[test]
+    fn bad_building_templates_are_reported_by_name() {
+        let contents = "%cottage\n#+#\n#.#\n%shack\n##\n#?\n%lean-to\n###\n#.\n";
+        let (buildings, errors) = parse_building_templates(contents);
+
+        assert_eq!(buildings.len(), 3);
+        assert_eq!(errors, vec!["buildings.txt: shack contains illegal character '?'".to_string(),
+            "buildings.txt: lean-to has rows of different lengths".to_string()]);
+    }
+
+    #[test]
+    fn rectangular_templates_rotate() {
+        let mut template = Template::new(5, 3, false);
+        template.sqs = "T####\n#.|.#\n##+##".lines().flat_map(|r| r.chars()).collect();
+
+        let rotated = rotate(&template);
+        assert_eq!((rotated.width, rotated.height), (3, 5));
+        // The top-left corner swings round to the top-right, the south door now faces west
+        // and the window is drawn for a wall running the other way
+        assert_eq!(rotated.sqs[2], 'T');
+        let expected: Vec<char> = "##T\n#.#\n+-#\n#.#\n###".lines().flat_map(|r| r.chars()).collect();
+        assert_eq!(rotated.sqs, expected);
+    }
+}
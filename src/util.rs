@@ -17,24 +17,99 @@
 
 extern crate rand;
 
-use rand::Rng;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
 
+use rand::{Rng, RngCore, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::Map;
+use crate::map::{DoorState, Tile};
 use crate::game_obj::GameObjectDB;
 use crate::npc::Pronouns;
 
 pub const ADJ: [(i32, i32); 8] = [(0, -1), (0, 1), (-1, 0), (1, 0), (-1, -1), (-1, 1), (1, -1), (1, 1)];
 
-pub fn std_guassian() -> f32 {
+// The RNG for game mechanics (attack and damage rolls, effect durations, monster spawns and loot,
+// NPC decisions, etc). World gen is handed one before the GameState exists and it then lives in
+// the GameState, rather than everyone calling thread_rng(), so that it can be swapped out for a
+// seeded one when I want a fight or a level to play out the same way every time.
+pub struct GameRng(StdRng);
+
+impl GameRng {
+	pub fn seeded(seed: u64) -> GameRng {
+		GameRng(StdRng::seed_from_u64(seed))
+	}
+}
+
+// Seeded off the thread rng rather than the OS so that it's cheap enough to swap in as a
+// placeholder while the real one is taken out of the GameState for a moment
+impl Default for GameRng {
+	fn default() -> GameRng {
+		GameRng(StdRng::from_rng(rand::thread_rng()).unwrap())
+	}
+}
+
+impl RngCore for GameRng {
+	fn next_u32(&mut self) -> u32 {
+		self.0.next_u32()
+	}
+
+	fn next_u64(&mut self) -> u64 {
+		self.0.next_u64()
+	}
+
+	fn fill_bytes(&mut self, dest: &mut [u8]) {
+		self.0.fill_bytes(dest)
+	}
+
+	fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+		self.0.try_fill_bytes(dest)
+	}
+}
+
+// Generic flood fill, used for finding valleys and caves during world gen, spreading noise, etc.
+// neighbours(pt) returns the squares that can be reached from pt along with the cost of stepping
+// to them. Returns every square reachable from start within max_cost and the cheapest cost of
+// getting there.
+pub fn floodfill<T, F>(start: T, max_cost: u32, mut neighbours: F) -> HashMap<T, u32>
+where
+	T: Copy + Eq + Hash,
+	F: FnMut(T) -> Vec<(T, u32)>,
+{
+	let mut reached: HashMap<T, u32> = HashMap::new();
+	let mut queue = VecDeque::new();
+	reached.insert(start, 0);
+	queue.push_back(start);
+
+	while let Some(pt) = queue.pop_front() {
+		let cost = reached[&pt];
+		for (n, step) in neighbours(pt) {
+			let total = cost.saturating_add(step);
+			if total > max_cost {
+				continue;
+			}
+			if reached.get(&n).is_none_or(|prev| total < *prev) {
+				reached.insert(n, total);
+				queue.push_back(n);
+			}
+		}
+	}
+
+	reached
+}
+
+pub fn std_guassian(rng: &mut GameRng) -> f32 {
 	let mut sum = 0.0;
 	for _ in 0..12 {
-		sum += rand::thread_rng().gen_range(0.0, 1.0);
+		sum += rng.gen_range(0.0, 1.0);
 	}
 
 	sum - 6.0
 }
 
-pub fn general_guassian(m: f32, sigma: f32) -> f32 {
-	std_guassian() * sigma + m
+pub fn general_guassian(m: f32, sigma: f32, rng: &mut GameRng) -> f32 {
+	std_guassian(rng) * sigma + m
 }
 
 pub fn num_to_nth(n: u8) -> String {
@@ -141,6 +216,29 @@ pub fn bresenham(r0: i32, c0: i32, r1: i32, c1: i32) -> Vec<(i32, i32)> {
 	pts
 }
 
+// Can something (a spell, a web, a thrown item) travel in a straight line between two squares?
+// Unlike FOV, barred windows and closed gates block effects too.
+pub fn line_of_effect(map: &Map, a: (i32, i32, i8), b: (i32, i32, i8)) -> bool {
+	if a.2 != b.2 {
+		return false;
+	}
+
+	let pts = bresenham(a.0, a.1, b.0, b.1);
+	for pt in pts.iter().skip(1) {
+		let loc = (pt.0, pt.1, a.2);
+		if loc == b {
+			break;
+		}
+		match map.get(&loc) {
+			Some(Tile::Gate(DoorState::Closed)) | Some(Tile::Gate(DoorState::Locked)) => return false,
+			Some(tile) if !tile.solid() => { },
+			_ => return false,
+		}
+	}
+
+	true
+}
+
 pub fn bresenham_circle(rc: i32, cc: i32, radius: i32) -> Vec<(i32, i32)> {
 	let mut pts = Vec::new();
 	let mut x = radius;
@@ -296,3 +394,43 @@ impl StringUtils for String {
 		}		
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn closed_gates_block_line_of_effect() {
+		let mut map = Map::new();
+		for c in 0..5 {
+			map.insert((0, c, 0), Tile::Grass);
+		}
+		assert!(line_of_effect(&map, (0, 0, 0), (0, 4, 0)));
+
+		map.insert((0, 2, 0), Tile::Gate(DoorState::Closed));
+		assert!(!line_of_effect(&map, (0, 0, 0), (0, 4, 0)));
+
+		map.insert((0, 2, 0), Tile::Gate(DoorState::Open));
+		assert!(line_of_effect(&map, (0, 0, 0), (0, 4, 0)));
+	}
+
+	#[test]
+	fn floodfill_reaches_exactly_what_it_can_afford() {
+		// '#' blocks, '~' costs 3 to step into and '.' costs 1
+		let grid = ["..#..",
+		            ".~#..",
+		            "....."];
+		let tile = |r: i32, c: i32| grid[r as usize].as_bytes()[c as usize] as char;
+		let reached = floodfill((0, 0), 4, |(r, c): (i32, i32)| {
+			[(-1, 0), (1, 0), (0, -1), (0, 1)].iter()
+				.map(|(dr, dc)| (r + dr, c + dc))
+				.filter(|(nr, nc)| *nr >= 0 && *nr < 3 && *nc >= 0 && *nc < 5 && tile(*nr, *nc) != '#')
+				.map(|(nr, nc)| ((nr, nc), if tile(nr, nc) == '~' { 3 } else { 1 }))
+				.collect()
+		});
+
+		let expected: HashMap<(i32, i32), u32> = [((0, 0), 0), ((0, 1), 1), ((1, 0), 1), ((2, 0), 2),
+			((2, 1), 3), ((1, 1), 4), ((2, 2), 4)].iter().copied().collect();
+		assert_eq!(reached, expected);
+	}
+}
@@ -15,27 +15,94 @@
 
 use std::collections::HashMap;
 
-use rand::{Rng, thread_rng};
+use rand::Rng;
 use rand::seq::SliceRandom;
 
 use super::Map;
 
 use crate::map::Tile;
 use crate::util;
+use crate::util::GameRng;
 use crate::world::WILDERNESS_SIZE;
 
-fn fuzz() -> f64 {
-	thread_rng().gen_range(-0.5, 0.5)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Biome {
+	Forest,
+	Plains,
+	Swamp,
+	Desert,
 }
 
-fn diamond_step(grid: &mut [f64], r: usize, c: usize, width: usize) {
+impl Biome {
+	// Odds a square of open ground starts out as a tree before the cellular automata 
+	// in lay_down_trees() smooths things out
+	fn tree_density(&self) -> f64 {
+		match self {
+			Biome::Forest => 0.6,
+			Biome::Plains => 0.35,
+			Biome::Swamp => 0.5,
+			Biome::Desert => 0.2,
+		}
+	}
+}
+
+// Biomes are a Voronoi diagram over a handful of random seed points. When picking which
+// seed is closest, each square's distance is fuzzed a bit so the borders between regions 
+// are ragged instead of straight lines.
+fn biome_map(rng: &mut GameRng) -> Vec<Biome> {
+	let mut seeds = Vec::new();
+	let all = [Biome::Forest, Biome::Plains, Biome::Swamp, Biome::Desert];
+	for j in 0..rng.gen_range(6, 10) {
+		let r = rng.gen_range(0, WILDERNESS_SIZE) as i32;
+		let c = rng.gen_range(0, WILDERNESS_SIZE) as i32;
+		// Make sure each biome shows up at least once
+		let biome = if j < all.len() { all[j] } else { *all.choose(rng).unwrap() };
+		seeds.push((r, c, biome));
+	}
+
+	let mut biomes = Vec::with_capacity(WILDERNESS_SIZE * WILDERNESS_SIZE);
+	for r in 0..WILDERNESS_SIZE as i32 {
+		for c in 0..WILDERNESS_SIZE as i32 {
+			let closest = seeds.iter()
+				.map(|s| (util::distance(r, c, s.0, s.1) + rng.gen_range(0.0, 8.0), s.2))
+				.min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+				.unwrap();
+			biomes.push(closest.1);
+		}
+	}
+
+	biomes
+}
+
+fn biome_at(biomes: &[Biome], loc: &(i32, i32, i8)) -> Biome {
+	biomes[loc.0 as usize * WILDERNESS_SIZE + loc.1 as usize]
+}
+
+// After the trees are in, deserts dry out into sand (keeping the odd tree as a bit of scrub) and
+// swamps get pools of shallow water.
+fn apply_biomes(map: &mut Map, biomes: &[Biome], rng: &mut GameRng) {
+	for (loc, tile) in map.iter_mut() {
+		match (biome_at(biomes, loc), *tile) {
+			(Biome::Desert, Tile::Grass) => *tile = Tile::Sand,
+			(Biome::Desert, Tile::Tree) if rng.gen_range(0.0, 1.0) < 0.85 => *tile = Tile::Sand,
+			(Biome::Swamp, Tile::Grass) if rng.gen_range(0.0, 1.0) < 0.35 => *tile = Tile::Water,
+			_ => { },
+		}
+	}
+}
+
+fn fuzz(rng: &mut GameRng) -> f64 {
+	rng.gen_range(-0.5, 0.5)
+}
+
+fn diamond_step(grid: &mut [f64], r: usize, c: usize, width: usize, rng: &mut GameRng) {
 	let avg = (grid[WILDERNESS_SIZE * r + c] + grid[WILDERNESS_SIZE * r + c + width - 1] +
 					grid[(r + width - 1) * WILDERNESS_SIZE + c] + grid[(r + width - 1) * WILDERNESS_SIZE + c + width - 1]) / 4.0;
 	
-	grid[(r + width / 2) * WILDERNESS_SIZE + c + width / 2] = avg + fuzz();
+	grid[(r + width / 2) * WILDERNESS_SIZE + c + width / 2] = avg + fuzz(rng);
 }
 
-fn calc_diamond_avg(grid: &mut [f64], r: usize, c: usize, width: usize) {
+fn calc_diamond_avg(grid: &mut [f64], r: usize, c: usize, width: usize, rng: &mut GameRng) {
 	let mut count = 0;
 	let mut avg = 0.0;
 	if width <= c {
@@ -55,31 +122,31 @@ fn calc_diamond_avg(grid: &mut [f64], r: usize, c: usize, width: usize) {
 		count += 1;
 	}
 	
-	grid[r * WILDERNESS_SIZE + c] = avg / count as f64 + fuzz();
+	grid[r * WILDERNESS_SIZE + c] = avg / count as f64 + fuzz(rng);
 }
 
-fn square_step(grid: &mut [f64], r: usize, c: usize, width: usize) {
+fn square_step(grid: &mut [f64], r: usize, c: usize, width: usize, rng: &mut GameRng) {
 	let half_width = width / 2;
 
-	calc_diamond_avg(grid, r - half_width, c, half_width);
-	calc_diamond_avg(grid, r + half_width, c, half_width);
-	calc_diamond_avg(grid, r, c - half_width, half_width);
-	calc_diamond_avg(grid, r, c + half_width, half_width);
+	calc_diamond_avg(grid, r - half_width, c, half_width, rng);
+	calc_diamond_avg(grid, r + half_width, c, half_width, rng);
+	calc_diamond_avg(grid, r, c - half_width, half_width, rng);
+	calc_diamond_avg(grid, r, c + half_width, half_width, rng);
 }
 
-fn midpoint_displacement(grid: &mut [f64], r: usize, c: usize, width: usize) {
-	diamond_step(grid, r, c, width);
+fn midpoint_displacement(grid: &mut [f64], r: usize, c: usize, width: usize, rng: &mut GameRng) {
+	diamond_step(grid, r, c, width, rng);
 	let half_width = width / 2;
-	square_step(grid, r + half_width, c + half_width, width);
+	square_step(grid, r + half_width, c + half_width, width, rng);
 
 	if half_width == 1 {
 		return;
 	}
 
-	midpoint_displacement(grid, r, c, half_width + 1);
-	midpoint_displacement(grid, r, c + half_width, half_width + 1);
-	midpoint_displacement(grid, r + half_width, c, half_width + 1);
-	midpoint_displacement(grid, r + half_width, c + half_width, half_width + 1);
+	midpoint_displacement(grid, r, c, half_width + 1, rng);
+	midpoint_displacement(grid, r, c + half_width, half_width + 1, rng);
+	midpoint_displacement(grid, r + half_width, c, half_width + 1, rng);
+	midpoint_displacement(grid, r + half_width, c + half_width, half_width + 1, rng);
 }
 
 // Average each point with its neighbours to smooth things out
@@ -130,7 +197,7 @@ fn smooth_map(grid: &mut [f64]) {
 	}
 }
 
-fn translate_to_tile(grid: &[f64]) -> Map {
+fn translate_to_tile(grid: &[f64], rng: &mut GameRng) -> Map {
 	let mut map = HashMap::new();
 
 	for r in 0..WILDERNESS_SIZE {
@@ -140,7 +207,7 @@ fn translate_to_tile(grid: &[f64]) -> Map {
 			} else if grid[r * WILDERNESS_SIZE + c] < 6.0 {
 				map.insert((r as i32, c as i32, 0), Tile::Grass);
 			} else {
-				if thread_rng().gen_range(0.0, 1.0) < 0.9 {
+				if rng.gen_range(0.0, 1.0) < 0.9 {
 					map.insert((r as i32, c as i32, 0), Tile::Mountain);
 				} else {
 					map.insert((r as i32, c as i32, 0), Tile::SnowPeak);
@@ -159,8 +226,7 @@ fn next_point(r: i32, c: i32, d: i32, angle: f64) -> (i32, i32, i8) {
 	(next_r, next_c, 0)
 }
 
-fn draw_river(map: &mut Map, start: (i32, i32, i8), angle: f64) {
-	let mut rng = rand::thread_rng();
+fn draw_river(map: &mut Map, start: (i32, i32, i8), angle: f64, rng: &mut GameRng) -> Vec<(i32, i32, i8)> {
 	let mut row = start.0;
 	let mut col = start.1;
 	let mut pts = Vec::new();
@@ -223,13 +289,125 @@ fn draw_river(map: &mut Map, start: (i32, i32, i8), angle: f64) {
 	for pt in extra_pts.iter() {
 		map.insert(*pt, Tile::DeepWater);
 	}
+
+	pts
+}
+
+// Rivers are deep water but every so often there's a shallow spot where it can be waded
+// across. Fords go where there's open ground on both banks, not in the mountains.
+fn add_fords(map: &mut Map, river: &[(i32, i32, i8)], rng: &mut GameRng) {
+	let open_ground = |map: &Map, loc: (i32, i32, i8)| matches!(map.get(&loc), Some(Tile::Grass) | Some(Tile::Tree) | Some(Tile::Sand) | Some(Tile::Dirt));
+	let candidates: Vec<(i32, i32, i8)> = river.iter()
+		.filter(|pt| {
+			let banks = util::ADJ.iter().filter(|a| open_ground(map, (pt.0 + a.0, pt.1 + a.1, 0))).count();
+			banks >= 3
+		})
+		.copied()
+		.collect();
+
+	let mut fords: Vec<(i32, i32, i8)> = Vec::new();
+	let num_of_fords = (river.len() / 40).max(1);
+	for _ in 0..num_of_fords * 3 {
+		if fords.len() >= num_of_fords {
+			break;
+		}
+		if let Some(pt) = candidates.choose(rng) {
+			if fords.iter().any(|f| util::distance(f.0, f.1, pt.0, pt.1) < 20.0) {
+				continue;
+			}
+			map.insert(*pt, Tile::Water);
+			for a in util::ADJ.iter() {
+				let n = (pt.0 + a.0, pt.1 + a.1, 0);
+				if map.get(&n) == Some(&Tile::DeepWater) {
+					map.insert(n, Tile::Water);
+				}
+			}
+			fords.push(*pt);
+		}
+	}
+}
+
+// A few lakes scattered around the lowlands: deep in the middle with a shallow margin. A lake
+// that would wall off one stretch of land from another (say by plugging a mountain pass) is
+// drained again, since that could leave the town cut off from the dungeon.
+fn add_lakes(map: &mut Map, rng: &mut GameRng) {
+	for _ in 0..rng.gen_range(2, 5) {
+		let r = rng.gen_range(20, WILDERNESS_SIZE - 20) as i32;
+		let c = rng.gen_range(20, WILDERNESS_SIZE - 20) as i32;
+		if !matches!(map.get(&(r, c, 0)), Some(Tile::Grass) | Some(Tile::Tree) | Some(Tile::Sand)) {
+			continue;
+		}
+
+		let radius = rng.gen_range(3, 7) as f64;
+		let mut replaced = Vec::new();
+		for dr in -8..=8 {
+			for dc in -8..=8 {
+				let loc = (r + dr, c + dc, 0);
+				let d = util::distance(r, c, loc.0, loc.1) + rng.gen_range(-0.5, 0.5);
+				let prev = match map.get(&loc) {
+					Some(t) if matches!(t, Tile::Grass | Tile::Tree | Tile::Sand) => *t,
+					_ => continue,
+				};
+				if d < radius {
+					map.insert(loc, Tile::DeepWater);
+					replaced.push((loc, prev));
+				} else if d < radius + 1.5 {
+					map.insert(loc, Tile::Water);
+					replaced.push((loc, prev));
+				}
+			}
+		}
+
+		if lake_cuts_off_land(map, (r, c, 0)) {
+			for (loc, prev) in replaced {
+				map.insert(loc, prev);
+			}
+		}
+	}
+}
+
+// Check that all the dry land bordering the deep part of a lake can still reach the rest of it
+// without swimming. The search is kept to the neighbourhood of the lake, so land that only joins
+// up by going the long way round counts as cut off, which is the cautious answer.
+fn lake_cuts_off_land(map: &Map, centre: (i32, i32, i8)) -> bool {
+	let near_lake = |loc: &(i32, i32, i8)| (loc.0 - centre.0).abs() <= 20 && (loc.1 - centre.1).abs() <= 20;
+	let dry = |loc: &(i32, i32, i8)| matches!(map.get(loc), Some(t) if t.passable_dry_land());
+
+	let mut shore = Vec::new();
+	for dr in -9..=9 {
+		for dc in -9..=9 {
+			let loc = (centre.0 + dr, centre.1 + dc, 0);
+			if map.get(&loc) != Some(&Tile::DeepWater) {
+				continue;
+			}
+			for a in util::ADJ.iter() {
+				let n = (loc.0 + a.0, loc.1 + a.1, 0);
+				if dry(&n) {
+					shore.push(n);
+				}
+			}
+		}
+	}
+
+	let start = match shore.first() {
+		Some(loc) => *loc,
+		None => return false,
+	};
+	let reached = util::floodfill(start, 200, |sq: (i32, i32, i8)| {
+		util::ADJ.iter()
+				 .map(|a| (sq.0 + a.0, sq.1 + a.1, 0))
+				 .filter(|n| near_lake(n) && dry(n))
+				 .map(|n| (n, 1))
+				 .collect()
+	});
+
+	shore.iter().any(|loc| !reached.contains_key(loc))
 }
 
-fn river_start(map: &Map, col_lo: usize, col_hi: usize) -> Option<(i32, i32, i8)> {
-	let mut rng = rand::thread_rng();
+fn river_start(map: &Map, col_lo: usize, col_hi: usize, rng: &mut GameRng) -> Option<(i32, i32, i8)> {
 	let x = WILDERNESS_SIZE / 3;
 
-	loop {
+	for _ in 0..500 {
 		let r = rng.gen_range(WILDERNESS_SIZE - x, WILDERNESS_SIZE - 2);
 		let c = rng.gen_range(col_lo, col_hi);
 
@@ -238,31 +416,35 @@ fn river_start(map: &Map, col_lo: usize, col_hi: usize) -> Option<(i32, i32, i8)
 			return Some((r as i32, c as i32, 0));
 		}
 	}
+
+	None
 }
 
-fn draw_rivers(map: &mut Map) {
+fn draw_rivers(map: &mut Map, rng: &mut GameRng) {
 	// Try to draw up to three rivers on the map
-	let mut rng = rand::thread_rng();
 	let mut opts = [0, 1, 2];
-	opts.shuffle(&mut rng);
+	opts.shuffle(rng);
 
 	let mut passes = 0;
 	for opt in opts.iter() {
-		if passes == 0 || rand::thread_rng().gen_range(0.0, 1.0) < 0.5 {
+		if passes == 0 || rng.gen_range(0.0, 1.0) < 0.5 {
 			if *opt == 0 {
-				if let Some(loc) = river_start(map, 2, WILDERNESS_SIZE / 3) {
+				if let Some(loc) = river_start(map, 2, WILDERNESS_SIZE / 3, rng) {
 					let angle = -0.28;
-					draw_river(map, loc, angle);
+					let river = draw_river(map, loc, angle, rng);
+					add_fords(map, &river, rng);
 				}
 			} else if *opt == 1 {
-				if let Some(loc) = river_start(map, WILDERNESS_SIZE / 3, (WILDERNESS_SIZE / 3) * 2) {
+				if let Some(loc) = river_start(map, WILDERNESS_SIZE / 3, (WILDERNESS_SIZE / 3) * 2, rng) {
 					let angle = -1.5;
-					draw_river(map, loc, angle);
+					let river = draw_river(map, loc, angle, rng);
+					add_fords(map, &river, rng);
 				}
 			} else {
-				if let Some(loc) = river_start(map, WILDERNESS_SIZE - WILDERNESS_SIZE / 3 - 2, WILDERNESS_SIZE - 2) {
+				if let Some(loc) = river_start(map, WILDERNESS_SIZE - WILDERNESS_SIZE / 3 - 2, WILDERNESS_SIZE - 2, rng) {
 					let angle = -2.5;
-					draw_river(map, loc, angle);
+					let river = draw_river(map, loc, angle, rng);
+					add_fords(map, &river, rng);
 				}
 			}
 		}
@@ -284,14 +466,14 @@ fn count_neighbouring_terrain(map: &Map, loc: (i32, i32, i8), tile: Tile) -> u32
 }
 
 // Lay down trees using a cellular automata rule starting with a
-// 50/50 mix of trees and grass
-fn lay_down_trees(map: &mut Map) -> Map {
+// mix of trees and grass (how many trees depends on the biome)
+fn lay_down_trees(map: &mut Map, biomes: &[Biome], rng: &mut GameRng) -> Map {
 	let keys = map.keys()
 				  .map(|k| *k)
 				  .collect::<Vec<(i32, i32, i8)>>();
 	
 	for k in &keys {
-		if map[&k] == Tile::Grass && thread_rng().gen_range(0.0, 1.0) < 0.5 {
+		if map[&k] == Tile::Grass && rng.gen_range(0.0, 1.0) < biome_at(biomes, k).tree_density() {
 			map.insert(*k, Tile::Tree);
 		}
 	}
@@ -327,8 +509,7 @@ fn lay_down_trees(map: &mut Map) -> Map {
 	result
 }
 
-fn draw_borders(map: &mut Map) {
-	let mut rng = rand::thread_rng();
+fn draw_borders(map: &mut Map, rng: &mut GameRng) {
 	for col in 0..WILDERNESS_SIZE {
 		for row in 0..rng.gen_range(5, 11) {
 			map.insert((row as i32, col as i32, 0), Tile::DeepWater);
@@ -352,20 +533,64 @@ fn draw_borders(map: &mut Map) {
 	}
 }
 
-pub fn gen_wilderness_map() -> Map {
+pub fn gen_wilderness_map(rng: &mut GameRng) -> Map {
 	let mut grid: [f64; WILDERNESS_SIZE * WILDERNESS_SIZE] = [0.0; WILDERNESS_SIZE * WILDERNESS_SIZE];
-	grid[0] = thread_rng().gen_range(-1.0, 1.0);
-	grid[WILDERNESS_SIZE - 1] = thread_rng().gen_range(1.0, 2.5);
-	grid[(WILDERNESS_SIZE - 1) * WILDERNESS_SIZE] = thread_rng().gen_range(10.0, 12.0);
-	grid[ WILDERNESS_SIZE * WILDERNESS_SIZE - 1] = thread_rng().gen_range(9.0, 11.0);
+	grid[0] = rng.gen_range(-1.0, 1.0);
+	grid[WILDERNESS_SIZE - 1] = rng.gen_range(1.0, 2.5);
+	grid[(WILDERNESS_SIZE - 1) * WILDERNESS_SIZE] = rng.gen_range(10.0, 12.0);
+	grid[ WILDERNESS_SIZE * WILDERNESS_SIZE - 1] = rng.gen_range(9.0, 11.0);
 
-	midpoint_displacement(&mut grid, 0, 0, WILDERNESS_SIZE);
+	midpoint_displacement(&mut grid, 0, 0, WILDERNESS_SIZE, rng);
 	smooth_map(&mut grid);
 
-	let mut map = translate_to_tile(&grid);
-	lay_down_trees(&mut map);
-	draw_rivers(&mut map);
-	draw_borders(&mut map);
+	let mut map = translate_to_tile(&grid, rng);
+	let biomes = biome_map(rng);
+	lay_down_trees(&mut map, &biomes, rng);
+	apply_biomes(&mut map, &biomes, rng);
+	add_lakes(&mut map, rng);
+	draw_rivers(&mut map, rng);
+	draw_borders(&mut map, rng);
 
 	map
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// A valley with mountains down the middle, crossed by a single pass at rows 28-31
+	fn valley_with_pass() -> Map {
+		let mut map = Map::new();
+		for r in 0..60 {
+			for c in 0..60 {
+				let tile = if c == 30 && !(28..32).contains(&r) { Tile::Mountain } else { Tile::Grass };
+				map.insert((r, c, 0), tile);
+			}
+		}
+
+		map
+	}
+
+	#[test]
+	fn lake_plugging_a_pass_cuts_off_land() {
+		let mut map = valley_with_pass();
+		for r in 27..33 {
+			for c in 29..32 {
+				map.insert((r, c, 0), Tile::DeepWater);
+			}
+		}
+		assert!(lake_cuts_off_land(&map, (30, 30, 0)));
+	}
+
+	#[test]
+	fn lake_in_open_country_leaves_land_connected() {
+		let mut map = valley_with_pass();
+		for r in 8..14 {
+			for c in 10..16 {
+				map.insert((r, c, 0), Tile::DeepWater);
+			}
+		}
+		assert!(!lake_cuts_off_land(&map, (11, 12, 0)));
+		assert!(!lake_cuts_off_land(&map, (30, 30, 0)));
+	}
+}
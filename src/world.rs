@@ -15,9 +15,9 @@
 
 extern crate serde;
 
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{HashMap, HashSet};
 use std::time::Instant;
-use rand::{prelude::{IteratorRandom}, thread_rng};
+use rand::prelude::{IteratorRandom, SliceRandom};
 use rand::Rng;
 use serde::{Serialize, Deserialize};
 
@@ -28,14 +28,21 @@ use crate::dungeon;
 use crate::dungeon::Vault;
 use crate::game_obj::{GameObject, GameObjects, GameObjectDB};
 use crate::items::{GoldPile, Item};
+use crate::magic;
 use crate::map::{DoorState, ShrineType, SpecialSquare, Tile};
 use crate::town;
-use crate::town::TownBuildings;
+use crate::town::{TownBuildings, TownOptions};
 use crate::pathfinding;
 use crate::util;
+use crate::util::GameRng;
 use crate::wilderness;
 
 pub const WILDERNESS_SIZE: usize = 257;
+pub const DEFAULT_DUNGEON_DEPTH: u8 = 5;
+// Dungeon levels are stored in the i8 z co-ordinate of a loc, so keep well clear of its limit
+pub const MAX_DUNGEON_DEPTH: u8 = 30;
+// How far from its leader the rest of a pack is placed
+const PACK_RADIUS: f64 = 3.0;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Fact {
@@ -59,58 +66,62 @@ pub struct WorldInfo {
     pub tavern_name: String,
     pub town_buildings: Option<TownBuildings>,
     pub player_name: String,
+    // NPCs who only periodically come to town (like the travelling merchant) are 
+    // kept here, out of the GameObjectDB, while they are away
+    pub visitors: Vec<GameObjects>,
+    pub visiting: Vec<usize>,
+    pub stash: Vec<GameObjects>, // items the player has left in storage at the inn
+    pub town_gates: Vec<(i32, i32, i8)>, // empty if the town isn't walled
+    pub dungeon_depth: u8,
 }
 
 impl WorldInfo {
     pub fn new(town_name: String, town_boundary: (i32, i32, i32, i32), tavern_name: String) -> WorldInfo {
         WorldInfo { town_name, facts: Vec::new(), town_boundary, town_square: HashSet::new(),
-            tavern_name, town_buildings: None, player_name: "".to_string() }
+            tavern_name, town_buildings: None, player_name: "".to_string(), visitors: Vec::new(), visiting: Vec::new(),
+            stash: Vec::new(), town_gates: Vec::new(), dungeon_depth: DEFAULT_DUNGEON_DEPTH }
     }
-}
 
-// The random wilderness generator will inevitably create pockets of
-// traversable land complately surrounded by mountains. I don't want to 
-// stick the main dungeon in one of those, and they might also be useful
-// for hidden secrets later on.
-// I could/should generalize this so it takes a set of tiles to ignore and
-// then I can search for any kind of distinct pockets on any level
-fn find_valley(map: &Map, start_loc: (i32, i32, i8)) -> HashSet<(i32, i32, i8)> {
-    let mut queue = vec![start_loc];
-    let mut visited = HashSet::new();
-
-    while !queue.is_empty() {
-        let loc = queue.pop().unwrap();
-        visited.insert(loc);
+    pub fn level_has(&self, detail: &str, level: i8) -> bool {
+        self.facts.iter().any(|f| f.detail == detail && f.location.2 == level)
+    }
 
-        // I'm going to only consider adjacent NESW squares in case I later decide
-        // diaganol movement isn't a thing 
-        let nl = (loc.0 - 1, loc.1, loc.2);
-        if !visited.contains(&nl) && map.contains_key(&nl) {
-            if map[&nl] != Tile::Mountain && map[&nl] != Tile::SnowPeak { 
-                queue.push(nl); 
-            }
+    // The messages to give the player the first time they arrive on a dungeon level, hinting at
+    // what they're in for
+    pub fn level_feelings(&self, level: i8) -> Vec<&'static str> {
+        let mut feelings = Vec::new();
+        if self.level_has("darkness", level) {
+            feelings.push("The darkness here seems to swallow the light.");
         }
-        let nl = (loc.0 + 1, loc.1, loc.2);
-        if !visited.contains(&nl) && map.contains_key(&nl) {
-            if map[&nl] != Tile::Mountain && map[&nl] != Tile::SnowPeak { 
-                queue.push(nl); 
-            }
+        if self.level_has("caves", level) {
+            feelings.push("You hear the groan of shifting stone.");
         }
-        let nl = (loc.0, loc.1 - 1, loc.2);
-        if !visited.contains(&nl) && map.contains_key(&nl) {
-            if map[&nl] != Tile::Mountain && map[&nl] != Tile::SnowPeak { 
-                queue.push(nl); 
-            }
+        if self.level_has("river", level) {
+            feelings.push("You hear the roar of rushing water.");
         }
-        let nl = (loc.0, loc.1 + 1, loc.2);
-        if !visited.contains(&nl) && map.contains_key(&nl) {
-            if map[&nl] != Tile::Mountain && map[&nl] != Tile::SnowPeak { 
-                queue.push(nl); 
-            }
+        if self.level_has("fog", level) {
+            feelings.push("A clammy mist hangs in the air.");
         }
+
+        feelings
     }
-    
-    visited
+}
+
+// The random wilderness generator will inevitably create pockets of
+// traversable land complately surrounded by mountains. I don't want to 
+// stick the main dungeon in one of those, and they might also be useful
+// for hidden secrets later on.
+fn find_valley(map: &Map, start_loc: (i32, i32, i8)) -> HashSet<(i32, i32, i8)> {
+    // I'm going to only consider adjacent NESW squares in case I later decide
+    // diaganol movement isn't a thing 
+    util::floodfill(start_loc, u32::MAX, |loc| {
+        [(-1, 0), (1, 0), (0, -1), (0, 1)].iter()
+            .map(|d| (loc.0 + d.0, loc.1 + d.1, loc.2))
+            .filter(|nl| matches!(map.get(nl), Some(t) if *t != Tile::Mountain && *t != Tile::SnowPeak))
+            .map(|nl| (nl, 1))
+            .collect()
+    }).into_keys()
+      .collect()
 }
 
 pub fn find_all_valleys(map: &Map) -> Vec<HashSet<(i32, i32, i8)>> {
@@ -158,12 +169,31 @@ fn check_entrance_candidate(map: &Map, loc: (i32, i32, i8)) -> (u8, u8) {
     (adj_mountains, adj_water)
 }
 
+// The squares the player can walk to from town without swimming. A valley can include
+// rivers and lakes, so being in the main valley doesn't mean a square is reachable on foot.
+fn reachable_over_land(map: &Map, start: (i32, i32, i8)) -> HashSet<(i32, i32, i8)> {
+    util::floodfill(start, u32::MAX, |loc| {
+        util::ADJ.iter()
+            .map(|d| (loc.0 + d.0, loc.1 + d.1, loc.2))
+            .filter(|nl| matches!(map.get(nl), Some(t) if t.passable_dry_land()))
+            .map(|nl| (nl, 1))
+            .collect()
+    }).into_keys()
+      .collect()
+}
+
 // We want the entrance to the main dungeon to be nicely nestled into the mountains so we'll look
-// for locations that are surround by at least 4 mountains
-fn find_good_dungeon_entrance(map: &Map, sqs: &HashSet<(i32, i32, i8)>) -> (i32, i32, i8) {
+// for locations that are surround by at least 4 mountains. It also has to be somewhere the player
+// can actually walk to from town.
+fn find_good_dungeon_entrance(map: &Map, sqs: &HashSet<(i32, i32, i8)>, reachable: &HashSet<(i32, i32, i8)>, rng: &mut GameRng) -> (i32, i32, i8) {
     let mut options = Vec::new();
+    let mut fallbacks = Vec::new();
 
     for loc in sqs {
+        if !reachable.contains(loc) {
+            continue;
+        }
+
         let (adj_mountains, adj_water) = check_entrance_candidate(map, *loc);
         if adj_mountains + adj_water > 7 {
             continue;
@@ -171,29 +201,31 @@ fn find_good_dungeon_entrance(map: &Map, sqs: &HashSet<(i32, i32, i8)>) -> (i32,
 
         if adj_mountains >= 4 {
             options.push(loc);
+        } else if adj_mountains >= 2 {
+            fallbacks.push(loc);
         }
     }
 
-    let j = thread_rng().gen_range(0, options.len());
+    // If there's nowhere snug in the mountains the player can reach, settle for the foothills
+    if options.is_empty() {
+        options = fallbacks;
+    }
+
+    if options.is_empty() {
+        return *reachable.iter().choose(rng).unwrap();
+    }
+
+    let j = rng.gen_range(0, options.len());
     *options[j]
 }
 
-// Add an old road leading away from the dungeon that eventually trails off
-// I think this sometimes hits an infinite loop looking for a good path for the 
-// trail, which suggests there's probably some degenerate cases for placing the
-// dungeon are actually impassable. But I have to debug that a bunch as it 
-// sometimes places the entrance in bad places like a mountain river with no
-// ground access
-fn add_old_road(map: &mut Map, start: (i32, i32, i8)) {
-    let mut rng = rand::thread_rng();
-    let mut passable = HashMap::new();
-    passable.insert(Tile::Grass, 1.0);
-    passable.insert(Tile::Dirt, 1.0);
-    passable.insert(Tile::Tree, 1.0);
-    passable.insert(Tile::StoneFloor, 1.0);
-    passable.insert(Tile::DeepWater, 1.0);
+// Add an old road leading away from the dungeon that eventually trails off. If we can't
+// find anywhere for the trail to go after a fair number of tries, the dungeon just goes
+// without one.
+fn add_old_road(map: &mut Map, start: (i32, i32, i8), rng: &mut GameRng) {
+    let passable = pathfinding::old_road_passable();
 
-    loop {
+    for _ in 0..100 {
         let row = start.0 - rng.gen_range(10, 20);
         let col = rng.gen_range(start.1 - 15, start.1 + 15);
         
@@ -215,19 +247,45 @@ fn add_old_road(map: &mut Map, start: (i32, i32, i8)) {
     }
 }
 
-fn random_sq<T: Copy>(sqs: &HashSet<T>) -> T {
+fn random_sq<T: Copy>(sqs: &HashSet<T>, rng: &mut GameRng) -> T {
     // I can't believe this is the easiest way I've found to pick a random element
     // from a HashSet T_T
     //
     // In C# you can just access HashSets by index :/
-    let mut rng = rand::thread_rng();
     let items = sqs.iter().copied();
                 
-    items.choose(&mut rng).unwrap()
+    items.choose(rng).unwrap()
+}
+
+// Dig a tunnel from sq to the nearest floor square. Used when two levels have no
+// floor squares in common, to give a forced stairway somewhere to connect to.
+fn tunnel_to_floor(level: &mut [Tile], open: &mut HashSet<(usize, usize)>, sq: (usize, usize), width: usize) {
+    let target = open.iter()
+                     .min_by_key(|o| (o.0 as i32 - sq.0 as i32).abs().max((o.1 as i32 - sq.1 as i32).abs()))
+                     .copied();
+    if let Some(target) = target {
+        let (mut r, mut c) = (sq.0 as i32, sq.1 as i32);
+        loop {
+            if level[r as usize * width + c as usize] != Tile::StoneFloor {
+                level[r as usize * width + c as usize] = Tile::StoneFloor;
+                open.insert((r as usize, c as usize));
+            }
+            if (r as usize, c as usize) == target {
+                break;
+            }
+            // Only ever step orthogonally, since creatures can't squeeze between wall corners
+            if r != target.0 as i32 {
+                r += (target.0 as i32 - r).signum();
+            } else {
+                c += (target.1 as i32 - c).signum();
+            }
+        }
+    }
 }
 
-fn set_stairs(dungeon: &mut Vec<Vec<Tile>>, width: usize, height: usize) -> (usize, usize) {
-    let mut rng = rand::thread_rng();
+// Returns the location of the entrance on level 1 along with the location of the stairs down
+// on each level (which are also the location of the stairs up on the level below)
+fn set_stairs(dungeon: &mut [Vec<Tile>], width: usize, height: usize, rng: &mut GameRng) -> ((usize, usize), Vec<(usize, usize)>) {
     let mut open_sqs = Vec::new();
     for (_, level) in dungeon.iter().enumerate() {
         let mut open = HashSet::new();
@@ -243,26 +301,36 @@ fn set_stairs(dungeon: &mut Vec<Vec<Tile>>, width: usize, height: usize) -> (usi
 
     // First find the up stairs on level 1 of the dungeon, which is the entrance. Just grab
     // any ol' open square 
-    let entrance = random_sq(&open_sqs[0]);
+    let entrance = random_sq(&open_sqs[0], rng);
     dungeon[0][entrance.0 * width + entrance.1] = Tile::StairsUp;
     open_sqs[0].remove(&entrance);
 
     // I wanted the levels of my dungeon to be aligned. (Ie., if the stairs down from level 3 are at 4,16 then
     // the stairs back up on level 4 will be at 4,16 as well)
+    let mut down_stairs = Vec::new();
     for n in 0..dungeon.len() - 1 {
         let options = open_sqs[n].intersection(&open_sqs[n + 1]);
-        let stairs = options.choose(&mut rng).unwrap().clone();
+        let stairs = match options.choose(rng) {
+            Some(sq) => *sq,
+            None => {
+                // The levels don't overlap anywhere so pick a spot on the upper level and
+                // tunnel out from it on the lower level.
+                let sq = random_sq(&open_sqs[n], rng);
+                tunnel_to_floor(&mut dungeon[n + 1], &mut open_sqs[n + 1], sq, width);
+                sq
+            },
+        };
         dungeon[n][stairs.0 * width + stairs.1] = Tile::StairsDown;
         dungeon[n + 1][stairs.0 * width + stairs.1] = Tile::StairsUp;
         open_sqs[n].remove(&stairs);
         open_sqs[n + 1].remove(&stairs);        
+        down_stairs.push(stairs);
     }
 
-    entrance
+    (entrance, down_stairs)
 }
 
-fn random_open_adj(open: &HashSet<(i32, i32, i8)>, loc: (i32, i32, i8)) -> Option<(i32, i32, i8)> {
-    let mut rng = rand::thread_rng();
+fn random_open_adj(open: &HashSet<(i32, i32, i8)>, loc: (i32, i32, i8), rng: &mut GameRng) -> Option<(i32, i32, i8)> {
     let options = util::ADJ.iter()
                            .map(|d| (loc.0 + d.0, loc.1 + d.1, loc.2))
                            .filter(|adj| open.contains(&adj))
@@ -275,15 +343,14 @@ fn random_open_adj(open: &HashSet<(i32, i32, i8)>, loc: (i32, i32, i8)) -> Optio
     }
 }
 
-fn add_fire_pit(level: usize, map: &mut Map, floor_sqs: &mut HashMap<usize, HashSet<(i32, i32, i8)>>, game_obj_db: &mut GameObjectDB) {
-    let mut rng = rand::thread_rng();
-    let loc = random_sq(&floor_sqs[&(level - 1)]);
+fn add_fire_pit(level: usize, map: &mut Map, floor_sqs: &mut HashMap<usize, HashSet<(i32, i32, i8)>>, game_obj_db: &mut GameObjectDB, rng: &mut GameRng) {
+    let loc = random_sq(&floor_sqs[&(level - 1)], rng);
     map.insert(loc, Tile::OldFirePit(rng.gen_range(0, 5)));
     floor_sqs.get_mut(&(level -1))
              .unwrap()
              .remove(&loc);
-    if let Some(adj) = random_open_adj(&floor_sqs[&(level - 1)], loc) {
-        let mut note = Item::get_item(game_obj_db, "note").unwrap();
+    if let Some(adj) = random_open_adj(&floor_sqs[&(level - 1)], loc, rng) {
+        let mut note = Item::get_item(game_obj_db, "note", rng).unwrap();
         note.set_loc(adj);
         if let GameObjects::Item(item) = &mut note {
             item.text = Some(("burnt scrap".to_string(), "Is there no end to the swarms of kobolds?".to_string()));            
@@ -296,8 +363,53 @@ fn add_fire_pit(level: usize, map: &mut Map, floor_sqs: &mut HashMap<usize, Hash
     game_obj_db.add(pile);
 }
 
-fn add_teleport_trap(level: usize, floor_sqs: &mut HashMap<usize, HashSet<(i32, i32, i8)>>, game_obj_db: &mut GameObjectDB) {
-    let loc = random_sq(&floor_sqs[&(level - 1)]);
+// Fill a patch of the level with a bank of fog, spreading out from a random floor square
+fn add_fog(world_info: &mut WorldInfo, level: usize, map: &mut Map, floor_sqs: &HashMap<usize, HashSet<(i32, i32, i8)>>, rng: &mut GameRng) {
+    let start = random_sq(&floor_sqs[&(level - 1)], rng);
+    let size = rng.gen_range(20, 60);
+    let bank = util::floodfill(start, size, |loc| {
+        util::ADJ.iter()
+            .map(|d| (loc.0 + d.0, loc.1 + d.1, loc.2))
+            .filter(|nl| map.get(nl) == Some(&Tile::StoneFloor))
+            .map(|nl| (nl, rng.gen_range(1, 4)))
+            .collect()
+    });
+
+    for loc in bank.keys() {
+        map.insert(*loc, Tile::Fog);
+    }
+    world_info.facts.push(Fact::new("fog".to_string(), 0, (0, 0, level as i8)));
+}
+
+fn add_spring(world_info: &mut WorldInfo, level: usize, map: &mut Map, floor_sqs: &mut HashMap<usize, HashSet<(i32, i32, i8)>>, rng: &mut GameRng) {
+    let loc = random_sq(&floor_sqs[&(level - 1)], rng);
+    map.insert(loc, Tile::Spring);
+    floor_sqs.get_mut(&(level - 1))
+            .unwrap()
+            .remove(&loc);
+    world_info.facts.push(Fact::new("spring".to_string(), 0, loc));
+}
+
+// An abandoned dwarven forge, still usable, with a bit of ore left lying around
+fn add_dungeon_forge(world_info: &mut WorldInfo, level: usize, map: &mut Map, floor_sqs: &mut HashMap<usize, HashSet<(i32, i32, i8)>>,
+        game_obj_db: &mut GameObjectDB, rng: &mut GameRng) {
+    let loc = random_sq(&floor_sqs[&(level - 1)], rng);
+    map.insert(loc, Tile::Forge);
+    floor_sqs.get_mut(&(level - 1))
+            .unwrap()
+            .remove(&loc);
+    if let Some(adj) = random_open_adj(&floor_sqs[&(level - 1)], loc, rng) {
+        for _ in 0..rng.gen_range(1, 4) {
+            let mut ore = Item::get_item(game_obj_db, "lump of iron ore", rng).unwrap();
+            ore.set_loc(adj);
+            game_obj_db.add(ore);
+        }
+    }
+    world_info.facts.push(Fact::new("forge".to_string(), 0, loc));
+}
+
+fn add_teleport_trap(level: usize, floor_sqs: &mut HashMap<usize, HashSet<(i32, i32, i8)>>, game_obj_db: &mut GameObjectDB, rng: &mut GameRng) {
+    let loc = random_sq(&floor_sqs[&(level - 1)], rng);
     let trap = SpecialSquare::teleport_trap(loc, game_obj_db);
     game_obj_db.listeners.insert((trap.obj_id(), EventType::SteppedOn));
     game_obj_db.add(trap);
@@ -307,17 +419,22 @@ fn add_teleport_trap(level: usize, floor_sqs: &mut HashMap<usize, HashSet<(i32,
             .remove(&loc);
 }
 
-fn add_shrine(world_info: &mut WorldInfo, level: usize, map: &mut Map, floor_sqs: &mut HashMap<usize, HashSet<(i32, i32, i8)>>, game_obj_db: &mut GameObjectDB) {
-    let loc = random_sq(&floor_sqs[&(level - 1)]);
-    let shrine = SpecialSquare::make(Tile::Shrine(ShrineType::Woden), loc, true, 3, game_obj_db);
-    game_obj_db.listeners.insert((shrine.obj_id(), EventType::Update));
+fn add_shrine(world_info: &mut WorldInfo, level: usize, stype: ShrineType, map: &mut Map, floor_sqs: &mut HashMap<usize, HashSet<(i32, i32, i8)>>, game_obj_db: &mut GameObjectDB, rng: &mut GameRng) {
+    let loc = random_sq(&floor_sqs[&(level - 1)], rng);
+    // Only Woden's shrines radiate a sanctuary
+    let radius = if stype == ShrineType::Woden { 3 } else { 0 };
+    let shrine = SpecialSquare::make(Tile::Shrine(stype), loc, true, radius, game_obj_db);
+    if stype == ShrineType::Woden {
+        game_obj_db.listeners.insert((shrine.obj_id(), EventType::Update));
+    }
     game_obj_db.add(shrine);
 
-    map.insert(loc, Tile::Shrine(ShrineType::Woden));
+    map.insert(loc, Tile::Shrine(stype));
     floor_sqs.get_mut(&(level - 1))
             .unwrap()
             .remove(&loc);
-    let fact = Fact::new(String::from("shrine to woden"), 0, loc);
+    let name = if stype == ShrineType::Woden { "shrine to woden" } else { "misshappen altar" };
+    let fact = Fact::new(String::from(name), 0, loc);
     world_info.facts.push(fact);
 }
 
@@ -393,10 +510,9 @@ fn light_triggered_gate(_world_info: &mut WorldInfo, map: &mut Map, floors: &mut
 }
 
 fn add_vault(world_info: &mut WorldInfo, map: &mut Map, floors: &mut HashSet<(i32, i32, i8)>,
-            game_obj_db: &mut GameObjectDB, vaults: &[Vault], level: i8) {
+            game_obj_db: &mut GameObjectDB, vaults: &[Vault], level: i8, rng: &mut GameRng) {
     // In the real game, I want to make sure I never create a gated vault in a room with the upstairs 
     // because that would result in a dungeon where the player probably can't progress without magic
-    let mut rng = rand::thread_rng();
     let vault_num = rng.gen_range(0, vaults.len());
     let vault = &vaults[vault_num];
     
@@ -405,61 +521,232 @@ fn add_vault(world_info: &mut WorldInfo, map: &mut Map, floors: &mut HashSet<(i3
 }
 
 fn decorate_levels(world_info: &mut WorldInfo, map: &mut Map, deepest_level: i8, floor_sqs: &mut HashMap<usize, HashSet<(i32, i32, i8)>>,
-            game_obj_db: &mut GameObjectDB, vaults: HashMap<usize, Vec<Vault>>) {
-    //let mut rng = rand::thread_rng();
+            game_obj_db: &mut GameObjectDB, vaults: HashMap<usize, Vec<Vault>>, rng: &mut GameRng) {
+    //let mut rng = rng;
     let mut curr_level = deepest_level;
     while curr_level > 0 {
         if curr_level < 3 {
-            add_fire_pit(curr_level as usize, map, floor_sqs, game_obj_db)             
+            add_fire_pit(curr_level as usize, map, floor_sqs, game_obj_db, rng)             
         }
 
         if curr_level == 1 {
-            add_shrine(world_info, curr_level as usize, map, floor_sqs, game_obj_db)
+            add_shrine(world_info, curr_level as usize, ShrineType::Woden, map, floor_sqs, game_obj_db, rng)
+        }
+
+        if curr_level == 3 {
+            add_shrine(world_info, curr_level as usize, ShrineType::Crawler, map, floor_sqs, game_obj_db, rng)
         }
 
         if !vaults[&(curr_level as usize - 1)].is_empty() {
             let floors = floor_sqs.get_mut(&(curr_level as usize - 1)).unwrap();
-            add_vault(world_info, map, floors, game_obj_db, &vaults[&(curr_level as usize - 1)], curr_level);
+            add_vault(world_info, map, floors, game_obj_db, &vaults[&(curr_level as usize - 1)], curr_level, rng);
         }
 
-        add_teleport_trap(curr_level as usize, floor_sqs, game_obj_db);
+        add_teleport_trap(curr_level as usize, floor_sqs, game_obj_db, rng);
         
         curr_level -= 1;
     }
 }
 
 fn populate_levels(_world_info: &mut WorldInfo, deepest_level: i8, floor_sqs: &HashMap<usize, HashSet<(i32, i32, i8)>>,
-            game_obj_db: &mut GameObjectDB, monster_fac: &MonsterFactory) {
+            game_obj_db: &mut GameObjectDB, monster_fac: &MonsterFactory, rng: &mut GameRng) {
     let mut curr_level = deepest_level;
 
     while curr_level > 0 {
         let level_index = curr_level as usize - 1;
 
         for _ in 0..10 {
-            let loc = random_sq(&floor_sqs[&level_index]);
-            monster_fac.monster_for_dungeon(loc, game_obj_db);
+            let loc = random_sq(&floor_sqs[&level_index], rng);
+            let name = monster_fac.monster_for_dungeon(loc, deepest_level as u8, game_obj_db, rng);
+            if monster_fac.travels_in_pack(&name) {
+                add_pack(&name, loc, &floor_sqs[&level_index], game_obj_db, monster_fac, rng);
+            }
         }
         curr_level -= 1;
     }
 }
 
-fn find_room_id(rooms: &[HashSet<(i32, i32)>], pt: &(i32, i32)) -> Option<usize> {
-    for room_id in 0..rooms.len() {
-        if rooms[room_id].contains(pt) {
-            return Some(room_id);
+// Surround a pack leader with a few more of its kind
+fn add_pack(name: &str, leader_loc: (i32, i32, i8), floor_sqs: &HashSet<(i32, i32, i8)>, game_obj_db: &mut GameObjectDB, monster_fac: &MonsterFactory, rng: &mut GameRng) {
+    let mut nearby: Vec<(i32, i32, i8)> = floor_sqs.iter()
+        .filter(|sq| **sq != leader_loc && util::distance(sq.0, sq.1, leader_loc.0, leader_loc.1) <= PACK_RADIUS)
+        .copied()
+        .collect();
+    nearby.shuffle(rng);
+
+    let pack_size = rng.gen_range(1, 4);
+    let mut members = vec![leader_loc];
+    for loc in nearby.iter().filter(|sq| !game_obj_db.location_occupied(sq)).take(pack_size).copied().collect::<Vec<(i32, i32, i8)>>() {
+        monster_fac.monster(name, loc, game_obj_db, rng);
+        members.push(loc);
+    }
+
+    // Remember who ran together so the survivors can lose their nerve if things go badly
+    let ids: Vec<usize> = members.iter().filter_map(|loc| game_obj_db.npc_at(loc)).collect();
+    if let Some(&leader_id) = ids.first() {
+        for id in ids.iter() {
+            game_obj_db.npc(*id).unwrap().pack = Some((leader_id, ids.len() as u8));
+        }
+    }
+}
+
+// Scatter a few points of interest around the main valley so that the overworld isn't entirely
+// empty outside of town. Each gets a small reward and a scrap of lore, and gets recorded as a
+// fact so villagers might eventually gossip about them.
+fn add_landmarks(world_info: &mut WorldInfo, map: &mut Map, valley: &HashSet<(i32, i32, i8)>, avoid: (i32, i32, i8), game_obj_db: &mut GameObjectDB, rng: &mut GameRng) {
+    let tb = world_info.town_boundary;
+    let town_centre = *world_info.town_square.iter().next().unwrap();
+    let candidates: HashSet<(i32, i32, i8)> = valley.iter()
+        .filter(|sq| map[sq] == Tile::Grass || map[sq] == Tile::Tree)
+        .filter(|sq| sq.0 < tb.0 - 10 || sq.0 > tb.2 + 10 || sq.1 < tb.1 - 10 || sq.1 > tb.3 + 10)
+        .filter(|sq| util::distance(sq.0, sq.1, avoid.0, avoid.1) > 15.0)
+        .copied()
+        .collect();
+
+    if candidates.is_empty() {
+        return;
+    }
+
+    let mut placed: Vec<(i32, i32, i8)> = Vec::new();
+    let mut kinds = vec!["standing stones", "ruined cottage", "abandoned camp"];
+    kinds.shuffle(rng);
+    for kind in kinds {
+        // try a few times to find a spot that isn't right on top of another landmark
+        for _ in 0..25 {
+            let loc = random_sq(&candidates, rng);
+            if placed.iter().any(|p| util::distance(p.0, p.1, loc.0, loc.1) < 20.0) {
+                continue;
+            }
+
+            // Landmarks can include walls, so remember what was there before in case the new
+            // walls cut the town off from the dungeon and we need to put things back
+            let footprint: Vec<(i32, i32, i8)> = (loc.0 - LANDMARK_REACH..=loc.0 + LANDMARK_REACH)
+                .flat_map(|r| (loc.1 - LANDMARK_REACH..=loc.1 + LANDMARK_REACH).map(move |c| (r, c, 0)))
+                .collect();
+            let prev_tiles: Vec<((i32, i32, i8), Tile)> = footprint.iter()
+                .filter_map(|sq| map.get(sq).map(|t| (*sq, *t)))
+                .collect();
+            let prev_objs: HashSet<usize> = footprint.iter()
+                .flat_map(|sq| objs_at(game_obj_db, sq))
+                .collect();
+
+            match kind {
+                "standing stones" => add_standing_stones(map, loc, game_obj_db, rng),
+                "ruined cottage" => add_ruined_cottage(map, loc, game_obj_db, rng),
+                _ => add_abandoned_camp(map, loc, game_obj_db, rng),
+            }
+
+            if !reachable_over_land(map, town_centre).contains(&avoid) {
+                for (sq, tile) in prev_tiles {
+                    map.insert(sq, tile);
+                }
+                for id in footprint.iter().flat_map(|sq| objs_at(game_obj_db, sq)).collect::<Vec<usize>>() {
+                    if !prev_objs.contains(&id) {
+                        game_obj_db.remove(id);
+                    }
+                }
+                continue;
+            }
+
+            world_info.facts.push(Fact::new(kind.to_string(), 0, loc));
+            placed.push(loc);
+            break;
+        }
+    }
+}
+
+fn place_lore_note(game_obj_db: &mut GameObjectDB, loc: (i32, i32, i8), title: &str, text: &str, rng: &mut GameRng) {
+    let mut note = Item::get_item(game_obj_db, "note", rng).unwrap();
+    note.set_loc(loc);
+    if let GameObjects::Item(item) = &mut note {
+        item.text = Some((title.to_string(), text.to_string()));
+    }
+    game_obj_db.add(note);
+}
+
+// Everything on a square, including hidden things like buried gold
+fn objs_at(game_obj_db: &GameObjectDB, loc: &(i32, i32, i8)) -> Vec<usize> {
+    match game_obj_db.obj_locs.get(loc) {
+        Some(ids) => ids.iter().copied().collect(),
+        None => Vec::new(),
+    }
+}
+
+// How far from its anchor square a landmark can spread (the ruined cottage is the biggest)
+const LANDMARK_REACH: i32 = 7;
+
+// Only overwrite squares that are open ground so we don't drop stones into rivers or mountains
+fn landmark_sq(map: &mut Map, loc: (i32, i32, i8), tile: Tile) {
+    if let Some(t) = map.get(&loc) {
+        if *t == Tile::Grass || *t == Tile::Tree || *t == Tile::Dirt {
+            map.insert(loc, tile);
         }
     }
+}
 
-    None
+fn add_standing_stones(map: &mut Map, centre: (i32, i32, i8), game_obj_db: &mut GameObjectDB, rng: &mut GameRng) {
+    for r in -3..4 {
+        for c in -3..4 {
+            landmark_sq(map, (centre.0 + r, centre.1 + c, 0), Tile::Grass);
+        }
+    }
+    for pt in util::bresenham_circle(centre.0, centre.1, 3) {
+        if (pt.0 + pt.1) % 2 == 0 {
+            landmark_sq(map, (pt.0, pt.1, 0), Tile::GraniteWall);
+        }
+    }
+    landmark_sq(map, centre, Tile::StoneFloor);
+    place_lore_note(game_obj_db, centre, "weathered rubbing", "The old stones remember when the mountain was whole.", rng);
+    let amt = rng.gen_range(5, 16);
+    let mut pile = GoldPile::make(game_obj_db, amt, centre);
+    pile.hide();
+    game_obj_db.add(pile);
 }
 
-// Moar floodfill -- I wonder if it's worth trying to amalgamate the 
-// places I've implemented floodfill in the code.
-fn connect_rooms(sqs: &mut Vec<Tile>, height: usize, width: usize) {
-    let mut rooms = Vec::new();
+fn add_ruined_cottage(map: &mut Map, nw: (i32, i32, i8), game_obj_db: &mut GameObjectDB, rng: &mut GameRng) {
+    for r in 0..5 {
+        for c in 0..7 {
+            let loc = (nw.0 + r, nw.1 + c, 0);
+            if r == 0 || r == 4 || c == 0 || c == 6 {
+                // much of the old walls have crumbled away
+                if rng.gen_range(0.0, 1.0) < 0.6 {
+                    landmark_sq(map, loc, Tile::Wall);
+                } else {
+                    landmark_sq(map, loc, Tile::Dirt);
+                }
+            } else {
+                landmark_sq(map, loc, Tile::StoneFloor);
+            }
+        }
+    }
+
+    let inside = (nw.0 + 2, nw.1 + 3, 0);
+    place_lore_note(game_obj_db, inside, "water-stained letter", "We leave tomorrow for the village. Things crawl up out of the hills at night now.", rng);
+    let mut item = if rng.gen_range(0, 2) == 0 {
+        Item::get_item(game_obj_db, "potion of healing", rng).unwrap()
+    } else {
+        Item::get_item(game_obj_db, "torch", rng).unwrap()
+    };
+    item.set_loc((nw.0 + 3, nw.1 + 5, 0));
+    game_obj_db.add(item);
+}
+
+fn add_abandoned_camp(map: &mut Map, loc: (i32, i32, i8), game_obj_db: &mut GameObjectDB, rng: &mut GameRng) {
+    for adj in util::ADJ.iter() {
+        landmark_sq(map, (loc.0 + adj.0, loc.1 + adj.1, 0), Tile::Dirt);
+    }
+    map.insert(loc, Tile::OldFirePit(rng.gen_range(0, 5)));
+    place_lore_note(game_obj_db, (loc.0 + 1, loc.1, 0), "torn page", "Three nights walking and still no sign of the entrance. Tomorrow we try the northern pass.", rng);
+    let amt = rng.gen_range(4, 11);
+    let mut pile = GoldPile::make(game_obj_db, amt, loc);
+    pile.hide();
+    game_obj_db.add(pile);
+}
+
+fn connect_rooms(sqs: &mut [Tile], height: usize, width: usize) {
+    let mut rooms: Vec<HashSet<(i32, i32)>> = Vec::new();
 
-    // find start point
-    let mut q = VecDeque::new();
+    // Each floodfill from a square we haven't seen yet finds a new room/cave
     let mut visited = HashSet::new();
     for j in 0..sqs.len() {
         if sqs[j] == Tile::Wall || sqs[j]  == Tile::GraniteWall{ continue; }
@@ -470,39 +757,20 @@ fn connect_rooms(sqs: &mut Vec<Tile>, height: usize, width: usize) {
             continue;
         }
 
-        q.push_front(start);
-        while !q.is_empty() {
-            let pt = q.pop_front().unwrap();
-            if visited.contains(&pt) {
-                continue;
-            }
-            
-            visited.insert(pt);
-    
-            // Find out it pt is in an existing cave, if not start a new cave
-            let room_d = if let Some(id) = find_room_id(&rooms, &pt) {
-                id 
-            } else {
-                rooms.push(HashSet::new());
-                let room_id = rooms.len() - 1;            
-                rooms[room_id].insert(pt);
-                room_id
-            };
-            
-            for adj in util::ADJ.iter() {
-                let n = (pt.0 + adj.0, pt.1 + adj.1);
-                if n.0 < 0 || n.1 < 0 || n.0 as usize >= height || n.1 as usize >= width {
-                    continue;
-                }
-                let i = n.0 as usize * width + n.1 as usize;
-                if !(sqs[i] == Tile::Wall || sqs[i] == Tile::GraniteWall) {
-                    rooms[room_d].insert(n);
-                    if !visited.contains(&n) {
-                        q.push_back(n);
-                    }
-                }
-            }
-        }
+        let room = util::floodfill(start, u32::MAX, |pt: (i32, i32)| {
+            util::ADJ.iter()
+                     .map(|adj| (pt.0 + adj.0, pt.1 + adj.1))
+                     .filter(|n| n.0 >= 0 && n.1 >= 0 && (n.0 as usize) < height && (n.1 as usize) < width)
+                     .filter(|n| {
+                         let i = n.0 as usize * width + n.1 as usize;
+                         !(sqs[i] == Tile::Wall || sqs[i] == Tile::GraniteWall)
+                     })
+                     .map(|n| (n, 1))
+                     .collect()
+        });
+        let room: HashSet<(i32, i32)> = room.into_keys().collect();
+        visited.extend(room.iter().copied());
+        rooms.push(room);
     }
 
     // Just fill in any small caves that are 3 squares or less
@@ -595,9 +863,8 @@ fn count_neighbours(sqs: &[bool], row: usize, col: usize, height: usize, width:
     sum
 }
 
-fn cave_overlay(width: usize, height: usize) -> Vec<bool> {
+fn cave_overlay(width: usize, height: usize, rng: &mut GameRng) -> Vec<bool> {
     // Classic ellular automata to make a cave system I can draw over part of a level
-    let mut rng = rand::thread_rng();
     let mut sqs: Vec<bool> = (0..width * height).map(|_|  rng.gen_range(0.0, 1.0) < 0.45).collect();
     
     for _ in 0..3 {
@@ -626,10 +893,9 @@ fn cave_overlay(width: usize, height: usize) -> Vec<bool> {
 // a tremor as a hint of what's to come. Some other ideas:
 //         - strew the area with rubble (once I decide how it'll effect the player)
 //         - maybe graveyards or more undead to reflect that a disaster happened?
-fn add_caves_to_level(tiles: &mut Vec<Tile>, height: usize, width: usize) {
-    let mut rng = rand::thread_rng();
+fn add_caves_to_level(tiles: &mut [Tile], height: usize, width: usize, rubble_chance: f64, rng: &mut GameRng) {
     let caves_width = rng.gen_range(40, 80);
-    let caves = cave_overlay(caves_width, height - 2);
+    let caves = cave_overlay(caves_width, height - 2, rng);
     let start_col = rng.gen_range(20, width - caves_width);
 
     for r in 0..height-2 {
@@ -640,8 +906,8 @@ fn add_caves_to_level(tiles: &mut Vec<Tile>, height: usize, width: usize) {
             // Make some of the tiles of the cave system rubble
             tiles[map_i] = if !caves[oi] {
                 Tile::Wall
-            } else if rng.gen_range(0.0, 1.0) < 0.2 {
-                Tile::Dirt // this will later be replaced with Rubble items
+            } else if rng.gen_range(0.0, 1.0) < rubble_chance {
+                Tile::Dirt // build_dungeon(rng) turns every one of these into floor with a rubble item on it
             } else {
                 Tile::StoneFloor
             };
@@ -661,10 +927,9 @@ fn next_col(col: i32, distance: i32, slope: f32) -> i32 {
     f32::round(next) as i32
 }
 
-fn add_river_to_level(tiles: &mut Vec<Tile>, height: usize, width: usize, top:bool, tile: Tile) {
+fn add_river_to_level(tiles: &mut [Tile], height: usize, width: usize, top:bool, tile: Tile, breadth: usize, rng: &mut GameRng) {
     // Let's say the 4 outer walls of the level are split and the river and start in any of them, so there are 6
     // different possibilities for start position and slope
-    let mut rng = rand::thread_rng();
     
     let (mut row, mut col, mut slope) = if  top {
         let col = rng.gen_range(5, width / 2) as i32;
@@ -705,18 +970,26 @@ fn add_river_to_level(tiles: &mut Vec<Tile>, height: usize, width: usize, top:bo
     }
 
     // now fatten up the river and maybe add river banks
+    // (working in rows and columns so that a wide river can't spill over onto the next row)
     for pt in pts_drawn {
-        tiles[pt + 1] = Tile::UndergroundRiver;
-        if pt - 1 > 2 && tiles[pt - 1] != Tile::UndergroundRiver && rng.gen_range(0.0, 1.0) < 0.75 {
+        let row = pt / width;
+        let col = pt % width;
+        for k in 1..breadth.max(2) {
+            if col + k < width - 1 {
+                tiles[row * width + col + k] = Tile::UndergroundRiver;
+            }
+        }
+        if col > 2 && tiles[pt - 1] != Tile::UndergroundRiver && rng.gen_range(0.0, 1.0) < 0.75 {
             tiles[pt - 1] = Tile::StoneFloor;
         }
-        if pt + 2 < width - 3 && tiles[pt + 2] != Tile::UndergroundRiver && rng.gen_range(0.0, 1.0) < 0.75 {
-            tiles[pt + 2] = Tile::StoneFloor;
+        let far_bank = col + breadth.max(2);
+        if far_bank < width - 3 && tiles[row * width + far_bank] != Tile::UndergroundRiver && rng.gen_range(0.0, 1.0) < 0.75 {
+            tiles[row * width + far_bank] = Tile::StoneFloor;
         }
     }
 }
 
-fn build_test_dungeon(world_info: &mut WorldInfo, map: &mut Map, entrance: (i32, i32, i8), game_obj_db: &mut GameObjectDB, monster_fac: &MonsterFactory) {
+fn build_test_dungeon(world_info: &mut WorldInfo, map: &mut Map, entrance: (i32, i32, i8), game_obj_db: &mut GameObjectDB, monster_fac: &MonsterFactory, rng: &mut GameRng) {
     for row in entrance.0-1..entrance.0+8 {
         for col in entrance.1-1..entrance.1+8 {
             map.insert((row, col, 1), Tile::StoneFloor);
@@ -738,42 +1011,50 @@ fn build_test_dungeon(world_info: &mut WorldInfo, map: &mut Map, entrance: (i32,
 
     //Item::mushroom(game_obj_db, (entrance.0 + 6, entrance.1 + 3, 1));
     //Item::mushroom(game_obj_db, (entrance.0 + 7, entrance.1 + 3, 1));
-    Item::mushroom(game_obj_db, (entrance.0 + 7, entrance.1 + 2, 1));
+    Item::mushroom(game_obj_db, (entrance.0 + 7, entrance.1 + 2, 1), rng);
     
     let loc = (entrance.0 + 3, entrance.1 + 5, 1);
-    monster_fac.monster("fungal growth", loc, game_obj_db);
+    monster_fac.monster("fungal growth", loc, game_obj_db, rng);
     map.insert((entrance.0, entrance.1, 1), Tile::StairsUp);
 }
 
-fn build_dungeon(world_info: &mut WorldInfo, map: &mut Map, entrance: (i32, i32, i8), game_obj_db: &mut GameObjectDB, monster_fac: &MonsterFactory) {
-    let mut rng = rand::thread_rng();
+fn build_dungeon(world_info: &mut WorldInfo, map: &mut Map, entrance: (i32, i32, i8), game_obj_db: &mut GameObjectDB,
+            monster_fac: &MonsterFactory, depth: u8, rng: &mut GameRng) {
     let width = 125;
     let height = 40;
     let mut floor_sqs = HashMap::new();
     let mut vaults = HashMap::new();
-    let max_level = 5;
+    let max_level = depth.clamp(1, MAX_DUNGEON_DEPTH) as usize;
+    world_info.dungeon_depth = max_level as u8;
     let mut dungeon = Vec::new();
 
     let mut river_levels = Vec::new();
     for n in 0..max_level {
-        let result = dungeon::draw_level(width, height);
+        let result = dungeon::draw_level(width, height, rng);
         let mut level = result.0;
         
-        // A few of the levels will have caves and/or rivers
-        if n > 1 && rng.gen_range(0, 5) == 0 {
-            add_caves_to_level(&mut level, height, width);
+        // A few of the levels will have caves and/or rivers, and they get more common (and nastier)
+        // the deeper you go
+        let deeper = n.saturating_sub(2) as f64;
+        if n > 1 && rng.gen_range(0.0, 1.0) < (0.2 + 0.05 * deeper).min(0.6) {
+            add_caves_to_level(&mut level, height, width, (0.15 + 0.02 * deeper).min(0.35), rng);
             connect_rooms(&mut level, height, width);
             world_info.facts.push(Fact::new("caves".to_string(), 0, (0, 0, n as i8 + 1)));
         }
-        if n > 1 && rng.gen_range(0, 2) == 0 {
+        if n > 1 && rng.gen_range(0.0, 1.0) < (0.5 + 0.05 * deeper).min(0.85) {
             // I should guarantee some means of crossing the river further up the dungeon
-            add_river_to_level(&mut level, height, width, true, Tile::UndergroundRiver);
-            if rng.gen_range(0, 3) == 0 {
-                add_river_to_level(&mut level, height, width, false, Tile::UndergroundRiver);
+            let breadth = if n > 4 && rng.gen_range(0.0, 1.0) < 0.5 { 3 } else { 2 };
+            add_river_to_level(&mut level, height, width, true, Tile::UndergroundRiver, breadth, rng);
+            if rng.gen_range(0.0, 1.0) < (0.33 + 0.04 * deeper).min(0.6) {
+                add_river_to_level(&mut level, height, width, false, Tile::UndergroundRiver, breadth, rng);
             }
             world_info.facts.push(Fact::new("river".to_string(), 0, (0, 0, n as i8 + 1)));
             river_levels.push(n);
         }
+        // On dark levels light sources don't reach as far
+        if n > 1 && rng.gen_range(0.0, 1.0) < (0.1 * deeper).min(0.5) {
+            world_info.facts.push(Fact::new("darkness".to_string(), 0, (0, 0, n as i8 + 1)));
+        }
         
         // TODO: I should probably clean out vaults that are mostly destroyed by caves
         dungeon.push(level);
@@ -782,11 +1063,12 @@ fn build_dungeon(world_info: &mut WorldInfo, map: &mut Map, entrance: (i32, i32,
     }
     println!("Rivers on: {:?}", river_levels);
 
-    let stairs = set_stairs(&mut dungeon, width, height);
-    // Copy the dungeon onto the world map
+    let (stairs, down_stairs) = set_stairs(&mut dungeon, width, height, rng);
+    // Copy the dungeon onto the world map. Every level gets the same offset so that the stairs
+    // set_stairs(rng) aligned stay aligned in world co-ordinates.
+    let stairs_row_delta = entrance.0 - stairs.0 as i32;
+    let stairs_col_delta = entrance.1 - stairs.1 as i32;
     for lvl in 0..max_level {
-        let stairs_row_delta = entrance.0 - stairs.0 as i32;
-        let stairs_col_delta = entrance.1 - stairs.1 as i32;
         for r in 0..height {
             for c in 0..width {
                 let i = r * width + c;
@@ -794,7 +1076,7 @@ fn build_dungeon(world_info: &mut WorldInfo, map: &mut Map, entrance: (i32, i32,
                 let curr_col = c as i32 + stairs_col_delta;
 
                 if let Tile::Door(_) = dungeon[lvl][i] {
-                    let roll = rand::thread_rng().gen_range(0.0, 1.0);
+                    let roll = rng.gen_range(0.0, 1.0);
                     if roll < 0.2 {
                         map.insert((curr_row, curr_col, lvl as i8 + 1), Tile::Door(DoorState::Locked));
                     } else if roll < 0.5 {
@@ -828,20 +1110,41 @@ fn build_dungeon(world_info: &mut WorldInfo, map: &mut Map, entrance: (i32, i32,
         }
     }
 
-    //decorate_levels(world_info, map, max_level as i8, &mut floor_sqs, game_obj_db, vaults);
-    populate_levels(world_info, max_level as i8, &floor_sqs, game_obj_db, monster_fac);
-    seed_items(max_level, &floor_sqs, game_obj_db);
+    for (lvl, sq) in down_stairs.iter().enumerate() {
+        let row = sq.0 as i32 + stairs_row_delta;
+        let col = sq.1 as i32 + stairs_col_delta;
+        debug_assert_eq!(map[&(row, col, lvl as i8 + 1)], Tile::StairsDown);
+        debug_assert_eq!(map[&(row, col, lvl as i8 + 2)], Tile::StairsUp);
+    }
+
+    //decorate_levels(world_info, map, max_level as i8, &mut floor_sqs, game_obj_db, vaults, rng);
+    // Fog banks would belong in decorate_levels(rng) but while that's switched off they're added here
+    for lvl in 3..=max_level {
+        if rng.gen_range(0.0, 1.0) < 0.33 {
+            add_fog(world_info, lvl, map, &floor_sqs, rng);
+        }
+    }
+    for lvl in 1..=max_level {
+        if rng.gen_range(0.0, 1.0) < 0.3 {
+            add_spring(world_info, lvl, map, &mut floor_sqs, rng);
+        }
+        if lvl > 1 && rng.gen_range(0.0, 1.0) < 0.15 {
+            add_dungeon_forge(world_info, lvl, map, &mut floor_sqs, game_obj_db, rng);
+        }
+    }
+    populate_levels(world_info, max_level as i8, &floor_sqs, game_obj_db, monster_fac, rng);
+    seed_items(max_level, &floor_sqs, game_obj_db, monster_fac.difficulty.loot_factor(), rng);
 
     // if there is a river on a level, make sure the player is able to find a way to cross it on 
     // an earlier level
     for river_on in river_levels.iter() {
-        let level = rand::thread_rng().gen_range(1, river_on);
-        let loc = random_sq(&floor_sqs[&level]);
+        let level = rng.gen_range(1, river_on);
+        let loc = random_sq(&floor_sqs[&level], rng);
 
-        let mut item = if rand::thread_rng().gen_range(0.0, 1.0) < 0.75 {
-            Item::get_item(game_obj_db, "potion of levitation").unwrap()
+        let mut item = if rng.gen_range(0.0, 1.0) < 0.75 {
+            Item::get_item(game_obj_db, "potion of levitation", rng).unwrap()
         } else {
-            Item::get_item(game_obj_db, "wand of frost").unwrap()            
+            Item::get_item(game_obj_db, "wand of frost", rng).unwrap()            
         };
 
         item.set_loc(loc);
@@ -849,26 +1152,33 @@ fn build_dungeon(world_info: &mut WorldInfo, map: &mut Map, entrance: (i32, i32,
     }
 }
 
-fn seed_items(deepest_level: usize, floor_sqs: &HashMap<usize, HashSet<(i32, i32, i8)>>, game_obj_db: &mut GameObjectDB) {
+// Relative odds (out of 100) of each kind of loot turning up on a dungeon floor. "spellbook"
+// and "gold" stand in for a random spellbook and a pile of gold.
+const FLOOR_LOOT: [(&str, u32); 16] = [
+    ("potion of healing", 17), ("potion of telepathy", 3), ("torch", 20), ("shield", 10),
+    ("scroll of blink", 10), ("longsword", 10), ("scroll of protection", 5), ("scroll of detect monsters", 5),
+    ("scroll of treasure detection", 5), ("scroll of magic mapping", 5), ("scroll of descent", 3),
+    ("scroll of gust of wind", 1), ("scroll of darkness", 1), ("lump of iron ore", 2), ("spellbook", 1),
+    ("gold", 2),
+];
+
+fn seed_items(deepest_level: usize, floor_sqs: &HashMap<usize, HashSet<(i32, i32, i8)>>, game_obj_db: &mut GameObjectDB, loot_factor: f64, rng: &mut GameRng) {
+    let items_per_level = (5.0 * loot_factor).round() as usize;
     for lvl in 0..deepest_level {
-        for _ in 0..5 {
-            let sq = random_sq(&floor_sqs[&lvl]);
-            let roll = rand::thread_rng().gen_range(0.0, 1.0);
-            let mut i = if roll < 0.20 {
-                Item::get_item(game_obj_db, "potion of healing").unwrap()
-            } else if roll < 0.4 {
-                Item::get_item(game_obj_db, "torch").unwrap()
-            } else if roll < 0.5 {
-                Item::get_item(game_obj_db, "shield").unwrap()
-            } else if roll < 0.6 {
-                Item::get_item(game_obj_db, "scroll of blink").unwrap()
-            } else if roll < 0.7 {
-                Item::get_item(game_obj_db, "longsword").unwrap()
-            } else if roll < 0.8 {
-                Item::get_item(game_obj_db, "scroll of protection").unwrap()
-            } else {
-                let amt = rand::thread_rng().gen_range(10, 21);
-                GoldPile::make(game_obj_db, amt, (0, 0, 0))
+        for _ in 0..items_per_level {
+            let sq = random_sq(&floor_sqs[&lvl], rng);
+            let (name, _) = FLOOR_LOOT.choose_weighted(rng, |l| l.1).unwrap();
+            let mut i = match *name {
+                "spellbook" => {
+                    let book = magic::SPELLBOOKS.choose(rng).unwrap();
+                    Item::get_item(game_obj_db, book, rng).unwrap()
+                },
+                "gold" => {
+                    // Gold piles get a little richer the deeper you go
+                    let amt = rng.gen_range(10, 21) + 2 * lvl as u32;
+                    GoldPile::make(game_obj_db, amt, (0, 0, 0))
+                },
+                _ => Item::get_item(game_obj_db, name, rng).unwrap(),
             };
 
             i.set_loc(sq);
@@ -877,14 +1187,15 @@ fn seed_items(deepest_level: usize, floor_sqs: &HashMap<usize, HashSet<(i32, i32
     }
 }
 
-pub fn generate_world(game_obj_db: &mut GameObjectDB, monster_fac: &MonsterFactory, player_name: &str) -> (Map, WorldInfo) {
+pub fn generate_world(game_obj_db: &mut GameObjectDB, monster_fac: &MonsterFactory, player_name: &str, town_opts: TownOptions,
+            dungeon_depth: u8, rng: &mut GameRng) -> (Map, WorldInfo) {
     let map_start = Instant::now();
-    let mut map = wilderness::gen_wilderness_map();
+    let mut map = wilderness::gen_wilderness_map(rng);
     let map_end = map_start.elapsed();
     println!("Time to make world map: {:?}", map_end);
 
     let town_start = Instant::now();
-    let mut world_info = town::create_town(&mut map, game_obj_db);
+    let mut world_info = town::create_town(&mut map, game_obj_db, town_opts, rng);
     let town_end = town_start.elapsed();
     world_info.player_name = player_name.to_string();
     println!("Town creation done {:?}", town_end);
@@ -904,19 +1215,122 @@ pub fn generate_world(game_obj_db: &mut GameObjectDB, monster_fac: &MonsterFacto
         }
     }
 
-    let dungeon_entrance = find_good_dungeon_entrance(&map, &valleys[max_id]);
+    let town_centre = *world_info.town_square.iter().next().unwrap();
+    let reachable = reachable_over_land(&map, town_centre);
+    let dungeon_entrance = find_good_dungeon_entrance(&map, &valleys[max_id], &reachable, rng);
     println!("Found a good dungeon entrance");
 
     let dungeon_start = Instant::now();
-    build_dungeon(&mut world_info, &mut map, dungeon_entrance, game_obj_db, monster_fac);
-    //build_test_dungeon(&mut world_info, &mut map, dungeon_entrance, game_obj_db, monster_fac);
+    build_dungeon(&mut world_info, &mut map, dungeon_entrance, game_obj_db, monster_fac, dungeon_depth, rng);
+    //build_test_dungeon(&mut world_info, &mut map, dungeon_entrance, game_obj_db, monster_fac, rng);
     let dungeon_end = dungeon_start.elapsed();
     println!("Time to make dungeon: {:?}", dungeon_end);
 
     world_info.facts.push(Fact::new("dungeon location".to_string(), 0, dungeon_entrance));
 
-    add_old_road(&mut map, dungeon_entrance);
+    add_old_road(&mut map, dungeon_entrance, rng);
     map.insert((dungeon_entrance.0 as i32, dungeon_entrance.1 as i32, 0), Tile::Portal);
+
+    add_landmarks(&mut world_info, &mut map, &valleys[max_id], dungeon_entrance, game_obj_db, rng);
     
     (map, world_info)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn floor_loot_table_names_real_items() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut rng = GameRng::seeded(1);
+        for (name, weight) in FLOOR_LOOT.iter() {
+            assert!(*weight > 0);
+            if *name != "spellbook" && *name != "gold" {
+                assert!(Item::get_item(&mut game_obj_db, name, &mut rng).is_some(), "no such item: {}", name);
+            }
+        }
+        assert_eq!(FLOOR_LOOT.iter().map(|l| l.1).sum::<u32>(), 100);
+    }
+
+    #[test]
+    fn same_seed_draws_the_same_dungeon_level() {
+        let (level_a, vaults_a) = dungeon::draw_level(80, 40, &mut GameRng::seeded(2181));
+        let (level_b, vaults_b) = dungeon::draw_level(80, 40, &mut GameRng::seeded(2181));
+        assert_eq!(level_a, level_b);
+        assert_eq!(vaults_a.len(), vaults_b.len());
+
+        let (level_c, _) = dungeon::draw_level(80, 40, &mut GameRng::seeded(2182));
+        assert_ne!(level_a, level_c);
+    }
+
+    #[test]
+    fn wide_rivers_stay_off_the_outer_walls() {
+        let (height, width) = (20, 30);
+        for seed in 0..50 {
+            let mut tiles: Vec<Tile> = (0..height * width)
+                .map(|i| if i / width == 0 || i / width == height - 1 || i % width == 0 || i % width == width - 1 { Tile::WorldEdge } else { Tile::Wall })
+                .collect();
+            add_river_to_level(&mut tiles, height, width, seed % 2 == 0, Tile::DeepWater, 5, &mut GameRng::seeded(seed));
+
+            for r in 0..height {
+                assert_eq!(tiles[r * width], Tile::WorldEdge, "seed {}: row {} west wall", seed, r);
+                assert_eq!(tiles[r * width + width - 1], Tile::WorldEdge, "seed {}: row {} east wall", seed, r);
+            }
+        }
+    }
+
+    #[test]
+    fn packs_gather_around_their_leader() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mf = MonsterFactory::init();
+        let mut rng = GameRng::seeded(2214);
+        let mut floor = HashSet::new();
+        for r in 0..20 {
+            for c in 0..20 {
+                floor.insert((r, c, 1));
+            }
+        }
+
+        let leader_loc = (10, 10, 1);
+        mf.monster("kobold", leader_loc, &mut game_obj_db, &mut rng);
+        add_pack("kobold", leader_loc, &floor, &mut game_obj_db, &mf, &mut rng);
+
+        let leader_id = game_obj_db.npc_at(&leader_loc).unwrap();
+        let pack: Vec<usize> = floor.iter().filter_map(|sq| game_obj_db.npc_at(sq)).collect();
+        assert!(pack.len() >= 2 && pack.len() <= 4);
+        for id in pack.iter() {
+            let npc = game_obj_db.npc(*id).unwrap();
+            let loc = npc.get_loc();
+            assert!(util::distance(loc.0, loc.1, leader_loc.0, leader_loc.1) <= PACK_RADIUS);
+            assert_eq!(npc.pack, Some((leader_id, pack.len() as u8)));
+        }
+    }
+
+    #[test]
+    fn deep_dungeons_are_connected_and_end_at_their_depth() {
+        let mut game_obj_db = GameObjectDB::new();
+        let mut map = Map::new();
+        let mut world_info = WorldInfo::new("Testville".to_string(), (0, 0, 10, 10), "The Test Inn".to_string());
+        let mf = MonsterFactory::init();
+        build_dungeon(&mut world_info, &mut map, (100, 100, 0), &mut game_obj_db, &mf, 10, &mut GameRng::seeded(2192));
+
+        assert_eq!(world_info.dungeon_depth, 10);
+        assert_eq!(map[&(100, 100, 1)], Tile::StairsUp);
+        for lvl in 1..10 {
+            let down = map.iter().find(|(sq, tile)| sq.2 == lvl && **tile == Tile::StairsDown).map(|(sq, _)| *sq);
+            let down = down.unwrap_or_else(|| panic!("no stairs down on level {}", lvl));
+            assert_eq!(map[&(down.0, down.1, lvl + 1)], Tile::StairsUp);
+        }
+        assert!(!map.iter().any(|(sq, tile)| sq.2 == 10 && *tile == Tile::StairsDown));
+        assert!(!map.keys().any(|sq| sq.2 > 10));
+
+        // Even standing on a way down, the player can't go below the bottom level
+        let bottom = *map.iter().find(|(sq, tile)| sq.2 == 10 && **tile == Tile::StoneFloor && !game_obj_db.location_occupied(sq)).unwrap().0;
+        map.insert(bottom, Tile::StairsDown);
+        let mut state = crate::GameState::init(map, world_info);
+        crate::player::Player::new_warrior(&mut game_obj_db, "Tester", crate::player::Race::Human, &mut state.rng);
+        assert!(game_obj_db.set_to_loc(0, bottom));
+        assert_eq!(crate::take_stairs(&mut state, &mut game_obj_db, true), 0.0);
+        assert_eq!(game_obj_db.player().unwrap().get_loc(), bottom);
+    }
+}